@@ -392,7 +392,7 @@ namespace sdk {
     nlohmann::json ga_rust::set_unspent_outputs_status(
         const nlohmann::json& details, const nlohmann::json& twofactor_data)
     {
-        throw std::runtime_error("set_unspent_outputs_status not implemented");
+        return rust_call("set_unspent_outputs_status", details, m_session);
     }
 
     wally_tx_ptr ga_rust::get_raw_transaction_details(const std::string& txhash_hex) const
@@ -53,4 +53,11 @@ impl BEOutPoint {
             Self::Elements(outpoint) => outpoint.vout,
         }
     }
+
+    pub fn elements(&self) -> Option<elements::OutPoint> {
+        match self {
+            Self::Bitcoin(_) => None,
+            Self::Elements(outpoint) => Some(*outpoint),
+        }
+    }
 }
@@ -1,5 +1,7 @@
 use bitcoin::blockdata::transaction::EcdsaSighashType as BitcoinSigHashType;
+use bitcoin::util::sighash::SchnorrSighashType as BitcoinSchnorrSigHashType;
 use elements::EcdsaSigHashType as ElementsSigHashType;
+use elements::SchnorrSigHashType as ElementsSchnorrSigHashType;
 
 use crate::error::Error;
 
@@ -43,6 +45,25 @@ impl BESigHashType {
         }
     }
 
+    /// The sighash to use for taproot key-path spends, `SIGHASH_ALL` is converted to
+    /// `SIGHASH_DEFAULT` which commits to the same data with a shorter signature
+    pub fn into_bitcoin_schnorr(&self) -> Result<BitcoinSchnorrSigHashType, Error> {
+        match self.into_bitcoin()? {
+            BitcoinSigHashType::All => Ok(BitcoinSchnorrSigHashType::Default),
+            sighash => BitcoinSchnorrSigHashType::from_consensus_u8(sighash.to_u32() as u8)
+                .map_err(|_| Error::InvalidSigHash),
+        }
+    }
+
+    /// See [`BESigHashType::into_bitcoin_schnorr`]
+    pub fn into_elements_schnorr(&self) -> Result<ElementsSchnorrSigHashType, Error> {
+        match self.into_elements()? {
+            ElementsSigHashType::All => Ok(ElementsSchnorrSigHashType::Default),
+            sighash => ElementsSchnorrSigHashType::from_u8(sighash.as_u32() as u8)
+                .ok_or(Error::InvalidSigHash),
+        }
+    }
+
     fn is_allowed(&self) -> Result<(), Error> {
         match self {
             BESigHashType::Bitcoin(BitcoinSigHashType::All)
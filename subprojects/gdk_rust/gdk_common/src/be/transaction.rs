@@ -1,7 +1,7 @@
 use crate::be::*;
 use crate::error::Error;
 use crate::model::{Balances, TransactionType};
-use crate::scripts::{p2pkh_script, ScriptType};
+use crate::scripts::{p2pkh_script, ScriptTemplate, ScriptType};
 use crate::NetworkId;
 use bitcoin::blockdata::script::Instruction;
 use bitcoin::blockdata::transaction::EcdsaSighashType as BitcoinSigHashType;
@@ -9,8 +9,9 @@ use bitcoin::consensus::encode::deserialize as btc_des;
 use bitcoin::consensus::encode::serialize as btc_ser;
 use bitcoin::hashes::hex::{FromHex, ToHex};
 use bitcoin::hashes::Hash;
-use bitcoin::secp256k1::{self, ecdsa::Signature, Message, Secp256k1};
-use bitcoin::util::sighash::SighashCache;
+use bitcoin::secp256k1::{self, ecdsa::Signature, schnorr, Message, Secp256k1};
+use bitcoin::util::schnorr::TapTweak;
+use bitcoin::util::sighash::{Prevouts, SchnorrSighashType, SighashCache};
 use bitcoin::{PackedLockTime, PublicKey, Sequence};
 use elements::confidential;
 use elements::confidential::{Asset, Value};
@@ -27,6 +28,9 @@ use std::str::FromStr;
 
 pub const DUST_VALUE: u64 = 546;
 
+/// The maximum payload of a standard OP_RETURN output
+pub const MAX_DATA_OUTPUT_SIZE: usize = 80;
+
 // 52-bit rangeproof size
 const DEFAULT_RANGEPROOF_SIZE: usize = 4174;
 // 3-input ASP size
@@ -102,6 +106,13 @@ impl BETransaction {
         }
     }
 
+    pub fn set_lock_time(&mut self, lock_time: u32) {
+        match self {
+            Self::Bitcoin(tx) => tx.lock_time = PackedLockTime(lock_time),
+            Self::Elements(tx) => tx.lock_time = lock_time,
+        }
+    }
+
     pub fn previous_outputs(&self) -> Vec<BEOutPoint> {
         match self {
             Self::Bitcoin(tx) => {
@@ -325,6 +336,66 @@ impl BETransaction {
         Ok(())
     }
 
+    /// Add an OP_RETURN output, in liquid with explicit value and asset. Its value is burnt.
+    pub fn add_data_output(
+        &mut self,
+        data: &[u8],
+        value: u64,
+        asset: Option<elements::issuance::AssetId>,
+    ) {
+        match self {
+            BETransaction::Bitcoin(tx) => tx.output.push(bitcoin::TxOut {
+                script_pubkey: bitcoin::Script::new_op_return(data),
+                value,
+            }),
+            BETransaction::Elements(tx) => {
+                let asset_id =
+                    asset.expect("add_data_output must be called with a non empty asset in liquid");
+                tx.output.push(elements::TxOut {
+                    asset: confidential::Asset::Explicit(asset_id),
+                    value: confidential::Value::Explicit(value),
+                    nonce: confidential::Nonce::Null,
+                    script_pubkey: elements::Script::new_op_return(data),
+                    witness: TxOutWitness::default(),
+                })
+            }
+        }
+    }
+
+    /// The data pushed by an OP_RETURN output, `None` for other outputs
+    pub fn output_data(&self, vout: u32) -> Option<Vec<u8>> {
+        let mut data = vec![];
+        match self {
+            Self::Bitcoin(tx) => {
+                let script = &tx.output[vout as usize].script_pubkey;
+                if !script.is_op_return() {
+                    return None;
+                }
+                for instruction in script.instructions().skip(1) {
+                    match instruction {
+                        Ok(Instruction::PushBytes(bytes)) => data.extend_from_slice(bytes),
+                        _ => return None,
+                    }
+                }
+            }
+            Self::Elements(tx) => {
+                let script = &tx.output[vout as usize].script_pubkey;
+                if !script.is_op_return() {
+                    return None;
+                }
+                for instruction in script.instructions().skip(1) {
+                    match instruction {
+                        Ok(elements::script::Instruction::PushBytes(bytes)) => {
+                            data.extend_from_slice(bytes)
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+        }
+        Some(data)
+    }
+
     pub fn scramble(&mut self) {
         let mut rng = thread_rng();
         match self {
@@ -341,38 +412,50 @@ impl BETransaction {
 
     /// estimates the fee of the final transaction given the `fee_rate`
     /// called when the tx is being built and miss things like signatures and changes outputs.
-    pub fn estimated_fee(&self, fee_rate: f64, more_changes: u8, script_type: ScriptType) -> u64 {
+    /// `script` describes the inputs, all spending outputs of the same account.
+    pub fn estimated_fee(&self, fee_rate: f64, more_changes: u8, script: ScriptTemplate) -> u64 {
+        let vbytes = self.estimated_vsize(more_changes, script);
+        // increasing estimated fee by 2% (3% for elements) to stay over relay fee, TODO improve fee estimation and lower this
+        let margin = match self {
+            BETransaction::Bitcoin(_) => 1.02,
+            BETransaction::Elements(_) => 1.03,
+        };
+        let fee_val = (vbytes * fee_rate * margin) as u64;
+        info!(
+            "DUMMYTX inputs:{} outputs:{} num_changes:{} vbytes:{} fee_val:{}",
+            self.input_len(),
+            self.output_len(),
+            more_changes,
+            vbytes,
+            fee_val
+        );
+        fee_val
+    }
+
+    /// estimates the virtual size of the final transaction, with mock signatures for the inputs
+    /// described by `script` and `more_changes` change outputs added
+    pub fn estimated_vsize(&self, more_changes: u8, script: ScriptTemplate) -> f64 {
         let dummy_tx = self.clone();
         match dummy_tx {
             BETransaction::Bitcoin(mut tx) => {
                 for input in tx.input.iter_mut() {
-                    input.witness = script_type.mock_witness();
-                    input.script_sig = script_type.mock_script_sig().into();
+                    input.witness = script.mock_witness();
+                    input.script_sig = script.mock_script_sig().into();
                 }
                 for _ in 0..more_changes {
                     tx.output.push(bitcoin::TxOut {
                         value: 0,
-                        script_pubkey: script_type.mock_script_pubkey().into(),
+                        script_pubkey: script.mock_script_pubkey().into(),
                     })
                 }
-                let vbytes = tx.weight() as f64 / 4.0;
-                let fee_val = (vbytes * fee_rate * 1.02) as u64; // increasing estimated fee by 2% to stay over relay fee TODO improve fee estimation and lower this
-                info!(
-                    "DUMMYTX inputs:{} outputs:{} num_changes:{} vbytes:{} fee_val:{}",
-                    tx.input.len(),
-                    tx.output.len(),
-                    more_changes,
-                    vbytes,
-                    fee_val
-                );
-                fee_val
+                tx.weight() as f64 / 4.0
             }
             BETransaction::Elements(mut tx) => {
                 for input in tx.input.iter_mut() {
                     let mut tx_wit = TxInWitness::default();
-                    tx_wit.script_witness = script_type.mock_witness().to_vec();
+                    tx_wit.script_witness = script.mock_witness().to_vec();
                     input.witness = tx_wit;
-                    input.script_sig = script_type.mock_script_sig().into();
+                    input.script_sig = script.mock_script_sig().into();
                 }
                 let mock_asset = confidential::Asset::Confidential(mock_asset());
                 let mock_value = confidential::Value::Confidential(mock_value());
@@ -382,30 +465,24 @@ impl BETransaction {
                         asset: mock_asset,
                         value: mock_value,
                         nonce: mock_nonce,
-                        script_pubkey: script_type.mock_script_pubkey().into(),
+                        script_pubkey: script.mock_script_pubkey().into(),
                         ..Default::default()
                     };
                     tx.output.push(new_out);
                 }
 
+                // OP_RETURN outputs are not blinded
                 let proofs_size = (DEFAULT_RANGEPROOF_SIZE + DEFAULT_SURJECTIONPROOF_SIZE)
-                    * tx.output.iter().filter(|o| o.witness.is_empty()).count();
+                    * tx.output
+                        .iter()
+                        .filter(|o| o.witness.is_empty() && !o.is_null_data())
+                        .count();
 
                 tx.output.push(elements::TxOut::new_fee(
                     0,
                     elements::issuance::AssetId::from_slice(&[0u8; 32]).unwrap(),
                 )); // mockup for the explicit fee output
-                let vbytes = (tx.weight() + proofs_size) as f64 / 4.0;
-                let fee_val = (vbytes * fee_rate * 1.03) as u64; // increasing estimated fee by 3% to stay over relay fee, TODO improve fee estimation and lower this
-                info!(
-                    "DUMMYTX inputs:{} outputs:{} num_changes:{} vbytes:{} fee_val:{}",
-                    tx.input.len(),
-                    tx.output.len(),
-                    more_changes,
-                    vbytes,
-                    fee_val
-                );
-                fee_val
+                (tx.weight() + proofs_size) as f64 / 4.0
             }
         }
     }
@@ -426,6 +503,7 @@ impl BETransaction {
                         .unwrap();
                     different_assets.insert(asset);
                 }
+                different_assets.extend(issued_amounts(tx).into_iter().map(|(asset, _)| asset));
                 if different_assets.is_empty() {
                     0
                 } else {
@@ -445,7 +523,7 @@ impl BETransaction {
         policy_asset: Option<elements::issuance::AssetId>,
         all_txs: &BETransactions,
         unblinded: &HashMap<elements::OutPoint, elements::TxOutSecrets>,
-        script_type: ScriptType,
+        script: ScriptTemplate,
     ) -> Vec<AssetValue> {
         match self {
             Self::Bitcoin(tx) => {
@@ -454,7 +532,7 @@ impl BETransaction {
                 let estimated_fee = self.estimated_fee(
                     fee_rate,
                     self.estimated_changes(no_change, all_txs, unblinded),
-                    script_type,
+                    script,
                 ); // send all does not create change
                 if sum_outputs + estimated_fee > sum_inputs {
                     vec![AssetValue::new_bitcoin(sum_outputs + estimated_fee - sum_inputs)]
@@ -488,11 +566,14 @@ impl BETransaction {
                         .unwrap();
                     *inputs.entry(asset).or_insert(0) += value;
                 }
+                for (asset, value) in issued_amounts(tx) {
+                    *inputs.entry(asset).or_insert(0) += value;
+                }
 
                 let estimated_fee = self.estimated_fee(
                     fee_rate,
                     self.estimated_changes(no_change, all_txs, unblinded),
-                    script_type,
+                    script,
                 );
                 *outputs.entry(policy_asset.clone()).or_insert(0) += estimated_fee;
 
@@ -562,6 +643,9 @@ impl BETransaction {
                         .unwrap();
                     *inputs_asset_amounts.entry(asset).or_insert(0) += value;
                 }
+                for (asset, value) in issued_amounts(tx) {
+                    *inputs_asset_amounts.entry(asset).or_insert(0) += value;
+                }
                 let mut result = vec![];
                 for (asset, value) in inputs_asset_amounts.iter() {
                     let mut sum = value - outputs_asset_amounts.remove(asset).unwrap_or(0);
@@ -601,6 +685,36 @@ impl BETransaction {
         Ok(())
     }
 
+    /// Set the sequence of the input spending `outpoint`, if any
+    pub fn set_input_sequence(&mut self, outpoint: &BEOutPoint, sequence: u32) {
+        match (outpoint, self) {
+            (BEOutPoint::Bitcoin(outpoint), BETransaction::Bitcoin(tx)) => {
+                for input in tx.input.iter_mut().filter(|i| i.previous_output == *outpoint) {
+                    input.sequence = Sequence(sequence);
+                }
+            }
+            (BEOutPoint::Elements(outpoint), BETransaction::Elements(tx)) => {
+                for input in tx.input.iter_mut().filter(|i| i.previous_output == *outpoint) {
+                    input.sequence = sequence;
+                }
+            }
+            _ => panic!("unexpected mix of bitcoin and elements types"),
+        }
+    }
+
+    /// Attach an asset issuance or reissuance to the input spending `outpoint`, elements only
+    pub fn set_input_issuance(
+        &mut self,
+        outpoint: &elements::OutPoint,
+        issuance: elements::AssetIssuance,
+    ) {
+        if let BETransaction::Elements(tx) = self {
+            for input in tx.input.iter_mut().filter(|i| i.previous_output == *outpoint) {
+                input.asset_issuance = issuance;
+            }
+        }
+    }
+
     pub fn add_input(&mut self, outpoint: BEOutPoint) {
         match (outpoint, self) {
             (BEOutPoint::Bitcoin(outpoint), BETransaction::Bitcoin(tx)) => {
@@ -631,7 +745,7 @@ impl BETransaction {
     /// for bitcoin it requires all previous output to get input values.
     /// for elements,
     ///     for complete transactions looks at the explicit fee output,
-    ///     for incomplete tx (without explicit fee output) take the sum previous outputs value, previously unblinded
+    ///     for incomplete tx (without explicit fee output) take the sum previous outputs value, previously unblinded, and issued amounts
     ///                       and use the outputs value that must be still unblinded
     pub fn fee(
         &self,
@@ -667,6 +781,7 @@ impl BETransaction {
                         .iter()
                         .map(|i| BEOutPoint::Elements(i.previous_output))
                         .filter_map(|o| all_txs.get_previous_output_value(&o, all_unblinded))
+                        .chain(issued_amounts(tx).into_iter().map(|(_, value)| value))
                         .sum();
 
                     sum_inputs
@@ -846,7 +961,9 @@ impl BETransaction {
     /// can be managed using gdk-rust. Implemented for Bitcoin only.
     ///
     /// The `hashcache` argument should be initialized as None for every tx and
-    /// reused for its inputs.
+    /// reused for its inputs. `prevouts` are the outputs spent by every input of the tx, they are
+    /// needed only by taproot inputs.
+    #[allow(clippy::too_many_arguments)]
     pub fn verify_input_sig<'a>(
         &'a self,
         secp: &Secp256k1<impl secp256k1::Verification>,
@@ -855,6 +972,7 @@ impl BETransaction {
         public_key: &PublicKey,
         value: u64,
         script_type: ScriptType,
+        prevouts: &[bitcoin::TxOut],
     ) -> Result<(), Error> {
         let tx = if let BETransaction::Bitcoin(tx) = self {
             tx
@@ -862,8 +980,13 @@ impl BETransaction {
             // Signature verification is currently only used on Bitcoin
             unimplemented!();
         };
+        if script_type.is_multisig() {
+            return verify_multisig_input_sig(secp, hashcache, tx, inv, public_key, value);
+        }
+
         let mut sig = match script_type {
-            ScriptType::P2wpkh | ScriptType::P2shP2wpkh => {
+            ScriptType::P2wsh | ScriptType::P2shP2wsh => unreachable!("handled above"),
+            ScriptType::P2wpkh | ScriptType::P2shP2wpkh | ScriptType::P2tr => {
                 tx.input[inv].witness.to_vec().get(0).cloned().ok_or(Error::InputValidationFailed)
             }
             ScriptType::P2pkh => match tx.input[inv].script_sig.instructions().next() {
@@ -872,6 +995,27 @@ impl BETransaction {
             },
         }?;
 
+        if script_type.is_taproot() {
+            // the sighash byte is omitted for SIGHASH_DEFAULT
+            let sighash = match sig.len() {
+                64 => SchnorrSighashType::Default,
+                65 => SchnorrSighashType::from_consensus_u8(sig.pop().expect("len 65"))?,
+                _ => return Err(Error::InputValidationFailed),
+            };
+            let hashcache = hashcache.get_or_insert_with(|| SighashCache::new(tx));
+            let hash = hashcache.taproot_key_spend_signature_hash(
+                inv,
+                &Prevouts::All(prevouts),
+                sighash,
+            )?;
+            let message = Message::from_slice(&hash.into_inner()[..]).unwrap();
+            let (internal_key, _) = public_key.inner.x_only_public_key();
+            let (output_key, _) = internal_key.tap_tweak(secp, None);
+            let sig = schnorr::Signature::from_slice(&sig)?;
+            secp.verify_schnorr(&sig, &message, &output_key.to_inner())?;
+            return Ok(());
+        }
+
         let sighash = sig.pop().ok_or_else(|| Error::InputValidationFailed)?;
         let sighash = BitcoinSigHashType::from_standard(sighash as u32)?;
 
@@ -904,6 +1048,56 @@ impl BETransaction {
     }
 }
 
+/// Verify a `sortedmulti` input: the witness script must contain `public_key` and be satisfied by
+/// the signatures in the witness.
+fn verify_multisig_input_sig<'a>(
+    secp: &Secp256k1<impl secp256k1::Verification>,
+    hashcache: &mut Option<SighashCache<&'a bitcoin::Transaction>>,
+    tx: &'a bitcoin::Transaction,
+    inv: usize,
+    public_key: &PublicKey,
+    value: u64,
+) -> Result<(), Error> {
+    // <empty> <sig_1> ... <sig_k> <witness script>
+    let mut witness = tx.input[inv].witness.to_vec();
+    let witness_script = bitcoin::Script::from(witness.pop().ok_or(Error::InputValidationFailed)?);
+    if witness.is_empty() || !witness.remove(0).is_empty() {
+        return Err(Error::InputValidationFailed);
+    }
+
+    // OP_k <key_1> ... <key_n> OP_n OP_CHECKMULTISIG
+    let mut threshold = None;
+    let mut keys = vec![];
+    for instruction in witness_script.instructions() {
+        match instruction.map_err(|_| Error::InputValidationFailed)? {
+            Instruction::PushBytes(bytes) if bytes.len() == 33 => {
+                keys.push(PublicKey::from_slice(bytes)?)
+            }
+            Instruction::Op(op) if threshold.is_none() => {
+                threshold = Some(op.to_u8().wrapping_sub(0x50) as usize)
+            }
+            _ => {}
+        }
+    }
+    if !keys.contains(public_key) || threshold != Some(witness.len()) {
+        return Err(Error::InputValidationFailed);
+    }
+
+    // Signatures must match the keys in the same order
+    let hashcache = hashcache.get_or_insert_with(|| SighashCache::new(tx));
+    let mut keys = keys.iter();
+    for mut sig in witness {
+        let sighash = sig.pop().ok_or(Error::InputValidationFailed)?;
+        let sighash = BitcoinSigHashType::from_standard(sighash as u32)?;
+        let hash = hashcache.segwit_signature_hash(inv, &witness_script, value, sighash)?;
+        let message = Message::from_slice(&hash.into_inner()[..]).unwrap();
+        let sig = Signature::from_der(&sig)?;
+        keys.find(|key| secp.verify_ecdsa(&message, &sig, &key.inner).is_ok())
+            .ok_or(Error::InputValidationFailed)?;
+    }
+    Ok(())
+}
+
 fn mock_pubkey() -> secp256k1::PublicKey {
     secp256k1::PublicKey::from_slice(&[2u8; 33]).unwrap()
 }
@@ -920,6 +1114,21 @@ fn mock_value() -> elements::secp256k1_zkp::PedersenCommitment {
     elements::secp256k1_zkp::PedersenCommitment::from_slice(&mock_value).unwrap()
 }
 
+/// The explicit amounts of the assets and reissuance tokens issued by the inputs of `tx`
+fn issued_amounts(tx: &elements::Transaction) -> Vec<(elements::issuance::AssetId, u64)> {
+    let mut result = vec![];
+    for input in tx.input.iter().filter(|i| i.has_issuance()) {
+        let (asset, token) = input.issuance_ids();
+        if let Value::Explicit(value) = input.asset_issuance.amount {
+            result.push((asset, value));
+        }
+        if let Value::Explicit(value) = input.asset_issuance.inflation_keys {
+            result.push((token, value));
+        }
+    }
+    result
+}
+
 fn sum_inputs(tx: &bitcoin::Transaction, all_txs: &BETransactions) -> u64 {
     tx.input
         .iter()
@@ -10,11 +10,12 @@ use crate::error::Error;
 use crate::scripts::ScriptType;
 use crate::wally::MasterBlindingKey;
 use bitcoin::blockdata::transaction::EcdsaSighashType as BitcoinSigHashType;
-use bitcoin::hashes::hex::ToHex;
-use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPubKey};
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPubKey, Fingerprint, KeySource};
 use std::convert::TryFrom;
 use std::fmt;
 use std::fmt::Display;
+use std::str::FromStr;
 
 #[derive(Debug, Deserialize)]
 pub struct InitParam {
@@ -26,6 +27,15 @@ pub struct InitParam {
 
 pub type Balances = HashMap<String, i64>;
 
+/// The balance of a subaccount split between spendable and frozen outputs
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct BalanceDetails {
+    /// The balance of the outputs available to coin selection
+    pub spendable: Balances,
+    /// The balance of the outputs frozen with `set_unspent_outputs_status`
+    pub frozen: Balances,
+}
+
 // =========== v exchange rate stuff v ===========
 
 // TODO use these types from bitcoin-exchange-rates lib once it's in there
@@ -88,10 +98,14 @@ impl ExchangeRateOk {
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct AddressAmount {
+    #[serde(default)]
     pub address: String, // could be bitcoin or elements
     pub satoshi: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub asset_id: Option<String>,
+    /// The hex payload of an OP_RETURN output, in which case the address is empty
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
 }
 
 impl AddressAmount {
@@ -113,6 +127,16 @@ pub enum UtxoStrategy {
 
     /// Uses all and only the utxos specified by the caller
     Manual,
+
+    /// Search for a selection that needs no change output, or fall back to `SingleRandomDraw`
+    BranchAndBound,
+
+    /// Add randomly picked utxos until the addressees amounts, fees and change are covered
+    SingleRandomDraw,
+
+    /// Pick the selection with the lowest waste, which accounts for the current fee rate versus
+    /// the long term fee rate and for the cost of the change
+    LowestWaste,
 }
 
 impl Default for UtxoStrategy {
@@ -132,6 +156,9 @@ pub struct CreateTransaction {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub previous_transaction: Option<TxListItem>,
+    /// Recipients to add to the outputs of `previous_transaction` when replacing it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub add_addressees: Vec<AddressAmount>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub memo: Option<String>,
     #[serde(default)]
@@ -143,6 +170,65 @@ pub struct CreateTransaction {
     pub confidential_utxos_only: bool,
     #[serde(default)]
     pub utxo_strategy: UtxoStrategy,
+    /// The fee rate expected to spend the utxos in the future, used by the waste metric of the
+    /// coin selection
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub long_term_fee_rate: Option<u64>, // in satoshi/kbyte
+
+    /// Replace `previous_transaction` with a transaction sending all its inputs back to the wallet
+    #[serde(default)]
+    pub cancel_transaction: bool,
+    /// Bump the fee of the unconfirmed `previous_transaction` with a child spending its outputs
+    #[serde(default)]
+    pub cpfp: bool,
+    /// The block height or the timestamp before which the transaction can not be mined, defaults to
+    /// the tip height to discourage fee sniping
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction_locktime: Option<u32>,
+    /// The BIP78 endpoint of the receiver, to replace the transaction with a PayJoin when sent
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payjoin_endpoint: Option<String>,
+    /// Liquid only, issue a new asset received by the wallet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuance: Option<IssuanceOpt>,
+    /// Liquid only, reissue an asset whose reissuance token is held by the wallet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reissuance: Option<ReissuanceOpt>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct IssuanceOpt {
+    /// The amount of the asset to issue
+    pub satoshi: u64,
+    /// The amount of the reissuance token to issue, 0 if the asset can not be reissued
+    #[serde(default)]
+    pub token_satoshi: u64,
+    /// The hex hash of the contract of the asset, committed to by its id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contract_hash: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ReissuanceOpt {
+    pub asset_id: String,
+    /// The amount of the asset to reissue
+    pub satoshi: u64,
+    /// The hex entropy of the asset, needed if the wallet has not synced any of its issuances
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entropy: Option<String>,
+    /// The hex hash of the contract of the asset, an alternative to `entropy` together with the
+    /// outpoint spent by the input of the asset issuance
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contract_hash: Option<String>,
+    /// The txid of the outpoint spent by the input of the asset issuance
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prevout_txhash: Option<String>,
+    /// The vout of the outpoint spent by the input of the asset issuance
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prevout_pt_idx: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -159,6 +245,9 @@ pub struct GetBalanceOpt {
     pub num_confs: u32,
     #[serde(rename = "confidential")]
     pub confidential_utxos_only: Option<bool>,
+    /// Whether to include the frozen outputs in the balance
+    #[serde(default)]
+    pub all_coins: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -167,7 +256,8 @@ pub struct GetUnspentOpt {
     pub num_confs: Option<u32>,
     #[serde(rename = "confidential")]
     pub confidential_utxos_only: Option<bool>,
-    pub all_coins: Option<bool>, // unused
+    /// Whether to include the frozen outputs
+    pub all_coins: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -196,10 +286,29 @@ pub struct GetAddressOpt {
     pub subaccount: u32,
     pub address_type: Option<String>, // unused
     pub is_internal: Option<bool>,    // true = get an internal change address
+    /// If set, also return a payment URI for the address
+    #[serde(default)]
+    pub uri: Option<AddressUriOpt>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AddressUriOpt {
+    /// The amount to request, for Liquid in the policy asset
+    pub satoshi: Option<u64>,
+    pub label: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ParsePaymentUriOpt {
+    pub uri: String,
+    #[serde(default)]
+    pub subaccount: u32,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct CreateAccountOpt {
+    /// Can be omitted if `descriptor` is given, it is then taken from its key origin
+    #[serde(default)]
     pub subaccount: u32,
     pub name: String,
     // The account xpub if passed by the caller
@@ -208,6 +317,101 @@ pub struct CreateAccountOpt {
     pub discovered: bool,
     #[serde(default)]
     pub is_already_created: bool,
+    /// Required to create `p2wsh` and `p2sh-p2wsh` subaccounts
+    #[serde(default)]
+    pub multisig: Option<MultisigParams>,
+    /// An output descriptor to import, it sets `subaccount`, `xpub` and `multisig`
+    #[serde(default)]
+    pub descriptor: Option<String>,
+}
+
+/// The parameters of a k-of-n multisig subaccount, whose scripts are `sortedmulti` of our key and
+/// the keys of the cosigners.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MultisigParams {
+    /// The number of signatures required to spend
+    pub threshold: u32,
+    /// The account xpubs of the other cosigners, derived as ours at
+    /// `m/48'/coin_type'/account'/script_type'` (BIP48), optionally with their key origin
+    pub cosigner_xpubs: Vec<CosignerXpub>,
+}
+
+/// The account xpub of a multisig cosigner, as `[fingerprint/path]xpub` or just `xpub` if the key
+/// origin is unknown.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct CosignerXpub {
+    /// The fingerprint of the cosigner master key and the path of `xpub` from it
+    pub origin: Option<KeySource>,
+    pub xpub: ExtendedPubKey,
+}
+
+impl CosignerXpub {
+    /// The key origin, if unknown `xpub` is considered the master key
+    pub fn key_source(&self) -> KeySource {
+        self.origin.clone().unwrap_or_else(|| (self.xpub.fingerprint(), DerivationPath::master()))
+    }
+}
+
+impl From<ExtendedPubKey> for CosignerXpub {
+    fn from(xpub: ExtendedPubKey) -> Self {
+        CosignerXpub {
+            origin: None,
+            xpub,
+        }
+    }
+}
+
+impl FromStr for CosignerXpub {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let (origin, xpub) = match s.strip_prefix('[') {
+            Some(rest) => {
+                let (origin, xpub) = rest
+                    .split_once(']')
+                    .ok_or_else(|| Error::Generic(format!("invalid key origin {}", s)))?;
+                let (fingerprint, path) = match origin.split_once('/') {
+                    Some((fingerprint, path)) => (fingerprint, format!("m/{}", path)),
+                    None => (origin, "m".to_string()),
+                };
+                let fingerprint = Fingerprint::from_hex(fingerprint)
+                    .map_err(|_| Error::Generic(format!("invalid key origin fingerprint {}", s)))?;
+                let path = DerivationPath::from_str(&path.replace('h', "'"))
+                    .map_err(|_| Error::Generic(format!("invalid key origin path {}", s)))?;
+                (Some((fingerprint, path)), xpub)
+            }
+            None => (None, s),
+        };
+        Ok(CosignerXpub {
+            origin,
+            xpub: ExtendedPubKey::from_str(xpub)?,
+        })
+    }
+}
+
+impl TryFrom<String> for CosignerXpub {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Error> {
+        s.parse()
+    }
+}
+
+impl Display for CosignerXpub {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((fingerprint, path)) = &self.origin {
+            // the path is displayed as m/..., the origin omits the m
+            write!(f, "[{}{}]", fingerprint, &path.to_string()[1..])?;
+        }
+        write!(f, "{}", self.xpub)
+    }
+}
+
+impl From<CosignerXpub> for String {
+    fn from(xpub: CosignerXpub) -> Self {
+        xpub.to_string()
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -215,6 +419,16 @@ pub struct DiscoverAccountOpt {
     #[serde(rename = "type")]
     pub script_type: ScriptType,
     pub xpub: ExtendedPubKey,
+    /// The number of unused addresses to look at, the default gap limit is used if None
+    #[serde(default)]
+    pub gap_limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RecoverAccountsOpt {
+    /// The number of unused addresses to look at, the default gap limit is used if None
+    #[serde(default)]
+    pub gap_limit: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -227,6 +441,13 @@ pub struct GetAccountPathResult {
     pub path: Vec<ChildNumber>,
 }
 
+/// The output descriptors of a subaccount, with key origins and checksums (BIP380)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountDescriptors {
+    pub external: String,
+    pub internal: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GetNextAccountOpt {
     #[serde(rename = "type")]
@@ -335,6 +556,19 @@ pub struct TransactionMeta {
     #[serde(rename = "transaction_locktime")]
     pub lock_time: u32,
     pub transaction_outputs: Vec<TransactionOutput>,
+    /// For multisig subaccounts, the base64 PSBT with the signatures collected so far, to be passed
+    /// to the other cosigners until enough of them signed.
+    ///
+    /// For watch-only sessions, the request to sign the created transaction elsewhere: a PSBT for
+    /// Bitcoin, a PSET for Liquid. Once signed it is passed back to `sign_transaction`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub psbt: Option<String>,
+    /// Liquid only, the id of the asset issued or reissued by the transaction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issued_asset_id: Option<String>,
+    /// Liquid only, the id of the reissuance token issued by the transaction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issued_token_id: Option<String>,
 }
 
 impl From<BETransaction> for TransactionMeta {
@@ -368,6 +602,9 @@ impl From<BETransaction> for TransactionMeta {
             version: transaction.version(),
             lock_time: transaction.lock_time(),
             transaction_outputs: vec![],
+            psbt: None,
+            issued_asset_id: None,
+            issued_token_id: None,
         }
     }
 }
@@ -428,6 +665,10 @@ pub struct TransactionOutput {
     #[serde(rename = "script")]
     pub script_pubkey: String,
     pub satoshi: u64,
+
+    /// The hex payload of an OP_RETURN output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
 }
 
 /// Input and output element for get_transactions
@@ -533,6 +774,10 @@ pub struct GetTxInOut {
     /// None for not relevant Liquid inputs (for which the address is the empty string).
     #[serde(rename = "script")]
     pub script_pubkey: String,
+
+    /// The label of the address, empty if not set.
+    #[serde(default)]
+    pub address_label: String,
 }
 
 /// Transaction type
@@ -582,6 +827,9 @@ pub struct TxListItem {
     pub spv_verified: String,
     pub fee: u64,
     pub fee_rate: u64,
+    /// Whether `fee` and `fee_rate` are unknown, and so 0, because some previous txs are missing
+    #[serde(default)]
+    pub fee_unknown: bool,
     pub inputs: Vec<GetTxInOut>,
     pub outputs: Vec<GetTxInOut>,
     pub transaction_size: usize,
@@ -604,6 +852,8 @@ pub struct AccountInfo {
     pub core_descriptors: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub slip132_extended_pubkey: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multisig: Option<MultisigParams>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -668,6 +918,22 @@ pub struct Credentials {
     pub bip39_passphrase: String,
 }
 
+/// The public keys of the accounts to watch in a watch-only session.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct WatchOnlyCredentials {
+    /// Account extended public keys in the SLIP-132 encoding (e.g. `zpub...`).
+    #[serde(default)]
+    pub slip132_extended_pubkeys: Vec<String>,
+
+    /// Account output descriptors, in the same form of `core_descriptors` in `AccountInfo`.
+    #[serde(default)]
+    pub core_descriptors: Vec<String>,
+
+    /// The SLIP-77 master blinding key in hex, required for Liquid.
+    #[serde(default)]
+    pub slip77_key: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AddressPointer {
     pub subaccount: u32,
@@ -681,6 +947,14 @@ pub struct AddressPointer {
     pub pointer: u32, // child_number in bip32 terminology
     pub user_path: Vec<ChildNumber>,
     pub is_internal: bool,
+    /// Set when the address is close to the gap limit, the number of new addresses that can
+    /// still be returned before exceeding it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gap_limit_remaining: Option<u32>,
+
+    /// The payment URI for the address, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
 }
 
 // This one is simple enough to derive a serializer
@@ -726,6 +1000,10 @@ impl Settings {
 pub struct AccountSettings {
     pub name: String,
     pub hidden: bool,
+    /// The number of consecutive unused addresses after which the wallet stops looking for
+    /// transactions, the default is used if None
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gap_limit: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -733,6 +1011,8 @@ pub struct UpdateAccountOpt {
     pub subaccount: u32,
     pub name: Option<String>,
     pub hidden: Option<bool>,
+    #[serde(default)]
+    pub gap_limit: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -801,6 +1081,9 @@ pub struct CreateTxUtxo {
     pub txid: String,
     #[serde(rename = "pt_idx")]
     pub vout: u32,
+    /// The sequence of the input spending the utxo, for relative timelocks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u32>,
 }
 
 pub type CreateTxUtxos = HashMap<String, Vec<CreateTxUtxo>>;
@@ -901,8 +1184,23 @@ pub struct UnspentOutput {
     pub value_commitment: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub nonce_commitment: Option<String>,
+
+    /// The label of the address, empty if not set
+    #[serde(default)]
+    pub address_label: String,
+
+    /// The label of the output, empty if not set
+    #[serde(default)]
+    pub output_label: String,
+
+    /// `USER_STATUS_DEFAULT` or `USER_STATUS_FROZEN`
+    #[serde(default)]
+    pub user_status: u32,
 }
 
+pub const USER_STATUS_DEFAULT: u32 = 0;
+pub const USER_STATUS_FROZEN: u32 = 1;
+
 impl UnspentOutput {
     pub fn sighash(&self) -> Result<BESigHashType, Error> {
         let is_elements = self.asset_id.is_some();
@@ -951,6 +1249,9 @@ impl TryFrom<Txo> for UnspentOutput {
             asset_commitment,
             value_commitment,
             nonce_commitment,
+            address_label: String::new(),
+            output_label: String::new(),
+            user_status: USER_STATUS_DEFAULT,
         })
     }
 }
@@ -1050,6 +1351,10 @@ pub struct PreviousAddress {
     /// this address.
     pub tx_count: u32,
 
+    /// The label of the address, empty if not set.
+    #[serde(default)]
+    pub address_label: String,
+
     // Liquid fields, None if Bitcoin
     #[serde(skip_serializing_if = "Option::is_none")]
     pub is_blinded: Option<bool>,
@@ -1101,13 +1406,15 @@ impl PsbtGetDetailsResult {
 
 #[derive(Debug, Clone, Serialize)]
 pub struct PsbtGetDetailsOut {
-    pub asset_id: elements::AssetId,
+    /// Only for Liquid
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asset_id: Option<elements::AssetId>,
     pub satoshi: u64,
     pub subaccount: u32,
 }
 
 impl PsbtGetDetailsOut {
-    pub fn new(asset_id: elements::AssetId, satoshi: u64, subaccount: u32) -> Self {
+    pub fn new(asset_id: Option<elements::AssetId>, satoshi: u64, subaccount: u32) -> Self {
         Self {
             asset_id,
             satoshi,
@@ -1116,19 +1423,89 @@ impl PsbtGetDetailsOut {
     }
 }
 
+/// Object returned by [`ElectrumSession::create_psbt`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatePsbtResult {
+    /// The base64 encoded BIP174 PSBT
+    pub psbt: String,
+}
+
+/// Parameters passed to [`ElectrumSession::finalize_psbt`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FinalizePsbtOpt {
+    /// The base64 encoded BIP174 PSBT with the signatures of all its inputs
+    pub psbt: String,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct AddressDataRequest {
     pub address: String,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SetAddressLabelOpt {
+    pub address: String,
+    /// The empty string removes the label
+    pub label: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GetAddressLabelOpt {
+    pub address: String,
+}
+
+/// Wallet labels in the BIP329 JSON Lines format
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Bip329Labels {
+    pub labels: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ImportLabelsResult {
+    /// The number of records added to the wallet
+    pub imported: u32,
+    /// The number of records without a label, with a reference that is invalid or unknown to the
+    /// wallet, or of a type that the wallet does not support
+    pub ignored: u32,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UnspentOutputStatus {
+    pub txhash: String,
+    pub pt_idx: u32,
+    pub user_status: u32,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SetUnspentOutputsStatusOpt {
+    pub list: Vec<UnspentOutputStatus>,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct AddressDataResult {
     pub user_path: Vec<ChildNumber>,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SignMessageOpt {
+    /// A wallet address, whose key signs the message
+    pub address: String,
+    pub message: String,
+    /// For native segwit and taproot addresses, return the BIP322 full signature instead of the
+    /// simple one
+    #[serde(default)]
+    pub bip322_full: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SignMessageResult {
+    /// The signature in base64
+    pub signature: String,
+}
+
 #[cfg(test)]
 mod test {
-    use crate::model::{parse_path, CreateTxUtxos, GetUnspentOutputs};
+    use crate::model::{parse_path, CosignerXpub, CreateTxUtxos, GetUnspentOutputs};
     use bitcoin::util::bip32::DerivationPath;
 
     #[test]
@@ -1145,4 +1522,24 @@ mod test {
         let _json: GetUnspentOutputs = serde_json::from_str(json_str).unwrap();
         let _json: CreateTxUtxos = serde_json::from_str(json_str).unwrap();
     }
+
+    #[test]
+    fn test_cosigner_xpub() {
+        let xpub = "xpub6BosfCnifzxcFwrSzQiqu2DBVTshkCXacvNsWGYJVVhhawA7d4R5WSWGFNbi8Aw6ZRc1brxMyWMzG3DSSSSoekkudhUd9yLb6qx39T9nMdj";
+        let with_origin = format!("[73c5da0a/48'/0'/0'/2']{}", xpub);
+        let cosigner: CosignerXpub = serde_json::from_value(with_origin.clone().into()).unwrap();
+        assert_eq!(cosigner.origin.as_ref().unwrap().0.to_string(), "73c5da0a");
+        assert_eq!(cosigner.key_source().1.to_string(), "m/48'/0'/0'/2'");
+        assert_eq!(serde_json::to_value(&cosigner).unwrap(), with_origin);
+        assert_eq!(
+            format!("[73c5da0a/48h/0h/0h/2h]{}", xpub).parse::<CosignerXpub>().unwrap(),
+            cosigner
+        );
+
+        let cosigner: CosignerXpub = xpub.parse().unwrap();
+        assert!(cosigner.origin.is_none());
+        assert_eq!(cosigner.key_source(), (cosigner.xpub.fingerprint(), DerivationPath::master()));
+        assert_eq!(cosigner.to_string(), xpub);
+        assert!(format!("[73c5da0a/48'{}", xpub).parse::<CosignerXpub>().is_err());
+    }
 }
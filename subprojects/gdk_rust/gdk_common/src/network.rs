@@ -25,6 +25,24 @@ pub struct NetworkParameters {
     pub electrum_tls: Option<bool>,
     pub electrum_url: Option<String>,
     pub electrum_onion_url: Option<String>,
+
+    /// The kind of server the session syncs from, `electrum` if not specified.
+    pub server_type: Option<String>,
+    pub esplora_url: Option<String>,
+    pub rpc_url: Option<String>,
+    /// `user:password` credentials for `rpc_url`
+    pub rpc_auth: Option<String>,
+    pub rpc_wallet: Option<String>,
+    /// Unix time before which the wallet has no transactions, required with `rpc_url` to limit
+    /// the rescans of the node, 0 rescans from genesis
+    pub rpc_wallet_birthday: Option<u64>,
+    /// `host:port` of a P2P node serving BIP157 compact block filters
+    pub compact_filters_peer: Option<String>,
+    /// `host:port` of other P2P nodes the filter headers of `compact_filters_peer` are checked
+    /// against, since they are not committed in blocks
+    pub compact_filters_check_peers: Option<Vec<String>>,
+    pub compact_filters_start_height: Option<u32>,
+
     pub validate_domain: Option<bool>,
     pub policy_asset: Option<String>,
     pub sync_interval: Option<u32>,
@@ -102,6 +120,15 @@ impl ElementsNetwork {
 }
 
 impl NetworkParameters {
+    /// The scheme of the payment URIs
+    pub fn bip21_prefix(&self) -> &'static str {
+        if self.liquid {
+            "liquidnetwork"
+        } else {
+            "bitcoin"
+        }
+    }
+
     pub fn id(&self) -> NetworkId {
         match (self.liquid, self.mainnet, self.development) {
             (true, true, false) => NetworkId::Elements(ElementsNetwork::Liquid),
@@ -122,6 +149,12 @@ impl NetworkParameters {
         }
     }
 
+    /// Whether the session syncs from BIP157 compact block filters, which do not provide the
+    /// previous transactions of the incoming ones
+    pub fn uses_compact_filters(&self) -> bool {
+        self.server_type.as_deref() == Some("compact_filters")
+    }
+
     pub fn use_tor(&self) -> bool {
         self.use_tor.unwrap_or(false)
     }
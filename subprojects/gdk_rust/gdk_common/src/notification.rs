@@ -1,4 +1,5 @@
 use crate::be::BEBlockHeader;
+use crate::scripts::ScriptType;
 use crate::wally::make_str;
 use crate::{be::BEBlockHash, model::Settings, model::TransactionType, State};
 use log::{info, warn};
@@ -29,6 +30,9 @@ pub struct Notification {
     #[serde(skip_serializing_if = "Option::is_none")]
     block: Option<BlockNotification>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subaccount_recovery: Option<RecoveryNotification>,
+
     event: Kind,
 }
 
@@ -38,6 +42,7 @@ enum Kind {
     Network,
     Transaction,
     Block,
+    SubaccountRecovery,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -70,6 +75,20 @@ pub struct TransactionNotification {
     pub type_: Option<TransactionType>,
 }
 
+/// Progress of `recover_subaccounts`, sent for every subaccount checked.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RecoveryNotification {
+    /// The subaccount number.
+    pub subaccount: u32,
+
+    /// The script type of the subaccount.
+    #[serde(rename = "type")]
+    pub script_type: ScriptType,
+
+    /// Whether the subaccount has transactions, the last subaccount of each script type is unused.
+    pub used: bool,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct BlockNotification {
     /// The height of the block.
@@ -92,6 +111,7 @@ impl Notification {
             }),
             transaction: None,
             block: None,
+            subaccount_recovery: None,
             event: Kind::Network,
         }
     }
@@ -101,6 +121,7 @@ impl Notification {
             network: None,
             transaction: Some(ntf.clone()),
             block: None,
+            subaccount_recovery: None,
             event: Kind::Transaction,
         }
     }
@@ -114,10 +135,21 @@ impl Notification {
                 block_hash: hash.into_bitcoin(),
                 previous_hash: prev_hash.into_bitcoin(),
             }),
+            subaccount_recovery: None,
             event: Kind::Block,
         }
     }
 
+    pub fn new_subaccount_recovery(ntf: &RecoveryNotification) -> Self {
+        Notification {
+            network: None,
+            transaction: None,
+            block: None,
+            subaccount_recovery: Some(ntf.clone()),
+            event: Kind::SubaccountRecovery,
+        }
+    }
+
     pub fn new_block_from_header(height: u32, header: &BEBlockHeader) -> Self {
         Notification {
             network: None,
@@ -127,6 +159,7 @@ impl Notification {
                 block_hash: header.block_hash().into_bitcoin(),
                 previous_hash: header.prev_block_hash().into_bitcoin(),
             }),
+            subaccount_recovery: None,
             event: Kind::Block,
         }
     }
@@ -186,6 +219,10 @@ impl NativeNotif {
         self.notify(Notification::new_network(current, desired));
     }
 
+    pub fn subaccount_recovery(&self, ntf: &RecoveryNotification) {
+        self.notify(Notification::new_subaccount_recovery(ntf));
+    }
+
     #[cfg(not(feature = "testing"))]
     pub fn push(&self, _value: Value) {
         //does nothing in non testing mode
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use bitcoin::blockdata::script::Builder;
+use bitcoin::blockdata::script::{Builder, Instruction};
 use bitcoin::hash_types::PubkeyHash;
 use bitcoin::hashes::Hash;
 use bitcoin::{Address, Network, PublicKey, Script, Witness};
@@ -15,9 +15,18 @@ pub enum ScriptType {
     P2wpkh = 1,
     #[serde(rename = "p2pkh")]
     P2pkh = 2,
+    #[serde(rename = "p2tr")]
+    P2tr = 3,
+    /// k-of-n `wsh(sortedmulti(...))`
+    #[serde(rename = "p2wsh")]
+    P2wsh = 4,
+    /// k-of-n `sh(wsh(sortedmulti(...)))`
+    #[serde(rename = "p2sh-p2wsh")]
+    P2shP2wsh = 5,
 }
 
-const TYPES: [ScriptType; 3] = [ScriptType::P2shP2wpkh, ScriptType::P2wpkh, ScriptType::P2pkh];
+const TYPES: [ScriptType; 4] =
+    [ScriptType::P2shP2wpkh, ScriptType::P2wpkh, ScriptType::P2pkh, ScriptType::P2tr];
 
 impl fmt::Display for ScriptType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -25,6 +34,9 @@ impl fmt::Display for ScriptType {
             Self::P2shP2wpkh => write!(f, "p2sh-p2wpkh"),
             Self::P2wpkh => write!(f, "p2wpkh"),
             Self::P2pkh => write!(f, "p2pkh"),
+            Self::P2tr => write!(f, "p2tr"),
+            Self::P2wsh => write!(f, "p2wsh"),
+            Self::P2shP2wsh => write!(f, "p2sh-p2wsh"),
         }
     }
 }
@@ -48,7 +60,13 @@ pub fn p2shwpkh_script_sig(public_key: &PublicKey) -> Script {
     Builder::new().push_slice(internal.as_bytes()).into_script()
 }
 
+/// The script sig of a p2sh-p2wsh input, pushing the p2wsh redeem script
+pub fn p2shwsh_script_sig(witness_script: &Script) -> Script {
+    Builder::new().push_slice(witness_script.to_v0_p2wsh().as_bytes()).into_script()
+}
+
 impl ScriptType {
+    /// The single key script types, whose accounts can be discovered from the wallet keys alone
     pub fn types() -> &'static [ScriptType] {
         &TYPES
     }
@@ -57,41 +75,138 @@ impl ScriptType {
         self as u32
     }
 
+    /// Whether the script type is segwit v0, i.e. signed with the BIP143 sighash
     pub fn is_segwit(self) -> bool {
-        matches!(self, ScriptType::P2wpkh | ScriptType::P2shP2wpkh)
+        matches!(
+            self,
+            ScriptType::P2wpkh | ScriptType::P2shP2wpkh | ScriptType::P2wsh | ScriptType::P2shP2wsh
+        )
+    }
+
+    /// Whether the script type is segwit v1, i.e. key-path spent with a BIP341 schnorr signature
+    pub fn is_taproot(self) -> bool {
+        matches!(self, ScriptType::P2tr)
+    }
+
+    /// Whether the script type is a k-of-n `sortedmulti`, which needs the keys of the cosigners
+    pub fn is_multisig(self) -> bool {
+        matches!(self, ScriptType::P2wsh | ScriptType::P2shP2wsh)
+    }
+}
+
+/// The largest number of keys allowed in a `sortedmulti` script, as for standard P2SH multisig
+pub const MAX_MULTISIG_KEYS: u32 = 15;
+
+/// A script type with, for multisig, the number of signatures required and of keys.
+///
+/// It determines the size of the inputs spending an account outputs, so it is what fee
+/// estimation needs to know about the account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScriptTemplate {
+    pub script_type: ScriptType,
+    /// `(threshold, num_keys)`, only for multisig script types
+    pub multisig: Option<(u32, u32)>,
+}
+
+impl From<ScriptType> for ScriptTemplate {
+    fn from(script_type: ScriptType) -> Self {
+        ScriptTemplate {
+            script_type,
+            multisig: None,
+        }
+    }
+}
+
+impl ScriptTemplate {
+    /// The size of a multisig witness script: OP_k <n compressed keys> OP_n OP_CHECKMULTISIG
+    fn multisig_script_len(&self) -> usize {
+        let (_, num_keys) = self.multisig.expect("multisig script type");
+        3 + 34 * num_keys as usize
     }
 
     /// Returns a mock witness with the expected size
-    pub fn mock_witness(self) -> Witness {
-        Witness::from_vec(match self {
+    pub fn mock_witness(&self) -> Witness {
+        Witness::from_vec(match self.script_type {
             // signature (72) + compressed public key (33)
             ScriptType::P2wpkh | ScriptType::P2shP2wpkh => vec![vec![0u8; 72], vec![0u8; 33]],
+            // schnorr signature (64) with the default sighash, which is implicit
+            ScriptType::P2tr => vec![vec![0u8; 64]],
             // empty for non-witness inputs
             ScriptType::P2pkh => vec![],
+            // empty element for the CHECKMULTISIG bug + k signatures (72) + witness script
+            ScriptType::P2wsh | ScriptType::P2shP2wsh => {
+                let (threshold, _) = self.multisig.expect("multisig script type");
+                let mut witness = vec![vec![]];
+                witness.extend((0..threshold).map(|_| vec![0u8; 72]));
+                witness.push(vec![0u8; self.multisig_script_len()]);
+                witness
+            }
         })
     }
 
     /// Returns a mock script sig with the expected size
-    pub fn mock_script_sig(self) -> Vec<u8> {
-        match self {
+    pub fn mock_script_sig(&self) -> Vec<u8> {
+        match self.script_type {
             // empty for native segwit
-            ScriptType::P2wpkh => vec![],
+            ScriptType::P2wpkh | ScriptType::P2tr | ScriptType::P2wsh => vec![],
             // OP_PUSHBYTES <22 bytes>
             ScriptType::P2shP2wpkh => vec![0u8; 23],
+            // OP_PUSHBYTES <34 bytes>
+            ScriptType::P2shP2wsh => vec![0u8; 35],
             // OP_PUSHBYTES <72 bytes sig> OP_PUSHBYTES <33 bytes compressed key>
             ScriptType::P2pkh => vec![0u8; 107],
         }
     }
 
     /// Returns a mock scriptPubkey with the expected size
-    pub fn mock_script_pubkey(self) -> Vec<u8> {
-        match self {
+    pub fn mock_script_pubkey(&self) -> Vec<u8> {
+        match self.script_type {
             // OP_0 OP_PUSHBYTES <20 bytes hash>
             ScriptType::P2wpkh => vec![0u8; 22],
             // OP_HASH160 OP_PUSHBYTES <20 bytes hash> OP_EQUAL
-            ScriptType::P2shP2wpkh => vec![0u8; 23],
+            ScriptType::P2shP2wpkh | ScriptType::P2shP2wsh => vec![0u8; 23],
             // OP_DUP OP_HASH160 OP_PUSHBYTES <20 bytes hash> OP_EQUALVERIFY OP_CHECKSIG
             ScriptType::P2pkh => vec![0u8; 25],
+            // OP_1 OP_PUSHBYTES <32 bytes x-only key>
+            ScriptType::P2tr => vec![0u8; 34],
+            // OP_0 OP_PUSHBYTES <32 bytes hash>
+            ScriptType::P2wsh => vec![0u8; 34],
         }
     }
 }
+
+/// A `sortedmulti` script: `threshold` signatures out of the given keys, sorted lexicographically
+pub fn sortedmulti_script(threshold: u32, public_keys: &[PublicKey]) -> Script {
+    let mut keys: Vec<Vec<u8>> = public_keys.iter().map(|pk| pk.to_bytes()).collect();
+    keys.sort();
+    let mut builder = Builder::new().push_int(threshold as i64);
+    for key in keys.iter() {
+        builder = builder.push_slice(key);
+    }
+    builder
+        .push_int(keys.len() as i64)
+        .push_opcode(bitcoin::blockdata::opcodes::all::OP_CHECKMULTISIG)
+        .into_script()
+}
+
+/// The threshold and the public keys of a script built by [`sortedmulti_script`], `None` for
+/// any other script
+pub fn parse_sortedmulti_script(script: &Script) -> Option<(u32, Vec<PublicKey>)> {
+    let threshold = match script.as_bytes().first() {
+        Some(&op) if (0x51..=0x60).contains(&op) => op as u32 - 0x50,
+        _ => return None,
+    };
+    let public_keys = script
+        .instructions()
+        .filter_map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(bytes)) => Some(PublicKey::from_slice(bytes).ok()),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if threshold as usize > public_keys.len()
+        || sortedmulti_script(threshold, &public_keys) != *script
+    {
+        return None;
+    }
+    Some((threshold, public_keys))
+}
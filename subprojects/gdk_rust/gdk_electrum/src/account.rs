@@ -1,63 +1,83 @@
-use std::cmp::Ordering;
-use std::collections::{HashMap, HashSet};
+use std::cmp::{max, min, Ordering};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryInto;
 use std::str::FromStr;
+use std::sync::Arc;
 
-use gdk_common::bitcoin::util::sighash::SighashCache;
+use gdk_common::bitcoin::util::sighash::{Prevouts, SighashCache};
+use gdk_common::bitcoin::util::taproot::{TapLeafHash, TapTweakHash};
 use gdk_common::log::{info, warn};
 
 use gdk_common::bitcoin::blockdata::script;
 use gdk_common::bitcoin::hashes::hex::{FromHex, ToHex};
+use gdk_common::bitcoin::hashes::sha256;
 use gdk_common::bitcoin::hashes::Hash;
-use gdk_common::bitcoin::secp256k1::{self, Message};
+use gdk_common::bitcoin::secp256k1::{self, Message, XOnlyPublicKey};
 use gdk_common::bitcoin::util::address::Payload;
 use gdk_common::bitcoin::util::bip32::{
-    ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey,
+    ChildNumber, DerivationPath, ExtendedPubKey, Fingerprint, KeySource,
 };
-use gdk_common::bitcoin::{PublicKey, Witness};
-use gdk_common::elements::confidential::Value;
+use gdk_common::bitcoin::util::psbt::{PartiallySignedTransaction, PsbtSighashType};
+use gdk_common::bitcoin::{EcdsaSig, PublicKey, Witness};
+use gdk_common::elements::confidential::{AssetBlindingFactor, Value};
+use gdk_common::elements::issuance::{AssetId, ContractHash};
+use gdk_common::elements::secp256k1_zkp::ZERO_TWEAK;
+use gdk_common::rand::Rng;
 use gdk_common::{bitcoin, elements, rand};
 
 use gdk_common::be::{
-    BEAddress, BEOutPoint, BEScript, BEScriptConvert, BESigHashType, BETransaction, BETxid,
-    ScriptBatch, DUST_VALUE,
+    BEAddress, BEBlockHash, BEOutPoint, BEScript, BEScriptConvert, BESigHashType, BETransaction,
+    BETxid, ScriptBatch, DUST_VALUE, MAX_DATA_OUTPUT_SIZE,
 };
 use gdk_common::error::fn_err;
 use gdk_common::model::{
     parse_path, AccountInfo, AddressAmount, AddressDataResult, AddressPointer, CreateTransaction,
-    GetPreviousAddressesOpt, GetTransactionsOpt, GetTxInOut, PreviousAddress, PreviousAddresses,
-    SPVVerifyTxResult, TransactionMeta, TransactionOutput, TxListItem, Txo, UnspentOutput,
-    UpdateAccountOpt, UtxoStrategy,
+    CreateTxUtxo, GetPreviousAddressesOpt, GetTransactionsOpt, GetTxInOut, MultisigParams,
+    PreviousAddress, PreviousAddresses, ReissuanceOpt, SPVVerifyTxResult, TransactionMeta,
+    TransactionOutput, TxListItem, Txo, UnspentOutput, UpdateAccountOpt, UtxoStrategy,
+};
+use gdk_common::scripts::{
+    p2pkh_script, p2shwpkh_script_sig, sortedmulti_script, ScriptTemplate, ScriptType,
+    MAX_MULTISIG_KEYS,
 };
-use gdk_common::scripts::{p2pkh_script, p2shwpkh_script_sig, ScriptType};
 use gdk_common::util::{now, weight_to_vsize};
 use gdk_common::wally::{
     asset_blinding_key_to_ec_private_key, ec_public_key_from_private_key, MasterBlindingKey,
 };
 use gdk_common::{ElementsNetwork, NetworkId, NetworkParameters};
 
+use crate::client::BlockchainClient;
+use crate::coin_selection::{select_coins, Candidate, SelectionTarget};
+use crate::descriptor::{AccountDescriptor, DescriptorKey};
 use crate::error::Error;
-use crate::interface::ElectrumUrl;
-use crate::store::{RawAccountCache, Store, BATCH_SIZE};
+use crate::interface::ServerUrl;
+use crate::payjoin;
+use crate::psbt::{clear_finalized_input, finalize_multisig_input, input_prevout};
+use crate::signer::Signer;
+use crate::store::{RawAccountCache, Store, StoreMeta, BATCH_SIZE, MAX_GAP_LIMIT};
 
 // The number of account types, including these reserved for future use.
-// Currently only 3 are used: P2SH-P2WPKH, P2WPKH and P2PKH
-const NUM_RESERVED_ACCOUNT_TYPES: u32 = 16;
+// Currently only 6 are used: P2SH-P2WPKH, P2WPKH, P2PKH, P2TR, P2WSH and P2SH-P2WSH
+pub(crate) const NUM_RESERVED_ACCOUNT_TYPES: u32 = 16;
 
 #[derive(Clone)]
 pub struct Account {
     account_num: u32,
     script_type: ScriptType,
 
-    /// The account extended private key
+    /// The signer of the wallet, whose keys are derived from the master key
     ///
-    /// This fields will be removed once we have full support for external signers.
-    /// For the time being, if it is None, the xpub cannot be verified and
-    /// `Account::sign` will always fail.
-    xprv: Option<ExtendedPrivKey>,
+    /// If it is None, as in watch-only sessions, the xpub cannot be verified and `Account::sign`
+    /// will always fail.
+    signer: Option<Arc<dyn Signer>>,
     xpub: ExtendedPubKey,
     chains: [ExtendedPubKey; 2],
     network: NetworkParameters,
+
+    /// For multisig accounts, the threshold and the cosigner xpubs
+    multisig: Option<MultisigParams>,
+    /// The cached internal/external chains of the cosigners, in the same order of their xpubs
+    cosigner_chains: Vec<[ExtendedPubKey; 2]>,
     store: Store,
     // elements only
     master_blinding: Option<MasterBlindingKey>,
@@ -78,38 +98,72 @@ pub fn xpubs_equivalent(xpub1: &ExtendedPubKey, xpub2: &ExtendedPubKey) -> Resul
 }
 
 impl Account {
+    /// Multisig accounts need `multisig` when created, afterwards it is persisted in the store.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         network: NetworkParameters,
-        master_xprv: &Option<ExtendedPrivKey>,
+        signer: &Option<Arc<dyn Signer>>,
         account_xpub: &Option<ExtendedPubKey>,
         master_blinding: Option<MasterBlindingKey>,
         store: Store,
         account_num: u32,
         discovered: bool,
+        multisig: Option<MultisigParams>,
     ) -> Result<Self, Error> {
         let (script_type, path) = get_account_derivation(account_num, network.id())?;
 
-        let (xprv, xpub) = if let Some(master_xprv) = master_xprv {
-            let xprv = master_xprv.derive_priv(&crate::EC, &path)?;
-            let xpub = ExtendedPubKey::from_priv(&crate::EC, &xprv);
+        let xpub = if let Some(signer) = signer {
+            let xpub = signer.xpub(&path)?;
             if let Some(account_xpub) = account_xpub {
                 xpubs_equivalent(&xpub, account_xpub)?;
             };
-            (Some(xprv), xpub)
+            xpub
         } else {
             if let Some(xpub) = account_xpub {
-                (None, xpub.clone())
+                xpub.clone()
             } else {
                 return Err(Error::Generic(
-                    "Account::new: either master_xprv or account_xpub must be Some".to_string(),
+                    "Account::new: either signer or account_xpub must be Some".to_string(),
                 ));
             }
         };
 
+        let multisig = if script_type.is_multisig() {
+            let multisig = match multisig {
+                Some(multisig) => multisig,
+                None => store
+                    .read()?
+                    .get_account_multisig(account_num)
+                    .cloned()
+                    .ok_or_else(|| Error::InvalidMultisig("missing cosigners".into()))?,
+            };
+            check_multisig(&multisig, &xpub, &network)?;
+            Some(multisig)
+        } else if multisig.is_some() {
+            return Err(Error::InvalidMultisig(format!("{} is not a multisig type", script_type)));
+        } else {
+            None
+        };
+
         // cache internal/external chains
         let chains = [xpub.ckd_pub(&crate::EC, 0.into())?, xpub.ckd_pub(&crate::EC, 1.into())?];
+        let cosigner_chains = multisig
+            .iter()
+            .flat_map(|m| m.cosigner_xpubs.iter())
+            .map(|cosigner| {
+                let xpub = &cosigner.xpub;
+                Ok([xpub.ckd_pub(&crate::EC, 0.into())?, xpub.ckd_pub(&crate::EC, 1.into())?])
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
 
-        store.write().unwrap().make_account(account_num, xpub.clone(), discovered)?;
+        let mut store_write = store.write()?;
+        store_write.make_account(account_num, xpub.clone(), discovered)?;
+        if let Some(multisig) = &multisig {
+            if store_write.get_account_multisig(account_num) != Some(multisig) {
+                store_write.set_account_multisig(account_num, multisig.clone())?;
+            }
+        }
+        drop(store_write);
 
         info!("initialized account #{} path={} type={:?}", account_num, path, script_type);
 
@@ -117,12 +171,14 @@ impl Account {
             network,
             account_num,
             script_type,
-            xprv,
+            signer: signer.clone(),
             xpub,
             chains,
             store,
             master_blinding,
             path,
+            multisig,
+            cosigner_chains,
         })
     }
 
@@ -134,20 +190,37 @@ impl Account {
         self.script_type
     }
 
-    fn descriptor(&self, is_internal: bool) -> String {
-        let internal_idx = if is_internal {
-            1
-        } else {
-            0
-        };
-        let (prefix, suffix) = match self.script_type {
-            ScriptType::P2shP2wpkh => ("sh(wpkh", ")"),
-            ScriptType::P2wpkh => ("wpkh", ""),
-            ScriptType::P2pkh => ("pkh", ""),
+    pub fn xpub(&self) -> &ExtendedPubKey {
+        &self.xpub
+    }
+
+    /// The output descriptor of the external or internal chain.
+    ///
+    /// Our key includes its origin if the master fingerprint is given, the keys of the cosigners
+    /// if it was given with their xpubs.
+    pub fn descriptor(
+        &self,
+        is_internal: bool,
+        master_fingerprint: Option<Fingerprint>,
+    ) -> AccountDescriptor {
+        let ours = DescriptorKey {
+            origin: master_fingerprint.map(|fingerprint| (fingerprint, self.path.clone())),
+            xpub: self.xpub,
+            is_internal,
         };
-        // TODO: add key origin identification
-        // TODO: add checksum
-        format!("{}({}/{}/*){}", prefix, self.xpub, internal_idx, suffix)
+        let cosigners =
+            self.multisig.iter().flat_map(|m| m.cosigner_xpubs.iter()).map(|cosigner| {
+                DescriptorKey {
+                    origin: cosigner.origin.clone(),
+                    xpub: cosigner.xpub,
+                    is_internal,
+                }
+            });
+        AccountDescriptor {
+            script_type: self.script_type,
+            threshold: self.multisig.as_ref().map(|m| m.threshold),
+            keys: std::iter::once(ours).chain(cosigners).collect(),
+        }
     }
 
     fn slip132_extended_pubkey(&self) -> Option<String> {
@@ -162,6 +235,10 @@ impl Account {
                 (false, ScriptType::P2pkh) => [0x04, 0x35, 0x87, 0xcf], // tpub
                 (false, ScriptType::P2shP2wpkh) => [0x04, 0x4a, 0x52, 0x62], // upub
                 (false, ScriptType::P2wpkh) => [0x04, 0x5f, 0x1c, 0xf6], // vpub
+                // SLIP-132 defines no version for taproot, descriptors should be used instead
+                (_, ScriptType::P2tr) => return None,
+                // Multisig versions would not describe the cosigners, descriptors do
+                (_, ScriptType::P2wsh) | (_, ScriptType::P2shP2wsh) => return None,
             };
             xpub_bytes[0..4].copy_from_slice(&slip132_version[0..4]);
             Some(bitcoin::util::base58::check_encode_slice(&xpub_bytes))
@@ -189,8 +266,12 @@ impl Account {
             receiving_id: "".to_string(),
             bip44_discovered: self.has_transactions()?,
             user_path: self.path.clone().into(),
-            core_descriptors: vec![self.descriptor(false), self.descriptor(true)],
+            core_descriptors: vec![
+                self.descriptor(false, None).to_string(),
+                self.descriptor(true, None).to_string(),
+            ],
             slip132_extended_pubkey: self.slip132_extended_pubkey(),
+            multisig: self.multisig.clone(),
         })
     }
 
@@ -204,6 +285,10 @@ impl Account {
         if let Some(hidden) = opt.hidden {
             settings.hidden = hidden;
         }
+        if let Some(gap_limit) = opt.gap_limit {
+            check_gap_limit(gap_limit)?;
+            settings.gap_limit = Some(gap_limit);
+        }
         store_write.set_account_settings(self.account_num, settings)?;
         Ok(true)
     }
@@ -215,7 +300,51 @@ impl Account {
         })
     }
 
+    /// Whether transactions of the account can be replaced with a PayJoin, which needs the inputs
+    /// added by the receiver to be signed for the same kind of script
+    pub fn supports_payjoin(&self) -> bool {
+        !self.network.liquid && self.multisig.is_none() && !self.script_type.is_taproot()
+    }
+
+    /// The script type along with the multisig parameters, what fee estimation needs
+    pub fn script_template(&self) -> ScriptTemplate {
+        ScriptTemplate {
+            script_type: self.script_type,
+            multisig: self
+                .multisig
+                .as_ref()
+                .map(|m| (m.threshold, m.cosigner_xpubs.len() as u32 + 1)),
+        }
+    }
+
+    /// The public keys of a multisig account at the given chain and index, ours first
+    fn multisig_keys(&self, is_internal: bool, index: u32) -> Result<Vec<PublicKey>, Error> {
+        std::iter::once(&self.chains[is_internal as usize])
+            .chain(self.cosigner_chains.iter().map(|chains| &chains[is_internal as usize]))
+            .map(|chain| Ok(chain.ckd_pub(&crate::EC, index.into())?.to_pub()))
+            .collect()
+    }
+
+    /// The `sortedmulti` witness script of a multisig account at the given chain and index
+    fn multisig_script(&self, is_internal: bool, index: u32) -> Result<bitcoin::Script, Error> {
+        let multisig = self.multisig.as_ref().expect("multisig account");
+        Ok(sortedmulti_script(multisig.threshold, &self.multisig_keys(is_internal, index)?))
+    }
+
+    fn multisig_script_at(&self, account_path: &DerivationPath) -> Result<bitcoin::Script, Error> {
+        let (is_internal, index) = parse_path(account_path)?;
+        self.multisig_script(is_internal, index)
+    }
+
     pub fn derive_address(&self, is_internal: bool, index: u32) -> Result<BEAddress, Error> {
+        if self.multisig.is_some() {
+            let witness_script = self.multisig_script(is_internal, index)?;
+            let network = self.network.id().get_bitcoin_network().expect("bitcoin only");
+            return Ok(BEAddress::Bitcoin(match self.script_type {
+                ScriptType::P2shP2wsh => bitcoin::Address::p2shwsh(&witness_script, network),
+                _ => bitcoin::Address::p2wsh(&witness_script, network),
+            }));
+        }
         derive_address(
             &self.chains[is_internal as usize],
             index,
@@ -225,17 +354,42 @@ impl Account {
         )
     }
 
+    /// The number of consecutive unused addresses after which sync and discovery stop looking
+    /// for transactions
+    pub fn gap_limit(&self) -> Result<u32, Error> {
+        let store = self.store.read()?;
+        let settings = store.get_account_settings(self.account_num);
+        Ok(settings.and_then(|s| s.gap_limit).unwrap_or(BATCH_SIZE))
+    }
+
     pub fn get_next_address(&self, is_internal: bool) -> Result<AddressPointer, Error> {
+        let gap_limit = self.gap_limit()?;
         let store = &mut self.store.write()?;
         let acc_store = store.account_cache_mut(self.account_num)?;
-        let pointer = {
-            if is_internal {
-                acc_store.indexes.internal += 1;
-                acc_store.indexes.internal
-            } else {
-                acc_store.indexes.external += 1;
-                acc_store.indexes.external
-            }
+        let (index, last_used) = if is_internal {
+            (&mut acc_store.indexes.internal, acc_store.last_used.internal)
+        } else {
+            (&mut acc_store.indexes.external, acc_store.last_used.external)
+        };
+        *index += 1;
+        let pointer = *index;
+        // Addresses beyond the gap limit might not be found by other wallets restoring from the
+        // same keys, but returning already given out addresses again would cause address reuse
+        if pointer > last_used.saturating_add(gap_limit) {
+            warn!(
+                "account {} exceeded the gap limit {}, address {} is beyond it",
+                self.account_num, gap_limit, pointer
+            );
+        }
+        let remaining = last_used.saturating_add(gap_limit).saturating_sub(pointer);
+        let gap_limit_remaining = if remaining <= max(gap_limit / 10, 1) {
+            warn!(
+                "account {} is about to exceed the gap limit {}, {} addresses left",
+                self.account_num, gap_limit, remaining
+            );
+            Some(remaining)
+        } else {
+            None
         };
         let account_path = DerivationPath::from(&[(is_internal as u32).into(), pointer.into()][..]);
         let user_path = self.get_full_path(&account_path);
@@ -260,6 +414,8 @@ impl Account {
             pointer: pointer,
             user_path: user_path.into(),
             is_internal: is_internal,
+            gap_limit_remaining,
+            uri: None,
         })
     }
 
@@ -299,6 +455,8 @@ impl Account {
                 Some(_pubkey) => Some(script_pubkey.to_hex()),
             };
             let tx_count = acc_store.all_txs.tx_count(&script_pubkey);
+            let address_label =
+                store.get_address_label(&script_pubkey).cloned().unwrap_or_default();
             previous_addresses.push(PreviousAddress {
                 address: address.to_string(),
                 address_type: self.script_type.to_string(),
@@ -308,6 +466,7 @@ impl Account {
                 script_pubkey: script_pubkey.to_hex(),
                 user_path: self.get_full_path(&account_path).into(),
                 tx_count,
+                address_label,
                 is_blinded,
                 unblinded_address,
                 blinding_script: blinding_script_hex,
@@ -362,11 +521,21 @@ impl Account {
 
             let memo = store.get_memo(tx_id).cloned().unwrap_or("".to_string());
 
-            let fee = tx.fee(
-                &acc_store.all_txs,
-                &acc_store.unblinded,
-                &self.network.policy_asset_id().ok(),
-            )?;
+            // with compact filters the previous txs of incoming txs may not be available, leaving
+            // their fee unknown
+            let fee_unknown = self.network.uses_compact_filters()
+                && tx.previous_outputs().iter().any(|o| {
+                    acc_store.all_txs.get_previous_output_value(o, &acc_store.unblinded).is_none()
+                });
+            let fee = if fee_unknown {
+                0
+            } else {
+                tx.fee(
+                    &acc_store.all_txs,
+                    &acc_store.unblinded,
+                    &self.network.policy_asset_id().ok(),
+                )?
+            };
 
             let fee_rate = txe.fee_rate(fee);
 
@@ -438,6 +607,12 @@ impl Account {
                             (address, script_pubkey, None, None, None)
                         };
 
+                    let address_label = acc_store
+                        .all_txs
+                        .get_previous_output_script_pubkey(beoutpoint)
+                        .and_then(|s| store.get_address_label(&s).cloned())
+                        .unwrap_or_default();
+
                     let satoshi = acc_store
                         .all_txs
                         .get_previous_output_value(beoutpoint, &acc_store.unblinded)
@@ -484,6 +659,7 @@ impl Account {
                         unblinded_address,
                         blinding_key,
                         script_pubkey,
+                        address_label,
                     })
                 })
                 .collect::<Result<Vec<GetTxInOut>, Error>>()?;
@@ -526,6 +702,11 @@ impl Account {
                             (address, script_pubkey, None, None, None)
                         };
 
+                    let address_label = store
+                        .get_address_label(&tx.output_script(vout))
+                        .cloned()
+                        .unwrap_or_default();
+
                     let satoshi = tx.output_value(vout, &acc_store.unblinded).unwrap_or(0);
                     let asset_id = tx.output_asset(vout, &acc_store.unblinded).map(|a| a.to_hex());
                     let asset_blinder = tx.output_assetblinder_hex(vout, &acc_store.unblinded);
@@ -553,10 +734,15 @@ impl Account {
                         unblinded_address,
                         blinding_key,
                         script_pubkey,
+                        address_label,
                     })
                 })
                 .collect::<Result<Vec<GetTxInOut>, Error>>()?;
 
+            // the outputs of unconfirmed txs can be spent to bump their fee
+            let can_cpfp =
+                height.is_none() && !self.network.liquid && outputs.iter().any(|o| o.is_relevant);
+
             txs.push(TxListItem {
                 block_height: height.unwrap_or(0),
                 created_at_ts: timestamp,
@@ -565,11 +751,12 @@ impl Account {
                 txhash: tx_id.to_string(),
                 satoshi,
                 rbf_optin,
-                can_cpfp: false,
+                can_cpfp,
                 can_rbf,
                 spv_verified: spv_verified.to_string(),
                 fee,
                 fee_rate,
+                fee_unknown,
                 inputs,
                 outputs,
                 transaction_size: txe.size,
@@ -588,8 +775,12 @@ impl Account {
     }
 
     pub fn script_code(&self, path: &DerivationPath) -> BEScript {
+        if self.multisig.is_some() {
+            // the witness script, the derivation path is checked when the script is indexed
+            return self.multisig_script_at(path).expect("path of an indexed script").into();
+        }
         let public_key = self.public_key(path);
-        // script code is the same for the currently supported script type
+        // script code is the same for the currently supported single key script types
         p2pkh_script(&public_key).into()
     }
 
@@ -603,6 +794,7 @@ impl Account {
             let address = tx.output_address(vout, self.network.id()).unwrap_or_default();
             let satoshi = tx.output_value(vout, &acc_store.unblinded).unwrap_or_default();
             let script_pubkey = tx.output_script(vout);
+            let data = tx.output_data(vout).map(|d| d.to_hex());
             tx_outputs.push(match acc_store.paths.get(&script_pubkey) {
                 None => TransactionOutput {
                     address,
@@ -616,6 +808,7 @@ impl Account {
                     pt_idx: vout,
                     script_pubkey: script_pubkey.to_hex(),
                     user_path: vec![],
+                    data,
                 },
                 Some(account_path) => {
                     let (is_internal, pointer) = parse_path(&account_path)?;
@@ -631,6 +824,7 @@ impl Account {
                         pt_idx: vout,
                         script_pubkey: script_pubkey.to_hex(),
                         user_path: self.get_full_path(&account_path).into(),
+                        data,
                     }
                 }
             });
@@ -737,16 +931,31 @@ impl Account {
         if request.subaccount != self.account_num {
             return Err(Error::InvalidSubaccount(request.subaccount));
         }
-        create_tx(self, request)
+        let mut created_tx = create_tx(self, request)?;
+        // watch-only sessions cannot sign, the transaction is exported to be signed elsewhere
+        if self.signer.is_none() {
+            created_tx.psbt = Some(self.signing_request(&created_tx)?);
+        }
+        Ok(created_tx)
     }
 
     // TODO when we can serialize psbt
     //pub fn sign(&self, psbt: PartiallySignedTransaction) -> Result<PartiallySignedTransaction, Error> { Err(Error::Generic("NotImplemented".to_string())) }
     pub fn sign(&self, request: &TransactionMeta) -> Result<TransactionMeta, Error> {
         info!("sign");
-        let xprv = self
-            .xprv
-            .ok_or_else(|| Error::Generic("Internal software signing is not supported".into()))?;
+        let signer = match (self.signer.as_deref(), &request.psbt) {
+            (Some(signer), Some(signing_request)) if self.multisig.is_none() => {
+                return self.sign_signing_request(signer, request, signing_request);
+            }
+            (None, Some(signed)) => {
+                let tx = self.finalize_signing_request(request, signed)?;
+                return self.complete_signing(request, tx.into());
+            }
+            (Some(signer), _) => signer,
+            (None, None) => {
+                return Err(Error::Generic("Signing requires the wallet signer".into()));
+            }
+        };
 
         let be_tx =
             BETransaction::deserialize(&Vec::<u8>::from_hex(&request.hex)?, self.network.id())?;
@@ -763,9 +972,25 @@ impl Account {
             return Err(Error::Generic("Mismatching used_utxos and transaction".into()));
         }
 
-        let mut betx: TransactionMeta = match be_tx {
+        let betx: TransactionMeta = match be_tx {
+            BETransaction::Bitcoin(tx) if self.multisig.is_some() => {
+                self.sign_multisig(tx, request, acc_store, signer, &sighashes)?
+            }
             BETransaction::Bitcoin(tx) => {
                 let mut out_tx = tx.clone();
+                // taproot signatures commit to all the outputs spent by the transaction
+                let prevouts = if self.script_type.is_taproot() {
+                    tx.input
+                        .iter()
+                        .map(|input| {
+                            let prev_output = input.previous_output;
+                            let prev_tx = acc_store.get_bitcoin_tx(&prev_output.txid)?;
+                            Ok(prev_tx.output[prev_output.vout as usize].clone())
+                        })
+                        .collect::<Result<Vec<_>, Error>>()?
+                } else {
+                    vec![]
+                };
 
                 for i in 0..tx.input.len() {
                     if request.used_utxos[i].skip_signing {
@@ -784,9 +1009,11 @@ impl Account {
                     let (script_sig, witness) = internal_sign_bitcoin(
                         &tx,
                         i,
-                        &xprv,
-                        &derivation_path,
+                        signer,
+                        &self.path.extend(derivation_path),
+                        &self.xpub.derive_pub(&crate::EC, derivation_path)?.to_pub(),
                         out.value,
+                        &prevouts,
                         self.script_type,
                         &sighashes[i],
                     )?;
@@ -805,6 +1032,23 @@ impl Account {
             }
             BETransaction::Elements(tx) => {
                 let mut tx = blind_tx(self, &tx)?;
+                let prevouts = if self.script_type.is_taproot() {
+                    tx.input
+                        .iter()
+                        .map(|input| {
+                            let prev_output = input.previous_output;
+                            let prev_tx = acc_store.get_liquid_tx(&prev_output.txid)?;
+                            Ok(prev_tx.output[prev_output.vout as usize].clone())
+                        })
+                        .collect::<Result<Vec<_>, Error>>()?
+                } else {
+                    vec![]
+                };
+                // elements taproot signatures also commit to the genesis block of the chain
+                let genesis_hash = match store_read.cache.genesis_hash {
+                    Some(BEBlockHash::Elements(genesis_hash)) => Some(genesis_hash),
+                    _ => None,
+                };
 
                 for i in 0..tx.input.len() {
                     if request.used_utxos[i].skip_signing {
@@ -819,9 +1063,12 @@ impl Account {
                     let (script_sig, witness) = internal_sign_elements(
                         &tx,
                         i,
-                        &xprv,
-                        &derivation_path,
+                        signer,
+                        &self.path.extend(derivation_path),
+                        &self.xpub.derive_pub(&crate::EC, derivation_path)?.to_pub(),
                         out.value,
+                        &prevouts,
+                        genesis_hash,
                         self.script_type,
                         &sighashes[i],
                     )?;
@@ -844,12 +1091,24 @@ impl Account {
             }
         };
 
+        drop(acc_store);
+        drop(store_read);
+        self.complete_signing(request, betx)
+    }
+
+    /// Add the data of the request to its signed transaction and update the store as the
+    /// transaction is going to be broadcast
+    fn complete_signing(
+        &self,
+        request: &TransactionMeta,
+        mut betx: TransactionMeta,
+    ) -> Result<TransactionMeta, Error> {
         betx.fee = request.fee;
         betx.create_transaction = request.create_transaction.clone();
         betx.used_utxos = request.used_utxos.clone();
+        betx.issued_asset_id = request.issued_asset_id.clone();
+        betx.issued_token_id = request.issued_token_id.clone();
 
-        drop(acc_store);
-        drop(store_read);
         let mut store_write = self.store.write()?;
         let mut acc_store = store_write.account_cache_mut(self.account_num)?;
 
@@ -870,15 +1129,537 @@ impl Account {
         Ok(betx)
     }
 
-    pub fn get_script_batch(&self, is_internal: bool, batch: u32) -> Result<ScriptBatch, Error> {
+    /// Sign the signing request of a watch-only session, returning it with our inputs finalized.
+    ///
+    /// Only the data in the request is used, so that the session signing it does not need to be
+    /// synced or even connected. Liquid requests are blinded first, with the secrets they carry.
+    fn sign_signing_request(
+        &self,
+        signer: &dyn Signer,
+        request: &TransactionMeta,
+        signing_request: &str,
+    ) -> Result<TransactionMeta, Error> {
+        let master_fingerprint = signer.xpub(&DerivationPath::master())?.fingerprint();
+        let signed = match BETransaction::from_hex(&request.hex, self.network.id())? {
+            BETransaction::Bitcoin(tx) => {
+                let mut psbt: PartiallySignedTransaction =
+                    bitcoin::consensus::deserialize(&base64::decode(signing_request)?)?;
+                crate::psbt::compare_except_script_sig_sequence(&psbt.unsigned_tx, &tx)?;
+                self.sign_psbt(&mut psbt, signer, master_fingerprint)?;
+                base64::encode(bitcoin::consensus::serialize(&psbt))
+            }
+            BETransaction::Elements(tx) => {
+                let mut pset: elements::pset::PartiallySignedTransaction =
+                    elements::encode::deserialize(&base64::decode(signing_request)?)?;
+                crate::pset::compare_except_script_sig_sequence(
+                    &crate::pset::unblinded_tx(&pset)?,
+                    &tx,
+                )?;
+                self.sign_pset(&mut pset, signer, master_fingerprint)?;
+                base64::encode(elements::encode::serialize(&pset))
+            }
+        };
+        let mut betx = request.clone();
+        betx.psbt = Some(signed);
+        Ok(betx)
+    }
+
+    fn sign_psbt(
+        &self,
+        psbt: &mut PartiallySignedTransaction,
+        signer: &dyn Signer,
+        master_fingerprint: Fingerprint,
+    ) -> Result<(), Error> {
+        let tx = psbt.unsigned_tx.clone();
+        let prevouts = psbt
+            .inputs
+            .iter()
+            .zip(tx.input.iter())
+            .map(|(input, txin)| input_prevout(input, &txin.previous_output))
+            .collect::<Result<Option<Vec<_>>, _>>()?;
+        for (i, input) in psbt.inputs.iter_mut().enumerate() {
+            let prevout = match input_prevout(input, &tx.input[i].previous_output)? {
+                Some(prevout) => prevout,
+                None => continue,
+            };
+            let origins = input
+                .bip32_derivation
+                .values()
+                .chain(input.tap_key_origins.values().map(|(_, origin)| origin));
+            let script_pubkey = prevout.script_pubkey.clone().into();
+            let path = match self.signing_path(origins, &script_pubkey, master_fingerprint)? {
+                Some(path) => path,
+                None => continue,
+            };
+            info!("input#{} signing request path:{}", i, path);
+            // taproot signatures commit to all the outputs spent by the transaction
+            let prevouts = match (&prevouts, self.script_type.is_taproot()) {
+                (Some(prevouts), _) => &prevouts[..],
+                (None, false) => &[],
+                (None, true) => return Err(Error::Generic("missing spent outputs".into())),
+            };
+            let sighash = input.sighash_type.map_or(0x01, |s| s.to_u32());
+            let (script_sig, witness) = internal_sign_bitcoin(
+                &tx,
+                i,
+                signer,
+                &self.path.extend(&path),
+                &self.public_key(&path),
+                prevout.value,
+                prevouts,
+                self.script_type,
+                &BESigHashType::from_u32(sighash, false)?,
+            )?;
+            input.final_script_sig = Some(script_sig).filter(|s| !s.is_empty());
+            input.final_script_witness = Some(Witness::from_vec(witness)).filter(|w| !w.is_empty());
+            clear_finalized_input(input);
+        }
+        Ok(())
+    }
+
+    fn sign_pset(
+        &self,
+        pset: &mut elements::pset::PartiallySignedTransaction,
+        signer: &dyn Signer,
+        master_fingerprint: Fingerprint,
+    ) -> Result<(), Error> {
+        let is_blinded = pset
+            .outputs()
+            .iter()
+            .all(|output| !output.is_marked_for_blinding() || output.is_partially_blinded());
+        if !is_blinded {
+            let mut inp_txout_sec = HashMap::new();
+            for i in 0..pset.inputs().len() {
+                if let Some(secrets) = crate::pset::input_secrets(pset, i)? {
+                    inp_txout_sec.insert(i, secrets);
+                }
+            }
+            pset.blind_last(&mut rand::thread_rng(), &crate::EC, &inp_txout_sec)?;
+        }
+
+        let tx = pset.extract_tx()?;
+        let prevouts = pset
+            .inputs()
+            .iter()
+            .map(|input| input.witness_utxo.clone())
+            .collect::<Option<Vec<_>>>();
+        let genesis_hash = crate::pset::genesis_hash(pset)?;
+        for (i, input) in pset.inputs_mut().iter_mut().enumerate() {
+            let prevout = match input.witness_utxo.clone() {
+                Some(prevout) => prevout,
+                None => continue,
+            };
+            let origins = input
+                .bip32_derivation
+                .values()
+                .chain(input.tap_key_origins.values().map(|(_, origin)| origin));
+            let script_pubkey = prevout.script_pubkey.clone().into();
+            let path = match self.signing_path(origins, &script_pubkey, master_fingerprint)? {
+                Some(path) => path,
+                None => continue,
+            };
+            info!("input#{} signing request path:{}", i, path);
+            let prevouts = match (&prevouts, self.script_type.is_taproot()) {
+                (Some(prevouts), _) => &prevouts[..],
+                (None, false) => &[],
+                (None, true) => return Err(Error::Generic("missing spent outputs".into())),
+            };
+            let sighash = input.sighash_type.map_or(0x01, |s| s.to_u32());
+            let (script_sig, witness) = internal_sign_elements(
+                &tx,
+                i,
+                signer,
+                &self.path.extend(&path),
+                &self.public_key(&path),
+                prevout.value,
+                prevouts,
+                genesis_hash,
+                self.script_type,
+                &BESigHashType::from_u32(sighash, true)?,
+            )?;
+            input.final_script_sig = Some(script_sig).filter(|s| !s.is_empty());
+            input.final_script_witness = Some(witness).filter(|w| !w.is_empty());
+        }
+        Ok(())
+    }
+
+    /// The path of our key among the key origins of a signing request input, if it is the key of
+    /// the spent script. Origins start either from the account key or from the master key.
+    fn signing_path<'a>(
+        &self,
+        origins: impl Iterator<Item = &'a KeySource>,
+        script_pubkey: &BEScript,
+        master_fingerprint: Fingerprint,
+    ) -> Result<Option<DerivationPath>, Error> {
+        let account_path: &[ChildNumber] = self.path.as_ref();
+        for (fingerprint, path) in origins {
+            let path: &[ChildNumber] = path.as_ref();
+            let path = if *fingerprint == self.xpub.fingerprint() {
+                path
+            } else if *fingerprint == master_fingerprint && path.starts_with(account_path) {
+                &path[account_path.len()..]
+            } else {
+                continue;
+            };
+            let path = DerivationPath::from(path);
+            let (is_internal, index) = match parse_path(&path) {
+                Ok(child) => child,
+                Err(_) => continue,
+            };
+            if &self.derive_address(is_internal, index)?.script_pubkey() == script_pubkey {
+                return Ok(Some(path));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The transaction of a signing request signed elsewhere, which must be the one we created
+    /// and have all its inputs signed
+    fn finalize_signing_request(
+        &self,
+        request: &TransactionMeta,
+        signed: &str,
+    ) -> Result<BETransaction, Error> {
+        match BETransaction::from_hex(&request.hex, self.network.id())? {
+            BETransaction::Bitcoin(tx) => {
+                let mut psbt: PartiallySignedTransaction =
+                    bitcoin::consensus::deserialize(&base64::decode(signed)?)?;
+                crate::psbt::compare_except_script_sig_sequence(&psbt.unsigned_tx, &tx)?;
+                Ok(BETransaction::Bitcoin(crate::psbt::finalize(&mut psbt)?))
+            }
+            BETransaction::Elements(tx) => {
+                let pset: elements::pset::PartiallySignedTransaction =
+                    elements::encode::deserialize(&base64::decode(signed)?)?;
+                crate::pset::compare_except_script_sig_sequence(
+                    &crate::pset::unblinded_tx(&pset)?,
+                    &tx,
+                )?;
+                let is_finalized = pset.inputs().iter().all(|input| {
+                    input.final_script_sig.is_some() || input.final_script_witness.is_some()
+                });
+                if !is_finalized {
+                    return Err(Error::IncompletePsbt);
+                }
+                Ok(BETransaction::Elements(pset.extract_tx()?))
+            }
+        }
+    }
+
+    /// Sign the inputs of a multisig account, returning the signatures in a PSBT.
+    ///
+    /// The PSBT given in the request, if any, is combined first so that the signatures of the
+    /// other cosigners are retained. Inputs with enough signatures are finalized and once all of
+    /// them are, the returned `hex` is the signed transaction.
+    fn sign_multisig(
+        &self,
+        tx: bitcoin::Transaction,
+        request: &TransactionMeta,
+        acc_store: &RawAccountCache,
+        signer: &dyn Signer,
+        sighashes: &[BESigHashType],
+    ) -> Result<TransactionMeta, Error> {
+        let multisig = self.multisig.as_ref().expect("multisig account");
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx.clone())?;
+        if let Some(other) = &request.psbt {
+            let other: PartiallySignedTransaction =
+                bitcoin::consensus::deserialize(&base64::decode(other)?)?;
+            psbt.combine(other)?;
+        }
+        // the key origins of all the cosigners, so that each of them can recognize its own keys
+        let master_fingerprint = signer.xpub(&DerivationPath::master())?.fingerprint();
+        let origins: Vec<(ExtendedPubKey, KeySource)> =
+            std::iter::once((self.xpub, (master_fingerprint, self.path.clone())))
+                .chain(multisig.cosigner_xpubs.iter().map(|c| (c.xpub, c.key_source())))
+                .collect();
+        for (xpub, origin) in origins.iter() {
+            psbt.xpub.insert(*xpub, origin.clone());
+        }
+
+        let mut hashcache = SighashCache::new(&tx);
+        for (i, txin) in tx.input.iter().enumerate() {
+            if request.used_utxos[i].skip_signing {
+                continue;
+            }
+            let prev_output = txin.previous_output;
+            let prev_tx = acc_store.get_bitcoin_tx(&prev_output.txid)?;
+            let out = prev_tx.output[prev_output.vout as usize].clone();
+            let derivation_path = acc_store.get_path(&out.script_pubkey.clone().into())?;
+            let (is_internal, index) = parse_path(derivation_path)?;
+            let witness_script = self.multisig_script(is_internal, index)?;
+            info!("input#{} prev_output:{:?} multisig path:{}", i, prev_output, derivation_path);
+
+            let input = &mut psbt.inputs[i];
+            if input.final_script_witness.is_some() {
+                continue;
+            }
+            for ((_, (fingerprint, path)), public_key) in
+                origins.iter().zip(self.multisig_keys(is_internal, index)?)
+            {
+                input
+                    .bip32_derivation
+                    .insert(public_key.inner, (*fingerprint, path.extend(derivation_path)));
+            }
+            if self.script_type == ScriptType::P2shP2wsh {
+                input.redeem_script = Some(witness_script.to_v0_p2wsh());
+            }
+
+            let sighash = sighashes[i].into_bitcoin()?;
+            let hash = hashcache.segwit_signature_hash(i, &witness_script, out.value, sighash)?;
+            let public_key = self.xpub.derive_pub(&crate::EC, derivation_path)?.to_pub();
+            let message = Message::from_slice(&hash.into_inner()[..]).unwrap();
+            let signature = EcdsaSig {
+                sig: signer.sign_ecdsa(&self.path.extend(derivation_path), &message)?,
+                hash_ty: sighash,
+            };
+            input.partial_sigs.insert(public_key, signature);
+            input.sighash_type = Some(PsbtSighashType::from(sighash));
+            input.witness_utxo = Some(out);
+            input.non_witness_utxo = Some(prev_tx);
+            input.witness_script = Some(witness_script);
+
+            finalize_multisig_input(input, multisig.threshold, self.script_type);
+        }
+
+        for (i, output) in tx.output.iter().enumerate() {
+            let path = match acc_store.paths.get(&output.script_pubkey.clone().into()) {
+                Some(path) => path,
+                None => continue,
+            };
+            let (is_internal, index) = parse_path(path)?;
+            let witness_script = self.multisig_script(is_internal, index)?;
+            let psbt_output = &mut psbt.outputs[i];
+            for ((_, (fingerprint, origin_path)), public_key) in
+                origins.iter().zip(self.multisig_keys(is_internal, index)?)
+            {
+                psbt_output
+                    .bip32_derivation
+                    .insert(public_key.inner, (*fingerprint, origin_path.extend(path)));
+            }
+            if self.script_type == ScriptType::P2shP2wsh {
+                psbt_output.redeem_script = Some(witness_script.to_v0_p2wsh());
+            }
+            psbt_output.witness_script = Some(witness_script);
+        }
+
+        let complete = psbt.inputs.iter().all(|input| input.final_script_witness.is_some());
+        let encoded = base64::encode(bitcoin::consensus::serialize(&psbt));
+        let tx = if complete {
+            psbt.extract_tx()
+        } else {
+            info!("multisig tx needs more signatures");
+            tx
+        };
+        let mut betx: TransactionMeta = BETransaction::Bitcoin(tx).into();
+        betx.psbt = Some(encoded);
+        Ok(betx)
+    }
+
+    /// Export an unsigned Bitcoin transaction of the account as a BIP174 PSBT.
+    ///
+    /// Our inputs have the transactions they spend, the spent output for segwit and taproot and
+    /// the origin of our keys, as our outputs. Key origins start from the master key if its
+    /// fingerprint is given, otherwise from the account key.
+    pub fn create_psbt(
+        &self,
+        request: &TransactionMeta,
+        master_fingerprint: Option<Fingerprint>,
+    ) -> Result<PartiallySignedTransaction, Error> {
+        let mut tx = match BETransaction::from_hex(&request.hex, self.network.id())? {
+            BETransaction::Bitcoin(tx) => tx,
+            BETransaction::Elements(_) => {
+                return Err(Error::Generic("Liquid transactions are exported as PSET".into()))
+            }
+        };
+        for txin in tx.input.iter_mut() {
+            txin.script_sig = bitcoin::Script::new();
+            txin.witness = Witness::default();
+        }
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx.clone())?;
+        let origin = match master_fingerprint {
+            Some(fingerprint) => (fingerprint, self.path.clone()),
+            None => (self.xpub.fingerprint(), DerivationPath::master()),
+        };
+        psbt.xpub.insert(self.xpub, origin.clone());
+        for cosigner in self.multisig.iter().flat_map(|m| m.cosigner_xpubs.iter()) {
+            psbt.xpub.insert(cosigner.xpub, cosigner.key_source());
+        }
+
+        let store_read = self.store.read()?;
+        let acc_store = store_read.account_cache(self.account_num)?;
+        for (i, txin) in tx.input.iter().enumerate() {
+            let prev_output = txin.previous_output;
+            let prev_tx = match acc_store.get_bitcoin_tx(&prev_output.txid) {
+                Ok(prev_tx) => prev_tx,
+                Err(_) => continue,
+            };
+            let out = match prev_tx.output.get(prev_output.vout as usize) {
+                Some(out) => out.clone(),
+                None => return Err(Error::Generic(format!("invalid input {}", prev_output))),
+            };
+            let path = match acc_store.paths.get(&out.script_pubkey.clone().into()) {
+                Some(path) => path,
+                None => continue,
+            };
+            let data = self.psbt_script_data(path, &origin)?;
+            let input = &mut psbt.inputs[i];
+            if self.script_type != ScriptType::P2pkh {
+                input.witness_utxo = Some(out);
+            }
+            // taproot signatures commit to the spent amounts, so the whole transaction is not needed
+            if !self.script_type.is_taproot() {
+                input.non_witness_utxo = Some(prev_tx);
+            }
+            if let Some(sighash) = request.used_utxos.get(i).and_then(|u| u.sighash) {
+                input.sighash_type = Some(PsbtSighashType::from_u32(sighash));
+            }
+            input.bip32_derivation = data.bip32_derivation;
+            input.redeem_script = data.redeem_script;
+            input.witness_script = data.witness_script;
+            input.tap_internal_key = data.tap_internal_key;
+            input.tap_key_origins = data.tap_key_origins;
+        }
+
+        for (i, output) in tx.output.iter().enumerate() {
+            let path = match acc_store.paths.get(&output.script_pubkey.clone().into()) {
+                Some(path) => path,
+                None => continue,
+            };
+            let data = self.psbt_script_data(path, &origin)?;
+            let output = &mut psbt.outputs[i];
+            output.bip32_derivation = data.bip32_derivation;
+            output.redeem_script = data.redeem_script;
+            output.witness_script = data.witness_script;
+            output.tap_internal_key = data.tap_internal_key;
+            output.tap_key_origins = data.tap_key_origins;
+        }
+        Ok(psbt)
+    }
+
+    /// The request to sign a transaction created by a watch-only session, in base64.
+    ///
+    /// For Bitcoin it is the PSBT of the transaction, for Liquid the PSET of the unblinded
+    /// transaction that also carries the secrets of the spent outputs to blind it and the genesis
+    /// block hash. Key origins start from the account key.
+    pub fn signing_request(&self, request: &TransactionMeta) -> Result<String, Error> {
+        let tx = match BETransaction::from_hex(&request.hex, self.network.id())? {
+            BETransaction::Bitcoin(_) => {
+                let psbt = self.create_psbt(request, None)?;
+                return Ok(base64::encode(bitcoin::consensus::serialize(&psbt)));
+            }
+            BETransaction::Elements(tx) => tx,
+        };
+        let mut pset = crate::pset::pset_from_tx(tx);
+        let origin = (self.xpub.fingerprint(), DerivationPath::master());
+
+        let store_read = self.store.read()?;
+        let acc_store = store_read.account_cache(self.account_num)?;
+        let mut input_secrets = vec![];
+        for (i, input) in pset.inputs_mut().iter_mut().enumerate() {
+            let previous_output =
+                elements::OutPoint::new(input.previous_txid, input.previous_output_index);
+            let unblinded = acc_store
+                .unblinded
+                .get(&previous_output)
+                .ok_or_else(|| Error::Generic("cannot find unblinded values".into()))?;
+            input_secrets.push(*unblinded);
+
+            let prev_tx = acc_store.get_liquid_tx(&input.previous_txid)?;
+            let txout = prev_tx.output[input.previous_output_index as usize].clone();
+            let path = acc_store.get_path(&txout.script_pubkey.clone().into())?;
+            let data = self.psbt_script_data(path, &origin)?;
+            input.witness_utxo = Some(txout);
+            if let Some(sighash) = request.used_utxos.get(i).and_then(|u| u.sighash) {
+                input.sighash_type = Some(elements::pset::PsbtSighashType::from_u32(sighash));
+            }
+            input.bip32_derivation = data
+                .bip32_derivation
+                .into_iter()
+                .map(|(public_key, origin)| (PublicKey::new(public_key), origin))
+                .collect();
+            input.redeem_script = data.redeem_script.map(BEScriptConvert::into_elements);
+            input.tap_internal_key = data.tap_internal_key;
+            input.tap_key_origins = data
+                .tap_key_origins
+                .into_iter()
+                .map(|(internal_key, (_, origin))| (internal_key, (vec![], origin)))
+                .collect();
+        }
+        for output in pset.outputs_mut().iter_mut() {
+            // We are the owner of all inputs and outputs
+            output.blinder_index = Some(0);
+        }
+        for (i, secrets) in input_secrets.iter().enumerate() {
+            crate::pset::set_input_secrets(&mut pset, i, secrets);
+        }
+        if let Some(BEBlockHash::Elements(genesis_hash)) = store_read.cache.genesis_hash {
+            crate::pset::set_genesis_hash(&mut pset, genesis_hash);
+        }
+        Ok(base64::encode(elements::encode::serialize(&pset)))
+    }
+
+    /// The keys with their origin and the scripts of the account script at `path`, as included in
+    /// PSBT inputs and outputs. The origin of the account key is `origin`, the ones of the
+    /// cosigner keys are taken from the multisig parameters.
+    fn psbt_script_data(
+        &self,
+        path: &DerivationPath,
+        origin: &(Fingerprint, DerivationPath),
+    ) -> Result<PsbtScriptData, Error> {
+        let (is_internal, index) = parse_path(path)?;
+        let our_origin = (origin.0, origin.1.extend(path));
+        let mut data = PsbtScriptData::default();
+        if let Some(multisig) = self.multisig.as_ref() {
+            let origins =
+                std::iter::once(our_origin).chain(multisig.cosigner_xpubs.iter().map(|c| {
+                    let (fingerprint, origin_path) = c.key_source();
+                    (fingerprint, origin_path.extend(path))
+                }));
+            for (origin, public_key) in origins.zip(self.multisig_keys(is_internal, index)?) {
+                data.bip32_derivation.insert(public_key.inner, origin);
+            }
+            let witness_script = self.multisig_script(is_internal, index)?;
+            if self.script_type == ScriptType::P2shP2wsh {
+                data.redeem_script = Some(witness_script.to_v0_p2wsh());
+            }
+            data.witness_script = Some(witness_script);
+            return Ok(data);
+        }
+
+        let public_key =
+            self.chains[is_internal as usize].ckd_pub(&crate::EC, index.into())?.to_pub();
+        match self.script_type {
+            ScriptType::P2tr => {
+                let (internal_key, _) = public_key.inner.x_only_public_key();
+                data.tap_internal_key = Some(internal_key);
+                data.tap_key_origins.insert(internal_key, (vec![], our_origin));
+            }
+            ScriptType::P2shP2wpkh => {
+                let wpubkey_hash = public_key.wpubkey_hash().expect("compressed key");
+                data.redeem_script = Some(bitcoin::Script::new_v0_p2wpkh(&wpubkey_hash));
+                data.bip32_derivation.insert(public_key.inner, our_origin);
+            }
+            _ => {
+                data.bip32_derivation.insert(public_key.inner, our_origin);
+            }
+        }
+        Ok(data)
+    }
+
+    /// Get the scripts of the given batch, whose size is the gap limit
+    pub fn get_script_batch(
+        &self,
+        is_internal: bool,
+        batch: u32,
+        gap_limit: u32,
+    ) -> Result<ScriptBatch, Error> {
         let store = self.store.read()?;
         let acc_store = store.account_cache(self.account_num)?;
 
         let mut result = ScriptBatch::default();
         result.cached = true;
 
-        let start = batch * BATCH_SIZE;
-        let end = start + BATCH_SIZE;
+        let start = batch * gap_limit;
+        let end = start + gap_limit;
         for j in start..end {
             let path = DerivationPath::from(&[(is_internal as u32).into(), j.into()][..]);
             let script = acc_store.scripts.get(&path).cloned().map_or_else(
@@ -919,6 +1700,35 @@ impl Account {
         })
     }
 
+    /// Sign the message with the key of one of our addresses, see [`crate::message`]
+    pub fn sign_message(
+        &self,
+        address: &BEAddress,
+        message: &str,
+        bip322_full: bool,
+    ) -> Result<String, Error> {
+        let path = {
+            let store_read = self.store.read()?;
+            let acc_store = store_read.account_cache(self.account_num)?;
+            acc_store.get_path(&address.script_pubkey())?.clone()
+        };
+        let signer = self
+            .signer
+            .as_deref()
+            .ok_or_else(|| Error::Generic("Signing requires the wallet signer".into()))?;
+        if self.network.liquid {
+            return Err(Error::Generic("Liquid addresses cannot sign messages".into()));
+        }
+        crate::message::sign_message(
+            signer,
+            &self.path.extend(&path),
+            &self.public_key(&path),
+            self.script_type,
+            message,
+            bip322_full,
+        )
+    }
+
     /// Verify that our own (outgoing) transactions were properly signed by the wallet.
     /// This is needed to prevent malicious servers from getting the user to fee-bump a
     /// transaction that they never signed in the first place.
@@ -938,11 +1748,36 @@ impl Account {
                 continue;
             }
             let mut hashcache = None;
+            let prevouts: Option<Vec<bitcoin::TxOut>> = tx
+                .previous_outputs()
+                .iter()
+                .map(|outpoint| {
+                    Some(bitcoin::TxOut {
+                        value: acc_store
+                            .all_txs
+                            .get_previous_output_value(outpoint, &acc_store.unblinded)?,
+                        script_pubkey: acc_store
+                            .all_txs
+                            .get_previous_output_script_pubkey(outpoint)?
+                            .into_bitcoin(),
+                    })
+                })
+                .collect();
+            // Taproot signatures commit to all the prevouts, without them we cannot verify
+            let prevouts = match prevouts {
+                Some(prevouts) => prevouts,
+                None if self.script_type == ScriptType::P2tr => {
+                    warn!("tx {} has unknown prevouts, skipping verification", txid);
+                    continue;
+                }
+                None => vec![],
+            };
             for (vin, outpoint) in tx.previous_outputs().iter().enumerate() {
-                let script = acc_store
-                    .all_txs
-                    .get_previous_output_script_pubkey(outpoint)
-                    .expect("prevout to be indexed");
+                // prevouts not involving the wallet may be unknown, e.g. with compact filters
+                let script = match acc_store.all_txs.get_previous_output_script_pubkey(outpoint) {
+                    Some(script) => script,
+                    None => continue,
+                };
                 let public_key = match acc_store.paths.get(&script) {
                     Some(path) => self.xpub.derive_pub(&crate::EC, path)?,
                     // We only need to check wallet-owned inputs
@@ -960,6 +1795,7 @@ impl Account {
                     &public_key,
                     value,
                     self.script_type,
+                    &prevouts,
                 ) {
                     warn!("tx {} verification failed: {:?}", txid, err);
                     acc_store.all_txs.remove(txid);
@@ -976,6 +1812,60 @@ impl Account {
     }
 }
 
+/// The key origins and scripts of an account script in a PSBT input or output
+#[derive(Default)]
+struct PsbtScriptData {
+    bip32_derivation: BTreeMap<secp256k1::PublicKey, KeySource>,
+    redeem_script: Option<bitcoin::Script>,
+    witness_script: Option<bitcoin::Script>,
+    tap_internal_key: Option<XOnlyPublicKey>,
+    tap_key_origins: BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, KeySource)>,
+}
+
+/// Check the multisig parameters of an account whose key is `xpub`
+fn check_multisig(
+    multisig: &MultisigParams,
+    xpub: &ExtendedPubKey,
+    network: &NetworkParameters,
+) -> Result<(), Error> {
+    let network = match network.id() {
+        NetworkId::Bitcoin(network) => network,
+        NetworkId::Elements(_) => {
+            return Err(Error::InvalidMultisig("not supported on liquid".into()));
+        }
+    };
+    let num_keys = multisig.cosigner_xpubs.len() as u32 + 1;
+    if num_keys > MAX_MULTISIG_KEYS {
+        return Err(Error::InvalidMultisig(format!("more than {} keys", MAX_MULTISIG_KEYS)));
+    }
+    if multisig.threshold == 0 || multisig.threshold > num_keys {
+        return Err(Error::InvalidMultisig(format!(
+            "threshold {} with {} keys",
+            multisig.threshold, num_keys
+        )));
+    }
+    let mut public_keys = HashSet::new();
+    for cosigner_xpub in
+        std::iter::once(xpub).chain(multisig.cosigner_xpubs.iter().map(|c| &c.xpub))
+    {
+        let is_mainnet = cosigner_xpub.network == bitcoin::Network::Bitcoin;
+        if is_mainnet != (network == bitcoin::Network::Bitcoin) {
+            return Err(Error::InvalidMultisig(format!("{} network mismatch", cosigner_xpub)));
+        }
+        if !public_keys.insert(cosigner_xpub.public_key) {
+            return Err(Error::InvalidMultisig(format!("{} is repeated", cosigner_xpub)));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn check_gap_limit(gap_limit: u32) -> Result<(), Error> {
+    if gap_limit == 0 || gap_limit > MAX_GAP_LIMIT {
+        return Err(Error::InvalidGapLimit(gap_limit, MAX_GAP_LIMIT));
+    }
+    Ok(())
+}
+
 /// Return the last (if any) and next account numbers for the given script type
 pub fn get_last_next_account_nums(
     existing: HashSet<u32>,
@@ -996,6 +1886,9 @@ pub fn get_account_script_purpose(account_num: u32) -> Result<(ScriptType, u32),
         0 => (ScriptType::P2shP2wpkh, 49),
         1 => (ScriptType::P2wpkh, 84),
         2 => (ScriptType::P2pkh, 44),
+        3 => (ScriptType::P2tr, 86),
+        4 => (ScriptType::P2wsh, 48),
+        5 => (ScriptType::P2shP2wsh, 48),
         _ => return Err(Error::InvalidSubaccount(account_num)),
     })
 }
@@ -1009,8 +1902,14 @@ pub fn get_account_derivation(
     let bip32_account_num = account_num / NUM_RESERVED_ACCOUNT_TYPES;
 
     // BIP44: m / purpose' / coin_type' / account' / change / address_index
-    let path: DerivationPath =
+    let mut path: DerivationPath =
         format!("m/{}'/{}'/{}'", purpose, coin_type, bip32_account_num).parse().unwrap();
+    // BIP48: m / purpose' / coin_type' / account' / script_type' / change / address_index
+    match script_type {
+        ScriptType::P2shP2wsh => path = path.child(ChildNumber::from_hardened_idx(1).unwrap()),
+        ScriptType::P2wsh => path = path.child(ChildNumber::from_hardened_idx(2).unwrap()),
+        _ => (),
+    }
 
     info!("derivation path for account {}: {}", account_num, path);
 
@@ -1070,6 +1969,13 @@ fn bitcoin_address(
         ScriptType::P2shP2wpkh => Address::p2shwpkh(public_key, net).expect("no compressed keys"),
         ScriptType::P2wpkh => Address::p2wpkh(public_key, net).expect("no compressed keys"),
         ScriptType::P2pkh => Address::p2pkh(public_key, net),
+        ScriptType::P2tr => {
+            let (internal_key, _) = public_key.inner.x_only_public_key();
+            Address::p2tr(&crate::EC, internal_key, None, net)
+        }
+        ScriptType::P2wsh | ScriptType::P2shP2wsh => {
+            unreachable!("multisig addresses are derived from all the cosigner keys")
+        }
     }
 }
 
@@ -1084,6 +1990,13 @@ fn elements_address(
         ScriptType::P2pkh => elements::Address::p2pkh(public_key, None, addr_params),
         ScriptType::P2shP2wpkh => elements::Address::p2shwpkh(public_key, None, addr_params),
         ScriptType::P2wpkh => elements::Address::p2wpkh(public_key, None, addr_params),
+        ScriptType::P2tr => {
+            let (internal_key, _) = public_key.inner.x_only_public_key();
+            elements::Address::p2tr(&crate::EC, internal_key, None, None, addr_params)
+        }
+        ScriptType::P2wsh | ScriptType::P2shP2wsh => {
+            unreachable!("multisig is not supported on liquid")
+        }
     };
     let script_pubkey = address.script_pubkey();
     let blinding_prv = asset_blinding_key_to_ec_private_key(master_blinding_key, &script_pubkey);
@@ -1092,27 +2005,45 @@ fn elements_address(
 }
 
 pub fn discover_account(
-    electrum_url: &ElectrumUrl,
+    server_url: &ServerUrl,
     proxy: Option<&str>,
     account_xpub: &ExtendedPubKey,
     script_type: ScriptType,
+    gap_limit: u32,
 ) -> Result<bool, Error> {
-    use electrum_client::ElectrumApi;
-
+    check_gap_limit(gap_limit)?;
     // build our own client so that the subscriptions are dropped at the end
-    let client = electrum_url.build_client(proxy, None)?;
+    let client = server_url.build_client(proxy, None)?;
+    is_account_used(client.as_ref(), account_xpub, script_type, gap_limit)
+}
 
-    // the batch size is the effective gap limit for our purposes. in reality it is a lower bound.
-    let gap_limit = BATCH_SIZE;
+/// Whether any of the first `gap_limit` addresses of either chain of the account has history
+pub fn is_account_used(
+    client: &dyn BlockchainClient,
+    account_xpub: &ExtendedPubKey,
+    script_type: ScriptType,
+    gap_limit: u32,
+) -> Result<bool, Error> {
+    if script_type.is_multisig() {
+        // the cosigner keys cannot be derived from our keys
+        return Err(Error::InvalidMultisig("multisig accounts cannot be discovered".into()));
+    }
 
-    let external_xpub = account_xpub.ckd_pub(&crate::EC, 0.into())?;
-    for index in 0..gap_limit {
-        let child_key = external_xpub.ckd_pub(&crate::EC, index.into())?;
-        // Every network has the same scriptpubkey
-        let script = bitcoin_address(&child_key.to_pub(), script_type, bitcoin::Network::Bitcoin)
-            .script_pubkey();
+    let mut scripts = Vec::with_capacity(2 * gap_limit as usize);
+    for chain in [0, 1] {
+        let chain_xpub = account_xpub.ckd_pub(&crate::EC, chain.into())?;
+        for index in 0..gap_limit {
+            let child_key = chain_xpub.ckd_pub(&crate::EC, index.into())?;
+            // Every network has the same scriptpubkey
+            let address =
+                bitcoin_address(&child_key.to_pub(), script_type, bitcoin::Network::Bitcoin);
+            scripts.push(address.script_pubkey());
+        }
+    }
 
-        if client.script_subscribe(&script)?.is_some() {
+    client.set_rescan(true);
+    for batch in scripts.chunks(BATCH_SIZE as usize) {
+        if client.batch_script_get_history(batch)?.iter().any(|h| !h.is_empty()) {
             return Ok(true);
         }
     }
@@ -1120,6 +2051,285 @@ pub fn discover_account(
     Ok(false)
 }
 
+/// The increase of `BETransaction::estimated_fee` when adding an input of the account and when
+/// adding a change output, rounded up.
+pub(crate) fn marginal_fees(id: NetworkId, fee_rate: f64, script: ScriptTemplate) -> (u64, u64) {
+    let txid: BETxid = match id {
+        NetworkId::Bitcoin(_) => bitcoin::Txid::all_zeros().into(),
+        NetworkId::Elements(_) => elements::Txid::all_zeros().into(),
+    };
+    let mut tx = BETransaction::new(id);
+    tx.add_input(BEOutPoint::new(txid, 0));
+    let fee = tx.estimated_fee(fee_rate, 0, script);
+    let change_fee = tx.estimated_fee(fee_rate, 1, script) - fee + 1;
+    tx.add_input(BEOutPoint::new(txid, 1));
+    let input_fee = tx.estimated_fee(fee_rate, 0, script) - fee + 1;
+    (input_fee, change_fee)
+}
+
+/// Group the utxos that must be spent together, for bitcoin the ones with the same script
+fn group_utxos<'a>(utxos: &[&'a Txo], id: NetworkId) -> Vec<Vec<&'a Txo>> {
+    match id {
+        NetworkId::Bitcoin(_) => {
+            let mut groups: Vec<Vec<&Txo>> = vec![];
+            for utxo in utxos {
+                match groups.iter_mut().find(|g| g[0].script_pubkey == utxo.script_pubkey) {
+                    Some(group) => group.push(utxo),
+                    None => groups.push(vec![utxo]),
+                }
+            }
+            groups
+        }
+        // see the comment in `create_tx` on spending the same script together in liquid
+        NetworkId::Elements(_) => utxos.iter().map(|u| vec![*u]).collect(),
+    }
+}
+
+/// Check that the address is a supported address of the network, for Liquid it must be
+/// confidential
+pub fn validate_address(address: &str, network: &NetworkParameters) -> Result<(), Error> {
+    match network.id() {
+        NetworkId::Bitcoin(network) => {
+            if let Ok(address) = bitcoin::Address::from_str(address) {
+                info!("address.network:{} network:{}", address.network, network);
+                if address.network == network
+                    || (address.network == bitcoin::Network::Testnet
+                        && network == bitcoin::Network::Regtest)
+                {
+                    // FIXME: use address.is_standard() once rust-bitcoin has P2tr variant
+                    if let Payload::WitnessProgram {
+                        version: v,
+                        program: p,
+                    } = &address.payload
+                    {
+                        // Do not support segwit greater than v1 and non-P2TR v1
+                        if v.to_num() > 1 || (v.to_num() == 1 && p.len() != 32) {
+                            return Err(Error::InvalidAddress);
+                        }
+                    }
+                    return Ok(());
+                }
+            }
+            Err(Error::InvalidAddress)
+        }
+        NetworkId::Elements(network) => {
+            let address = elements::Address::parse_with_params(address, network.address_params())
+                .map_err(|_| Error::InvalidAddress)?;
+            if !address.is_blinded() {
+                return Err(Error::NonConfidentialAddress);
+            }
+            if let elements::address::Payload::WitnessProgram {
+                version: v,
+                program: p,
+            } = &address.payload
+            {
+                // Do not support segwit greater than v1 and non-P2TR v1
+                if v.to_u8() > 1 || (v.to_u8() == 1 && p.len() != 32) {
+                    return Err(Error::InvalidAddress);
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Add the output paying `addressee`, an OP_RETURN output if it carries data
+fn add_addressee_output(
+    tx: &mut BETransaction,
+    addressee: &AddressAmount,
+    id: NetworkId,
+) -> Result<(), Error> {
+    match addressee.data {
+        Some(ref data) => {
+            let data = Vec::<u8>::from_hex(data).map_err(|_| Error::InvalidDataOutput)?;
+            tx.add_data_output(&data, addressee.satoshi, addressee.asset_id());
+        }
+        None => tx
+            .add_output(&addressee.address, addressee.satoshi, addressee.asset_id(), id)
+            .map_err(|_| Error::InvalidAddress)?,
+    }
+    Ok(())
+}
+
+/// The addressee of an output, `None` if it has neither an address nor data
+fn output_addressee(
+    tx: &BETransaction,
+    vout: u32,
+    id: NetworkId,
+    unblinded: &HashMap<elements::OutPoint, elements::TxOutSecrets>,
+) -> Option<AddressAmount> {
+    let satoshi = tx.output_value(vout, unblinded).unwrap_or_default();
+    let asset_id = tx.output_asset(vout, unblinded).map(|a| a.to_hex());
+    if let Some(address) = tx.output_address(vout, id) {
+        Some(AddressAmount {
+            address,
+            satoshi,
+            asset_id,
+            data: None,
+        })
+    } else {
+        tx.output_data(vout).map(|data| AddressAmount {
+            address: String::new(),
+            satoshi,
+            asset_id,
+            data: Some(data.to_hex()),
+        })
+    }
+}
+
+/// The locktime discouraging miners from reorganizing the chain to take the fees, like Bitcoin Core
+/// it is sometimes randomly set further back to make the transactions that were delayed less
+/// distinguishable
+fn anti_fee_sniping_lock_time(tip_height: u32) -> u32 {
+    let mut rng = rand::thread_rng();
+    if rng.gen_range(0..10) == 0 {
+        tip_height.saturating_sub(rng.gen_range(0..100))
+    } else {
+        tip_height
+    }
+}
+
+/// The sequence of inputs that disables both the locktime and the relative timelock
+const SEQUENCE_FINAL: u32 = 0xffff_ffff;
+
+/// The bit of the sequence that disables its relative timelock, as in BIP68
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+
+/// The maximum amount of an asset that a single issuance or reissuance can create, as in elements
+const MAX_ISSUANCE_AMOUNT: u64 = 21_000_000 * 100_000_000;
+
+/// The explicit value of an issued amount, null if nothing is issued
+fn issuance_value(satoshi: u64) -> Value {
+    if satoshi == 0 {
+        Value::Null
+    } else {
+        Value::Explicit(satoshi)
+    }
+}
+
+/// The utxo whose input carries an issuance or reissuance, with the ids of what it issues
+struct IssuanceInput<'a> {
+    utxo: &'a Txo,
+    asset_issuance: elements::AssetIssuance,
+    asset_id: AssetId,
+    token_id: Option<AssetId>,
+}
+
+/// The input carrying the requested issuance or reissuance, if any
+fn issuance_input<'a>(
+    request: &CreateTransaction,
+    utxos: &'a [Txo],
+    store: &StoreMeta,
+    policy_asset: Option<AssetId>,
+) -> Result<Option<IssuanceInput<'a>>, Error> {
+    // frozen utxos can carry the issuance only if they are selected manually
+    let is_selectable =
+        |u: &Txo| request.utxo_strategy == UtxoStrategy::Manual || !store.is_frozen(&u.outpoint);
+
+    if let Some(ref issuance) = request.issuance {
+        if issuance.satoshi == 0 && issuance.token_satoshi == 0
+            || issuance.satoshi > MAX_ISSUANCE_AMOUNT
+            || issuance.token_satoshi > MAX_ISSUANCE_AMOUNT
+        {
+            return Err(Error::InvalidIssuance("invalid amount".into()));
+        }
+        let contract_hash = match issuance.contract_hash {
+            Some(ref hash) => ContractHash::from_hex(hash)
+                .map_err(|_| Error::InvalidIssuance("invalid contract hash".into()))?,
+            None => ContractHash::all_zeros(),
+        };
+        // any input can carry a new issuance, the fees are paid by the policy asset anyway
+        let utxo = utxos
+            .iter()
+            .filter(|u| u.asset_id() == policy_asset && is_selectable(u))
+            .max_by_key(|u| u.satoshi)
+            .ok_or(Error::InsufficientFunds)?;
+        let outpoint = utxo.outpoint.elements().expect("liquid utxo");
+        let entropy = AssetId::generate_asset_entropy(outpoint, contract_hash);
+        let asset_issuance = elements::AssetIssuance {
+            asset_blinding_nonce: ZERO_TWEAK,
+            asset_entropy: contract_hash.into_inner(),
+            amount: issuance_value(issuance.satoshi),
+            inflation_keys: issuance_value(issuance.token_satoshi),
+        };
+        let token_id = (issuance.token_satoshi > 0)
+            .then(|| AssetId::reissuance_token_from_entropy(entropy, false));
+        return Ok(Some(IssuanceInput {
+            utxo,
+            asset_issuance,
+            asset_id: AssetId::from_entropy(entropy),
+            token_id,
+        }));
+    }
+
+    if let Some(ref reissuance) = request.reissuance {
+        let asset_id =
+            AssetId::from_str(&reissuance.asset_id).map_err(|_| Error::InvalidAssetId)?;
+        if reissuance.satoshi == 0 || reissuance.satoshi > MAX_ISSUANCE_AMOUNT {
+            return Err(Error::InvalidIssuance("invalid amount".into()));
+        }
+        let not_found = || Error::ReissuanceTokenNotFound(reissuance.asset_id.clone());
+        let entropy = reissuance_entropy(reissuance, &asset_id, store)?.ok_or_else(not_found)?;
+        // the token id depends on whether the issued amount was blinded
+        let token_ids = [
+            AssetId::reissuance_token_from_entropy(entropy, false),
+            AssetId::reissuance_token_from_entropy(entropy, true),
+        ];
+        let utxo = utxos
+            .iter()
+            .filter(|u| is_selectable(u))
+            .find(|u| matches!(u.asset_id(), Some(a) if token_ids.contains(&a)))
+            .ok_or_else(not_found)?;
+        // the asset blinding factor of the spent token proves its ownership, so it must be blinded
+        let asset_bf = utxo.txoutsecrets.as_ref().map(|s| s.asset_bf).ok_or_else(not_found)?;
+        if asset_bf == AssetBlindingFactor::zero() {
+            return Err(Error::InvalidIssuance("the reissuance token is not blinded".into()));
+        }
+        let asset_issuance = elements::AssetIssuance {
+            asset_blinding_nonce: asset_bf.into_inner(),
+            asset_entropy: entropy.into_inner(),
+            amount: issuance_value(reissuance.satoshi),
+            inflation_keys: Value::Null,
+        };
+        return Ok(Some(IssuanceInput {
+            utxo,
+            asset_issuance,
+            asset_id,
+            token_id: None,
+        }));
+    }
+
+    Ok(None)
+}
+
+/// The entropy of the asset to reissue, given by the request or synced by the wallet
+fn reissuance_entropy(
+    reissuance: &ReissuanceOpt,
+    asset_id: &AssetId,
+    store: &StoreMeta,
+) -> Result<Option<sha256::Midstate>, Error> {
+    let invalid = |what: &str| Error::InvalidIssuance(format!("invalid {}", what));
+    let entropy = if let Some(ref entropy) = reissuance.entropy {
+        sha256::Midstate::from_hex(entropy).map_err(|_| invalid("entropy"))?
+    } else if let Some(ref txhash) = reissuance.prevout_txhash {
+        let txid = elements::Txid::from_hex(txhash).map_err(|_| invalid("prevout"))?;
+        let vout = reissuance.prevout_pt_idx.ok_or_else(|| invalid("prevout"))?;
+        let contract_hash = match reissuance.contract_hash {
+            Some(ref hash) => ContractHash::from_hex(hash).map_err(|_| invalid("contract hash"))?,
+            None => ContractHash::all_zeros(),
+        };
+        AssetId::generate_asset_entropy(elements::OutPoint::new(txid, vout), contract_hash)
+    } else if reissuance.contract_hash.is_some() {
+        return Err(invalid("prevout"));
+    } else {
+        return Ok(store.issuance_entropy(asset_id).map(sha256::Midstate::from_inner));
+    };
+    if AssetId::from_entropy(entropy) != *asset_id {
+        return Err(Error::InvalidIssuance("the entropy does not match the asset id".into()));
+    }
+    Ok(Some(entropy))
+}
+
 #[allow(clippy::cognitive_complexity)]
 pub fn create_tx(
     account: &Account,
@@ -1139,92 +2349,267 @@ pub fn create_tx(
     }
 
     // convert from satoshi/kbyte to satoshi/byte
-    let fee_rate = (*fee_rate_sat_kb as f64) / 1000.0;
+    let mut fee_rate = (*fee_rate_sat_kb as f64) / 1000.0;
     info!("target fee_rate {:?} satoshi/byte", fee_rate);
 
     // TODO put checks into CreateTransaction::validate
     // eagerly check for address validity
-    for addressee in request.addressees.iter() {
-        match network.id() {
-            NetworkId::Bitcoin(network) => {
-                if let Ok(address) = bitcoin::Address::from_str(&addressee.address) {
-                    info!("address.network:{} network:{}", address.network, network);
-                    if address.network == network
-                        || (address.network == bitcoin::Network::Testnet
-                            && network == bitcoin::Network::Regtest)
-                    {
-                        // FIXME: use address.is_standard() once rust-bitcoin has P2tr variant
-                        if let Payload::WitnessProgram {
-                            version: v,
-                            program: p,
-                        } = &address.payload
-                        {
-                            // Do not support segwit greater than v1 and non-P2TR v1
-                            if v.to_num() > 1 || (v.to_num() == 1 && p.len() != 32) {
-                                return Err(Error::InvalidAddress);
-                            }
-                        }
-                        continue;
-                    }
-                }
-                return Err(Error::InvalidAddress);
+    for addressee in request.addressees.iter().chain(request.add_addressees.iter()) {
+        if let Some(ref data) = addressee.data {
+            let data = Vec::<u8>::from_hex(data).map_err(|_| Error::InvalidDataOutput)?;
+            // on liquid the value of a data output burns an amount of any asset
+            let is_burn = network.liquid && addressee.satoshi != 0;
+            if !addressee.address.is_empty()
+                || (addressee.satoshi != 0 && !is_burn)
+                || data.len() > MAX_DATA_OUTPUT_SIZE
+            {
+                return Err(Error::InvalidDataOutput);
             }
-            NetworkId::Elements(network) => {
-                if let Ok(address) = elements::Address::parse_with_params(
-                    &addressee.address,
-                    network.address_params(),
-                ) {
-                    if !address.is_blinded() {
-                        return Err(Error::NonConfidentialAddress);
-                    }
-                    if let elements::address::Payload::WitnessProgram {
-                        version: v,
-                        program: p,
-                    } = &address.payload
-                    {
-                        // Do not support segwit greater than v1 and non-P2TR v1
-                        if v.to_u8() > 1 || (v.to_u8() == 1 && p.len() != 32) {
-                            return Err(Error::InvalidAddress);
-                        }
-                    }
-                } else {
-                    return Err(Error::InvalidAddress);
-                }
-                if let Some(Ok(_)) = addressee
-                    .asset_id
-                    .as_ref()
-                    .map(|asset_id| elements::issuance::AssetId::from_str(&asset_id))
-                {
-                    // non-empty and valid asset id
-                } else {
+            if is_burn {
+                if addressee.asset_id().is_none() {
                     return Err(Error::InvalidAssetId);
                 }
+            } else if addressee.asset_id() != network.policy_asset_id().ok() {
+                // the value of data outputs is explicit, so they do not need inputs of their asset
+                return Err(Error::InvalidAssetId);
+            }
+            continue;
+        }
+        validate_address(&addressee.address, network)?;
+        if network.liquid {
+            if let Some(Ok(_)) = addressee
+                .asset_id
+                .as_ref()
+                .map(|asset_id| elements::issuance::AssetId::from_str(&asset_id))
+            {
+                // non-empty and valid asset id
+            } else {
+                return Err(Error::InvalidAssetId);
             }
         }
     }
 
-    let send_all = request.send_all;
-    if !send_all && request.addressees.iter().any(|a| a.satoshi == 0) {
+    let is_issuance = request.issuance.is_some() || request.reissuance.is_some();
+    if is_issuance {
+        if !network.liquid {
+            return Err(Error::InvalidIssuance("assets can be issued only on liquid".into()));
+        }
+        if request.issuance.is_some() && request.reissuance.is_some() {
+            return Err(Error::InvalidIssuance(
+                "issue and reissue in separate transactions".into(),
+            ));
+        }
+        if request.send_all {
+            return Err(Error::InvalidIssuance("can not send all".into()));
+        }
+    }
+
+    if let Some(ref endpoint) = request.payjoin_endpoint {
+        if !account.supports_payjoin() {
+            return Err(Error::UnsupportedPayjoin);
+        }
+        payjoin::validate_endpoint(endpoint, network.development)?;
+    }
+
+    let mut send_all = request.send_all;
+    if !send_all && request.addressees.iter().any(|a| a.satoshi == 0 && a.data.is_none()) {
         return Err(Error::InvalidAmount);
     }
 
     let mut template_tx = None;
     let mut change_addresses = vec![];
+    // The outputs of a replaced transaction can not fund its replacement
+    let mut replaced_txid = None;
 
     let store_read = account.store.read()?;
     let acc_store = store_read.account_cache(account.num())?;
 
-    // When a previous transaction is replaced, use it as a template for the new transaction
-    if let Some(ref prev_txitem) = request.previous_transaction {
+    let is_cpfp = request.cpfp;
+    if is_cpfp && (request.cancel_transaction || request.previous_transaction.is_none()) {
+        return Err(Error::InvalidCpfpRequest);
+    }
+    // recipients can be added only to a replaced transaction
+    if !request.add_addressees.is_empty()
+        && (request.previous_transaction.is_none() || request.cancel_transaction || is_cpfp)
+    {
+        return Err(Error::InvalidReplacementRequest);
+    }
+
+    if is_cpfp {
+        // Child pays for parent: spend our outputs of the unconfirmed previous transaction back to
+        // the wallet, paying for the parent what it lacks to reach the target fee rate
+        let prev_txitem = request.previous_transaction.as_ref().expect("checked by is_cpfp");
+        if network.liquid {
+            return Err(Error::InvalidCpfpRequest);
+        }
+        let txid = BETxid::from_hex(&prev_txitem.txhash, network.id())?;
+        if acc_store.heights.get(&txid) != Some(&None) {
+            // confirmed, or replaced or dropped from the mempool
+            return Err(Error::InvalidCpfpRequest);
+        }
+
+        let mut parent_utxos = vec![];
+        for utxo in request.utxos.values().flatten() {
+            if utxo.outpoint(network.id())?.txid() == txid {
+                parent_utxos.push(utxo.clone());
+            }
+        }
+        if parent_utxos.is_empty() {
+            return Err(Error::InvalidCpfpRequest);
+        }
+        let address = account.derive_address(true, acc_store.indexes.internal + 1)?.to_string();
+
+        let mut child_tx = BETransaction::new(network.id());
+        for utxo in parent_utxos.iter() {
+            child_tx.add_input(utxo.outpoint(network.id())?);
+        }
+        child_tx.add_output(&address, 0, None, network.id())?;
+        let child_vsize = child_tx.estimated_vsize(0, account.script_template());
+
+        // miners consider the parent with its unconfirmed ancestors, so the child pays for all
+        // the ones known by the wallet to reach the target fee rate
+        let mut parent_fee = 0;
+        let mut parent_vsize = 0.0;
+        let mut visited = HashSet::new();
+        let mut to_visit = vec![txid];
+        while let Some(ancestor_txid) = to_visit.pop() {
+            if !visited.insert(ancestor_txid) {
+                continue;
+            }
+            let ancestor = acc_store
+                .all_txs
+                .get(&ancestor_txid)
+                .ok_or_else(|| Error::TxNotFound(ancestor_txid))?;
+            // the fee of incoming txs is unknown if their previous txs are not available, in
+            // which case the child pays for the whole tx
+            let missing_previous = ancestor.tx.previous_outputs().iter().any(|o| {
+                acc_store.all_txs.get_previous_output_value(o, &acc_store.unblinded).is_none()
+            });
+            if !missing_previous {
+                parent_fee += ancestor.tx.fee(&acc_store.all_txs, &acc_store.unblinded, &None)?;
+            }
+            parent_vsize += weight_to_vsize(ancestor.weight) as f64;
+            to_visit.extend(
+                ancestor
+                    .tx
+                    .previous_output_txids()
+                    .into_iter()
+                    .filter(|t| acc_store.heights.get(t) == Some(&None)),
+            );
+        }
+        let parent_shortfall = (fee_rate * parent_vsize - parent_fee as f64).max(0.0);
+        fee_rate += parent_shortfall / child_vsize;
+        info!(
+            "cpfp package fee:{} vsize:{} ancestors:{} shortfall:{} child fee_rate:{}",
+            parent_fee,
+            parent_vsize,
+            visited.len() - 1,
+            parent_shortfall,
+            fee_rate
+        );
+
+        request.utxos = vec![("btc".to_string(), parent_utxos)].into_iter().collect();
+        request.addressees = vec![AddressAmount {
+            address,
+            satoshi: 0,
+            asset_id: None,
+            data: None,
+        }];
+        request.utxo_strategy = UtxoStrategy::Manual;
+        request.send_all = true;
+        send_all = true;
+    } else if request.cancel_transaction {
+        // Cancel: double spend all the inputs of the previous transaction to the wallet, paying
+        // enough to satisfy BIP125 rules 3 and 4
+        let prev_txitem =
+            request.previous_transaction.as_ref().ok_or(Error::InvalidReplacementRequest)?;
+        if network.liquid {
+            return Err(Error::InvalidReplacementRequest);
+        }
+        let txid = BETxid::from_hex(&prev_txitem.txhash, network.id())?;
+        let prev_tx = &acc_store.all_txs.get(&txid).ok_or_else(|| Error::TxNotFound(txid))?.tx;
+        if acc_store.heights.get(&txid) != Some(&None) || !prev_tx.rbf_optin() {
+            return Err(Error::InvalidReplacementRequest);
+        }
+
+        let address = account.derive_address(true, acc_store.indexes.internal + 1)?.to_string();
+        let mut cancel_tx = BETransaction::new(network.id());
+        let mut prev_utxos = vec![];
+        for outpoint in prev_tx.previous_outputs() {
+            // all the inputs must be ours to send them back to the wallet
+            if account.txo(&outpoint, acc_store).is_err() {
+                return Err(Error::InvalidReplacementRequest);
+            }
+            cancel_tx.add_input(outpoint.clone());
+            prev_utxos.push(CreateTxUtxo {
+                txid: outpoint.txid().to_hex(),
+                vout: outpoint.vout(),
+                sequence: None,
+            });
+        }
+        cancel_tx.add_output(&address, 0, None, network.id())?;
+        let cancel_vsize = cancel_tx.estimated_vsize(0, account.script_template());
+
+        // rule 3: pay at least the fees of all the evicted transactions, the previous one and its
+        // unconfirmed descendants known by the wallet, rule 4: pay for the replacement bandwidth
+        // at the incremental relay fee, which defaults to the minimum relay fee
+        let mut prev_fee = prev_tx.fee(&acc_store.all_txs, &acc_store.unblinded, &None)?;
+        let mut evicted = HashSet::from([txid]);
+        loop {
+            let descendants: Vec<BETxid> = acc_store
+                .heights
+                .iter()
+                .filter(|(t, height)| height.is_none() && !evicted.contains(t))
+                .map(|(t, _)| *t)
+                .filter(|t| match acc_store.all_txs.get(t) {
+                    Some(txe) => txe.tx.previous_output_txids().iter().any(|p| evicted.contains(p)),
+                    None => false,
+                })
+                .collect();
+            if descendants.is_empty() {
+                break;
+            }
+            for descendant in descendants {
+                let descendant_tx = &acc_store.all_txs.get(&descendant).expect("filtered").tx;
+                let missing_previous = descendant_tx.previous_outputs().iter().any(|o| {
+                    acc_store.all_txs.get_previous_output_value(o, &acc_store.unblinded).is_none()
+                });
+                if missing_previous {
+                    return Err(Error::UnknownDescendantFee(descendant.to_string()));
+                }
+                prev_fee += descendant_tx.fee(&acc_store.all_txs, &acc_store.unblinded, &None)?;
+                evicted.insert(descendant);
+            }
+        }
+        let incremental_fee_rate = default_min_fee_rate as f64 / 1000.0;
+        let min_fee_rate = (prev_fee as f64 + incremental_fee_rate * cancel_vsize) / cancel_vsize;
+        if fee_rate < min_fee_rate {
+            fee_rate = min_fee_rate;
+        }
+        info!("cancel previous fee:{} vsize:{} fee_rate:{}", prev_fee, cancel_vsize, fee_rate);
+
+        request.utxos = vec![("btc".to_string(), prev_utxos)].into_iter().collect();
+        request.addressees = vec![AddressAmount {
+            address,
+            satoshi: 0,
+            asset_id: None,
+            data: None,
+        }];
+        request.utxo_strategy = UtxoStrategy::Manual;
+        request.send_all = true;
+        send_all = true;
+    } else if let Some(ref prev_txitem) = request.previous_transaction {
+        // When a previous transaction is replaced, use it as a template for the new transaction
         if send_all || network.liquid {
             return Err(Error::InvalidReplacementRequest);
         }
 
         let txid = BETxid::from_hex(&prev_txitem.txhash, network.id())?;
         let prev_tx = &acc_store.all_txs.get(&txid).ok_or_else(|| Error::TxNotFound(txid))?.tx;
+        replaced_txid = Some(txid);
 
         // Strip the mining fee change output from the transaction, keeping the change address for reuse
-        template_tx = Some(prev_tx.filter_outputs(&acc_store.unblinded, |vout, script, asset| {
+        let mut template = prev_tx.filter_outputs(&acc_store.unblinded, |vout, script, asset| {
             if asset == None && account.get_wallet_chain_type(&script) == Some(1) {
                 let change_address = prev_tx
                     .output_address(vout, network.id())
@@ -1234,43 +2619,39 @@ pub fn create_tx(
             } else {
                 true
             }
-        }));
+        });
+        let template_addressees = |tx: &BETransaction| -> Vec<AddressAmount> {
+            (0..tx.output_len() as u32)
+                .filter_map(|vout| output_addressee(tx, vout, network.id(), &acc_store.unblinded))
+                .collect()
+        };
 
-        if let (Some(BETransaction::Bitcoin(tx)), NetworkId::Bitcoin(net)) =
-            (&template_tx, network.id())
-        {
-            request.addressees = tx
-                .output
-                .iter()
-                .filter_map(|o| {
-                    Some(AddressAmount {
-                        address: bitcoin::Address::from_script(&o.script_pubkey, net)
-                            .ok()?
-                            .to_string(),
-                        satoshi: o.value,
-                        asset_id: None,
-                    })
-                })
-                .collect();
-        } else {
-            return Err(Error::InvalidReplacementRequest);
+        // The addressees are the outputs of the previous transaction and the added recipients
+        for addressee in request.add_addressees.iter() {
+            if addressee.data.is_none() && addressee.satoshi <= DUST_VALUE {
+                return Err(Error::InvalidAmount);
+            }
+            add_addressee_output(&mut template, addressee, network.id())?;
         }
+        request.addressees = template_addressees(&template);
+        template_tx = Some(template);
 
         // Keep the previous transaction memo
         if request.memo.is_none() && !prev_txitem.memo.is_empty() {
             request.memo = Some(prev_txitem.memo.clone());
         }
     } else {
-        if request.addressees.is_empty() {
+        // issued assets are received by the wallet, so the transaction needs no other recipient
+        if request.addressees.is_empty() && !is_issuance {
             return Err(Error::EmptyAddressees);
         }
 
-        if !send_all && request.addressees.iter().any(|a| a.satoshi == 0) {
+        if !send_all && request.addressees.iter().any(|a| a.satoshi == 0 && a.data.is_none()) {
             return Err(Error::InvalidAmount);
         }
 
         if !send_all {
-            for address_amount in request.addressees.iter() {
+            for address_amount in request.addressees.iter().filter(|a| a.data.is_none()) {
                 if address_amount.satoshi <= DUST_VALUE {
                     match network.id() {
                         NetworkId::Bitcoin(_) => return Err(Error::InvalidAmount),
@@ -1293,10 +2674,20 @@ pub fn create_tx(
     }
 
     let id = network.id();
+    // Frozen utxos can only be spent by selecting them manually, the outputs of the transaction
+    // to bump and the inputs of the transaction to cancel are selected by the request
+    let skip_frozen =
+        !is_cpfp && !request.cancel_transaction && request.utxo_strategy != UtxoStrategy::Manual;
     let mut utxos: Vec<Txo> = vec![];
     for (_, outpoints) in request.utxos.iter() {
         for o in outpoints {
             let outpoint = o.outpoint(id)?;
+            if skip_frozen && store_read.is_frozen(&outpoint) {
+                continue;
+            }
+            if replaced_txid == Some(outpoint.txid()) {
+                continue;
+            }
             // TODO: check that the outpoint is not confirmed
             // TODO: check that outpoints are unique
             let utxo = account.txo(&outpoint, acc_store)?;
@@ -1329,7 +2720,7 @@ pub fn create_tx(
                 .add_output(&out.address, out.satoshi, out.asset_id(), network.id())
                 .map_err(|_| Error::InvalidAddress)?;
             // estimating 2 satoshi more as estimating less would later result in InsufficientFunds
-            let estimated_fee = dummy_tx.estimated_fee(fee_rate, 0, account.script_type) + 2;
+            let estimated_fee = dummy_tx.estimated_fee(fee_rate, 0, account.script_template()) + 2;
             total_amount_utxos.checked_sub(estimated_fee).ok_or_else(|| Error::InsufficientFunds)?
         } else {
             total_amount_utxos
@@ -1351,27 +2742,72 @@ pub fn create_tx(
         || -> Result<_, Error> {
             let mut new_tx = BETransaction::new(network.id());
             for out in request.addressees.iter() {
-                new_tx
-                    .add_output(&out.address, out.satoshi, out.asset_id(), network.id())
-                    .map_err(|_| Error::InvalidAddress)?;
+                add_addressee_output(&mut new_tx, out, network.id())?;
             }
             Ok(new_tx)
         },
         Ok,
     )?;
+    // more data outputs would not be relayed
+    if (0..tx.output_len() as u32).filter(|&vout| tx.output_data(vout).is_some()).count() > 1 {
+        return Err(Error::InvalidDataOutput);
+    }
+
+    // The issuance is attached to an input added before the coin selection, so that the issued
+    // amounts are accounted like the values of the inputs and received by the change outputs
+    let issuance = issuance_input(request, &utxos, &store_read, network.policy_asset_id().ok())?;
+    let issuance_outpoint = issuance.as_ref().map(|issuance| {
+        let outpoint = issuance.utxo.outpoint.clone();
+        tx.add_input(outpoint.clone());
+        tx.set_input_issuance(&outpoint.elements().expect("liquid utxo"), issuance.asset_issuance);
+        outpoint
+    });
+
+    // The excess of a changeless selection goes to the fee instead of a change output
+    let mut changeless = false;
 
     // STEP 2) add utxos until tx outputs are covered (including fees) or fail
     match request.utxo_strategy {
-        UtxoStrategy::Default => {
-            let mut used_utxo: HashSet<BEOutPoint> = HashSet::new();
+        UtxoStrategy::Manual => {
+            for utxo in utxos.iter().filter(|u| Some(&u.outpoint) != issuance_outpoint.as_ref()) {
+                tx.add_input(utxo.outpoint.clone());
+            }
+            let needs = tx.needs(
+                fee_rate,
+                send_all,
+                network.policy_asset_id().ok(),
+                &acc_store.all_txs,
+                &acc_store.unblinded,
+                account.script_template(),
+            );
+            if !needs.is_empty() {
+                return Err(Error::InsufficientFunds);
+            }
+        }
+        _ => {
+            let mut used_utxo: HashSet<BEOutPoint> = issuance_outpoint.into_iter().collect();
+            // send_all spends all the utxos of the asset, so it doesn't need a selection
+            let use_coin_selection = !send_all && request.utxo_strategy != UtxoStrategy::Default;
+            // sat/kbyte, the long term default is close to the historical average of the mempool
+            let default_long_term_fee_rate = match network.id() {
+                NetworkId::Bitcoin(_) => 10_000,
+                NetworkId::Elements(_) => 100,
+            };
+            let long_term_fee_rate =
+                request.long_term_fee_rate.unwrap_or(default_long_term_fee_rate) as f64 / 1000.0;
+            let (input_fee, change_fee) =
+                marginal_fees(network.id(), fee_rate, account.script_template());
+            let (long_term_input_fee, _) =
+                marginal_fees(network.id(), long_term_fee_rate, account.script_template());
+            let mut rng = rand::thread_rng();
             loop {
                 let mut needs = tx.needs(
                     fee_rate,
-                    send_all,
+                    send_all || changeless,
                     network.policy_asset_id().ok(),
                     &acc_store.all_txs,
                     &acc_store.unblinded,
-                    account.script_type,
+                    account.script_template(),
                 ); // "policy asset" is last, in bitcoin max 1 element
                 info!("needs: {:?}", needs);
                 if needs.is_empty() {
@@ -1388,6 +2824,66 @@ pub fn create_tx(
                     })
                     .collect();
 
+                if use_coin_selection {
+                    // the fee is paid by the policy asset, which for bitcoin is `None` as the asset
+                    let pays_fee = current_need.asset == network.policy_asset_id().ok();
+                    let target = if pays_fee {
+                        let amount = tx
+                            .needs(
+                                fee_rate,
+                                true,
+                                network.policy_asset_id().ok(),
+                                &acc_store.all_txs,
+                                &acc_store.unblinded,
+                                account.script_template(),
+                            )
+                            .into_iter()
+                            .find(|n| n.asset == current_need.asset)
+                            .map_or(0, |n| n.satoshi);
+                        SelectionTarget {
+                            amount,
+                            change_fee,
+                            // an excess above the dust would be turned into a change output
+                            cost_of_change: min(change_fee + long_term_input_fee, DUST_VALUE),
+                            min_change: DUST_VALUE + 1,
+                        }
+                    } else {
+                        SelectionTarget {
+                            amount: current_need.satoshi,
+                            ..Default::default()
+                        }
+                    };
+                    let groups = group_utxos(&asset_utxos, network.id());
+                    let candidates: Vec<Candidate> = groups
+                        .iter()
+                        .map(|group| Candidate {
+                            value: group.iter().map(|u| u.satoshi).sum(),
+                            fee: if pays_fee {
+                                input_fee * group.len() as u64
+                            } else {
+                                0
+                            },
+                            long_term_fee: if pays_fee {
+                                long_term_input_fee * group.len() as u64
+                            } else {
+                                0
+                            },
+                        })
+                        .collect();
+                    if let Some(selection) =
+                        select_coins(&request.utxo_strategy, &candidates, &target, &mut rng)
+                    {
+                        info!("coin selection: {:?}", selection);
+                        for utxo in selection.indexes.iter().flat_map(|&i| groups[i].iter()) {
+                            used_utxo.insert(utxo.outpoint.clone());
+                            tx.add_input(utxo.outpoint.clone());
+                        }
+                        changeless = pays_fee && selection.changeless;
+                        continue;
+                    }
+                    changeless = false;
+                }
+
                 // sort by biggest utxo, random maybe another option, but it should be deterministically random (purely random breaks send_all algorithm)
                 asset_utxos.sort_by(|a, b| a.satoshi.cmp(&b.satoshi));
                 let utxo = asset_utxos.pop().ok_or(Error::InsufficientFunds)?;
@@ -1414,29 +2910,13 @@ pub fn create_tx(
                 }
             }
         }
-        UtxoStrategy::Manual => {
-            for utxo in utxos.iter() {
-                tx.add_input(utxo.outpoint.clone());
-            }
-            let needs = tx.needs(
-                fee_rate,
-                send_all,
-                network.policy_asset_id().ok(),
-                &acc_store.all_txs,
-                &acc_store.unblinded,
-                account.script_type,
-            );
-            if !needs.is_empty() {
-                return Err(Error::InsufficientFunds);
-            }
-        }
     }
 
     // STEP 3) adding change(s)
     let estimated_fee = tx.estimated_fee(
         fee_rate,
-        tx.estimated_changes(send_all, &acc_store.all_txs, &acc_store.unblinded),
-        account.script_type,
+        tx.estimated_changes(send_all || changeless, &acc_store.all_txs, &acc_store.unblinded),
+        account.script_template(),
     );
     let changes = tx.changes(
         estimated_fee,
@@ -1459,6 +2939,41 @@ pub fn create_tx(
         tx.add_output(&change_address, change.satoshi, change.asset, network.id())?;
     }
 
+    let inputs: HashSet<BEOutPoint> = tx.previous_outputs().into_iter().collect();
+    for utxo in request.utxos.values().flatten() {
+        if let Some(sequence) = utxo.sequence {
+            let outpoint = utxo.outpoint(id)?;
+            if !inputs.contains(&outpoint) {
+                return Err(Error::InvalidTimelock(format!(
+                    "sequence of {}:{} not spent by the transaction",
+                    utxo.txid, utxo.vout
+                )));
+            }
+            // BIP68 relative timelocks are enforced only from transaction version 2
+            if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0 && tx.version() < 2 {
+                return Err(Error::InvalidTimelock(format!(
+                    "relative timelock with transaction version {}",
+                    tx.version()
+                )));
+            }
+            tx.set_input_sequence(&outpoint, sequence);
+        }
+    }
+    // replacements keep the locktime of the replaced transaction unless requested otherwise
+    if let Some(lock_time) = request.transaction_locktime {
+        // the locktime is enforced only if an input has a non final sequence
+        let all_final = tx
+            .previous_sequence_and_outpoints()
+            .iter()
+            .all(|(sequence, _)| *sequence == SEQUENCE_FINAL);
+        if lock_time != 0 && all_final {
+            return Err(Error::InvalidTimelock("locktime disabled by final sequences".into()));
+        }
+        tx.set_lock_time(lock_time);
+    } else if replaced_txid.is_none() {
+        tx.set_lock_time(anti_fee_sniping_lock_time(store_read.cache.tip_height()));
+    }
+
     // randomize inputs and outputs, BIP69 has been rejected because lacks wallets adoption
     tx.scramble();
 
@@ -1492,24 +3007,42 @@ pub fn create_tx(
     created_tx.used_utxos = used_utxos;
     created_tx.transaction_outputs = tx_outputs;
     created_tx.changes_used = Some(changes.len() as u32);
-    created_tx.addressees_read_only = request.previous_transaction.is_some();
+    if let Some(issuance) = issuance {
+        created_tx.issued_asset_id = Some(issuance.asset_id.to_hex());
+        created_tx.issued_token_id = issuance.token_id.map(|t| t.to_hex());
+    }
+    // Fee bumps can add recipients, but the outputs of cpfp and cancel transactions are fixed
+    created_tx.addressees_read_only = is_cpfp || request.cancel_transaction;
     info!("returning: {:?}", created_tx);
 
     Ok(created_tx)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn internal_sign_bitcoin(
     tx: &bitcoin::Transaction,
     input_index: usize,
-    xprv: &ExtendedPrivKey,
+    signer: &dyn Signer,
     path: &DerivationPath,
+    public_key: &PublicKey,
     value: u64,
+    prevouts: &[bitcoin::TxOut],
     script_type: ScriptType,
     sighash: &BESigHashType,
 ) -> Result<(bitcoin::Script, Vec<Vec<u8>>), Error> {
-    let xprv = xprv.derive_priv(&crate::EC, &path).unwrap();
-    let private_key = &xprv.to_priv();
-    let public_key = &PublicKey::from_private_key(&crate::EC, private_key);
+    if script_type.is_taproot() {
+        let sighash = sighash.into_bitcoin_schnorr()?;
+        let hash = SighashCache::new(tx).taproot_key_spend_signature_hash(
+            input_index,
+            &Prevouts::All(prevouts),
+            sighash,
+        )?;
+        let (internal_key, _) = public_key.inner.x_only_public_key();
+        let tweak = TapTweakHash::from_key_and_tweak(internal_key, None).to_scalar();
+        let message = Message::from_slice(&hash.into_inner()[..]).unwrap();
+        let signature = signer.sign_schnorr(path, &message, &tweak)?;
+        return Ok((bitcoin::Script::new(), vec![schnorr_witness(&signature, sighash as u8)]));
+    }
     let script_code = p2pkh_script(public_key);
 
     let sighash = sighash.into_bitcoin()?;
@@ -1520,26 +3053,46 @@ fn internal_sign_bitcoin(
     };
 
     let message = Message::from_slice(&hash.into_inner()[..]).unwrap();
-    let signature = crate::EC.sign_ecdsa(&message, &private_key.inner);
+    let signature = signer.sign_ecdsa(path, &message)?;
 
     let mut signature = signature.serialize_der().to_vec();
     signature.push(sighash as u8);
 
-    Ok(prepare_input(&public_key, signature, script_type))
+    Ok(prepare_input(public_key, signature, script_type))
 }
 
+#[allow(clippy::too_many_arguments)]
 fn internal_sign_elements(
     tx: &elements::Transaction,
     input_index: usize,
-    xprv: &ExtendedPrivKey,
+    signer: &dyn Signer,
     path: &DerivationPath,
+    public_key: &PublicKey,
     value: Value,
+    prevouts: &[elements::TxOut],
+    genesis_hash: Option<elements::BlockHash>,
     script_type: ScriptType,
     sighash: &BESigHashType,
 ) -> Result<(elements::Script, Vec<Vec<u8>>), Error> {
-    let xprv = xprv.derive_priv(&crate::EC, &path).unwrap();
-    let private_key = &xprv.to_priv();
-    let public_key = &PublicKey::from_private_key(&crate::EC, private_key);
+    if script_type.is_taproot() {
+        let genesis_hash = genesis_hash
+            .ok_or_else(|| Error::Generic("genesis block hash not synced yet".into()))?;
+        let sighash = sighash.into_elements_schnorr()?;
+        let hash = elements::sighash::SigHashCache::new(tx).taproot_key_spend_signature_hash(
+            input_index,
+            &elements::sighash::Prevouts::All(prevouts),
+            sighash,
+            genesis_hash,
+        )?;
+        // elements uses its own tagged hash for the taproot tweak
+        let (internal_key, _) = public_key.inner.x_only_public_key();
+        let tweak = elements::taproot::TapTweakHash::from_key_and_tweak(internal_key, None);
+        let tweak = secp256k1::Scalar::from_be_bytes(tweak.into_inner())
+            .map_err(|_| Error::Generic("invalid taproot tweak".into()))?;
+        let message = secp256k1::Message::from_slice(&hash[..]).unwrap();
+        let signature = signer.sign_schnorr(path, &message, &tweak)?;
+        return Ok((elements::Script::new(), vec![schnorr_witness(&signature, sighash as u8)]));
+    }
 
     let script_code = p2pkh_script(public_key).into_elements();
     let sighash = sighash.into_elements()?;
@@ -1554,14 +3107,24 @@ fn internal_sign_elements(
         elements::sighash::SigHashCache::new(tx).legacy_sighash(input_index, &script_code, sighash)
     };
     let message = secp256k1::Message::from_slice(&hash[..]).unwrap();
-    let signature = crate::EC.sign_ecdsa(&message, &private_key.inner);
+    let signature = signer.sign_ecdsa(path, &message)?;
     let mut signature = signature.serialize_der().to_vec();
     signature.push(sighash as u8);
 
-    let (script_sig, witness) = prepare_input(&public_key, signature, script_type);
+    let (script_sig, witness) = prepare_input(public_key, signature, script_type);
     Ok((script_sig.into_elements(), witness))
 }
 
+/// Schnorr signature serialized for the witness, the sighash byte is omitted for
+/// `SIGHASH_DEFAULT` (0x00)
+fn schnorr_witness(signature: &secp256k1::schnorr::Signature, sighash: u8) -> Vec<u8> {
+    let mut signature = signature.as_ref().to_vec();
+    if sighash != 0x00 {
+        signature.push(sighash);
+    }
+    signature
+}
+
 // Get the input's script sig and witness data
 fn prepare_input(
     public_key: &PublicKey,
@@ -1580,6 +3143,10 @@ fn prepare_input(
                 .into_script(),
             vec![],
         ),
+        ScriptType::P2tr => unreachable!("taproot inputs are signed with schnorr"),
+        ScriptType::P2wsh | ScriptType::P2shP2wsh => {
+            unreachable!("multisig inputs are signed with a psbt")
+        }
     }
 }
 
@@ -1587,6 +3154,7 @@ fn blind_tx(account: &Account, tx: &elements::Transaction) -> Result<elements::T
     info!("blind_tx {}", tx.txid());
     let is_already_blinded = tx.output.iter().all(|o| {
         o.is_fee()
+            || o.is_null_data()
             || (o.asset.is_confidential()
                 && o.value.is_confidential()
                 && o.nonce.is_confidential()
@@ -1599,7 +3167,7 @@ fn blind_tx(account: &Account, tx: &elements::Transaction) -> Result<elements::T
     let store_read = account.store.read()?;
     let acc_store = store_read.account_cache(account.num())?;
 
-    let mut pset = elements::pset::PartiallySignedTransaction::from_tx(tx.clone());
+    let mut pset = crate::pset::pset_from_tx(tx.clone());
     let mut inp_txout_sec: HashMap<usize, elements::TxOutSecrets> = HashMap::new();
 
     for (i, input) in pset.inputs_mut().iter_mut().enumerate() {
@@ -1646,20 +3214,28 @@ mod test {
         test_derivation(0, ScriptType::P2shP2wpkh, "m/49'/1'/0'");
         test_derivation(1, ScriptType::P2wpkh, "m/84'/1'/0'");
         test_derivation(2, ScriptType::P2pkh, "m/44'/1'/0'");
+        test_derivation(3, ScriptType::P2tr, "m/86'/1'/0'");
+
+        test_derivation(4, ScriptType::P2wsh, "m/48'/1'/0'/2'");
+        test_derivation(5, ScriptType::P2shP2wsh, "m/48'/1'/0'/1'");
 
         // reserved for future use, currently rejected
-        for n in 3..=15 {
+        for n in 6..=15 {
             test_derivation_fails(n);
         }
 
         test_derivation(16, ScriptType::P2shP2wpkh, "m/49'/1'/1'");
         test_derivation(17, ScriptType::P2wpkh, "m/84'/1'/1'");
         test_derivation(18, ScriptType::P2pkh, "m/44'/1'/1'");
-        test_derivation_fails(19);
+        test_derivation(19, ScriptType::P2tr, "m/86'/1'/1'");
+        test_derivation(20, ScriptType::P2wsh, "m/48'/1'/1'/2'");
+        test_derivation(21, ScriptType::P2shP2wsh, "m/48'/1'/1'/1'");
+        test_derivation_fails(22);
 
         test_derivation(160, ScriptType::P2shP2wpkh, "m/49'/1'/10'");
         test_derivation(161, ScriptType::P2wpkh, "m/84'/1'/10'");
         test_derivation(162, ScriptType::P2pkh, "m/44'/1'/10'");
+        test_derivation(163, ScriptType::P2tr, "m/86'/1'/10'");
     }
 
     #[test]
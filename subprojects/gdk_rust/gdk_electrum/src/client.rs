@@ -0,0 +1,133 @@
+use electrum_client::{Client, ElectrumApi, GetHistoryRes, GetMerkleRes};
+use gdk_common::bitcoin;
+use gdk_common::model::FeeEstimate;
+
+use crate::error::Error;
+use crate::filters::FiltersState;
+
+/// The blockchain data source a session syncs from.
+///
+/// Every backend exposes the same primitives the Electrum protocol offers, so that the syncer,
+/// the tipper and the headers thread are agnostic of the server they are talking to.
+pub trait BlockchainClient: Send {
+    /// Check the server is reachable.
+    fn ping(&self) -> Result<(), Error>;
+
+    /// Height and serialized header of the current best block.
+    fn tip(&self) -> Result<(u32, Vec<u8>), Error>;
+
+    /// For every script, the list of transactions involving it.
+    fn batch_script_get_history(
+        &self,
+        scripts: &[bitcoin::Script],
+    ) -> Result<Vec<Vec<GetHistoryRes>>, Error>;
+
+    /// Serialized transactions, in the same order as `txids`.
+    fn batch_transaction_get_raw(&self, txids: &[bitcoin::Txid]) -> Result<Vec<Vec<u8>>, Error>;
+
+    /// Serialized block headers, in the same order as `heights`.
+    fn batch_block_header_raw(&self, heights: Vec<u32>) -> Result<Vec<Vec<u8>>, Error>;
+
+    /// At most `count` consecutive bitcoin block headers starting at `start_height`.
+    fn block_headers(
+        &self,
+        start_height: usize,
+        count: usize,
+    ) -> Result<Vec<bitcoin::BlockHeader>, Error>;
+
+    /// Merkle inclusion proof of `txid`, confirmed at `height`, in the Electrum format.
+    fn transaction_get_merkle(
+        &self,
+        txid: &bitcoin::Txid,
+        height: usize,
+    ) -> Result<GetMerkleRes, Error>;
+
+    /// Broadcast a serialized transaction, returning its txid.
+    fn transaction_broadcast_raw(&self, raw_tx: &[u8]) -> Result<bitcoin::Txid, Error>;
+
+    /// The minimum relay fee followed by the estimates to confirm within 1 to 24 blocks, see
+    /// `ElectrumSession::get_fee_estimates`.
+    fn fee_estimates(&self) -> Result<Vec<FeeEstimate>, Error>;
+
+    /// Whether any transaction can be fetched, rather than only those involving the wallet.
+    fn indexes_all_transactions(&self) -> bool {
+        true
+    }
+
+    /// Tell whether the scripts looked up next might have received transactions before being
+    /// derived, as the ones of restored accounts. Backends that rescan the chain for new scripts
+    /// use it to skip needless rescans.
+    fn set_rescan(&self, _rescan: bool) {}
+
+    /// Whether looking up the history of scripts not seen before makes the backend rescan the
+    /// chain. Each lookup rescans once, so the syncer then looks up the scripts ahead in one batch.
+    fn rescans_new_scripts(&self) -> bool {
+        false
+    }
+
+    /// The state of backends matching the wallet scripts locally, to persist in the wallet cache
+    /// so that matching resumes where it stopped, see `FiltersConfig::restore_state`.
+    fn scan_state(&self) -> Result<Option<FiltersState>, Error> {
+        Ok(None)
+    }
+}
+
+impl BlockchainClient for Client {
+    fn ping(&self) -> Result<(), Error> {
+        Ok(ElectrumApi::ping(self)?)
+    }
+
+    fn tip(&self) -> Result<(u32, Vec<u8>), Error> {
+        let header = self.block_headers_subscribe_raw()?;
+        Ok((header.height as u32, header.header))
+    }
+
+    fn batch_script_get_history(
+        &self,
+        scripts: &[bitcoin::Script],
+    ) -> Result<Vec<Vec<GetHistoryRes>>, Error> {
+        Ok(ElectrumApi::batch_script_get_history(self, scripts.iter())?)
+    }
+
+    fn batch_transaction_get_raw(&self, txids: &[bitcoin::Txid]) -> Result<Vec<Vec<u8>>, Error> {
+        Ok(ElectrumApi::batch_transaction_get_raw(self, txids.iter())?)
+    }
+
+    fn batch_block_header_raw(&self, heights: Vec<u32>) -> Result<Vec<Vec<u8>>, Error> {
+        Ok(ElectrumApi::batch_block_header_raw(self, heights)?)
+    }
+
+    fn block_headers(
+        &self,
+        start_height: usize,
+        count: usize,
+    ) -> Result<Vec<bitcoin::BlockHeader>, Error> {
+        Ok(ElectrumApi::block_headers(self, start_height, count)?.headers)
+    }
+
+    fn transaction_get_merkle(
+        &self,
+        txid: &bitcoin::Txid,
+        height: usize,
+    ) -> Result<GetMerkleRes, Error> {
+        Ok(ElectrumApi::transaction_get_merkle(self, txid, height)?)
+    }
+
+    fn transaction_broadcast_raw(&self, raw_tx: &[u8]) -> Result<bitcoin::Txid, Error> {
+        Ok(ElectrumApi::transaction_broadcast_raw(self, raw_tx)?)
+    }
+
+    fn fee_estimates(&self) -> Result<Vec<FeeEstimate>, Error> {
+        let relay_fee = (self.relay_fee()? * 100_000_000.0) as u64;
+        let blocks: Vec<usize> = (1..25).collect();
+        // max is covering a rounding errors in production electrs which sometimes cause a fee
+        // estimates lower than relay fee
+        let mut estimates: Vec<FeeEstimate> = self
+            .batch_estimate_fee(blocks)?
+            .iter()
+            .map(|e| FeeEstimate(relay_fee.max((*e * 100_000_000.0) as u64)))
+            .collect();
+        estimates.insert(0, FeeEstimate(relay_fee));
+        Ok(estimates)
+    }
+}
@@ -0,0 +1,322 @@
+//! Coin selection algorithms used by the `UtxoStrategy` variants other than `Default` and
+//! `Manual`.
+//!
+//! The algorithms work on effective values, the value of a candidate minus the fee for spending
+//! it, and return the indexes of the selected candidates. They select the inputs for a single
+//! asset, the transaction is then completed by the `needs`/`changes` flow of `create_tx`.
+
+use std::cmp::Reverse;
+
+use gdk_common::model::UtxoStrategy;
+use gdk_common::rand::seq::SliceRandom;
+use gdk_common::rand::Rng;
+
+/// The maximum number of branches explored by `branch_and_bound`
+pub const BNB_MAX_TRIES: usize = 100_000;
+
+/// Outputs that are spent together
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub value: u64,
+
+    /// The fee for spending the candidate at the current fee rate
+    pub fee: u64,
+
+    /// The fee for spending the candidate at the long term fee rate
+    pub long_term_fee: u64,
+}
+
+impl Candidate {
+    pub fn effective_value(&self) -> u64 {
+        self.value.saturating_sub(self.fee)
+    }
+
+    /// Positive if spending the candidate now costs more than spending it in the long term
+    fn waste(&self) -> i64 {
+        self.fee as i64 - self.long_term_fee as i64
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SelectionTarget {
+    /// The effective value to select for a transaction without change
+    pub amount: u64,
+
+    /// The fee for adding a change output
+    pub change_fee: u64,
+
+    /// The cost of creating and later spending a change output, changeless selections can
+    /// exceed `amount` by at most this value
+    pub cost_of_change: u64,
+
+    /// The minimum value of a change output
+    pub min_change: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    pub indexes: Vec<usize>,
+
+    /// Whether the excess is left to the fee instead of going to a change output
+    pub changeless: bool,
+
+    /// The waste metric, lower is better
+    pub waste: i64,
+}
+
+impl Selection {
+    fn new(
+        candidates: &[Candidate],
+        indexes: Vec<usize>,
+        target: &SelectionTarget,
+        changeless: bool,
+    ) -> Self {
+        let inputs_waste: i64 = indexes.iter().map(|&i| candidates[i].waste()).sum();
+        let waste = if changeless {
+            let selected: u64 = indexes.iter().map(|&i| candidates[i].effective_value()).sum();
+            inputs_waste + selected.saturating_sub(target.amount) as i64
+        } else {
+            inputs_waste + target.cost_of_change as i64
+        };
+        Selection {
+            indexes,
+            changeless,
+            waste,
+        }
+    }
+}
+
+/// Select the candidates for the given strategy, `None` for the strategies that are not
+/// implemented here or if the candidates are not enough.
+///
+/// `BranchAndBound` falls back to `SingleRandomDraw` if there is no changeless solution, while
+/// `LowestWaste` picks the selection with the lowest waste among branch and bound, single random
+/// draw and largest first.
+pub fn select_coins<R: Rng + ?Sized>(
+    strategy: &UtxoStrategy,
+    candidates: &[Candidate],
+    target: &SelectionTarget,
+    rng: &mut R,
+) -> Option<Selection> {
+    match strategy {
+        UtxoStrategy::BranchAndBound => branch_and_bound(candidates, target)
+            .or_else(|| single_random_draw(candidates, target, rng)),
+        UtxoStrategy::SingleRandomDraw => single_random_draw(candidates, target, rng),
+        UtxoStrategy::LowestWaste => vec![
+            branch_and_bound(candidates, target),
+            single_random_draw(candidates, target, rng),
+            largest_first(candidates, target),
+        ]
+        .into_iter()
+        .flatten()
+        .min_by_key(|s| s.waste),
+        UtxoStrategy::Default | UtxoStrategy::Manual => None,
+    }
+}
+
+/// Depth first search for a changeless selection with effective value between `amount` and
+/// `amount + cost_of_change`, minimizing the waste.
+pub fn branch_and_bound(candidates: &[Candidate], target: &SelectionTarget) -> Option<Selection> {
+    let mut pool: Vec<usize> =
+        (0..candidates.len()).filter(|&i| candidates[i].effective_value() > 0).collect();
+    pool.sort_by_key(|&i| Reverse(candidates[i].effective_value()));
+    let value = |depth: usize| candidates[pool[depth]].effective_value();
+
+    let mut available: u64 = pool.iter().map(|&i| candidates[i].effective_value()).sum();
+    if pool.is_empty() || available < target.amount {
+        return None;
+    }
+    let upper_bound = target.amount + target.cost_of_change;
+    let is_fee_rate_high = candidates[pool[0]].waste() > 0;
+
+    // positions in `pool` of the selected candidates
+    let mut selected: Vec<usize> = vec![];
+    let mut selected_value = 0u64;
+    let mut selected_waste = 0i64;
+    let mut best: Option<(i64, Vec<usize>)> = None;
+    let mut depth = 0;
+
+    for _ in 0..BNB_MAX_TRIES {
+        let best_waste = best.as_ref().map_or(i64::MAX, |(w, _)| *w);
+        let mut backtrack = false;
+        if selected_value + available < target.amount
+            || selected_value > upper_bound
+            || (is_fee_rate_high && selected_waste > best_waste)
+        {
+            backtrack = true;
+        } else if selected_value >= target.amount {
+            let waste = selected_waste + (selected_value - target.amount) as i64;
+            if waste <= best_waste {
+                best = Some((waste, selected.clone()));
+            }
+            backtrack = true;
+        }
+
+        if backtrack {
+            let last = match selected.last() {
+                Some(last) => *last,
+                // every branch has been explored
+                None => break,
+            };
+            // put back the candidates after the last selected one and omit the latter
+            depth -= 1;
+            while depth > last {
+                available += value(depth);
+                depth -= 1;
+            }
+            selected.pop();
+            selected_value -= value(depth);
+            selected_waste -= candidates[pool[depth]].waste();
+        } else {
+            available -= value(depth);
+            // selecting a candidate equivalent to the previous, omitted, one would explore the
+            // same branch again
+            let is_duplicate = depth > 0
+                && !selected.is_empty()
+                && selected.last() != Some(&(depth - 1))
+                && candidates[pool[depth]] == candidates[pool[depth - 1]];
+            if !is_duplicate {
+                selected.push(depth);
+                selected_value += value(depth);
+                selected_waste += candidates[pool[depth]].waste();
+            }
+        }
+        depth += 1;
+    }
+
+    let (_, positions) = best.filter(|(_, positions)| !positions.is_empty())?;
+    let indexes = positions.into_iter().map(|p| pool[p]).collect();
+    Some(Selection::new(candidates, indexes, target, true))
+}
+
+/// Select random candidates until the amount, the change fee and the minimum change are covered
+pub fn single_random_draw<R: Rng + ?Sized>(
+    candidates: &[Candidate],
+    target: &SelectionTarget,
+    rng: &mut R,
+) -> Option<Selection> {
+    let mut order: Vec<usize> = (0..candidates.len()).collect();
+    order.shuffle(rng);
+    accumulate(candidates, order, target)
+}
+
+/// Select the largest candidates until the amount, the change fee and the minimum change are
+/// covered
+pub fn largest_first(candidates: &[Candidate], target: &SelectionTarget) -> Option<Selection> {
+    let mut order: Vec<usize> = (0..candidates.len()).collect();
+    order.sort_by_key(|&i| Reverse(candidates[i].effective_value()));
+    accumulate(candidates, order, target)
+}
+
+fn accumulate(
+    candidates: &[Candidate],
+    order: Vec<usize>,
+    target: &SelectionTarget,
+) -> Option<Selection> {
+    let goal = target.amount + target.change_fee + target.min_change;
+    let mut indexes = vec![];
+    let mut sum = 0u64;
+    for i in order.into_iter().filter(|&i| candidates[i].effective_value() > 0) {
+        indexes.push(i);
+        sum += candidates[i].effective_value();
+        if sum >= goal {
+            return Some(Selection::new(candidates, indexes, target, false));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::rand::thread_rng;
+
+    fn candidates(values: &[u64], fee: u64, long_term_fee: u64) -> Vec<Candidate> {
+        values
+            .iter()
+            .map(|&value| Candidate {
+                value,
+                fee,
+                long_term_fee,
+            })
+            .collect()
+    }
+
+    fn target(amount: u64) -> SelectionTarget {
+        SelectionTarget {
+            amount,
+            change_fee: 50,
+            cost_of_change: 100,
+            min_change: 547,
+        }
+    }
+
+    fn values(candidates: &[Candidate], selection: &Selection) -> Vec<u64> {
+        let mut values: Vec<u64> = selection.indexes.iter().map(|&i| candidates[i].value).collect();
+        values.sort_unstable();
+        values
+    }
+
+    #[test]
+    fn test_branch_and_bound() {
+        let c = candidates(&[1_000, 2_000, 3_000, 4_000, 50_000], 100, 50);
+
+        // 2_000 + 4_000, minus the fees, is an exact match
+        let selection = branch_and_bound(&c, &target(5_800)).unwrap();
+        assert!(selection.changeless);
+        assert_eq!(values(&c, &selection), vec![2_000, 4_000]);
+        assert_eq!(selection.waste, 100);
+
+        // The excess is within the cost of change
+        let selection = branch_and_bound(&c, &target(5_750)).unwrap();
+        assert_eq!(selection.waste, 150);
+
+        // No combination is within the window
+        assert!(branch_and_bound(&c, &target(40_000)).is_none());
+        assert!(branch_and_bound(&c, &target(100_000)).is_none());
+        assert!(branch_and_bound(&[], &target(1)).is_none());
+    }
+
+    #[test]
+    fn test_branch_and_bound_waste() {
+        // With a low fee rate spending more inputs is preferred
+        let c = candidates(&[3_000, 2_000, 1_050], 100, 200);
+        let selection = branch_and_bound(&c, &target(2_850)).unwrap();
+        assert_eq!(values(&c, &selection), vec![1_050, 2_000]);
+
+        // With a high fee rate spending fewer inputs is preferred
+        let c = candidates(&[3_000, 2_000, 1_050], 100, 10);
+        let selection = branch_and_bound(&c, &target(2_850)).unwrap();
+        assert_eq!(values(&c, &selection), vec![3_000]);
+    }
+
+    #[test]
+    fn test_single_random_draw() {
+        let c = candidates(&[1_000, 2_000, 3_000, 4_000], 100, 50);
+        let mut rng = thread_rng();
+        let selection = single_random_draw(&c, &target(5_000), &mut rng).unwrap();
+        assert!(!selection.changeless);
+        let selected: u64 = selection.indexes.iter().map(|&i| c[i].effective_value()).sum();
+        assert!(selected >= 5_000 + 50 + 547);
+        assert!(single_random_draw(&c, &target(9_100), &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_select_coins() {
+        let c = candidates(&[1_000, 2_000, 4_000, 50_000], 100, 50);
+        let mut rng = thread_rng();
+
+        let selection =
+            select_coins(&UtxoStrategy::LowestWaste, &c, &target(5_800), &mut rng).unwrap();
+        assert!(selection.changeless);
+        assert_eq!(values(&c, &selection), vec![2_000, 4_000]);
+
+        // Without a changeless solution, branch and bound falls back to single random draw
+        let selection =
+            select_coins(&UtxoStrategy::BranchAndBound, &c, &target(10_000), &mut rng).unwrap();
+        assert!(!selection.changeless);
+        assert!(selection.indexes.contains(&3));
+
+        assert!(select_coins(&UtxoStrategy::Default, &c, &target(1), &mut rng).is_none());
+    }
+}
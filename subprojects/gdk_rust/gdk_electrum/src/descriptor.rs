@@ -0,0 +1,389 @@
+//! Output descriptors of the subaccounts (BIP380), with checksums and key origins.
+//!
+//! Only the forms that correspond to our subaccounts are supported: `pkh(KEY)`, `sh(wpkh(KEY))`,
+//! `wpkh(KEY)`, `tr(KEY)`, `wsh(sortedmulti(k,KEY,...))` and `sh(wsh(sortedmulti(k,KEY,...)))`,
+//! where `KEY` is an extended public key followed by `/0/*` or `/1/*`, optionally preceded by its
+//! key origin.
+
+use std::fmt;
+use std::str::FromStr;
+
+use gdk_common::bitcoin::hashes::hex::FromHex;
+use gdk_common::bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPubKey, Fingerprint};
+use gdk_common::model::{CosignerXpub, MultisigParams};
+use gdk_common::scripts::ScriptType;
+use gdk_common::{bitcoin, NetworkParameters};
+
+use crate::account::{get_account_script_purpose, NUM_RESERVED_ACCOUNT_TYPES};
+use crate::error::Error;
+
+const INPUT_CHARSET: &str = "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn invalid(msg: impl Into<String>) -> Error {
+    Error::InvalidDescriptor(msg.into())
+}
+
+fn polymod(c: u64, val: u64) -> u64 {
+    let c0 = c >> 35;
+    let mut c = ((c & 0x7ffffffff) << 5) ^ val;
+    if c0 & 1 != 0 {
+        c ^= 0xf5dee51989;
+    }
+    if c0 & 2 != 0 {
+        c ^= 0xa9fdca3312;
+    }
+    if c0 & 4 != 0 {
+        c ^= 0x1bab10e32d;
+    }
+    if c0 & 8 != 0 {
+        c ^= 0x3706b1677a;
+    }
+    if c0 & 16 != 0 {
+        c ^= 0x644d626ffd;
+    }
+    c
+}
+
+/// Compute the BIP380 checksum of a descriptor without checksum
+pub fn checksum(desc: &str) -> Result<String, Error> {
+    let mut c = 1;
+    let mut cls = 0;
+    let mut clscount = 0;
+    for ch in desc.chars() {
+        let pos = INPUT_CHARSET
+            .find(ch)
+            .ok_or_else(|| invalid(format!("invalid character {:?} in descriptor", ch)))?
+            as u64;
+        c = polymod(c, pos & 31);
+        cls = cls * 3 + (pos >> 5);
+        clscount += 1;
+        if clscount == 3 {
+            c = polymod(c, cls);
+            cls = 0;
+            clscount = 0;
+        }
+    }
+    if clscount > 0 {
+        c = polymod(c, cls);
+    }
+    for _ in 0..8 {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+    Ok((0..8).map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char).collect())
+}
+
+/// Strip the checksum from a descriptor, verifying it if present
+pub fn strip_checksum(s: &str) -> Result<&str, Error> {
+    match s.split_once('#') {
+        Some((desc, given)) => {
+            if checksum(desc)? != given {
+                return Err(invalid(format!("invalid checksum {}", s)));
+            }
+            Ok(desc)
+        }
+        None => Ok(s),
+    }
+}
+
+/// A key expression, `[fingerprint/origin_path]xpub/chain/*`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescriptorKey {
+    pub origin: Option<(Fingerprint, DerivationPath)>,
+    pub xpub: ExtendedPubKey,
+    pub is_internal: bool,
+}
+
+impl DescriptorKey {
+    fn parse(s: &str, network: &NetworkParameters) -> Result<Self, Error> {
+        let (origin, key) = match s.strip_prefix('[') {
+            Some(rest) => {
+                let (origin, key) = rest
+                    .split_once(']')
+                    .ok_or_else(|| invalid(format!("invalid key origin {}", s)))?;
+                let (fingerprint, path) = match origin.split_once('/') {
+                    Some((fingerprint, path)) => (fingerprint, format!("m/{}", path)),
+                    None => (origin, "m".to_string()),
+                };
+                let fingerprint = Fingerprint::from_hex(fingerprint)
+                    .map_err(|_| invalid(format!("invalid key origin fingerprint {}", s)))?;
+                let path = DerivationPath::from_str(&path.replace('h', "'"))
+                    .map_err(|_| invalid(format!("invalid key origin path {}", s)))?;
+                (Some((fingerprint, path)), key)
+            }
+            None => (None, s),
+        };
+        let (key, is_internal) = match (key.strip_suffix("/0/*"), key.strip_suffix("/1/*")) {
+            (Some(key), _) => (key, false),
+            (_, Some(key)) => (key, true),
+            _ => return Err(invalid(format!("descriptor keys must derive /0/* or /1/* {}", s))),
+        };
+        let xpub = ExtendedPubKey::from_str(key)?;
+        if (xpub.network == bitcoin::Network::Bitcoin) != network.mainnet {
+            return Err(invalid(format!("extended public key for the wrong network {}", s)));
+        }
+        Ok(DescriptorKey {
+            origin,
+            xpub,
+            is_internal,
+        })
+    }
+}
+
+impl fmt::Display for DescriptorKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((fingerprint, path)) = &self.origin {
+            // the path is displayed as m/..., the origin omits the m
+            write!(f, "[{}{}]", fingerprint, &path.to_string()[1..])?;
+        }
+        write!(f, "{}/{}/*", self.xpub, self.is_internal as u32)
+    }
+}
+
+/// The descriptor of one of the chains of a subaccount
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountDescriptor {
+    pub script_type: ScriptType,
+    /// The number of signatures required, for multisig descriptors
+    pub threshold: Option<u32>,
+    pub keys: Vec<DescriptorKey>,
+}
+
+impl AccountDescriptor {
+    /// Parse a descriptor in one of the supported forms, the checksum is verified if present.
+    pub fn parse(s: &str, network: &NetworkParameters) -> Result<Self, Error> {
+        let desc = strip_checksum(s.trim())?;
+        let (script_type, inner) = [
+            ("sh(wsh(sortedmulti(", ")))", ScriptType::P2shP2wsh),
+            ("wsh(sortedmulti(", "))", ScriptType::P2wsh),
+            ("sh(wpkh(", "))", ScriptType::P2shP2wpkh),
+            ("wpkh(", ")", ScriptType::P2wpkh),
+            ("pkh(", ")", ScriptType::P2pkh),
+            ("tr(", ")", ScriptType::P2tr),
+        ]
+        .iter()
+        .find_map(|(prefix, suffix, script_type)| {
+            Some((*script_type, desc.strip_prefix(prefix)?.strip_suffix(suffix)?))
+        })
+        .ok_or_else(|| invalid(format!("unsupported descriptor {}", s)))?;
+
+        let (threshold, keys) = if script_type.is_multisig() {
+            let (threshold, keys) = inner
+                .split_once(',')
+                .ok_or_else(|| invalid(format!("sortedmulti without keys {}", s)))?;
+            let threshold = threshold
+                .parse()
+                .map_err(|_| invalid(format!("invalid sortedmulti threshold {}", s)))?;
+            (Some(threshold), keys.split(',').collect())
+        } else {
+            (None, vec![inner])
+        };
+        let keys = keys
+            .into_iter()
+            .map(|key| DescriptorKey::parse(key, network))
+            .collect::<Result<Vec<_>, _>>()?;
+        if keys.iter().any(|key| key.is_internal != keys[0].is_internal) {
+            return Err(invalid(format!("keys derive from different chains {}", s)));
+        }
+        Ok(AccountDescriptor {
+            script_type,
+            threshold,
+            keys,
+        })
+    }
+
+    /// The BIP32 account index of one of the keys.
+    ///
+    /// It is taken from the key origin if present, which must then follow the derivation of our
+    /// subaccounts of the same script type, or from the key itself otherwise.
+    pub fn bip32_account(&self, key: &DescriptorKey) -> Result<u32, Error> {
+        let account_child = match &key.origin {
+            Some((_, path)) if path.len() >= 3 => {
+                let (_, purpose) =
+                    get_account_script_purpose(self.script_type.first_account_num())?;
+                if path[0] != ChildNumber::from_hardened_idx(purpose)? {
+                    return Err(invalid(format!("unexpected purpose in key origin {}", key)));
+                }
+                path[2]
+            }
+            Some((_, path)) => *path
+                .into_iter()
+                .last()
+                .ok_or_else(|| invalid(format!("empty key origin {}", key)))?,
+            None => key.xpub.child_number,
+        };
+        match account_child {
+            ChildNumber::Hardened {
+                index,
+            } => Ok(index),
+            ChildNumber::Normal {
+                ..
+            } => Err(invalid(format!("account key must be hardened derived {}", key))),
+        }
+    }
+
+    /// The subaccount number of one of the keys
+    pub fn account_num(&self, key: &DescriptorKey) -> Result<u32, Error> {
+        let bip32_account = self.bip32_account(key)?;
+        bip32_account
+            .checked_mul(NUM_RESERVED_ACCOUNT_TYPES)
+            .and_then(|n| n.checked_add(self.script_type.first_account_num()))
+            .ok_or_else(|| invalid(format!("account index too large {}", bip32_account)))
+    }
+
+    /// The descriptor followed by its checksum
+    pub fn to_string_with_checksum(&self) -> String {
+        let desc = self.to_string();
+        let checksum = checksum(&desc).expect("descriptors we display have valid characters");
+        format!("{}#{}", desc, checksum)
+    }
+}
+
+impl fmt::Display for AccountDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let keys: Vec<String> = self.keys.iter().map(ToString::to_string).collect();
+        let keys = keys.join(",");
+        match (self.script_type, self.threshold) {
+            (ScriptType::P2shP2wpkh, _) => write!(f, "sh(wpkh({}))", keys),
+            (ScriptType::P2wpkh, _) => write!(f, "wpkh({})", keys),
+            (ScriptType::P2pkh, _) => write!(f, "pkh({})", keys),
+            (ScriptType::P2tr, _) => write!(f, "tr({})", keys),
+            (ScriptType::P2wsh, Some(k)) => write!(f, "wsh(sortedmulti({},{}))", k, keys),
+            (ScriptType::P2shP2wsh, Some(k)) => write!(f, "sh(wsh(sortedmulti({},{})))", k, keys),
+            (ScriptType::P2wsh, None) | (ScriptType::P2shP2wsh, None) => {
+                unreachable!("multisig descriptors have a threshold")
+            }
+        }
+    }
+}
+
+/// The subaccount to create to import a descriptor in the wallet with the given master
+/// fingerprint: its number, our account xpub and, for multisig, the other cosigners.
+///
+/// For multisig descriptors our key is the one whose origin has our master fingerprint.
+pub fn account_from_descriptor(
+    s: &str,
+    master_fingerprint: Fingerprint,
+    network: &NetworkParameters,
+) -> Result<(u32, ExtendedPubKey, Option<MultisigParams>), Error> {
+    let desc = AccountDescriptor::parse(s, network)?;
+    let is_ours = |key: &DescriptorKey| match &key.origin {
+        Some((fingerprint, _)) => *fingerprint == master_fingerprint,
+        None => false,
+    };
+    let ours = match desc.threshold {
+        Some(_) => desc.keys.iter().position(is_ours),
+        None if desc.keys[0].origin.is_none() || is_ours(&desc.keys[0]) => Some(0),
+        None => None,
+    }
+    .ok_or_else(|| invalid(format!("no key of this wallet in {}", s)))?;
+
+    let key = &desc.keys[ours];
+    let multisig = desc.threshold.map(|threshold| MultisigParams {
+        threshold,
+        cosigner_xpubs: desc
+            .keys
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != ours)
+            .map(|(_, key)| CosignerXpub {
+                origin: key.origin.clone(),
+                xpub: key.xpub,
+            })
+            .collect(),
+    });
+    Ok((desc.account_num(key)?, key.xpub, multisig))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mainnet() -> NetworkParameters {
+        let mut network = NetworkParameters::default();
+        network.mainnet = true;
+        network
+    }
+
+    // "abandon ... about" mnemonic, account 0 keys of BIP84 and BIP44
+    const ZPUB_XPUB: &str = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+    const XPUB: &str = "xpub6BosfCnifzxcFwrSzQiqu2DBVTshkCXacvNsWGYJVVhhawA7d4R5WSWGFNbi8Aw6ZRc1brxMyWMzG3DSSSSoekkudhUd9yLb6qx39T9nMdj";
+    const FINGERPRINT: &str = "73c5da0a";
+    // account 0 key of BIP48 p2wsh of another wallet
+    const XPUB2: &str = "xpub6F6kdziwgeYBty6BhQQxf73MxQDNPcKKApsvENDUEx3mBPumWC4GcBFi7gk8wE7r2V4gKRmC92UXPWPxz7HxP3U4n46xutvWWLsUAWswLWe";
+
+    #[test]
+    fn test_checksum() {
+        // Test vector from BIP380
+        let desc = "raw(deadbeef)";
+        assert_eq!(checksum(desc).unwrap(), "89f8spxm");
+        assert_eq!(strip_checksum("raw(deadbeef)#89f8spxm").unwrap(), desc);
+        assert_eq!(strip_checksum(desc).unwrap(), desc);
+        assert!(strip_checksum("raw(deedbeef)#89f8spxm").is_err());
+        assert!(checksum("raw(deadbeef)\u{e9}").is_err());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let network = mainnet();
+        let desc = format!("wpkh([{}/84'/0'/0']{}/0/*)", FINGERPRINT, ZPUB_XPUB);
+        let parsed = AccountDescriptor::parse(&desc, &network).unwrap();
+        assert_eq!(parsed.script_type, ScriptType::P2wpkh);
+        assert_eq!(parsed.to_string(), desc);
+        let with_checksum = parsed.to_string_with_checksum();
+        assert_eq!(AccountDescriptor::parse(&with_checksum, &network).unwrap(), parsed);
+        assert_eq!(parsed.account_num(&parsed.keys[0]).unwrap(), 1);
+
+        let desc = format!(
+            "sh(wsh(sortedmulti(1,{}/1/*,[{}/48'/0'/3'/1']{}/1/*)))",
+            XPUB, FINGERPRINT, ZPUB_XPUB
+        );
+        let parsed = AccountDescriptor::parse(&desc, &network).unwrap();
+        assert_eq!(parsed.script_type, ScriptType::P2shP2wsh);
+        assert_eq!(parsed.threshold, Some(1));
+        assert!(parsed.keys[0].is_internal);
+        assert_eq!(parsed.to_string(), desc);
+        assert_eq!(parsed.account_num(&parsed.keys[1]).unwrap(), 3 * 16 + 5);
+
+        // Mixed chains, unsupported scripts, wrong purposes
+        let desc = format!("wsh(sortedmulti(1,{}/1/*,{}/0/*))", XPUB, ZPUB_XPUB);
+        assert!(AccountDescriptor::parse(&desc, &network).is_err());
+        let desc = format!("wsh(multi(1,{}/1/*))", XPUB);
+        assert!(AccountDescriptor::parse(&desc, &network).is_err());
+        let desc = format!("pkh([{}/84'/0'/0']{}/0/*)", FINGERPRINT, ZPUB_XPUB);
+        let parsed = AccountDescriptor::parse(&desc, &network).unwrap();
+        assert!(parsed.account_num(&parsed.keys[0]).is_err());
+    }
+
+    #[test]
+    fn test_account_from_descriptor() {
+        let network = mainnet();
+        let fingerprint = Fingerprint::from_hex(FINGERPRINT).unwrap();
+        let desc = format!(
+            "wsh(sortedmulti(2,{}/0/*,[{}/48'/0'/0'/2']{}/0/*,[e2867bb6/48'/0'/0'/2']{}/0/*))",
+            XPUB, FINGERPRINT, ZPUB_XPUB, XPUB2
+        );
+        let (account_num, xpub, multisig) =
+            account_from_descriptor(&desc, fingerprint, &network).unwrap();
+        assert_eq!(account_num, 4);
+        assert_eq!(xpub.to_string(), ZPUB_XPUB);
+        let multisig = multisig.unwrap();
+        assert_eq!(multisig.threshold, 2);
+        assert_eq!(
+            multisig.cosigner_xpubs,
+            vec![
+                XPUB.parse::<CosignerXpub>().unwrap(),
+                format!("[e2867bb6/48'/0'/0'/2']{}", XPUB2).parse().unwrap()
+            ]
+        );
+
+        let other = Fingerprint::from_hex("00000000").unwrap();
+        assert!(account_from_descriptor(&desc, other, &network).is_err());
+
+        let desc = format!("pkh({}/0/*)", XPUB);
+        let (account_num, _, multisig) = account_from_descriptor(&desc, other, &network).unwrap();
+        assert_eq!(account_num, 2);
+        assert!(multisig.is_none());
+    }
+}
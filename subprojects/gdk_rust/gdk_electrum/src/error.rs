@@ -39,6 +39,9 @@ pub enum Error {
     #[error(transparent)]
     BitcoinHashes(#[from] bitcoin::hashes::error::Error),
 
+    #[error(transparent)]
+    BitcoinPsbt(#[from] bitcoin::util::psbt::Error),
+
     #[error(transparent)]
     BitcoinHexError(#[from] bitcoin::hashes::hex::Error),
 
@@ -60,6 +63,9 @@ pub enum Error {
     #[error(transparent)]
     ElementsPset(#[from] elements::pset::Error),
 
+    #[error(transparent)]
+    ElementsSighash(#[from] elements::sighash::Error),
+
     #[error("addressees cannot be empty")]
     EmptyAddressees,
 
@@ -102,9 +108,65 @@ pub enum Error {
     #[error("id_invalid_pin")]
     InvalidPin,
 
+    #[error("invalid descriptor: {0}")]
+    InvalidDescriptor(String),
+
+    #[error("invalid labels: {0}")]
+    InvalidLabels(String),
+
+    #[error("invalid gap limit {0}, must be between 1 and {1}")]
+    InvalidGapLimit(u32, u32),
+
+    #[error("invalid multisig: {0}")]
+    InvalidMultisig(String),
+
     #[error("invalid replacement request fields")]
     InvalidReplacementRequest,
 
+    #[error("invalid cpfp request, the transaction must be unconfirmed and have unspent outputs")]
+    InvalidCpfpRequest,
+
+    #[error(
+        "the transaction can not be replaced, the fee of its unconfirmed descendant {0} is unknown"
+    )]
+    UnknownDescendantFee(String),
+
+    #[error("invalid payment uri: {0}")]
+    InvalidPaymentUri(String),
+
+    #[error("unknown required payment uri parameter {0}")]
+    UnknownPaymentUriParameter(String),
+
+    #[error("payment uri with an amount must specify the asset id")]
+    MissingPaymentUriAssetId,
+
+    #[error("invalid payjoin endpoint {0}, it must be an https or onion url")]
+    InvalidPayjoinEndpoint(String),
+
+    #[error("invalid payjoin proposal: {0}")]
+    InvalidPayjoinProposal(String),
+
+    #[error("payjoin is only supported by bitcoin singlesig subaccounts not using taproot")]
+    UnsupportedPayjoin,
+
+    #[error("the psbt is missing some signatures")]
+    IncompletePsbt,
+
+    #[error("invalid psbt input spending {0}, its previous outputs are inconsistent")]
+    InvalidPsbtInput(String),
+
+    #[error("invalid data output, only one OP_RETURN output with up to 80 bytes of data is standard, only on liquid it can burn a value")]
+    InvalidDataOutput,
+
+    #[error("invalid issuance: {0}")]
+    InvalidIssuance(String),
+
+    #[error("invalid timelock: {0}")]
+    InvalidTimelock(String),
+
+    #[error("the wallet does not hold the reissuance token of asset {0}")]
+    ReissuanceTokenNotFound(String),
+
     #[error("invalid sighash")]
     InvalidSigHash,
 
@@ -114,6 +176,9 @@ pub enum Error {
     #[error(transparent)]
     InvalidStrUtf8(#[from] std::str::Utf8Error),
 
+    #[error("invalid watch-only credentials: {0}")]
+    InvalidWatchOnlyCredentials(String),
+
     #[error("invalid subaccount {0}")]
     InvalidSubaccount(u32),
 
@@ -132,12 +197,21 @@ pub enum Error {
     #[error("id_connection_failed")]
     PinError,
 
+    #[error("PSBT and Tx mismatch ({0} vs {1})")]
+    PsbtAndTxMismatch(bitcoin::Txid, bitcoin::Txid),
+
     #[error("PSET and Tx mismatch ({0} vs {1})")]
     PsetAndTxMismatch(Txid, Txid),
 
     #[error(transparent)]
     PsetBlindError(#[from] elements::pset::PsetBlindError),
 
+    #[error("rpc error {code}: {message}")]
+    Rpc {
+        code: i64,
+        message: String,
+    },
+
     #[error("RW lock is poisoned: {0}")]
     RwLockPoisonError(String),
 
@@ -159,6 +233,9 @@ pub enum Error {
     #[error(transparent)]
     SerdeCborError(#[from] serde_cbor::error::Error),
 
+    #[error("signer error: {0}")]
+    Signer(String),
+
     #[error(transparent)]
     SliceConversionError(#[from] std::array::TryFromSliceError),
 
@@ -267,6 +344,8 @@ impl Error {
             PinError => "id_connection_failed",
             InvalidPin => "id_invalid_pin",
             EmptyAddressees => "id_no_recipients",
+            UnknownPaymentUriParameter(_) => "id_unknown_bip21_parameter",
+            MissingPaymentUriAssetId => "id_invalid_payment_request_assetid",
             _ => "id_unknown",
         }
         .to_string()
@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::Mutex;
+use std::thread;
+
+use electrum_client::{GetHistoryRes, GetMerkleRes};
+use gdk_common::bitcoin::hashes::hex::{FromHex, ToHex};
+use gdk_common::bitcoin::hashes::{sha256, Hash};
+use gdk_common::log::trace;
+use gdk_common::model::FeeEstimate;
+use gdk_common::{bitcoin, network, ureq};
+use serde::Deserialize;
+
+use crate::client::BlockchainClient;
+use crate::error::Error;
+
+/// Number of confirmed transactions returned by a single `/scripthash/:hash/txs/chain` page.
+const CONFIRMED_TXS_PER_PAGE: usize = 25;
+
+/// Number of blocks returned by a single `/blocks/:start_height` page.
+const BLOCKS_PER_PAGE: u32 = 10;
+
+/// Maximum number of requests sent to the server at once.
+const MAX_CONCURRENT_REQUESTS: usize = 8;
+
+/// A client for the [Esplora HTTP API](https://github.com/Blockstream/esplora/blob/master/API.md)
+pub struct EsploraClient {
+    base_url: String,
+    agent: ureq::Agent,
+
+    /// Hashes of the blocks by height, cleared when the tip changes since a reorg may replace them
+    block_hashes: Mutex<(Option<String>, HashMap<u32, String>)>,
+}
+
+#[derive(Deserialize)]
+struct EsploraTx {
+    txid: bitcoin::Txid,
+    status: EsploraTxStatus,
+}
+
+#[derive(Deserialize)]
+struct EsploraTxStatus {
+    confirmed: bool,
+    block_height: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct EsploraBlock {
+    id: String,
+    height: u32,
+}
+
+impl EsploraClient {
+    /// Create a client for the Esplora instance at `url`, which is assumed to be plain http if it
+    /// has no scheme (e.g. `127.0.0.1:3000`).
+    pub fn new(url: &str, proxy: Option<&str>) -> Result<Self, Error> {
+        let url = url.trim_end_matches('/');
+        let base_url = if url.contains("://") {
+            url.to_string()
+        } else {
+            format!("http://{}", url)
+        };
+        Ok(EsploraClient {
+            base_url,
+            agent: network::build_request_agent(proxy)?,
+            block_hashes: Mutex::new((None, HashMap::new())),
+        })
+    }
+
+    /// Apply `f` to all the `items`, sending up to `MAX_CONCURRENT_REQUESTS` requests at once.
+    fn concurrent_map<T, R, F>(&self, items: &[T], f: F) -> Result<Vec<R>, Error>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&T) -> Result<R, Error> + Sync,
+    {
+        let f = &f;
+        let mut results = Vec::with_capacity(items.len());
+        for chunk in items.chunks(MAX_CONCURRENT_REQUESTS) {
+            let chunk_results: Vec<Result<R, Error>> = thread::scope(|s| {
+                let handles: Vec<_> = chunk.iter().map(|item| s.spawn(move || f(item))).collect();
+                handles.into_iter().map(|h| h.join().expect("esplora request panicked")).collect()
+            });
+            for result in chunk_results {
+                results.push(result?);
+            }
+        }
+        Ok(results)
+    }
+
+    fn get(&self, path: &str) -> Result<ureq::Response, Error> {
+        let url = format!("{}/{}", self.base_url, path);
+        trace!("esplora GET {}", url);
+        Ok(self.agent.get(&url).call()?)
+    }
+
+    fn get_string(&self, path: &str) -> Result<String, Error> {
+        Ok(self.get(path)?.into_string()?)
+    }
+
+    fn get_bytes(&self, path: &str) -> Result<Vec<u8>, Error> {
+        let mut bytes = vec![];
+        self.get(path)?.into_reader().read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn get_json<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
+        Ok(self.get(path)?.into_json()?)
+    }
+
+    /// The blocks at `height` and the `BLOCKS_PER_PAGE - 1` ones below it.
+    fn blocks_page(&self, height: u32) -> Result<Vec<EsploraBlock>, Error> {
+        match self.get_json(&format!("blocks/{}", height)) {
+            Ok(blocks) => Ok(blocks),
+            Err(Error::UreqError(ureq::Error::Status(404, _))) => Ok(vec![]),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Hashes of the blocks at `heights`, `None` for those above the tip.
+    ///
+    /// The hashes not cached are fetched a page of blocks at a time, the pages concurrently.
+    fn block_hashes(&self, heights: &[u32]) -> Result<Vec<Option<String>>, Error> {
+        // heights from which the blocks are known to be above the tip
+        let mut above_tip = u32::MAX;
+        loop {
+            let mut missing: Vec<u32> = {
+                let cache = self.block_hashes.lock().unwrap();
+                heights
+                    .iter()
+                    .filter(|h| **h < above_tip && !cache.1.contains_key(h))
+                    .cloned()
+                    .collect()
+            };
+            if missing.is_empty() {
+                break;
+            }
+            missing.sort_unstable_by(|a, b| b.cmp(a));
+            missing.dedup();
+
+            // each page covers its start height and the ones below it
+            let mut starts: Vec<u32> = vec![];
+            for height in missing {
+                match starts.last() {
+                    Some(start) if height + BLOCKS_PER_PAGE > *start => (),
+                    _ => starts.push(height),
+                }
+            }
+            let pages = self.concurrent_map(&starts, |start| self.blocks_page(*start))?;
+
+            let mut cache = self.block_hashes.lock().unwrap();
+            for (start, page) in starts.into_iter().zip(pages) {
+                // pages starting above the tip are empty or start from the tip
+                let first = page.first().map_or(start, |block| block.height + 1).min(start);
+                for block in page {
+                    cache.1.insert(block.height, block.id);
+                }
+                if !cache.1.contains_key(&start) {
+                    above_tip = above_tip.min(first);
+                }
+            }
+        }
+
+        let cache = self.block_hashes.lock().unwrap();
+        Ok(heights.iter().map(|h| cache.1.get(h).cloned()).collect())
+    }
+
+    fn block_header_raw(&self, hash: &str) -> Result<Vec<u8>, Error> {
+        Ok(Vec::<u8>::from_hex(&self.get_string(&format!("block/{}/header", hash))?)?)
+    }
+
+    fn script_get_history(&self, script: &bitcoin::Script) -> Result<Vec<GetHistoryRes>, Error> {
+        // Esplora identifies scripts by their sha256, without the byte reversal used by Electrum
+        let scripthash = sha256::Hash::hash(script.as_bytes()).to_hex();
+
+        // The first page contains all the mempool transactions and the first confirmed ones,
+        // the following confirmed transactions are paginated using the last txid seen.
+        let mut txs: Vec<EsploraTx> = self.get_json(&format!("scripthash/{}/txs", scripthash))?;
+        let mut last_page_confirmed = txs.iter().filter(|tx| tx.status.confirmed).count();
+        while last_page_confirmed >= CONFIRMED_TXS_PER_PAGE {
+            let last_txid = txs.last().expect("non empty").txid;
+            let page: Vec<EsploraTx> =
+                self.get_json(&format!("scripthash/{}/txs/chain/{}", scripthash, last_txid))?;
+            last_page_confirmed = page.len();
+            txs.extend(page);
+        }
+
+        Ok(txs
+            .into_iter()
+            .map(|tx| GetHistoryRes {
+                height: tx.status.block_height.filter(|_| tx.status.confirmed).unwrap_or(0) as i32,
+                tx_hash: tx.txid,
+                fee: None,
+            })
+            .collect())
+    }
+}
+
+impl BlockchainClient for EsploraClient {
+    fn ping(&self) -> Result<(), Error> {
+        self.get_string("blocks/tip/height")?;
+        Ok(())
+    }
+
+    fn tip(&self) -> Result<(u32, Vec<u8>), Error> {
+        // Query by hash so that height and header refer to the same block even if a new one
+        // arrives in between the calls
+        let hash = self.get_string("blocks/tip/hash")?;
+        let block: EsploraBlock = self.get_json(&format!("block/{}", hash))?;
+        let mut cache = self.block_hashes.lock().unwrap();
+        if cache.0.as_ref() != Some(&hash) {
+            *cache = (Some(hash.clone()), HashMap::new());
+        }
+        drop(cache);
+        Ok((block.height, self.block_header_raw(&hash)?))
+    }
+
+    fn batch_script_get_history(
+        &self,
+        scripts: &[bitcoin::Script],
+    ) -> Result<Vec<Vec<GetHistoryRes>>, Error> {
+        scripts.iter().map(|script| self.script_get_history(script)).collect()
+    }
+
+    fn batch_transaction_get_raw(&self, txids: &[bitcoin::Txid]) -> Result<Vec<Vec<u8>>, Error> {
+        txids.iter().map(|txid| self.get_bytes(&format!("tx/{}/raw", txid))).collect()
+    }
+
+    fn batch_block_header_raw(&self, heights: Vec<u32>) -> Result<Vec<Vec<u8>>, Error> {
+        let hashes = heights
+            .iter()
+            .zip(self.block_hashes(&heights)?)
+            .map(|(height, hash)| {
+                hash.ok_or_else(|| {
+                    Error::Generic(format!("esplora: block at height {} not found", height))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.concurrent_map(&hashes, |hash| self.block_header_raw(hash))
+    }
+
+    fn block_headers(
+        &self,
+        start_height: usize,
+        count: usize,
+    ) -> Result<Vec<bitcoin::BlockHeader>, Error> {
+        let heights: Vec<u32> = (start_height as u32..(start_height + count) as u32).collect();
+        // stop at the tip
+        let hashes: Vec<String> =
+            self.block_hashes(&heights)?.into_iter().map_while(|hash| hash).collect();
+        self.concurrent_map(&hashes, |hash| {
+            Ok(bitcoin::consensus::deserialize(&self.block_header_raw(hash)?)?)
+        })
+    }
+
+    fn transaction_get_merkle(
+        &self,
+        txid: &bitcoin::Txid,
+        _height: usize,
+    ) -> Result<GetMerkleRes, Error> {
+        // Esplora returns the proof in the same format as Electrum
+        self.get_json(&format!("tx/{}/merkle-proof", txid))
+    }
+
+    fn transaction_broadcast_raw(&self, raw_tx: &[u8]) -> Result<bitcoin::Txid, Error> {
+        let url = format!("{}/tx", self.base_url);
+        let txid = match self.agent.post(&url).send_string(&raw_tx.to_hex()) {
+            Ok(response) => response.into_string()?,
+            // The body contains the reason the node rejected the transaction
+            Err(ureq::Error::Status(_, response)) => {
+                return Err(Error::Generic(response.into_string()?))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(bitcoin::Txid::from_hex(txid.trim())?)
+    }
+
+    fn fee_estimates(&self) -> Result<Vec<FeeEstimate>, Error> {
+        // Estimates are in sat/vbyte, indexed by confirmation target
+        let estimates: HashMap<String, f64> = self.get_json("fee-estimates")?;
+        let estimates: HashMap<u16, u64> = estimates
+            .into_iter()
+            .filter_map(|(target, fee)| Some((target.parse().ok()?, (fee * 1000.0) as u64)))
+            .collect();
+
+        // Esplora does not expose the relay fee, the estimate for the farthest target is the
+        // closest approximation of it
+        let relay_fee = estimates
+            .iter()
+            .max_by_key(|(target, _)| **target)
+            .map(|(_, fee)| *fee)
+            .ok_or_else(|| Error::Generic("esplora: no fee estimates available".into()))?;
+
+        let mut result = vec![FeeEstimate(relay_fee)];
+        for target in 1..25 {
+            // Targets without an estimate get the one of the closest farther target available
+            let fee = estimates
+                .iter()
+                .filter(|(t, _)| **t >= target)
+                .min_by_key(|(t, _)| **t)
+                .map(|(_, fee)| *fee)
+                .unwrap_or(relay_fee);
+            result.push(FeeEstimate(relay_fee.max(fee)));
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,818 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufReader, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use electrum_client::{GetHistoryRes, GetMerkleRes};
+use gdk_common::bitcoin::consensus::{serialize, Decodable};
+use gdk_common::bitcoin::hash_types::{FilterHash, FilterHeader};
+use gdk_common::bitcoin::hashes::Hash;
+use gdk_common::bitcoin::network::constants::ServiceFlags;
+use gdk_common::bitcoin::network::message::{NetworkMessage, RawNetworkMessage};
+use gdk_common::bitcoin::network::message_blockdata::{GetHeadersMessage, Inventory};
+use gdk_common::bitcoin::network::message_filter::{CFHeaders, GetCFHeaders, GetCFilters};
+use gdk_common::bitcoin::network::message_network::VersionMessage;
+use gdk_common::bitcoin::network::Address;
+use gdk_common::bitcoin::util::bip158::BlockFilter;
+use gdk_common::bitcoin::{Block, BlockHash, Network, OutPoint, Script, Transaction, Txid};
+use gdk_common::log::{info, trace, warn};
+use gdk_common::model::FeeEstimate;
+use gdk_common::network::NETWORK_REQUEST_TIMEOUT;
+use gdk_common::rand;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::client::BlockchainClient;
+use crate::error::Error;
+use crate::headers::bitcoin::HeadersChain;
+use crate::headers::merkle_branch;
+
+/// Maximum number of headers returned by a `getheaders` request.
+const MAX_HEADERS: usize = 2000;
+
+/// Maximum number of filters returned by a `getcfilters` request.
+const MAX_FILTERS: u32 = 1000;
+
+/// BIP158 basic filter type, the only one defined.
+const BASIC_FILTER: u8 = 0;
+
+/// Protocol version supporting BIP157 messages.
+const PROTOCOL_VERSION: u32 = 70016;
+
+/// Guards the chains of headers of the compact filters peers, shared by all the sessions.
+static CHAIN_MUTEX: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// Connection details of a node serving BIP157 compact block filters.
+#[derive(Clone, Debug)]
+pub struct FiltersConfig {
+    /// `host:port` of the peer
+    pub peer: String,
+
+    /// `host:port` of other peers the filter headers served by `peer` are checked against
+    pub check_peers: Vec<String>,
+
+    pub network: Network,
+
+    /// Directory where the chain of headers is persisted
+    pub state_dir: PathBuf,
+
+    /// Height of the first block that could contain wallet transactions
+    pub start_height: u32,
+
+    /// Scan state of the wallet, shared by the clients of the session
+    pub state: Arc<Mutex<FiltersState>>,
+}
+
+impl FiltersConfig {
+    /// Restore the scan state persisted in the wallet cache, unless the session has one already.
+    ///
+    /// The state contains the scripts of the wallet and its transactions, so it's persisted
+    /// encrypted in the wallet cache rather than next to the headers.
+    pub fn restore_state(&self, state: FiltersState) -> Result<(), Error> {
+        let mut current = self.state.lock()?;
+        if current.next_heights.is_empty() {
+            *current = state;
+        }
+        Ok(())
+    }
+}
+
+/// What has been found matching the filters against the wallet scripts.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FiltersState {
+    /// For every tracked script, the height of the first block whose filter is not matched yet
+    next_heights: HashMap<Script, u32>,
+
+    /// Confirmed transactions involving each script, with their heights
+    history: HashMap<Script, HashMap<Txid, u32>>,
+
+    /// Outputs paying to tracked scripts, to recognize the transactions spending them
+    outpoints: HashMap<OutPoint, Script>,
+
+    /// Transactions involving tracked scripts
+    txs: HashMap<Txid, Transaction>,
+
+    /// Transactions broadcasted through the peer and not yet seen in a block, or confirmed in a
+    /// block reorged away
+    unconfirmed: HashSet<Txid>,
+
+    /// Hashes of the blocks containing transactions involving tracked scripts
+    block_hashes: HashMap<u32, BlockHash>,
+
+    /// Height, hash and filter header of the highest block whose filter has been matched
+    scanned_tip: Option<(u32, BlockHash, FilterHeader)>,
+}
+
+impl FiltersState {
+    /// Record the transactions of `block` involving tracked scripts.
+    ///
+    /// Processing the same block again is a no-op.
+    fn process_block(&mut self, height: u32, block: &Block) {
+        for tx in block.txdata.iter() {
+            let txid = tx.txid();
+            let mut scripts = vec![];
+            for input in tx.input.iter() {
+                if let Some(script) = self.outpoints.get(&input.previous_output) {
+                    scripts.push(script.clone());
+                }
+            }
+            for (vout, output) in tx.output.iter().enumerate() {
+                if self.next_heights.contains_key(&output.script_pubkey) {
+                    let outpoint = OutPoint::new(txid, vout as u32);
+                    self.outpoints.insert(outpoint, output.script_pubkey.clone());
+                    scripts.push(output.script_pubkey.clone());
+                }
+            }
+            if scripts.is_empty() {
+                continue;
+            }
+            info!("tx {} at height {} matches wallet scripts", txid, height);
+            self.block_hashes.insert(height, block.block_hash());
+            for script in scripts {
+                self.history.entry(script).or_default().insert(txid, height);
+            }
+            self.unconfirmed.remove(&txid);
+            self.txs.insert(txid, tx.clone());
+        }
+    }
+
+    /// Record a transaction broadcasted through the peer, so that it's part of the history of the
+    /// involved scripts until it's confirmed.
+    fn add_unconfirmed(&mut self, tx: Transaction) {
+        let txid = tx.txid();
+        for (vout, output) in tx.output.iter().enumerate() {
+            if self.next_heights.contains_key(&output.script_pubkey) {
+                self.outpoints
+                    .insert(OutPoint::new(txid, vout as u32), output.script_pubkey.clone());
+            }
+        }
+        self.unconfirmed.insert(txid);
+        self.txs.insert(txid, tx);
+    }
+
+    /// Forget what has been found in blocks above `height`, which are not in the best chain anymore.
+    ///
+    /// The transactions of those blocks spending wallet outputs are unconfirmed again, as the ones
+    /// broadcasted. The others are forgotten, since the peer mempool is not queried they would
+    /// stay unconfirmed even if double spent; if confirmed again they are found in their new block.
+    fn rollback(&mut self, height: u32) {
+        let mut orphaned = HashSet::new();
+        for txs in self.history.values_mut() {
+            txs.retain(|txid, h| {
+                if *h > height {
+                    orphaned.insert(*txid);
+                }
+                *h <= height
+            });
+        }
+        for txid in orphaned.iter() {
+            let spends_wallet =
+                self.txs.get(txid).into_iter().flat_map(|tx| tx.input.iter()).any(|input| {
+                    self.outpoints.contains_key(&input.previous_output)
+                        && !orphaned.contains(&input.previous_output.txid)
+                });
+            if spends_wallet {
+                self.unconfirmed.insert(*txid);
+            } else {
+                self.txs.remove(txid);
+            }
+        }
+        let unconfirmed = &self.unconfirmed;
+        self.outpoints.retain(|outpoint, _| {
+            !orphaned.contains(&outpoint.txid) || unconfirmed.contains(&outpoint.txid)
+        });
+        self.block_hashes.retain(|h, _| *h <= height);
+        for next_height in self.next_heights.values_mut() {
+            *next_height = (*next_height).min(height + 1);
+        }
+        if matches!(self.scanned_tip, Some((tip, _, _)) if tip > height) {
+            self.scanned_tip = None;
+        }
+    }
+
+    /// The height of the fork between the blocks matched and `chain`, if some of them are not in
+    /// the chain anymore, as after a reorg happened while the state was not in use.
+    ///
+    /// The fork is located at the highest block matched still in the chain, below the lowest one
+    /// not in it, or below `start_height` if there is none.
+    fn fork_height(&self, chain: &HeadersChain, start_height: u32) -> Result<Option<u32>, Error> {
+        let mut matched: Vec<(u32, BlockHash)> =
+            self.block_hashes.iter().map(|(height, hash)| (*height, *hash)).collect();
+        matched.extend(self.scanned_tip.map(|(height, hash, _)| (height, hash)));
+        matched.sort();
+
+        let mut fork_height = start_height.saturating_sub(1);
+        for (height, hash) in matched {
+            if height > chain.height() || chain.get(height)?.block_hash() != hash {
+                return Ok(Some(fork_height));
+            }
+            fork_height = height;
+        }
+        Ok(None)
+    }
+
+    fn script_history(&self, script: &Script) -> Vec<GetHistoryRes> {
+        let mut history: Vec<GetHistoryRes> = self
+            .history
+            .get(script)
+            .into_iter()
+            .flatten()
+            .map(|(txid, height)| GetHistoryRes {
+                height: *height as i32,
+                tx_hash: *txid,
+                fee: None,
+            })
+            .collect();
+        history.sort_by_key(|h| h.height);
+
+        for txid in self.unconfirmed.iter() {
+            let tx = &self.txs[txid];
+            let spends = tx
+                .input
+                .iter()
+                .any(|input| self.outpoints.get(&input.previous_output) == Some(script));
+            let receives = tx.output.iter().any(|output| &output.script_pubkey == script);
+            if spends || receives {
+                history.push(GetHistoryRes {
+                    height: 0,
+                    tx_hash: *txid,
+                    fee: None,
+                });
+            }
+        }
+        history
+    }
+}
+
+/// A light client using the P2P protocol to download headers and BIP158 compact block filters
+/// from a single peer, for instance a bitcoind with `-blockfilterindex -peerblockfilters`.
+///
+/// Filters are matched locally against the wallet scripts and only the matching blocks are
+/// downloaded, so the peer does not learn which scripts belong to the wallet. Since the peer
+/// mempool is not queried, incoming transactions show up once confirmed, while outgoing ones are
+/// known since their broadcast.
+///
+/// Filter headers are not committed in blocks, so a peer could serve filters omitting the wallet
+/// transactions along with their headers. The filter headers are thus checked against the ones
+/// served by the `check_peers`, if any.
+pub struct FiltersClient {
+    config: FiltersConfig,
+    reader: RefCell<BufReader<TcpStream>>,
+    writer: TcpStream,
+    timeout: Option<u8>,
+
+    /// Height of the best block of the peer when connected
+    peer_height: Cell<u32>,
+
+    /// Whether the chain of headers has been synced with the peer by this client
+    headers_synced: Cell<bool>,
+
+    /// Connections to the `check_peers`, opened when filter headers are first checked
+    check_clients: RefCell<Option<Vec<FiltersClient>>>,
+}
+
+impl FiltersClient {
+    /// Connect to the peer and perform the version handshake.
+    pub fn new(
+        config: &FiltersConfig,
+        proxy: Option<&str>,
+        timeout: Option<u8>,
+    ) -> Result<Self, Error> {
+        if proxy.filter(|p| !p.trim().is_empty()).is_some() {
+            return Err(Error::Generic("compact filters backend does not support proxies".into()));
+        }
+        let stream_timeout =
+            timeout.map(|t| Duration::from_secs(t as u64)).unwrap_or(NETWORK_REQUEST_TIMEOUT);
+        let addr: SocketAddr = config
+            .peer
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::AddrParse(config.peer.clone()))?;
+        let stream = TcpStream::connect_timeout(&addr, stream_timeout)?;
+        stream.set_read_timeout(Some(stream_timeout))?;
+        stream.set_write_timeout(Some(stream_timeout))?;
+
+        let client = FiltersClient {
+            config: config.clone(),
+            reader: RefCell::new(BufReader::new(stream.try_clone()?)),
+            writer: stream,
+            timeout,
+            peer_height: Cell::new(0),
+            headers_synced: Cell::new(false),
+            check_clients: RefCell::new(None),
+        };
+        client.handshake(addr)?;
+        Ok(client)
+    }
+
+    fn handshake(&self, addr: SocketAddr) -> Result<(), Error> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let local = Address::new(&self.writer.local_addr()?, ServiceFlags::NONE);
+        let mut version = VersionMessage::new(
+            ServiceFlags::NONE,
+            timestamp as i64,
+            Address::new(&addr, ServiceFlags::NONE),
+            local,
+            rand::random(),
+            "/gdk:0.1.0/".into(),
+            0,
+        );
+        version.version = PROTOCOL_VERSION;
+        // we are not interested in the peer mempool
+        version.relay = false;
+        self.send(NetworkMessage::Version(version))?;
+
+        let (services, start_height) = self.wait_for(|msg| match msg {
+            NetworkMessage::Version(version) => Some((version.services, version.start_height)),
+            _ => None,
+        })?;
+        self.peer_height.set(start_height.max(0) as u32);
+        if !services.has(ServiceFlags::COMPACT_FILTERS) {
+            return Err(Error::Generic(format!(
+                "peer {} does not serve compact block filters",
+                self.config.peer
+            )));
+        }
+        self.send(NetworkMessage::Verack)?;
+        self.wait_for(|msg| match msg {
+            NetworkMessage::Verack => Some(()),
+            _ => None,
+        })?;
+        info!("connected to compact filters peer {}", self.config.peer);
+        Ok(())
+    }
+
+    fn send(&self, payload: NetworkMessage) -> Result<(), Error> {
+        trace!("p2p send {}", payload.cmd());
+        let message = RawNetworkMessage {
+            magic: self.config.network.magic(),
+            payload,
+        };
+        (&self.writer).write_all(&serialize(&message))?;
+        Ok(())
+    }
+
+    /// Wait for the first message accepted by `f`, answering pings and ignoring other messages.
+    fn wait_for<T, F>(&self, mut f: F) -> Result<T, Error>
+    where
+        F: FnMut(NetworkMessage) -> Option<T>,
+    {
+        loop {
+            let message = RawNetworkMessage::consensus_decode(&mut *self.reader.borrow_mut())?;
+            if message.magic != self.config.network.magic() {
+                return Err(Error::Generic("p2p message for another network".into()));
+            }
+            trace!("p2p recv {}", message.cmd());
+            match message.payload {
+                NetworkMessage::Ping(nonce) => self.send(NetworkMessage::Pong(nonce))?,
+                payload => {
+                    if let Some(result) = f(payload) {
+                        return Ok(result);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The peer processes messages in order, thus once the pong is received the previous
+    /// messages have been handled.
+    fn ping_pong(&self) -> Result<(), Error> {
+        let nonce: u64 = rand::random();
+        self.send(NetworkMessage::Ping(nonce))?;
+        self.wait_for(|msg| match msg {
+            NetworkMessage::Pong(n) if n == nonce => Some(()),
+            _ => None,
+        })
+    }
+
+    fn chain(&self) -> Result<HeadersChain, Error> {
+        let mut path = self.config.state_dir.clone();
+        path.push("compact_filters");
+        HeadersChain::new(path, self.config.network)
+    }
+
+    /// Lock the chain of headers and the scan state of the wallet.
+    fn with_state<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut FiltersState, &mut HeadersChain) -> Result<T, Error>,
+    {
+        let _chain_lock = CHAIN_MUTEX.lock()?;
+        let mut state = self.config.state.lock()?;
+        let mut chain = self.chain()?;
+        if !self.headers_synced.get() {
+            self.sync_headers(&mut state, &mut chain)?;
+            self.headers_synced.set(true);
+        }
+        // a state restored from the wallet cache, or synced by another session, may have been
+        // matched on blocks reorged meanwhile
+        if let Some(fork_height) = state.fork_height(&chain, self.config.start_height)? {
+            warn!("blocks matched not in the best chain, rolling back to height {}", fork_height);
+            state.rollback(fork_height);
+        }
+        f(&mut state, &mut chain)
+    }
+
+    /// Download the headers the peer has beyond our tip, handling reorgs.
+    fn sync_headers(
+        &self,
+        state: &mut FiltersState,
+        chain: &mut HeadersChain,
+    ) -> Result<(), Error> {
+        loop {
+            let locator = locator(chain)?;
+            let hashes = locator.iter().map(|(_, hash)| *hash).collect();
+            self.send(NetworkMessage::GetHeaders(GetHeadersMessage::new(
+                hashes,
+                BlockHash::all_zeros(),
+            )))?;
+            let headers = self.wait_for(|msg| match msg {
+                NetworkMessage::Headers(headers) => Some(headers),
+                _ => None,
+            })?;
+            let count = headers.len();
+            if let Some(first) = headers.first() {
+                let fork_height = locator
+                    .iter()
+                    .find(|(_, hash)| *hash == first.prev_blockhash)
+                    .map(|(height, _)| *height)
+                    .ok_or(Error::InvalidHeaders)?;
+                if fork_height < chain.height() {
+                    warn!("reorg, removing headers above height {}", fork_height);
+                    chain.remove(chain.height() - fork_height)?;
+                    state.rollback(fork_height);
+                }
+                chain.push(headers)?;
+            }
+            if count < MAX_HEADERS {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Match the filters of the blocks not yet scanned for some tracked script, downloading the
+    /// blocks that match.
+    fn scan(&self, state: &mut FiltersState, chain: &HeadersChain) -> Result<(), Error> {
+        let tip = chain.height();
+        let mut start = match state.next_heights.values().min() {
+            Some(start) => *start,
+            None => return Ok(()),
+        };
+        let mut previous_filter_header: Option<FilterHeader> = None;
+        while start <= tip {
+            let stop = tip.min(start + MAX_FILTERS - 1);
+            let scripts: Vec<Script> = state
+                .next_heights
+                .iter()
+                .filter(|(_, next_height)| **next_height <= stop)
+                .map(|(script, _)| script.clone())
+                .collect();
+            info!("matching {} scripts against filters {}..={}", scripts.len(), start, stop);
+
+            let block_hashes: Vec<BlockHash> = (start..=stop)
+                .map(|h| Ok(chain.get(h)?.block_hash()))
+                .collect::<Result<_, Error>>()?;
+            let stop_hash = *block_hashes.last().expect("start <= stop");
+
+            let cf_headers = self.filter_hashes(start, stop_hash)?;
+            if cf_headers.filter_hashes.len() != block_hashes.len() {
+                return Err(Error::Generic("unexpected number of filter hashes".into()));
+            }
+            self.check_filter_hashes(start, stop, &cf_headers)?;
+            // filter headers of consecutive ranges must be linked
+            if let Some(previous) = previous_filter_header {
+                if previous != cf_headers.previous_filter_header {
+                    return Err(Error::Generic("filter headers are not linked".into()));
+                }
+            }
+            let mut filter_headers = Vec::with_capacity(cf_headers.filter_hashes.len());
+            let mut filter_header = cf_headers.previous_filter_header;
+            for filter_hash in cf_headers.filter_hashes.iter() {
+                filter_header = filter_hash.filter_header(&filter_header);
+                filter_headers.push(filter_header);
+            }
+            previous_filter_header = Some(filter_header);
+            // and the same as the ones matched before
+            if let Some((height, _, scanned)) = state.scanned_tip {
+                if (start..=stop).contains(&height)
+                    && filter_headers[(height - start) as usize] != scanned
+                {
+                    return Err(Error::Generic(
+                        "filter headers differ from the ones matched before".into(),
+                    ));
+                }
+            }
+
+            self.send(NetworkMessage::GetCFilters(GetCFilters {
+                filter_type: BASIC_FILTER,
+                start_height: start,
+                stop_hash,
+            }))?;
+            let mut matching = vec![];
+            for (i, block_hash) in block_hashes.iter().enumerate() {
+                let cf_filter = self.wait_for(|msg| match msg {
+                    NetworkMessage::CFilter(cf_filter) => Some(cf_filter),
+                    _ => None,
+                })?;
+                if cf_filter.block_hash != *block_hash
+                    || FilterHash::hash(&cf_filter.filter) != cf_headers.filter_hashes[i]
+                {
+                    return Err(Error::Generic("filter does not match its header".into()));
+                }
+                let filter = BlockFilter::new(&cf_filter.filter);
+                let mut query = scripts.iter().map(|s| s.as_bytes());
+                if filter
+                    .match_any(block_hash, &mut query)
+                    .map_err(|e| Error::Generic(format!("invalid filter: {}", e)))?
+                {
+                    matching.push((start + i as u32, *block_hash));
+                }
+            }
+
+            for (height, block_hash) in matching {
+                let block = self.get_block(block_hash)?;
+                state.process_block(height, &block);
+            }
+            for script in scripts {
+                state.next_heights.insert(script, stop + 1);
+            }
+            match state.scanned_tip {
+                Some((height, _, _)) if height > stop => (),
+                _ => state.scanned_tip = Some((stop, stop_hash, filter_header)),
+            }
+            start = stop + 1;
+        }
+        Ok(())
+    }
+
+    fn filter_hashes(&self, start_height: u32, stop_hash: BlockHash) -> Result<CFHeaders, Error> {
+        self.send(NetworkMessage::GetCFHeaders(GetCFHeaders {
+            filter_type: BASIC_FILTER,
+            start_height,
+            stop_hash,
+        }))?;
+        self.wait_for(|msg| match msg {
+            NetworkMessage::CFHeaders(cf_headers) if cf_headers.stop_hash == stop_hash => {
+                Some(cf_headers)
+            }
+            _ => None,
+        })
+    }
+
+    /// Check that the `check_peers` serve the same filter hashes as the peer, those lagging
+    /// behind `stop_height` are skipped.
+    fn check_filter_hashes(
+        &self,
+        start_height: u32,
+        stop_height: u32,
+        cf_headers: &CFHeaders,
+    ) -> Result<(), Error> {
+        let mut check_clients = self.check_clients.borrow_mut();
+        if check_clients.is_none() {
+            let mut clients = vec![];
+            for peer in self.config.check_peers.iter() {
+                let config = FiltersConfig {
+                    peer: peer.clone(),
+                    check_peers: vec![],
+                    ..self.config.clone()
+                };
+                clients.push(FiltersClient::new(&config, None, self.timeout)?);
+            }
+            *check_clients = Some(clients);
+        }
+
+        for client in check_clients.iter().flatten() {
+            if client.peer_height.get() < stop_height {
+                info!("peer {} is behind height {}, not checked", client.config.peer, stop_height);
+                continue;
+            }
+            let other = client.filter_hashes(start_height, cf_headers.stop_hash)?;
+            if other.previous_filter_header != cf_headers.previous_filter_header
+                || other.filter_hashes != cf_headers.filter_hashes
+            {
+                return Err(Error::Generic(format!(
+                    "filter headers from {} differ from the ones from {}",
+                    client.config.peer, self.config.peer
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn get_block(&self, block_hash: BlockHash) -> Result<Block, Error> {
+        self.send(NetworkMessage::GetData(vec![Inventory::WitnessBlock(block_hash)]))?;
+        let block = self.wait_for(|msg| match msg {
+            NetworkMessage::Block(block) if block.block_hash() == block_hash => Some(Ok(block)),
+            NetworkMessage::NotFound(_) => {
+                Some(Err(Error::Generic(format!("block {} not found", block_hash))))
+            }
+            _ => None,
+        })??;
+        if !block.check_merkle_root() || !block.check_witness_commitment() {
+            return Err(Error::Generic(format!("block {} is invalid", block_hash)));
+        }
+        Ok(block)
+    }
+}
+
+/// Heights and hashes of the blocks to locate our tip in the peer chain, dense at the tip and
+/// exponentially sparser going back to the genesis block.
+fn locator(chain: &HeadersChain) -> Result<Vec<(u32, BlockHash)>, Error> {
+    let mut locator = vec![];
+    let mut height = chain.height();
+    let mut step = 1;
+    loop {
+        locator.push((height, chain.get(height)?.block_hash()));
+        if height == 0 {
+            return Ok(locator);
+        }
+        if locator.len() >= 10 {
+            step *= 2;
+        }
+        height = height.saturating_sub(step);
+    }
+}
+
+impl BlockchainClient for FiltersClient {
+    fn ping(&self) -> Result<(), Error> {
+        self.ping_pong()
+    }
+
+    fn tip(&self) -> Result<(u32, Vec<u8>), Error> {
+        // a client is short lived, but make sure the tip is fresh when asked explicitly
+        self.headers_synced.set(false);
+        self.with_state(|_, chain| Ok((chain.height(), serialize(&chain.tip()))))
+    }
+
+    fn batch_script_get_history(
+        &self,
+        scripts: &[Script],
+    ) -> Result<Vec<Vec<GetHistoryRes>>, Error> {
+        self.with_state(|state, chain| {
+            for script in scripts {
+                state.next_heights.entry(script.clone()).or_insert(self.config.start_height);
+            }
+            self.scan(state, chain)?;
+            Ok(scripts.iter().map(|script| state.script_history(script)).collect())
+        })
+    }
+
+    fn batch_transaction_get_raw(&self, txids: &[Txid]) -> Result<Vec<Vec<u8>>, Error> {
+        // only the transactions involving tracked scripts are known, fetching arbitrary ones would
+        // need a transaction index the P2P protocol does not offer
+        self.with_state(|state, _| {
+            txids
+                .iter()
+                .map(|txid| {
+                    state.txs.get(txid).map(serialize).ok_or_else(|| {
+                        Error::Generic(format!("transaction {} not available from filters", txid))
+                    })
+                })
+                .collect()
+        })
+    }
+
+    fn batch_block_header_raw(&self, heights: Vec<u32>) -> Result<Vec<Vec<u8>>, Error> {
+        self.with_state(|_, chain| {
+            heights.into_iter().map(|height| Ok(serialize(&chain.get(height)?))).collect()
+        })
+    }
+
+    fn block_headers(
+        &self,
+        start_height: usize,
+        count: usize,
+    ) -> Result<Vec<gdk_common::bitcoin::BlockHeader>, Error> {
+        self.with_state(|_, chain| {
+            let end = (start_height + count).min(chain.height() as usize + 1);
+            (start_height..end).map(|height| chain.get(height as u32)).collect()
+        })
+    }
+
+    fn transaction_get_merkle(&self, txid: &Txid, height: usize) -> Result<GetMerkleRes, Error> {
+        let block_hash = self.with_state(|_, chain| Ok(chain.get(height as u32)?.block_hash()))?;
+        let block = self.get_block(block_hash)?;
+        let txids: Vec<Txid> = block.txdata.iter().map(Transaction::txid).collect();
+        let pos = txids
+            .iter()
+            .position(|t| t == txid)
+            .ok_or_else(|| Error::Generic(format!("tx {} not in block {}", txid, block_hash)))?;
+        Ok(GetMerkleRes {
+            block_height: height,
+            pos,
+            merkle: merkle_branch(&txids, pos),
+        })
+    }
+
+    fn transaction_broadcast_raw(&self, raw_tx: &[u8]) -> Result<Txid, Error> {
+        let tx: Transaction = gdk_common::bitcoin::consensus::deserialize(raw_tx)?;
+        let txid = tx.txid();
+        self.send(NetworkMessage::Tx(tx.clone()))?;
+        // the peer does not report whether the transaction has been accepted, at least make sure
+        // it has been received
+        self.ping_pong()?;
+        self.with_state(|state, _| {
+            state.add_unconfirmed(tx);
+            Ok(())
+        })?;
+        Ok(txid)
+    }
+
+    fn fee_estimates(&self) -> Result<Vec<FeeEstimate>, Error> {
+        Err(Error::Generic("fee estimates are not available from compact filters peers".into()))
+    }
+
+    fn indexes_all_transactions(&self) -> bool {
+        false
+    }
+
+    fn scan_state(&self) -> Result<Option<FiltersState>, Error> {
+        Ok(Some(self.config.state.lock()?.clone()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::bitcoin::blockdata::constants::genesis_block;
+    use gdk_common::bitcoin::{PackedLockTime, Sequence, TxIn, TxOut, Witness};
+
+    fn tx(previous_output: OutPoint, script_pubkey: &Script) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output,
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: 1000,
+                script_pubkey: script_pubkey.clone(),
+            }],
+        }
+    }
+
+    fn block(txdata: Vec<Transaction>) -> Block {
+        Block {
+            header: genesis_block(Network::Regtest).header,
+            txdata,
+        }
+    }
+
+    #[test]
+    fn test_filters_state() {
+        let wallet_script = Script::from(vec![0x51]);
+        let other_script = Script::from(vec![0x52]);
+        let mut state = FiltersState::default();
+        state.next_heights.insert(wallet_script.clone(), 0);
+
+        let unrelated = tx(OutPoint::default(), &other_script);
+        let funding = tx(OutPoint::new(unrelated.txid(), 0), &wallet_script);
+        state.process_block(10, &block(vec![unrelated.clone(), funding.clone()]));
+        state.process_block(10, &block(vec![unrelated.clone(), funding.clone()]));
+        let history = state.script_history(&wallet_script);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].tx_hash, funding.txid());
+        assert_eq!(history[0].height, 10);
+        assert!(!state.txs.contains_key(&unrelated.txid()));
+
+        // spending the wallet output involves the wallet script even if paying elsewhere
+        let spending = tx(OutPoint::new(funding.txid(), 0), &other_script);
+        state.add_unconfirmed(spending.clone());
+        let history = state.script_history(&wallet_script);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].tx_hash, spending.txid());
+        assert_eq!(history[1].height, 0);
+
+        state.process_block(12, &block(vec![spending.clone()]));
+        assert!(state.unconfirmed.is_empty());
+        assert_eq!(state.script_history(&wallet_script)[1].height, 12);
+
+        // the state is persisted in the wallet cache
+        let restored: FiltersState =
+            serde_cbor::from_slice(&serde_cbor::to_vec(&state).unwrap()).unwrap();
+        assert_eq!(restored, state);
+
+        // the spending tx reorged away is unconfirmed again
+        state.next_heights.insert(wallet_script.clone(), 13);
+        state.rollback(11);
+        let history = state.script_history(&wallet_script);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].tx_hash, spending.txid());
+        assert_eq!(history[1].height, 0);
+        assert_eq!(state.next_heights[&wallet_script], 12);
+        assert!(!state.block_hashes.contains_key(&12));
+
+        // while the funding tx, not spending wallet outputs, is forgotten
+        state.rollback(9);
+        assert!(state.script_history(&wallet_script).is_empty());
+        assert!(!state.txs.contains_key(&funding.txid()));
+        assert!(state.outpoints.is_empty());
+        assert!(state.block_hashes.is_empty());
+        assert_eq!(state.next_heights[&wallet_script], 10);
+    }
+}
@@ -58,6 +58,30 @@ where
     Ok(N::from_slice(&current)?)
 }
 
+/// compute the merkle path of the tx at `pos` among `txids` in electrum format, the inverse of
+/// [`compute_merkle_root`]
+pub(crate) fn merkle_branch<T>(txids: &[T], mut pos: usize) -> Vec<[u8; 32]>
+where
+    T: Hash<Inner = [u8; 32]>, // bitcoin::Txid or elements::Txid
+{
+    let mut level: Vec<[u8; 32]> = txids.iter().map(|txid| txid.into_inner()).collect();
+    let mut branch = vec![];
+    while level.len() > 1 {
+        if level.len() % 2 == 1 {
+            level.push(*level.last().expect("non empty"));
+        }
+        let mut sibling = level[pos ^ 1];
+        sibling.reverse();
+        branch.push(sibling);
+        level = level
+            .chunks(2)
+            .map(|pair| sha256d::Hash::hash(&[pair[0], pair[1]].concat()).into_inner())
+            .collect();
+        pos /= 2;
+    }
+    branch
+}
+
 trait ParamsMethods {
     fn build_client(&self) -> Result<Client, Error>;
     fn headers_chain(&self) -> Result<HeadersChain, Error>;
@@ -276,3 +300,36 @@ impl VerifiedCache {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::bitcoin::hashes::hex::FromHex;
+    use gdk_common::bitcoin::{TxMerkleNode, Txid};
+
+    #[test]
+    fn test_merkle_branch() {
+        // Block 100000 of bitcoin mainnet
+        let txids: Vec<Txid> = [
+            "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+            "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+            "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+            "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+        ]
+        .iter()
+        .map(|t| Txid::from_hex(t).unwrap())
+        .collect();
+        let merkle_root = "f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766";
+
+        for (pos, txid) in txids.iter().enumerate() {
+            let merkle = GetMerkleRes {
+                block_height: 100_000,
+                pos,
+                merkle: merkle_branch(&txids, pos),
+            };
+            assert_eq!(merkle.merkle.len(), 2);
+            let root: TxMerkleNode = compute_merkle_root(txid, merkle).unwrap();
+            assert_eq!(root.to_hex(), merkle_root);
+        }
+    }
+}
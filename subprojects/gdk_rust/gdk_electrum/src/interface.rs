@@ -1,6 +1,10 @@
 use serde::{Deserialize, Serialize};
 
+use crate::client::BlockchainClient;
 use crate::error::*;
+use crate::esplora::EsploraClient;
+use crate::filters::{FiltersClient, FiltersConfig};
+use crate::rpc::{RpcClient, RpcConfig};
 
 use electrum_client::{Client, ConfigBuilder};
 use gdk_common::network::NETWORK_REQUEST_TIMEOUT;
@@ -49,6 +53,41 @@ impl ElectrumUrl {
     }
 }
 
+/// The server a session syncs from.
+#[derive(Clone, Debug)]
+pub enum ServerUrl {
+    Electrum(ElectrumUrl),
+    Esplora(String),
+    Rpc(RpcConfig),
+    CompactFilters(FiltersConfig),
+}
+
+impl ServerUrl {
+    pub fn build_client(
+        &self,
+        proxy: Option<&str>,
+        timeout: Option<u8>,
+    ) -> Result<Box<dyn BlockchainClient>, Error> {
+        Ok(match self {
+            ServerUrl::Electrum(url) => Box::new(url.build_client(proxy, timeout)?),
+            ServerUrl::Esplora(url) => Box::new(EsploraClient::new(url, proxy)?),
+            ServerUrl::Rpc(config) => Box::new(RpcClient::new(config, proxy)?),
+            ServerUrl::CompactFilters(config) => {
+                Box::new(FiltersClient::new(config, proxy, timeout)?)
+            }
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        match self {
+            ServerUrl::Electrum(url) => url.url(),
+            ServerUrl::Esplora(url) => url,
+            ServerUrl::Rpc(config) => &config.url,
+            ServerUrl::CompactFilters(config) => &config.peer,
+        }
+    }
+}
+
 // Parse the standard <host>:<port>:<t|s> string format,
 // with an optional non-standard `:noverify` suffix to skip tls validation
 impl FromStr for ElectrumUrl {
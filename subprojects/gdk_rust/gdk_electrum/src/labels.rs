@@ -0,0 +1,115 @@
+//! Wallet labels in the BIP329 format, a JSON record per line.
+//!
+//! Transaction memos, address labels, output labels and subaccount names map to the `tx`, `addr`,
+//! `output` and `xpub` record types. The `pubkey` and `input` types are parsed, but have no
+//! counterpart in the wallet.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelType {
+    Tx,
+    Addr,
+    Pubkey,
+    Input,
+    Output,
+    Xpub,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    #[serde(rename = "type")]
+    pub type_: LabelType,
+
+    /// The txid, address, public key, outpoint or extended public key being labeled
+    #[serde(rename = "ref")]
+    pub reference: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+
+    /// The descriptor of the wallet the record belongs to, without the keys derivation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+
+    /// For outputs, whether the output can be spent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spendable: Option<bool>,
+}
+
+impl Label {
+    pub fn new(type_: LabelType, reference: String, label: String) -> Self {
+        Label {
+            type_,
+            reference,
+            label: Some(label),
+            origin: None,
+            spendable: None,
+        }
+    }
+}
+
+/// Parse the records, skipping empty lines
+pub fn parse_labels(s: &str) -> Result<Vec<Label>, Error> {
+    s.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| Error::InvalidLabels(format!("line {}: {}", i + 1, e)))
+        })
+        .collect()
+}
+
+/// Serialize the records, each one on its own line
+pub fn serialize_labels(labels: &[Label]) -> Result<String, Error> {
+    let mut s = String::new();
+    for label in labels {
+        s.push_str(&serde_json::to_string(label)?);
+        s.push('\n');
+    }
+    Ok(s)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // From the BIP329 test vectors
+    const LABELS: &str = r#"{"type":"tx","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd","label":"Transaction","origin":"wpkh([d34db33f/84'/0'/0'])"}
+{"type":"addr","ref":"bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c","label":"Address"}
+
+{"type":"pubkey","ref":"0283409659355b6d1cc3c32decd5d561abaac86c37a353b52895a5e6c196d6f448","label":"Public Key"}
+{"type":"input","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:0","label":"Input"}
+{"type":"output","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:1","label":"Output","spendable":false}
+{"type":"xpub","ref":"xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8","label":"Extended Public Key"}
+"#;
+
+    #[test]
+    fn test_labels_roundtrip() {
+        let labels = parse_labels(LABELS).unwrap();
+        assert_eq!(labels.len(), 6);
+        assert_eq!(labels[0].type_, LabelType::Tx);
+        assert_eq!(labels[0].origin.as_deref(), Some("wpkh([d34db33f/84'/0'/0'])"));
+        assert_eq!(labels[4].type_, LabelType::Output);
+        assert_eq!(labels[4].spendable, Some(false));
+        assert_eq!(labels[5].label.as_deref(), Some("Extended Public Key"));
+
+        let s = serialize_labels(&labels).unwrap();
+        assert_eq!(s, LABELS.replace("\n\n", "\n"));
+        assert_eq!(parse_labels(&s).unwrap(), labels);
+    }
+
+    #[test]
+    fn test_invalid_labels() {
+        assert!(parse_labels(r#"{"type":"tx"}"#).is_err());
+        assert!(parse_labels(r#"{"type":"block","ref":"00","label":"Block"}"#).is_err());
+        let err = parse_labels("\n{}").unwrap_err();
+        assert!(err.to_string().contains("line 2"));
+        // The label is optional
+        assert_eq!(parse_labels(r#"{"type":"tx","ref":"00"}"#).unwrap()[0].label, None);
+    }
+}
@@ -11,23 +11,45 @@ use headers::bitcoin::HEADERS_FILE_MUTEX;
 use serde_json::Value;
 
 pub mod account;
+pub mod client;
+pub mod coin_selection;
+pub mod descriptor;
 pub mod error;
+pub mod esplora;
+pub mod filters;
 pub mod headers;
 pub mod interface;
+pub mod labels;
+pub mod message;
+pub mod payjoin;
+pub mod payment_uri;
 pub mod pin;
+pub mod psbt;
 pub mod pset;
+pub mod rpc;
 pub mod session;
+pub mod signer;
 pub mod spv;
+pub mod watch_only;
 
 use crate::account::{
-    discover_account, get_account_derivation, get_account_script_purpose,
-    get_last_next_account_nums, Account,
+    check_gap_limit, discover_account, get_account_derivation, get_account_script_purpose,
+    get_last_next_account_nums, is_account_used, marginal_fees, validate_address, xpubs_equivalent,
+    Account, NUM_RESERVED_ACCOUNT_TYPES,
 };
+use crate::client::BlockchainClient;
+use crate::descriptor::account_from_descriptor;
 use crate::error::Error;
-use crate::interface::ElectrumUrl;
+use crate::interface::ServerUrl;
+use crate::labels::{parse_labels, serialize_labels, Label, LabelType};
+use crate::payjoin::PayjoinParams;
+use crate::payment_uri::PaymentUri;
+use crate::signer::{Signer, SoftwareSigner};
 use crate::store::*;
+use crate::watch_only::{parse_slip77_key, watch_only_accounts, watch_only_master_xpub};
 
 use gdk_common::bitcoin::hashes::hex::{FromHex, ToHex};
+use gdk_common::bitcoin::hashes::{sha256, Hash};
 use gdk_common::bitcoin::secp256k1::{self, SecretKey};
 use gdk_common::bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey};
 use gdk_common::{bitcoin, elements};
@@ -42,7 +64,9 @@ use gdk_common::{be::*, State};
 
 use gdk_common::elements::confidential::{self, Asset, Nonce};
 use gdk_common::elements::encode;
+use gdk_common::elements::issuance::{AssetId, ContractHash};
 use gdk_common::elements::pset::PartiallySignedTransaction;
+use gdk_common::elements::secp256k1_zkp::ZERO_TWEAK;
 use gdk_common::exchange_rates::ExchangeRatesCache;
 use gdk_common::network;
 use gdk_common::scripts::ScriptType;
@@ -63,10 +87,11 @@ use crate::spv::SpvCrossValidator;
 use block_modes::block_padding::Pkcs7;
 use block_modes::BlockMode;
 use block_modes::Cbc;
-use electrum_client::{Client, ElectrumApi};
 use gdk_common::aes::Aes256;
 use gdk_common::bitcoin::blockdata::constants::DIFFCHANGE_INTERVAL;
-pub use gdk_common::notification::{NativeNotif, Notification, TransactionNotification};
+pub use gdk_common::notification::{
+    NativeNotif, Notification, RecoveryNotification, TransactionNotification,
+};
 use gdk_common::rand::seq::SliceRandom;
 use gdk_common::rand::thread_rng;
 use gdk_common::rand::Rng;
@@ -78,6 +103,9 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
 use std::thread::JoinHandle;
 
+/// Batches of scripts per chain looked up at once by backends that rescan the chain for new scripts
+const RESCAN_LOOKAHEAD_BATCHES: u32 = 5;
+
 const CROSS_VALIDATION_RATE: u8 = 4; // Once every 4 thread loop runs, or roughly 28 seconds
 
 static EC: Lazy<secp256k1::Secp256k1<secp256k1::All>> = Lazy::new(|| {
@@ -112,7 +140,7 @@ pub struct ElectrumSession {
     pub proxy: Option<String>,
     pub timeout: Option<u8>,
     pub network: NetworkParameters,
-    pub url: ElectrumUrl,
+    pub url: ServerUrl,
 
     /// Accounts of the wallet
     pub accounts: Arc<RwLock<HashMap<u32, Account>>>,
@@ -132,10 +160,12 @@ pub struct ElectrumSession {
 
     pub store: Option<Store>,
 
-    /// Master xprv of the signer associated to the session
-    ///
-    /// FIXME: remove this once we have fully migrated to the hw signer interface
-    pub master_xprv: Option<ExtendedPrivKey>,
+    /// The signer of the wallet, it is Some after login unless the session is watch-only
+    pub signer: Option<Arc<dyn Signer>>,
+
+    /// Whether the session was logged in with `login_wo`, then `master_xpub` does not belong to
+    /// the wallet signer
+    pub watch_only: bool,
 
     /// Spent utxos
     ///
@@ -185,20 +215,6 @@ fn socksify(proxy: Option<&str>) -> Option<String> {
     }
 }
 
-fn try_get_fee_estimates(client: &Client) -> Result<Vec<FeeEstimate>, Error> {
-    let relay_fee = (client.relay_fee()? * 100_000_000.0) as u64;
-    let blocks: Vec<usize> = (1..25).collect();
-    // max is covering a rounding errors in production electrs which sometimes cause a fee
-    // estimates lower than relay fee
-    let mut estimates: Vec<FeeEstimate> = client
-        .batch_estimate_fee(blocks)?
-        .iter()
-        .map(|e| FeeEstimate(relay_fee.max((*e * 100_000_000.0) as u64)))
-        .collect();
-    estimates.insert(0, FeeEstimate(relay_fee));
-    Ok(estimates)
-}
-
 impl ElectrumSession {
     pub fn get_accounts(&self) -> Result<Vec<Account>, Error> {
         // The Account struct is immutable and we don't allow account deletion.
@@ -241,19 +257,19 @@ impl ElectrumSession {
             self.last_network_call_succeeded.load(Ordering::Relaxed)
         } else {
             // We can't call start_threads() here because not everything is loaded before login,
-            // but we need to emit a network notification, to do so we test the electrum server
+            // but we need to emit a network notification, to do so we test the server
             // with a ping to emit a notification
-            let electrum_url = self.url.clone();
+            let server_url = self.url.clone();
             let proxy = self.proxy.clone();
-            match electrum_url.build_client(proxy.as_deref(), None) {
+            match server_url.build_client(proxy.as_deref(), None) {
                 Ok(client) => match client.ping() {
                     Ok(_) => {
-                        info!("succesfully pinged electrum server {:?}", electrum_url.url());
+                        info!("succesfully pinged server {:?}", server_url.url());
                         self.last_network_call_succeeded.store(true, Ordering::Relaxed);
                         true
                     }
                     Err(e) => {
-                        warn!("failed to ping electrum server {:?}: {:?}", electrum_url.url(), e);
+                        warn!("failed to ping server {:?}: {:?}", server_url.url(), e);
                         false
                     }
                 },
@@ -339,6 +355,11 @@ impl ElectrumSession {
 
             info!("Store root path: {:?}", path);
             let store = StoreMeta::new(&path, &opt.master_xpub, self.network.id())?;
+            if let (ServerUrl::CompactFilters(config), Some(state)) =
+                (&self.url, store.cache.filters_state.clone())
+            {
+                config.restore_state(state)?;
+            }
             let store = Arc::new(RwLock::new(store));
             self.store = Some(store);
         }
@@ -389,8 +410,18 @@ impl ElectrumSession {
             return self.get_wallet_hash_id();
         }
 
-        let (master_xprv, master_xpub, master_blinding_key) =
-            keys_from_credentials(&credentials, self.network.bip32_network())?;
+        let signer = SoftwareSigner::from_credentials(&credentials, self.network.bip32_network())?;
+        self.login_with_signer(Arc::new(signer))
+    }
+
+    /// Login with the given signer, which holds the keys of the wallet so that they do not need
+    /// to be in this process
+    pub fn login_with_signer(&mut self, signer: Arc<dyn Signer>) -> Result<LoginData, Error> {
+        if self.master_xpub.is_some() {
+            return self.get_wallet_hash_id();
+        }
+
+        let master_xpub = signer.xpub(&DerivationPath::master())?;
 
         self.load_store(&LoadStoreOpt {
             master_xpub: master_xpub.clone(),
@@ -399,21 +430,19 @@ impl ElectrumSession {
         if self.network.liquid {
             if self.get_master_blinding_key()?.master_blinding_key.is_none() {
                 self.set_master_blinding_key(&SetMasterBlindingKeyOpt {
-                    master_blinding_key,
+                    master_blinding_key: signer.master_blinding_key()?,
                 })?;
             }
         }
 
-        // Set the master xprv
-        self.master_xprv = Some(master_xprv);
+        self.signer = Some(signer.clone());
 
         // Get xpubs from signer and (re)create subaccounts
         for account_num in self.get_subaccount_nums()? {
             let path = self.get_subaccount_root_path(GetAccountPathOpt {
                 subaccount: account_num,
             })?;
-            let xprv = master_xprv.derive_priv(&crate::EC, &path.path).unwrap();
-            let xpub = ExtendedPubKey::from_priv(&crate::EC, &xprv);
+            let xpub = signer.xpub(&path.path.into())?;
 
             self.create_subaccount(CreateAccountOpt {
                 subaccount: account_num,
@@ -421,6 +450,8 @@ impl ElectrumSession {
                 xpub: Some(xpub),
                 discovered: false,
                 is_already_created: true,
+                multisig: None,
+                descriptor: None,
             })?;
         }
 
@@ -428,6 +459,70 @@ impl ElectrumSession {
         self.get_wallet_hash_id()
     }
 
+    /// Log in a watch-only session from the public keys of its accounts.
+    ///
+    /// The session syncs and creates transactions as usual, but it cannot sign them.
+    pub fn login_wo(&mut self, credentials: WatchOnlyCredentials) -> Result<LoginData, Error> {
+        info!(
+            "login_wo {:?} last network call succeeded {:?}",
+            self.network, self.last_network_call_succeeded
+        );
+
+        // This check must be done before everything else to allow re-login
+        if self.master_xpub.is_some() {
+            return self.get_wallet_hash_id();
+        }
+
+        let accounts = watch_only_accounts(&credentials, &self.network)?;
+        let master_xpub = watch_only_master_xpub(&accounts, &self.network)?;
+
+        self.load_store(&LoadStoreOpt {
+            master_xpub,
+        })?;
+        self.watch_only = true;
+
+        if self.network.liquid {
+            let slip77_key =
+                credentials.slip77_key.as_ref().ok_or_else(|| Error::MissingMasterBlindingKey)?;
+            let master_blinding_key = parse_slip77_key(slip77_key)?;
+            match self.get_master_blinding_key()?.master_blinding_key {
+                None => self.set_master_blinding_key(&SetMasterBlindingKeyOpt {
+                    master_blinding_key,
+                })?,
+                Some(cached) if cached != master_blinding_key => {
+                    return Err(Error::InvalidWatchOnlyCredentials(
+                        "SLIP-77 key does not match the one of the wallet".into(),
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+
+        {
+            // Accounts are not created through `create_subaccount`, the watched ones do not need
+            // to be consecutive
+            let store = self.store()?;
+            let master_blinding = store.read()?.cache.master_blinding.clone();
+            let mut session_accounts = self.accounts.write()?;
+            for (account_num, xpub) in accounts {
+                let account = Account::new(
+                    self.network.clone(),
+                    &None,
+                    &Some(xpub),
+                    master_blinding.clone(),
+                    store.clone(),
+                    account_num,
+                    false,
+                    None,
+                )?;
+                session_accounts.insert(account_num, account);
+            }
+        }
+
+        self.start_threads()?;
+        self.get_wallet_hash_id()
+    }
+
     pub fn join_threads(&mut self) {
         while let Some(handle) = self.handles.pop() {
             handle.join().expect("Couldn't join on the associated thread");
@@ -483,7 +578,7 @@ impl ElectrumSession {
             info!("building built end");
             let fee_store = self.store()?;
             thread::spawn(move || {
-                match try_get_fee_estimates(&fee_client) {
+                match fee_client.fee_estimates() {
                     Ok(fee_estimates) => {
                         fee_store.write().unwrap().cache.fee_estimates = fee_estimates
                     }
@@ -547,7 +642,7 @@ impl ElectrumSession {
                                 info!("closing headers thread");
                                 break 'outer;
                             }
-                            match headers.ask(chunk_size, &client) {
+                            match headers.ask(chunk_size, &*client) {
                                 Ok(headers_found) => {
                                     if headers_found < chunk_size {
                                         break;
@@ -572,7 +667,7 @@ impl ElectrumSession {
                             }
                         }
 
-                        match headers.get_proofs(&client) {
+                        match headers.get_proofs(&*client) {
                             Ok(found) => {
                                 if found > 0 {
                                     info!("found proof {}", found)
@@ -635,7 +730,8 @@ impl ElectrumSession {
         let syncer_tipper_handle = thread::spawn(move || {
             info!("starting syncer & tipper thread");
 
-            let update_tip = |client: &Client, do_update: bool| match tipper.tip(&client, do_update)
+            let update_tip = |client: &dyn BlockchainClient, do_update: bool| match tipper
+                .tip(client, do_update)
             {
                 Ok(Some((height, header))) => {
                     // This is a new block
@@ -651,8 +747,8 @@ impl ElectrumSession {
                 }
             };
 
-            let sync = |client: &Client| {
-                match syncer.sync(&client) {
+            let sync = |client: &dyn BlockchainClient| {
+                match syncer.sync(client) {
                     Ok(tx_ntfs) => {
                         state_updater.update_if_needed(true);
                         // Skip sending transaction notifications if it's the
@@ -677,16 +773,16 @@ impl ElectrumSession {
             loop {
                 match url.build_client(proxy.as_deref(), None) {
                     Ok(client) => {
-                        let tip_before_sync = match update_tip(&client, false) {
+                        let tip_before_sync = match update_tip(&*client, false) {
                             Ok(height) => height,
                             Err(_) => {
                                 continue;
                             }
                         };
 
-                        sync(&client);
+                        sync(&*client);
 
-                        let tip_after_sync = match update_tip(&client, true) {
+                        let tip_after_sync = match update_tip(&*client, true) {
                             Ok(height) => height,
                             Err(_) => {
                                 continue;
@@ -735,12 +831,71 @@ impl ElectrumSession {
 
     pub fn get_receive_address(&self, opt: &GetAddressOpt) -> Result<AddressPointer, Error> {
         debug!("get_receive_address {:?}", opt);
-        let address =
+        let mut address =
             self.get_account(opt.subaccount)?.get_next_address(opt.is_internal.unwrap_or(false))?;
+        if let Some(ref uri_opt) = opt.uri {
+            // Liquid amounts must specify the asset
+            let asset_id = uri_opt.satoshi.and(self.network.policy_asset.clone());
+            let payment_uri = PaymentUri {
+                address: address.address.clone(),
+                satoshi: uri_opt.satoshi,
+                asset_id,
+                label: uri_opt.label.clone(),
+                message: None,
+                payjoin_endpoint: None,
+            };
+            address.uri = Some(payment_uri.to_uri(self.network.bip21_prefix()));
+        }
         debug!("get_address {:?}", address);
         Ok(address)
     }
 
+    /// Parse a `bitcoin:` or `liquidnetwork:` payment URI into a transaction request to its
+    /// address, with its amount and asset if specified and its message, or label, as memo.
+    pub fn parse_payment_uri(&self, opt: ParsePaymentUriOpt) -> Result<CreateTransaction, Error> {
+        let payment_uri = PaymentUri::parse(&opt.uri, self.network.bip21_prefix())?;
+        validate_address(&payment_uri.address, &self.network)?;
+        match payment_uri.asset_id {
+            Some(ref asset_id) if !self.network.liquid => {
+                return Err(Error::InvalidPaymentUri(format!("unexpected asset {}", asset_id)))
+            }
+            Some(ref asset_id) => {
+                elements::issuance::AssetId::from_str(asset_id)
+                    .map_err(|_| Error::InvalidAssetId)?;
+            }
+            None if self.network.liquid && payment_uri.satoshi.is_some() => {
+                return Err(Error::MissingPaymentUriAssetId)
+            }
+            None => {}
+        }
+        // PayJoin is optional for the sender, use it only if the subaccount can
+        let payjoin_endpoint = match payment_uri.payjoin_endpoint {
+            Some(endpoint) if self.get_account(opt.subaccount)?.supports_payjoin() => {
+                // BIP78: with an invalid endpoint the sender falls back to a normal payment
+                match payjoin::validate_endpoint(&endpoint, self.network.development) {
+                    Ok(()) => Some(endpoint),
+                    Err(e) => {
+                        warn!("ignoring payjoin endpoint: {}", e);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        Ok(CreateTransaction {
+            addressees: vec![AddressAmount {
+                address: payment_uri.address,
+                satoshi: payment_uri.satoshi.unwrap_or(0),
+                asset_id: payment_uri.asset_id,
+                data: None,
+            }],
+            subaccount: opt.subaccount,
+            memo: payment_uri.message.or(payment_uri.label),
+            payjoin_endpoint,
+            ..Default::default()
+        })
+    }
+
     pub fn get_previous_addresses(
         &self,
         opt: &GetPreviousAddressesOpt,
@@ -804,8 +959,39 @@ impl ElectrumSession {
         })
     }
 
-    pub fn create_subaccount(&mut self, opt: CreateAccountOpt) -> Result<AccountInfo, Error> {
-        let master_xprv = self.master_xprv.clone();
+    /// The output descriptors of a subaccount, with key origins unless the session is watch-only.
+    pub fn get_subaccount_descriptors(
+        &self,
+        opt: GetAccountPathOpt,
+    ) -> Result<AccountDescriptors, Error> {
+        let master_xpub = self.master_xpub.ok_or_else(|| Error::WalletNotInitialized)?;
+        let master_fingerprint = if self.watch_only {
+            None
+        } else {
+            Some(master_xpub.fingerprint())
+        };
+        let account = self.get_account(opt.subaccount)?;
+        Ok(AccountDescriptors {
+            external: account.descriptor(false, master_fingerprint).to_string_with_checksum(),
+            internal: account.descriptor(true, master_fingerprint).to_string_with_checksum(),
+        })
+    }
+
+    pub fn create_subaccount(&mut self, mut opt: CreateAccountOpt) -> Result<AccountInfo, Error> {
+        if let Some(descriptor) = opt.descriptor.take() {
+            let master_xpub = self.master_xpub.ok_or_else(|| Error::WalletNotInitialized)?;
+            if self.watch_only {
+                return Err(Error::InvalidDescriptor(
+                    "cannot import descriptors in watch-only sessions".into(),
+                ));
+            }
+            let (subaccount, xpub, multisig) =
+                account_from_descriptor(&descriptor, master_xpub.fingerprint(), &self.network)?;
+            opt.subaccount = subaccount;
+            opt.xpub = Some(xpub);
+            opt.multisig = multisig;
+        }
+        let signer = self.signer.clone();
         let store = self.store()?.clone();
         let master_blinding = store.read()?.cache.master_blinding.clone();
         let network = self.network.clone();
@@ -834,12 +1020,13 @@ impl ElectrumSession {
             Entry::Vacant(entry) => {
                 let account = entry.insert(Account::new(
                     network,
-                    &master_xprv,
+                    &signer,
                     &opt.xpub, // account xpub
                     master_blinding,
                     store,
                     opt.subaccount,
                     opt.discovered,
+                    opt.multisig,
                 )?);
                 if !opt.name.is_empty() {
                     account.set_name(&opt.name)?;
@@ -851,7 +1038,58 @@ impl ElectrumSession {
     }
 
     pub fn discover_subaccount(&self, opt: DiscoverAccountOpt) -> Result<bool, Error> {
-        discover_account(&self.url, self.proxy.as_deref(), &opt.xpub, opt.script_type)
+        let gap_limit = opt.gap_limit.unwrap_or(BATCH_SIZE);
+        discover_account(&self.url, self.proxy.as_deref(), &opt.xpub, opt.script_type, gap_limit)
+    }
+
+    /// Create every used singlesig subaccount of the wallet, as when restoring from a seed.
+    ///
+    /// For each script type, BIP32 accounts are checked in order and the search stops at the
+    /// first one without transactions, as in BIP44 account discovery. A notification is sent for
+    /// every subaccount checked. Returns the subaccounts that were created.
+    pub fn recover_subaccounts(
+        &mut self,
+        opt: RecoverAccountsOpt,
+    ) -> Result<Vec<AccountInfo>, Error> {
+        // Without the signer, as in watch-only sessions, the caller must discover the
+        // subaccounts one by one with `discover_subaccount`.
+        let signer = self.signer.clone().ok_or_else(|| {
+            Error::Generic("recovering subaccounts requires the wallet credentials".into())
+        })?;
+        let gap_limit = opt.gap_limit.unwrap_or(BATCH_SIZE);
+        check_gap_limit(gap_limit)?;
+        let client = self.url.build_client(self.proxy.as_deref(), None)?;
+
+        let mut recovered = vec![];
+        for script_type in ScriptType::types() {
+            let mut account_num = script_type.first_account_num();
+            loop {
+                let (_, path) = get_account_derivation(account_num, self.network.id())?;
+                let xpub = signer.xpub(&path)?;
+                let used = is_account_used(client.as_ref(), &xpub, *script_type, gap_limit)?;
+                self.notify.subaccount_recovery(&RecoveryNotification {
+                    subaccount: account_num,
+                    script_type: *script_type,
+                    used,
+                });
+                if !used {
+                    break;
+                }
+                if !self.accounts.read()?.contains_key(&account_num) {
+                    info!("recovered subaccount {}", account_num);
+                    recovered.push(self.create_subaccount(CreateAccountOpt {
+                        subaccount: account_num,
+                        xpub: Some(xpub),
+                        discovered: true,
+                        // the account is used, even if the previous one is not synced yet
+                        is_already_created: true,
+                        ..Default::default()
+                    })?);
+                }
+                account_num += NUM_RESERVED_ACCOUNT_TYPES;
+            }
+        }
+        Ok(recovered)
     }
 
     pub fn get_next_subaccount(&self, opt: GetNextAccountOpt) -> Result<u32, Error> {
@@ -871,6 +1109,7 @@ impl ElectrumSession {
             subaccount: opt.subaccount,
             name: Some(opt.new_name),
             hidden: None,
+            gap_limit: None,
         })
     }
 
@@ -879,6 +1118,7 @@ impl ElectrumSession {
             subaccount: opt.subaccount,
             hidden: Some(opt.hidden),
             name: None,
+            gap_limit: None,
         })
     }
 
@@ -906,26 +1146,45 @@ impl ElectrumSession {
     }
 
     pub fn get_balance(&self, opt: &GetBalanceOpt) -> Result<Balances, Error> {
-        let mut result = HashMap::new();
-        // bitcoin balance is always set even if 0
-        match self.network.id() {
-            NetworkId::Bitcoin(_) => result.entry("btc".to_string()).or_insert(0),
-            NetworkId::Elements(_) => {
-                result.entry(self.network.policy_asset.as_ref().unwrap().clone()).or_insert(0)
+        let BalanceDetails {
+            mut spendable,
+            frozen,
+        } = self.get_balance_details(opt)?;
+        if opt.all_coins.unwrap_or(false) {
+            for (asset, satoshi) in frozen {
+                *spendable.entry(asset).or_default() += satoshi;
             }
+        }
+        Ok(spendable)
+    }
+
+    /// Get the spendable and the frozen balance of each asset, `all_coins` is ignored
+    pub fn get_balance_details(&self, opt: &GetBalanceOpt) -> Result<BalanceDetails, Error> {
+        let mut result = BalanceDetails::default();
+        // bitcoin balance is always set even if 0
+        let policy_asset = match self.network.id() {
+            NetworkId::Bitcoin(_) => "btc".to_string(),
+            NetworkId::Elements(_) => self.network.policy_asset.as_ref().unwrap().clone(),
         };
+        result.spendable.insert(policy_asset.clone(), 0);
+        result.frozen.insert(policy_asset, 0);
 
         // Compute balance from get_unspent_outputs
         let opt = GetUnspentOpt {
             subaccount: opt.subaccount,
             num_confs: Some(opt.num_confs),
             confidential_utxos_only: opt.confidential_utxos_only,
-            all_coins: None,
+            all_coins: Some(true),
         };
         let unspent_outputs = self.get_unspent_outputs(&opt)?;
         for (asset, utxos) in unspent_outputs.0.iter() {
-            let asset_balance = utxos.iter().map(|u| u.satoshi).sum::<u64>();
-            *result.entry(asset.clone()).or_default() += asset_balance as i64;
+            for utxo in utxos {
+                let balances = match utxo.user_status {
+                    USER_STATUS_FROZEN => &mut result.frozen,
+                    _ => &mut result.spendable,
+                };
+                *balances.entry(asset.clone()).or_default() += utxo.satoshi as i64;
+            }
         }
 
         Ok(result)
@@ -967,10 +1226,7 @@ impl ElectrumSession {
         params: PsbtGetDetailsParams,
     ) -> Result<PsbtGetDetailsResult, Error> {
         if !self.network.liquid {
-            return Err(Error::Generic(
-                "`ElectrumSession::psbt_get_details` is currently only supported for Liquid"
-                    .to_owned(),
-            ));
+            return self.psbt_get_details_bitcoin(params);
         }
 
         let pset = {
@@ -1035,7 +1291,9 @@ impl ElectrumSession {
                 })?;
 
                 unblind_output(tx_out, &master_blinding, None)
-                    .map(|secrets| PsbtGetDetailsOut::new(secrets.asset, secrets.value, subaccount))
+                    .map(|secrets| {
+                        PsbtGetDetailsOut::new(Some(secrets.asset), secrets.value, subaccount)
+                    })
                     .ok()
             })
             .collect::<Vec<_>>();
@@ -1043,6 +1301,76 @@ impl ElectrumSession {
         Ok(PsbtGetDetailsResult::new(inputs, outputs))
     }
 
+    fn psbt_get_details_bitcoin(
+        &self,
+        params: PsbtGetDetailsParams,
+    ) -> Result<PsbtGetDetailsResult, Error> {
+        let psbt = bitcoin_psbt_from_base64(&params.psbt)?;
+
+        let inputs = psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .filter_map(|txin| {
+                params
+                    .utxos
+                    .iter()
+                    .find(|utxo| {
+                        utxo.txhash == txin.previous_output.txid.to_string()
+                            && utxo.pt_idx == txin.previous_output.vout
+                    })
+                    .cloned()
+            })
+            .collect();
+
+        let store = self.store()?;
+        let store_read = store.read()?;
+        let mut outputs = vec![];
+        for output in psbt.unsigned_tx.output.iter() {
+            let script = BEScript::Bitcoin(output.script_pubkey.clone());
+            for account in self.get_accounts()? {
+                if store_read.account_cache(account.num())?.get_path(&script).is_ok() {
+                    outputs.push(PsbtGetDetailsOut::new(None, output.value, account.num()));
+                    break;
+                }
+            }
+        }
+
+        Ok(PsbtGetDetailsResult::new(inputs, outputs))
+    }
+
+    /// Export an unsigned Bitcoin transaction created by `create_transaction` as a BIP174 PSBT,
+    /// so that it can be signed by an external signer.
+    pub fn create_psbt(&self, tx: &TransactionMeta) -> Result<CreatePsbtResult, Error> {
+        info!("electrum create_psbt {:?}", tx);
+        let account_num = tx
+            .create_transaction
+            .as_ref()
+            .ok_or_else(|| Error::Generic("Cannot export without tx data".into()))?
+            .subaccount;
+        let master_fingerprint = match (self.watch_only, self.master_xpub) {
+            (false, Some(master_xpub)) => Some(master_xpub.fingerprint()),
+            _ => None,
+        };
+        let psbt = self.get_account(account_num)?.create_psbt(tx, master_fingerprint)?;
+        Ok(CreatePsbtResult {
+            psbt: base64::encode(bitcoin::consensus::serialize(&psbt)),
+        })
+    }
+
+    /// Finalize a Bitcoin PSBT having the signatures of all its inputs, returning the signed
+    /// transaction that can be passed to `send_transaction`.
+    pub fn finalize_psbt(&self, opt: FinalizePsbtOpt) -> Result<TransactionMeta, Error> {
+        if self.network.liquid {
+            return Err(Error::Generic("Liquid transactions are handled as PSET".into()));
+        }
+        let mut psbt = bitcoin_psbt_from_base64(&opt.psbt)?;
+        let tx = psbt::finalize(&mut psbt)?;
+        let mut tx_meta: TransactionMeta = BETransaction::Bitcoin(tx).into();
+        tx_meta.fee = psbt::fee(&psbt).unwrap_or_default();
+        Ok(tx_meta)
+    }
+
     pub fn sign_transaction(&self, create_tx: &TransactionMeta) -> Result<TransactionMeta, Error> {
         info!("electrum sign_transaction {:?}", create_tx);
         let account_num = create_tx
@@ -1060,14 +1388,35 @@ impl ElectrumSession {
     }
 
     pub fn send_transaction(&mut self, tx: &TransactionMeta) -> Result<TransactionMeta, Error> {
-        info!("electrum send_transaction {:#?}", tx);
+        info!("send_transaction {:#?}", tx);
         let client = self.url.build_client(self.proxy.as_deref(), None)?;
+        let payjoin_endpoint =
+            tx.create_transaction.as_ref().and_then(|c| c.payjoin_endpoint.as_ref());
+        // The original transaction is sent if the PayJoin fails at any step
+        let payjoin = payjoin_endpoint.and_then(|endpoint| match self.payjoin(tx, endpoint) {
+            Ok(payjoin) => Some(payjoin),
+            Err(e) => {
+                warn!("payjoin failed, sending the original transaction: {}", e);
+                None
+            }
+        });
+        let (tx, txid) = match payjoin {
+            Some(payjoin) => match client
+                .transaction_broadcast_raw(&Vec::<u8>::from_hex(&payjoin.hex)?)
+            {
+                Ok(txid) => (payjoin, txid),
+                Err(e) => {
+                    warn!("payjoin broadcast failed, sending the original transaction: {}", e);
+                    (tx.clone(), client.transaction_broadcast_raw(&Vec::<u8>::from_hex(&tx.hex)?)?)
+                }
+            },
+            None => (tx.clone(), client.transaction_broadcast_raw(&Vec::<u8>::from_hex(&tx.hex)?)?),
+        };
         let tx_bytes = Vec::<u8>::from_hex(&tx.hex)?;
-        let txid = client.transaction_broadcast_raw(&tx_bytes)?;
         if let Some(memo) = tx.create_transaction.as_ref().and_then(|o| o.memo.as_ref()) {
             self.store()?.write()?.insert_memo(txid.into(), memo)?;
         }
-        let mut tx = tx.clone();
+        let mut tx = tx;
         // If sign transaction happens externally txid might not have been updated
         tx.txid = txid.to_string();
         let betx = BETransaction::deserialize(&tx_bytes[..], self.network.id())?;
@@ -1075,6 +1424,102 @@ impl ElectrumSession {
         Ok(tx)
     }
 
+    /// Replace the signed transaction with a PayJoin negotiated with the receiver endpoint, returning
+    /// it signed
+    fn payjoin(&self, tx: &TransactionMeta, endpoint: &str) -> Result<TransactionMeta, Error> {
+        let create_tx = tx
+            .create_transaction
+            .as_ref()
+            .ok_or_else(|| Error::Generic("Cannot payjoin without tx data".into()))?;
+        let account = self.get_account(create_tx.subaccount)?;
+        if !account.supports_payjoin() {
+            return Err(Error::UnsupportedPayjoin);
+        }
+        let original = match BETransaction::from_hex(&tx.hex, self.network.id())? {
+            BETransaction::Bitcoin(tx) => tx,
+            BETransaction::Elements(_) => return Err(Error::UnsupportedPayjoin),
+        };
+        let fee_rate = create_tx.fee_rate.unwrap_or(1000) as f64 / 1000.0;
+        let payees = create_tx
+            .addressees
+            .iter()
+            .filter_map(|a| bitcoin::Address::from_str(&a.address).ok())
+            .map(|a| a.script_pubkey())
+            .collect::<Vec<_>>();
+
+        let store = self.store()?;
+        let (original_psbt, params) = {
+            let store_read = store.read()?;
+            let acc_store = store_read.account_cache(account.num())?;
+            let prev_txs = original
+                .input
+                .iter()
+                .map(|txin| acc_store.get_bitcoin_tx(&txin.previous_output.txid))
+                .collect::<Result<Vec<_>, _>>()?;
+            // the receiver can take the fee of its inputs from our change
+            let additional_fee_output_index = original.output.iter().position(|o| {
+                !payees.contains(&o.script_pubkey)
+                    && acc_store.get_path(&o.script_pubkey.clone().into()).is_ok()
+            });
+            let params = PayjoinParams {
+                additional_fee_output_index,
+                max_additional_fee_contribution: match additional_fee_output_index {
+                    Some(_) => {
+                        marginal_fees(self.network.id(), fee_rate, account.script_template()).0
+                    }
+                    None => 0,
+                },
+                min_fee_rate: fee_rate,
+            };
+            (payjoin::original_psbt(&original, &prev_txs)?, params)
+        };
+
+        let agent = self.build_request_agent()?;
+        let proposal = payjoin::request_proposal(&agent, endpoint, &original_psbt, &params)?;
+        let (proposal, fee) = {
+            let store_read = store.read()?;
+            let acc_store = store_read.account_cache(account.num())?;
+            payjoin::check_proposal(&original_psbt, &proposal, &params, |script| {
+                acc_store.get_path(&script.clone().into()).is_ok()
+            })?
+        };
+        info!("payjoin proposal {} with fee {}", proposal.txid(), fee);
+
+        // only our inputs are signed, the ones of the receiver are already finalized
+        let used_utxos = proposal
+            .input
+            .iter()
+            .map(|txin| {
+                match original.input.iter().position(|o| o.previous_output == txin.previous_output)
+                {
+                    Some(i) => tx.used_utxos[i].clone(),
+                    None => UnspentOutput {
+                        skip_signing: true,
+                        ..Default::default()
+                    },
+                }
+            })
+            .collect();
+        let signed = account.sign(&TransactionMeta {
+            hex: BETransaction::Bitcoin(proposal).serialize().to_hex(),
+            used_utxos,
+            fee,
+            // the change indexes have already been used when signing the original
+            changes_used: None,
+            ..tx.clone()
+        })?;
+        Ok(TransactionMeta {
+            hex: signed.hex,
+            txid: signed.txid,
+            fee: signed.fee,
+            weight: signed.weight,
+            vsize: signed.vsize,
+            size: signed.size,
+            used_utxos: signed.used_utxos,
+            ..tx.clone()
+        })
+    }
+
     pub fn broadcast_transaction(&mut self, tx_hex: &str) -> Result<String, Error> {
         let transaction = BETransaction::from_hex(&tx_hex, self.network.id())?;
 
@@ -1096,9 +1541,11 @@ impl ElectrumSession {
             NetworkId::Bitcoin(_) => 1000,
             NetworkId::Elements(_) => 100,
         };
-        let fee_estimates =
-            try_get_fee_estimates(&self.url.build_client(self.proxy.as_deref(), None)?)
-                .unwrap_or_else(|_| vec![FeeEstimate(min_fee); 25]);
+        let fee_estimates = self
+            .url
+            .build_client(self.proxy.as_deref(), None)?
+            .fee_estimates()
+            .unwrap_or_else(|_| vec![FeeEstimate(min_fee); 25]);
         self.store()?.write()?.cache.fee_estimates = fee_estimates.clone();
         Ok(fee_estimates)
         //TODO better implement default
@@ -1133,8 +1580,13 @@ impl ElectrumSession {
 
         let num_confs = opt.num_confs.unwrap_or(0);
         let confidential_utxos_only = opt.confidential_utxos_only.unwrap_or(false);
+        let all_coins = opt.all_coins.unwrap_or(false);
 
         for outpoint in account.unspents()? {
+            let is_frozen = store_read.is_frozen(&outpoint);
+            if is_frozen && !all_coins {
+                continue;
+            }
             let utxo = account.txo(&outpoint, acc_store)?;
             let confirmations = match utxo.height {
                 None | Some(0) => 0,
@@ -1147,18 +1599,62 @@ impl ElectrumSession {
                 None => "btc".to_string(),
                 Some(s) => s.asset.to_hex(),
             };
-            (*unspent_outputs.entry(asset_id).or_insert(vec![])).push(utxo.try_into()?);
+            let mut utxo: UnspentOutput = utxo.try_into()?;
+            if let Some(label) = store_read.get_address_label(&utxo.scriptpubkey) {
+                utxo.address_label = label.clone();
+            }
+            if let Some(label) = store_read.get_output_label(&outpoint) {
+                utxo.output_label = label.clone();
+            }
+            if is_frozen {
+                utxo.user_status = USER_STATUS_FROZEN;
+            }
+            (*unspent_outputs.entry(asset_id).or_insert(vec![])).push(utxo);
         }
         Ok(GetUnspentOutputs(unspent_outputs))
     }
 
+    /// Freeze or unfreeze outputs of the wallet.
+    ///
+    /// Frozen outputs are not returned by `get_unspent_outputs` nor counted by `get_balance`
+    /// unless `all_coins` is set, and are never selected automatically when creating transactions.
+    pub fn set_unspent_outputs_status(&self, opt: SetUnspentOutputsStatusOpt) -> Result<(), Error> {
+        let mut outpoints = vec![];
+        for status in opt.list {
+            let is_frozen = match status.user_status {
+                USER_STATUS_DEFAULT => false,
+                USER_STATUS_FROZEN => true,
+                s => return Err(Error::Generic(format!("Invalid user status {}", s))),
+            };
+            let txid = BETxid::from_hex(&status.txhash, self.network.id())?;
+            outpoints.push((BEOutPoint::new(txid, status.pt_idx), is_frozen));
+        }
+        let unspents = self.wallet_unspents()?;
+        if let Some((outpoint, _)) = outpoints.iter().find(|(o, f)| *f && !unspents.contains(o)) {
+            let outpoint = format!("{}:{}", outpoint.txid(), outpoint.vout());
+            return Err(Error::Generic(format!("Unspent output not found ({})", outpoint)));
+        }
+        self.store()?.write()?.set_frozen(&outpoints)
+    }
+
+    /// The unspent outputs of all the subaccounts
+    fn wallet_unspents(&self) -> Result<HashSet<BEOutPoint>, Error> {
+        let mut unspents = HashSet::new();
+        for account in self.get_accounts()? {
+            unspents.extend(account.unspents()?);
+        }
+        Ok(unspents)
+    }
+
+    fn parse_address(&self, address: &str) -> Result<BEAddress, Error> {
+        Ok(match self.network.id() {
+            NetworkId::Bitcoin(_) => BEAddress::Bitcoin(bitcoin::Address::from_str(address)?),
+            NetworkId::Elements(_) => BEAddress::Elements(elements::Address::from_str(address)?),
+        })
+    }
+
     pub fn get_address_data(&self, opt: AddressDataRequest) -> Result<AddressDataResult, Error> {
-        let address = match self.network.id() {
-            NetworkId::Bitcoin(_) => BEAddress::Bitcoin(bitcoin::Address::from_str(&opt.address)?),
-            NetworkId::Elements(_) => {
-                BEAddress::Elements(elements::Address::from_str(&opt.address)?)
-            }
-        };
+        let address = self.parse_address(&opt.address)?;
         self.get_accounts()?
             .into_iter()
             .filter_map(|a| a.get_address_data(&address).ok())
@@ -1166,6 +1662,162 @@ impl ElectrumSession {
             .ok_or(Error::ScriptPubkeyNotFound)
     }
 
+    /// Sign a message with the key of a wallet address, proving its control
+    pub fn sign_message(&self, opt: SignMessageOpt) -> Result<SignMessageResult, Error> {
+        let address = self.parse_address(&opt.address)?;
+        for account in self.get_accounts()? {
+            match account.sign_message(&address, &opt.message, opt.bip322_full) {
+                Err(Error::ScriptPubkeyNotFound) => continue,
+                result => {
+                    return result.map(|signature| SignMessageResult {
+                        signature,
+                    })
+                }
+            }
+        }
+        Err(Error::ScriptPubkeyNotFound)
+    }
+
+    /// Set the label of an address, which is returned along with its script pubkey by
+    /// `get_previous_addresses`, `get_unspent_outputs` and `get_transactions`.
+    pub fn set_address_label(&self, opt: SetAddressLabelOpt) -> Result<(), Error> {
+        let address = self.parse_address(&opt.address)?;
+        if opt.label.len() > 1024 {
+            return Err(Error::Generic("Too long label (max 1024)".into()));
+        }
+        self.store()?.write()?.insert_address_label(&address.script_pubkey(), &opt.label)
+    }
+
+    pub fn get_address_label(&self, opt: GetAddressLabelOpt) -> Result<String, Error> {
+        let address = self.parse_address(&opt.address)?;
+        let store = self.store()?;
+        let store_read = store.read()?;
+        Ok(store_read.get_address_label(&address.script_pubkey()).cloned().unwrap_or_default())
+    }
+
+    /// Export the transaction memos, address and output labels and subaccount names in the
+    /// BIP329 format.
+    ///
+    /// Liquid addresses are exported unconfidential, since the labels are keyed by script pubkey.
+    pub fn export_labels(&self) -> Result<Bip329Labels, Error> {
+        let mut labels = vec![];
+        {
+            let store = self.store()?;
+            let store_read = store.read()?;
+            for (txid, memo) in store_read.memos() {
+                labels.push(Label::new(LabelType::Tx, txid.to_string(), memo.clone()));
+            }
+            for (script, label) in store_read.address_labels() {
+                let address = match self.network.id() {
+                    NetworkId::Bitcoin(network) => {
+                        bitcoin::Address::from_script(script, network).map(|a| a.to_string()).ok()
+                    }
+                    NetworkId::Elements(network) => {
+                        let script = script.clone().into_elements();
+                        elements::Address::from_script(&script, None, network.address_params())
+                            .map(|a| a.to_string())
+                    }
+                };
+                if let Some(address) = address {
+                    labels.push(Label::new(LabelType::Addr, address, label.clone()));
+                }
+            }
+            for (outpoint, label) in store_read.output_labels() {
+                labels.push(Label::new(LabelType::Output, outpoint.to_string(), label.clone()));
+            }
+            for outpoint in store_read.frozen_outpoints() {
+                let reference = outpoint.to_string();
+                match labels
+                    .iter_mut()
+                    .find(|l| l.type_ == LabelType::Output && l.reference == reference)
+                {
+                    Some(label) => label.spendable = Some(false),
+                    None => labels.push(Label {
+                        type_: LabelType::Output,
+                        reference,
+                        label: None,
+                        origin: None,
+                        spendable: Some(false),
+                    }),
+                }
+            }
+        }
+        for account in self.get_accounts()? {
+            let name = self.store()?.read()?.get_account_name(account.num()).cloned();
+            if let Some(name) = name.filter(|n| !n.is_empty()) {
+                labels.push(Label::new(LabelType::Xpub, account.xpub().to_string(), name));
+            }
+        }
+        labels.sort_by(|a, b| (a.type_, &a.reference).cmp(&(b.type_, &b.reference)));
+        Ok(Bip329Labels {
+            labels: serialize_labels(&labels)?,
+        })
+    }
+
+    /// Import labels in the BIP329 format, replacing the existing labels with the same reference
+    /// and keeping the others.
+    pub fn import_labels(&self, opt: Bip329Labels) -> Result<ImportLabelsResult, Error> {
+        let accounts = self.get_accounts()?;
+        let mut memos = HashMap::new();
+        let mut address_labels = HashMap::new();
+        let mut output_labels = HashMap::new();
+        let mut account_names = vec![];
+        let mut frozen = vec![];
+        let mut result = ImportLabelsResult::default();
+        for record in parse_labels(&opt.labels)? {
+            let label = record.label.filter(|l| !l.is_empty() && l.len() <= 1024);
+            let reference = &record.reference;
+            let imported = match (record.type_, label) {
+                // The spendable flag of the outputs maps to their frozen status
+                (LabelType::Output, label) => match bitcoin::OutPoint::from_str(reference) {
+                    Ok(outpoint) => {
+                        let be_outpoint = BEOutPoint::new(
+                            outpoint.txid.into_net(self.network.id()),
+                            outpoint.vout,
+                        );
+                        if let Some(spendable) = record.spendable {
+                            frozen.push((be_outpoint, !spendable));
+                        }
+                        let labeled = label.map(|l| output_labels.insert(outpoint, l)).is_some();
+                        labeled || record.spendable.is_some()
+                    }
+                    Err(_) => false,
+                },
+                (LabelType::Pubkey | LabelType::Input, _) | (_, None) => false,
+                (LabelType::Tx, Some(label)) => BETxid::from_hex(reference, self.network.id())
+                    .map(|txid| memos.insert(txid.into_bitcoin(), label))
+                    .is_ok(),
+                (LabelType::Addr, Some(label)) => self
+                    .parse_address(reference)
+                    .map(|a| address_labels.insert(a.script_pubkey().into_bitcoin(), label))
+                    .is_ok(),
+                (LabelType::Xpub, Some(label)) => match ExtendedPubKey::from_str(reference) {
+                    Ok(xpub) => {
+                        let account =
+                            accounts.iter().find(|a| xpubs_equivalent(a.xpub(), &xpub).is_ok());
+                        account.map(|a| account_names.push((a.clone(), label))).is_some()
+                    }
+                    Err(_) => false,
+                },
+            };
+            if imported {
+                result.imported += 1;
+            } else {
+                result.ignored += 1;
+            }
+        }
+        {
+            let store = self.store()?;
+            let mut store_write = store.write()?;
+            store_write.merge_labels(memos, address_labels, output_labels)?;
+            store_write.set_frozen(&frozen)?;
+        }
+        for (account, name) in account_names {
+            account.set_name(&name)?;
+        }
+        Ok(result)
+    }
+
     pub fn export_cache(&mut self) -> Result<RawCache, Error> {
         self.store()?.write()?.export_cache()
     }
@@ -1210,12 +1862,11 @@ pub fn keys_from_credentials(
 impl Tipper {
     pub fn tip(
         &self,
-        client: &Client,
+        client: &dyn BlockchainClient,
         update_cache: bool,
     ) -> Result<Option<(u32, BEBlockHeader)>, Error> {
-        let header = client.block_headers_subscribe_raw()?;
-        let new_height = header.height as u32;
-        let new_header = BEBlockHeader::deserialize(&header.header, self.network.id())?;
+        let (new_height, header) = client.tip()?;
+        let new_header = BEBlockHeader::deserialize(&header, self.network.id())?;
         if !update_cache {
             return Ok(Some((new_height, new_header)));
         }
@@ -1236,10 +1887,14 @@ impl Tipper {
 }
 
 impl Headers {
-    pub fn ask(&mut self, chunk_size: usize, client: &Client) -> Result<usize, Error> {
+    pub fn ask(
+        &mut self,
+        chunk_size: usize,
+        client: &dyn BlockchainClient,
+    ) -> Result<usize, Error> {
         if let ChainOrVerifier::Chain(chain) = &mut self.checker {
             info!("asking headers, current height:{} chunk_size:{} ", chain.height(), chunk_size);
-            let headers = client.block_headers(chain.height() as usize + 1, chunk_size)?.headers;
+            let headers = client.block_headers(chain.height() as usize + 1, chunk_size)?;
             let len = headers.len();
             chain.push(headers)?;
             Ok(len)
@@ -1249,7 +1904,7 @@ impl Headers {
         }
     }
 
-    pub fn get_proofs(&mut self, client: &Client) -> Result<usize, Error> {
+    pub fn get_proofs(&mut self, client: &dyn BlockchainClient) -> Result<usize, Error> {
         let mut proofs_done = 0;
         let account_nums = self.store.read()?.account_nums();
 
@@ -1360,15 +2015,24 @@ impl Headers {
 struct DownloadTxResult {
     txs: Vec<(BETxid, BETransaction)>,
     unblinds: Vec<(elements::OutPoint, elements::TxOutSecrets)>,
+    /// the ids and entropy of the assets newly issued by the downloaded txs
+    issuances: Vec<(elements::issuance::AssetId, [u8; 32])>,
     is_previous: HashSet<BETxid>,
 }
 
 impl Syncer {
     /// Sync the wallet, return the set of updated accounts
-    pub fn sync(&self, client: &Client) -> Result<Vec<TransactionNotification>, Error> {
+    pub fn sync(
+        &self,
+        client: &dyn BlockchainClient,
+    ) -> Result<Vec<TransactionNotification>, Error> {
         trace!("start sync");
         let start = Instant::now();
 
+        if self.network.liquid && self.store.read()?.cache.genesis_hash.is_none() {
+            self.download_genesis_hash(client)?;
+        }
+
         let accounts = self.accounts.read().unwrap();
         let mut updated_txs: HashMap<BETxid, TransactionNotification> = HashMap::new();
 
@@ -1379,18 +2043,34 @@ impl Syncer {
             let mut scripts = HashMap::new();
 
             let mut last_used = Indexes::default();
+            let gap_limit = account.gap_limit()?;
+            // the scripts of accounts without transactions might have past history if restored
+            client.set_rescan(self.store.read()?.account_cache(account.num())?.heights.is_empty());
+            if client.rescans_new_scripts() {
+                // look up the scripts ahead in one batch so that the chain is rescanned once,
+                // the batches below are then already known to the backend
+                let mut lookahead = vec![];
+                for is_internal in [false, true] {
+                    for batch_count in 0..RESCAN_LOOKAHEAD_BATCHES {
+                        let batch =
+                            account.get_script_batch(is_internal, batch_count, gap_limit)?;
+                        lookahead.extend(batch.value.into_iter().map(|e| e.0.into_bitcoin()));
+                    }
+                }
+                client.batch_script_get_history(&lookahead)?;
+            }
             let mut wallet_chains = vec![0, 1];
             wallet_chains.shuffle(&mut thread_rng());
             for i in wallet_chains {
                 let is_internal = i == 1;
                 let mut batch_count = 0;
                 loop {
-                    let batch = account.get_script_batch(is_internal, batch_count)?;
+                    let batch = account.get_script_batch(is_internal, batch_count, gap_limit)?;
                     // convert the BEScript into bitcoin::Script for electrum-client
                     let b_scripts =
                         batch.value.iter().map(|e| e.0.clone().into_bitcoin()).collect::<Vec<_>>();
                     let result: Vec<Vec<GetHistoryRes>> =
-                        client.batch_script_get_history(b_scripts.iter())?;
+                        client.batch_script_get_history(&b_scripts)?;
                     if !batch.cached {
                         scripts.extend(batch.value);
                     }
@@ -1402,9 +2082,9 @@ impl Syncer {
                         .max();
                     if let Some(max) = max {
                         if is_internal {
-                            last_used.internal = max + batch_count * BATCH_SIZE;
+                            last_used.internal = max + batch_count * gap_limit;
                         } else {
-                            last_used.external = max + batch_count * BATCH_SIZE;
+                            last_used.external = max + batch_count * gap_limit;
                         }
                     };
 
@@ -1436,12 +2116,13 @@ impl Syncer {
                 }
             }
 
-            let new_txs = self.download_txs(account.num(), &history_txs_id, &scripts, &client)?;
-            let headers = self.download_headers(account.num(), &heights_set, &client)?;
+            let new_txs = self.download_txs(account.num(), &history_txs_id, &scripts, client)?;
+            let headers = self.download_headers(account.num(), &heights_set, client)?;
 
             let store_read = self.store.read()?;
             let acc_store = store_read.account_cache(account.num())?;
             let store_indexes = acc_store.indexes.clone();
+            let store_last_used = acc_store.last_used.clone();
             let txs_heights_changed = txid_height
                 .iter()
                 .any(|(txid, height)| acc_store.heights.get(txid) != Some(height))
@@ -1449,9 +2130,15 @@ impl Syncer {
             drop(acc_store);
             drop(store_read);
 
+            // addresses already returned are never returned again, even if still unused
+            let indexes = Indexes {
+                external: store_indexes.external.max(last_used.external),
+                internal: store_indexes.internal.max(last_used.internal),
+            };
             let changed = if !new_txs.txs.is_empty()
                 || !headers.is_empty()
-                || store_indexes != last_used
+                || store_indexes != indexes
+                || store_last_used != last_used
                 || !scripts.is_empty()
                 || txs_heights_changed
             {
@@ -1462,14 +2149,39 @@ impl Syncer {
                     txid_height
                 );
                 let mut store_write = self.store.write()?;
+
+                // txs no longer confirmed at their height were in blocks reorged away, they are
+                // verified again, and the headers of those blocks are downloaded again if needed
+                let reorged: Vec<(BETxid, u32)> = store_write
+                    .account_cache(account.num())?
+                    .heights
+                    .iter()
+                    .filter_map(|(txid, height)| Some((*txid, (*height)?)))
+                    .filter(|(txid, height)| txid_height.get(txid) != Some(&Some(*height)))
+                    .collect();
+                for (txid, height) in reorged {
+                    store_write.cache.txs_verif.remove(&txid);
+                    let still_confirmed = txid_height.values().any(|h| *h == Some(height))
+                        || store_write
+                            .cache
+                            .accounts
+                            .iter()
+                            .filter(|(num, _)| **num != account.num())
+                            .any(|(_, acc)| acc.heights.values().any(|h| *h == Some(height)));
+                    if !still_confirmed {
+                        store_write.cache.headers.remove(&height);
+                    }
+                }
                 store_write.cache.headers.extend(headers);
 
                 let mut acc_store = store_write.account_cache_mut(account.num())?;
-                acc_store.indexes = last_used;
+                acc_store.indexes = indexes;
+                acc_store.last_used = last_used;
                 acc_store
                     .all_txs
                     .extend(new_txs.txs.iter().cloned().map(|(txid, tx)| (txid, tx.into())));
                 acc_store.unblinded.extend(new_txs.unblinds);
+                acc_store.issuances.extend(new_txs.issuances);
 
                 // height map is used for the live list of transactions, since due to reorg or rbf tx
                 // could disappear from the list, we clear the list and keep only the last values returned by the server
@@ -1531,6 +2243,14 @@ impl Syncer {
             );
         }
 
+        if let Some(state) = client.scan_state()? {
+            let mut store_write = self.store.write()?;
+            if store_write.cache.filters_state.as_ref() != Some(&state) {
+                store_write.cache.filters_state = Some(state);
+                store_write.flush()?;
+            }
+        }
+        self.store.write()?.prune_frozen()?;
         self.empty_recent_spent_utxos()?;
         Ok(updated_txs.into_values().collect())
     }
@@ -1560,11 +2280,23 @@ impl Syncer {
         }
     }
 
+    /// The genesis block hash differs between elements chains, even among regtest ones
+    fn download_genesis_hash(&self, client: &dyn BlockchainClient) -> Result<(), Error> {
+        let header = client
+            .batch_block_header_raw(vec![0])?
+            .pop()
+            .ok_or_else(|| Error::Generic("missing genesis block header".into()))?;
+        let header = BEBlockHeader::deserialize(&header, self.network.id())?;
+        info!("genesis block hash {:?}", header.block_hash());
+        self.store.write()?.cache.genesis_hash = Some(header.block_hash());
+        Ok(())
+    }
+
     fn download_headers(
         &self,
         account_num: u32,
         heights_set: &HashSet<u32>,
-        client: &Client,
+        client: &dyn BlockchainClient,
     ) -> Result<Vec<(u32, BEBlockHeader)>, Error> {
         let heights_in_db: HashSet<u32> = {
             let store_read = self.store.read()?;
@@ -1598,10 +2330,11 @@ impl Syncer {
         account_num: u32,
         history_txs_id: &HashSet<BETxid>,
         scripts: &HashMap<BEScript, DerivationPath>,
-        client: &Client,
+        client: &dyn BlockchainClient,
     ) -> Result<DownloadTxResult, Error> {
         let mut txs = vec![];
         let mut unblinds = vec![];
+        let mut issuances = vec![];
         let mut is_previous = HashSet::new();
 
         let mut txs_in_db =
@@ -1610,7 +2343,7 @@ impl Syncer {
         let txs_to_download: Vec<bitcoin::Txid> =
             history_txs_id.difference(&txs_in_db).map(BETxidConvert::into_bitcoin).collect();
         if !txs_to_download.is_empty() {
-            let txs_bytes_downloaded = client.batch_transaction_get_raw(txs_to_download.iter())?;
+            let txs_bytes_downloaded = client.batch_transaction_get_raw(&txs_to_download)?;
             let mut txs_downloaded: Vec<BETransaction> = vec![];
             for vec in txs_bytes_downloaded {
                 let tx = BETransaction::deserialize(&vec, self.network.id())?;
@@ -1649,6 +2382,19 @@ impl Syncer {
                             }
                         }
                     }
+                    // reissuances need the entropy of the asset, which for a new issuance is
+                    // computed from the spent outpoint and the contract hash, while a
+                    // reissuance carries it
+                    for input in tx.input.iter().filter(|i| i.has_issuance()) {
+                        let issuance = &input.asset_issuance;
+                        let entropy = if issuance.asset_blinding_nonce == ZERO_TWEAK {
+                            let contract_hash = ContractHash::from_inner(issuance.asset_entropy);
+                            AssetId::generate_asset_entropy(input.previous_output, contract_hash)
+                        } else {
+                            sha256::Midstate::from_inner(issuance.asset_entropy)
+                        };
+                        issuances.push((AssetId::from_entropy(entropy), entropy.into_inner()));
+                    }
                 } else {
                     // download all previous output only for bitcoin (to calculate fee of incoming tx)
                     for previous_txid in tx.previous_output_txids() {
@@ -1664,8 +2410,22 @@ impl Syncer {
                 .collect();
 
             if !txs_to_download.is_empty() {
-                let txs_bytes_downloaded =
-                    client.batch_transaction_get_raw(txs_to_download.iter())?;
+                let txs_bytes_downloaded = if client.indexes_all_transactions() {
+                    client.batch_transaction_get_raw(&txs_to_download)?
+                } else {
+                    // compact block filters can't provide txs not involving the wallet, which are
+                    // needed only to compute the fee of incoming txs
+                    txs_to_download
+                        .iter()
+                        .filter_map(|txid| match client.batch_transaction_get_raw(&[*txid]) {
+                            Ok(mut txs) => txs.pop(),
+                            Err(e) => {
+                                warn!("cannot download previous tx {}, ignoring {:?}", txid, e);
+                                None
+                            }
+                        })
+                        .collect()
+                };
                 for vec in txs_bytes_downloaded {
                     let tx = BETransaction::deserialize(&vec, self.network.id())?;
                     let txid = tx.txid();
@@ -1678,6 +2438,7 @@ impl Syncer {
             Ok(DownloadTxResult {
                 txs,
                 unblinds,
+                issuances,
                 is_previous,
             })
         } else {
@@ -1728,6 +2489,12 @@ fn wait_or_close(user_wants_to_sync: &Arc<AtomicBool>, interval: u32) -> bool {
     false
 }
 
+fn bitcoin_psbt_from_base64(
+    psbt: &str,
+) -> Result<bitcoin::util::psbt::PartiallySignedTransaction, Error> {
+    Ok(bitcoin::consensus::deserialize(&base64::decode(psbt)?)?)
+}
+
 #[cfg(feature = "testing")]
 impl ElectrumSession {
     pub fn filter_events(&self, event: &str) -> Vec<Value> {
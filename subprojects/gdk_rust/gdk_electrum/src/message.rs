@@ -0,0 +1,340 @@
+//! Messages signed with the key of an address, proving its control.
+//!
+//! P2PKH and P2SH-P2WPKH addresses use the legacy Bitcoin Signed Message format, with the BIP137
+//! header identifying the address type. Native segwit and taproot addresses use BIP322, whose
+//! signatures are either "simple", the witness spending the BIP322 `to_spend` transaction, or
+//! "full", the whole `to_sign` transaction.
+
+use std::str::FromStr;
+
+use gdk_common::bitcoin::blockdata::opcodes;
+use gdk_common::bitcoin::blockdata::script::Builder;
+use gdk_common::bitcoin::consensus::{deserialize, serialize};
+use gdk_common::bitcoin::hashes::{sha256, Hash, HashEngine};
+use gdk_common::bitcoin::secp256k1::ecdsa::{RecoverableSignature, RecoveryId, Signature};
+use gdk_common::bitcoin::secp256k1::{Message, XOnlyPublicKey};
+use gdk_common::bitcoin::util::bip32::DerivationPath;
+use gdk_common::bitcoin::util::misc::signed_msg_hash;
+use gdk_common::bitcoin::util::sighash::{Prevouts, SighashCache};
+use gdk_common::bitcoin::util::taproot::TapTweakHash;
+use gdk_common::bitcoin::{
+    Address, EcdsaSig, EcdsaSighashType, OutPoint, PackedLockTime, PublicKey, SchnorrSig,
+    SchnorrSighashType, Script, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use gdk_common::scripts::{p2pkh_script, ScriptType};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::signer::Signer;
+
+/// BIP137 header of the signatures of P2PKH addresses with compressed keys, plus the recovery id
+const P2PKH_HEADER: u8 = 31;
+
+/// BIP137 header of the signatures of P2SH-P2WPKH addresses, plus the recovery id
+const P2SH_P2WPKH_HEADER: u8 = 35;
+
+/// BIP137 header of the signatures of P2WPKH addresses, which we sign with BIP322 but verify in
+/// the legacy format too
+const P2WPKH_HEADER: u8 = 39;
+
+const BIP322_TAG: &[u8] = b"BIP0322-signed-message";
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyMessageParam {
+    pub address: String,
+    pub message: String,
+    /// The signature in base64, in the legacy format or BIP322 simple or full
+    pub signature: String,
+}
+#[derive(Debug, Serialize)]
+pub struct VerifyMessageResult {
+    pub valid: bool,
+}
+/// Verify that the message is signed by the key of the Bitcoin address
+pub fn verify_message(param: &VerifyMessageParam) -> Result<VerifyMessageResult, Error> {
+    let address = Address::from_str(&param.address).map_err(|_| Error::InvalidAddress)?;
+    let valid = match base64::decode(&param.signature) {
+        Ok(signature) => verify(&address, &param.message, &signature),
+        Err(_) => false,
+    };
+    Ok(VerifyMessageResult {
+        valid,
+    })
+}
+
+fn verify(address: &Address, message: &str, signature: &[u8]) -> bool {
+    if signature.len() == 65 && (27..=42).contains(&signature[0]) {
+        return verify_legacy(address, message, signature).unwrap_or(false);
+    }
+    let to_spend = bip322_to_spend(&address.script_pubkey(), message);
+    let to_sign = match deserialize::<Witness>(signature) {
+        Ok(witness) => {
+            let mut to_sign = bip322_to_sign(&to_spend);
+            to_sign.input[0].witness = witness;
+            to_sign
+        }
+        Err(_) => match deserialize::<Transaction>(signature) {
+            Ok(to_sign) => to_sign,
+            Err(_) => return false,
+        },
+    };
+    verify_bip322(&to_spend, &to_sign).unwrap_or(false)
+}
+
+fn verify_legacy(address: &Address, message: &str, signature: &[u8]) -> Option<bool> {
+    let header = signature[0];
+    let recovery_id = RecoveryId::from_i32(((header - 27) % 4) as i32).ok()?;
+    let signature = RecoverableSignature::from_compact(&signature[1..], recovery_id).ok()?;
+    let message = Message::from_slice(&signed_msg_hash(message)[..]).ok()?;
+    let public_key = PublicKey {
+        inner: crate::EC.recover_ecdsa(&message, &signature).ok()?,
+        compressed: header >= P2PKH_HEADER,
+    };
+    let expected = if header < P2SH_P2WPKH_HEADER {
+        Script::new_p2pkh(&public_key.pubkey_hash())
+    } else if header < P2WPKH_HEADER {
+        Script::new_p2sh(&p2wpkh(&public_key)?.script_hash())
+    } else {
+        p2wpkh(&public_key)?
+    };
+    Some(expected == address.script_pubkey())
+}
+
+fn p2wpkh(public_key: &PublicKey) -> Option<Script> {
+    Some(Script::new_v0_p2wpkh(&public_key.wpubkey_hash()?))
+}
+
+fn verify_bip322(to_spend: &Transaction, to_sign: &Transaction) -> Option<bool> {
+    let spends_to_spend = to_sign.input.len() == 1
+        && to_sign.input[0].previous_output == OutPoint::new(to_spend.txid(), 0)
+        && to_sign.output.len() == 1
+        && to_sign.output[0].script_pubkey.is_op_return();
+    if !spends_to_spend {
+        return Some(false);
+    }
+    let script_pubkey = &to_spend.output[0].script_pubkey;
+    let witness = to_sign.input[0].witness.to_vec();
+    let mut cache = SighashCache::new(to_sign);
+    if script_pubkey.is_v0_p2wpkh() && witness.len() == 2 {
+        let signature = EcdsaSig::from_slice(&witness[0]).ok()?;
+        let public_key = PublicKey::from_slice(&witness[1]).ok()?;
+        if p2wpkh(&public_key)? != *script_pubkey {
+            return Some(false);
+        }
+        let script_code = p2pkh_script(&public_key);
+        let hash = cache.segwit_signature_hash(0, &script_code, 0, signature.hash_ty).ok()?;
+        let message = Message::from_slice(&hash[..]).ok()?;
+        Some(crate::EC.verify_ecdsa(&message, &signature.sig, &public_key.inner).is_ok())
+    } else if script_pubkey.is_v1_p2tr() && witness.len() == 1 {
+        let output_key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..]).ok()?;
+        let signature = SchnorrSig::from_slice(&witness[0]).ok()?;
+        let prevouts = Prevouts::All(&to_spend.output);
+        let hash = cache.taproot_key_spend_signature_hash(0, &prevouts, signature.hash_ty).ok()?;
+        let message = Message::from_slice(&hash[..]).ok()?;
+        Some(crate::EC.verify_schnorr(&signature.sig, &message, &output_key).is_ok())
+    } else {
+        Some(false)
+    }
+}
+
+/// Sign the message with the key at `path`, of a single key script of type `script_type`,
+/// returning the signature in base64
+pub(crate) fn sign_message(
+    signer: &dyn Signer,
+    path: &DerivationPath,
+    public_key: &PublicKey,
+    script_type: ScriptType,
+    message: &str,
+    bip322_full: bool,
+) -> Result<String, Error> {
+    let signature = match script_type {
+        ScriptType::P2pkh | ScriptType::P2shP2wpkh => {
+            let message = Message::from_slice(&signed_msg_hash(message)[..]).unwrap();
+            let signature = signer.sign_ecdsa(path, &message)?;
+            let (recovery_id, compact) =
+                recoverable(&signature, &message, public_key)?.serialize_compact();
+            let header = match script_type {
+                ScriptType::P2pkh => P2PKH_HEADER,
+                _ => P2SH_P2WPKH_HEADER,
+            };
+            let mut signature = vec![header + recovery_id.to_i32() as u8];
+            signature.extend_from_slice(&compact);
+            signature
+        }
+        ScriptType::P2wpkh | ScriptType::P2tr => {
+            let (internal_key, _) = public_key.inner.x_only_public_key();
+            let script_pubkey = match script_type {
+                ScriptType::P2tr => Script::new_v1_p2tr(&crate::EC, internal_key, None),
+                _ => p2wpkh(public_key).expect("compressed key"),
+            };
+            let to_spend = bip322_to_spend(&script_pubkey, message);
+            let mut to_sign = bip322_to_sign(&to_spend);
+            let mut cache = SighashCache::new(&to_sign);
+            let witness = if script_type == ScriptType::P2tr {
+                let hash = cache.taproot_key_spend_signature_hash(
+                    0,
+                    &Prevouts::All(&to_spend.output),
+                    SchnorrSighashType::Default,
+                )?;
+                let tweak = TapTweakHash::from_key_and_tweak(internal_key, None).to_scalar();
+                let message = Message::from_slice(&hash[..]).unwrap();
+                vec![signer.sign_schnorr(path, &message, &tweak)?.as_ref().to_vec()]
+            } else {
+                let script_code = p2pkh_script(public_key);
+                let hash =
+                    cache.segwit_signature_hash(0, &script_code, 0, EcdsaSighashType::All)?;
+                let message = Message::from_slice(&hash[..]).unwrap();
+                let signature = EcdsaSig::sighash_all(signer.sign_ecdsa(path, &message)?);
+                vec![signature.to_vec(), public_key.to_bytes()]
+            };
+            to_sign.input[0].witness = Witness::from_vec(witness);
+            if bip322_full {
+                serialize(&to_sign)
+            } else {
+                serialize(&to_sign.input[0].witness)
+            }
+        }
+        ScriptType::P2wsh | ScriptType::P2shP2wsh => {
+            return Err(Error::Generic("multisig subaccounts cannot sign messages".into()))
+        }
+    };
+    Ok(base64::encode(signature))
+}
+
+/// The recoverable form of the signature, whose recovery id is found trying all of them
+fn recoverable(
+    signature: &Signature,
+    message: &Message,
+    public_key: &PublicKey,
+) -> Result<RecoverableSignature, Error> {
+    let compact = signature.serialize_compact();
+    (0..4)
+        .filter_map(|id| RecoveryId::from_i32(id).ok())
+        .filter_map(|id| RecoverableSignature::from_compact(&compact, id).ok())
+        .find(|sig| crate::EC.recover_ecdsa(message, sig).ok() == Some(public_key.inner))
+        .ok_or_else(|| Error::Generic("the signature is not of the address key".into()))
+}
+
+/// The BIP322 transaction committing to the message, whose output is spent by the signature
+fn bip322_to_spend(script_pubkey: &Script, message: &str) -> Transaction {
+    let tag = sha256::Hash::hash(BIP322_TAG);
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag);
+    engine.input(&tag);
+    engine.input(message.as_bytes());
+    let message_hash = sha256::Hash::from_engine(engine);
+
+    Transaction {
+        version: 0,
+        lock_time: PackedLockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0xFFFFFFFF),
+            script_sig: Builder::new().push_int(0).push_slice(&message_hash[..]).into_script(),
+            sequence: Sequence(0),
+            witness: Witness::default(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    }
+}
+
+/// The unsigned BIP322 transaction spending `to_spend`
+fn bip322_to_sign(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: 0,
+        lock_time: PackedLockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.txid(), 0),
+            script_sig: Script::new(),
+            sequence: Sequence(0),
+            witness: Witness::default(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: Builder::new().push_opcode(opcodes::all::OP_RETURN).into_script(),
+        }],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::signer::SoftwareSigner;
+    use gdk_common::bitcoin::util::bip32::ExtendedPrivKey;
+    use gdk_common::bitcoin::Network;
+    use gdk_common::wally::MasterBlindingKey;
+
+    // Test vectors of BIP322
+    const ADDRESS: &str = "bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l";
+    const HELLO_WORLD: &str = "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=";
+
+    #[test]
+    fn test_bip322_message_hash() {
+        let to_spend = bip322_to_spend(&Script::new(), "Hello World");
+        let script_sig = to_spend.input[0].script_sig.as_bytes();
+        assert_eq!(
+            sha256::Hash::from_slice(&script_sig[2..]).unwrap().to_string(),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+    }
+
+    #[test]
+    fn test_verify_bip322() {
+        let verify = |message: &str, signature: &str| {
+            verify_message(&VerifyMessageParam {
+                address: ADDRESS.into(),
+                message: message.into(),
+                signature: signature.into(),
+            })
+            .unwrap()
+            .valid
+        };
+        assert!(verify("Hello World", HELLO_WORLD));
+        assert!(!verify("Hello World!", HELLO_WORLD));
+        assert!(!verify("Hello World", "invalid"));
+        assert!(verify_message(&VerifyMessageParam {
+            address: "invalid".into(),
+            message: "Hello World".into(),
+            signature: HELLO_WORLD.into(),
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let master_xprv = ExtendedPrivKey::new_master(Network::Regtest, &[1u8; 32]).unwrap();
+        let signer = SoftwareSigner::new(master_xprv, MasterBlindingKey([2u8; 64]));
+        let path = DerivationPath::from_str("m/84'/1'/0'/0/1").unwrap();
+        let public_key = signer.xpub(&path).unwrap().to_pub();
+        let (internal_key, _) = public_key.inner.x_only_public_key();
+        let wpkh = p2wpkh(&public_key).unwrap();
+        let scripts = [
+            (ScriptType::P2pkh, Script::new_p2pkh(&public_key.pubkey_hash())),
+            (ScriptType::P2shP2wpkh, Script::new_p2sh(&wpkh.script_hash())),
+            (ScriptType::P2wpkh, wpkh.clone()),
+            (ScriptType::P2tr, Script::new_v1_p2tr(&crate::EC, internal_key, None)),
+        ];
+        for (script_type, script_pubkey) in scripts.iter() {
+            let address = Address::from_script(script_pubkey, Network::Regtest).unwrap();
+            for bip322_full in [false, true] {
+                let signature =
+                    sign_message(&signer, &path, &public_key, *script_type, "msg", bip322_full)
+                        .unwrap();
+                let verify = |message: &str| {
+                    verify_message(&VerifyMessageParam {
+                        address: address.to_string(),
+                        message: message.into(),
+                        signature: signature.clone(),
+                    })
+                    .unwrap()
+                    .valid
+                };
+                assert!(verify("msg"), "{:?} {}", script_type, bip322_full);
+                assert!(!verify("other msg"));
+            }
+        }
+        assert!(sign_message(&signer, &path, &public_key, ScriptType::P2wsh, "msg", false).is_err());
+    }
+}
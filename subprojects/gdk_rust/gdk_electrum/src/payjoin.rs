@@ -0,0 +1,353 @@
+//! Sender side of PayJoin as defined by BIP78.
+//!
+//! The signed original transaction is sent as a PSBT to the endpoint of the receiver, which replies
+//! with a proposal adding some of its own inputs. The proposal is checked against the sender
+//! checklist of the BIP before our inputs are signed again. Output substitution is always
+//! disabled, so the receiver can not change the outputs we are paying.
+
+use std::collections::HashSet;
+
+use gdk_common::bitcoin::consensus::{deserialize, serialize};
+use gdk_common::bitcoin::util::psbt::PartiallySignedTransaction;
+use gdk_common::bitcoin::{Script, Transaction, Witness};
+use gdk_common::log::info;
+use gdk_common::ureq;
+use gdk_common::util::weight_to_vsize;
+
+use crate::error::Error;
+use crate::psbt::input_prevout;
+
+/// The optional parameters of the request to the receiver
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayjoinParams {
+    /// The index of our output that the receiver can decrease to pay the fee of its inputs
+    pub additional_fee_output_index: Option<usize>,
+
+    /// The maximum amount in satoshi the receiver can take from that output
+    pub max_additional_fee_contribution: u64,
+
+    /// The minimum fee rate of the proposal in satoshi/vbyte
+    pub min_fee_rate: f64,
+}
+
+/// Check that the endpoint is an https url, or an http one for onion services or if `allow_http`
+pub fn validate_endpoint(endpoint: &str, allow_http: bool) -> Result<(), Error> {
+    let invalid = || Error::InvalidPayjoinEndpoint(endpoint.to_string());
+    let (scheme, rest) = endpoint.split_once("://").ok_or_else(invalid)?;
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority.rsplit('@').next().unwrap_or_default();
+    let host = host.split(':').next().unwrap_or_default().to_ascii_lowercase();
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    match scheme.to_ascii_lowercase().as_str() {
+        "https" => Ok(()),
+        "http" if allow_http || host.ends_with(".onion") => Ok(()),
+        _ => Err(invalid()),
+    }
+}
+
+/// Build the original PSBT from the signed transaction and the transactions spent by its inputs,
+/// in the same order
+pub fn original_psbt(
+    tx: &Transaction,
+    prev_txs: &[Transaction],
+) -> Result<PartiallySignedTransaction, Error> {
+    let mut unsigned_tx = tx.clone();
+    for txin in unsigned_tx.input.iter_mut() {
+        txin.script_sig = Script::new();
+        txin.witness = Witness::default();
+    }
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx)?;
+    for ((input, txin), prev_tx) in psbt.inputs.iter_mut().zip(tx.input.iter()).zip(prev_txs) {
+        if txin.script_sig.is_empty() && txin.witness.is_empty() {
+            return Err(Error::Generic("PayJoin requires a signed transaction".into()));
+        }
+        if !txin.script_sig.is_empty() {
+            input.final_script_sig = Some(txin.script_sig.clone());
+        }
+        if txin.witness.is_empty() {
+            input.non_witness_utxo = Some(prev_tx.clone());
+        } else {
+            input.final_script_witness = Some(txin.witness.clone());
+            let vout = txin.previous_output.vout as usize;
+            input.witness_utxo = Some(prev_tx.output.get(vout).cloned().ok_or_else(|| {
+                Error::Generic("Mismatching previous transactions and inputs".into())
+            })?);
+        }
+    }
+    Ok(psbt)
+}
+
+/// Send the original PSBT to the endpoint of the receiver and return its proposal
+pub fn request_proposal(
+    agent: &ureq::Agent,
+    endpoint: &str,
+    original: &PartiallySignedTransaction,
+    params: &PayjoinParams,
+) -> Result<PartiallySignedTransaction, Error> {
+    let mut request = agent
+        .post(endpoint)
+        .set("Content-Type", "text/plain")
+        .query("v", "1")
+        .query("disableoutputsubstitution", "true")
+        .query("minfeerate", &params.min_fee_rate.to_string());
+    if let Some(index) = params.additional_fee_output_index {
+        request = request.query("additionalfeeoutputindex", &index.to_string()).query(
+            "maxadditionalfeecontribution",
+            &params.max_additional_fee_contribution.to_string(),
+        );
+    }
+    info!("payjoin request to {}", request.url());
+
+    let proposal = match request.send_string(&base64::encode(serialize(original))) {
+        Ok(response) => response.into_string()?,
+        // the body is a json with the `errorCode` and the `message` of the receiver
+        Err(ureq::Error::Status(status, response)) => {
+            let reason = response.into_string().unwrap_or_default();
+            return Err(Error::InvalidPayjoinProposal(format!("status {} {}", status, reason)));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    Ok(deserialize(&base64::decode(proposal.trim())?)?)
+}
+
+/// Check the proposal against the sender checklist of BIP78, `is_mine` tells whether a script
+/// belongs to the wallet.
+///
+/// Returns the proposal transaction with the inputs of the receiver finalized, ours still to be
+/// signed, and its fee.
+pub fn check_proposal(
+    original: &PartiallySignedTransaction,
+    proposal: &PartiallySignedTransaction,
+    params: &PayjoinParams,
+    is_mine: impl Fn(&Script) -> bool,
+) -> Result<(Transaction, u64), Error> {
+    let invalid = |reason: &str| Error::InvalidPayjoinProposal(reason.to_string());
+    let original_tx = &original.unsigned_tx;
+    let mut tx = proposal.unsigned_tx.clone();
+    if tx.version != original_tx.version || tx.lock_time != original_tx.lock_time {
+        return Err(invalid("changed version or locktime"));
+    }
+
+    let original_prevouts = original_tx
+        .input
+        .iter()
+        .zip(original.inputs.iter())
+        .map(|(txin, input)| input_prevout(input, &txin.previous_output))
+        .collect::<Result<Option<Vec<_>>, _>>()?
+        .ok_or_else(|| invalid("missing previous outputs of the original"))?;
+    let script_kind =
+        |script: &Script| (script.is_p2pkh(), script.is_p2sh(), script.is_v0_p2wpkh());
+    let our_script_kind =
+        script_kind(&original_prevouts.first().ok_or_else(|| invalid("no inputs"))?.script_pubkey);
+
+    // the size of the proposal once signed, our signatures have the size of the original ones
+    let mut signed_tx = tx.clone();
+    let mut our_inputs = HashSet::new();
+    let mut input_value = 0;
+    let mut receiver_inputs_weight = 0;
+    for (i, input) in proposal.inputs.iter().enumerate() {
+        let txin = &mut tx.input[i];
+        let prevout = match original_tx
+            .input
+            .iter()
+            .position(|o| o.previous_output == txin.previous_output)
+        {
+            Some(j) => {
+                if txin.sequence != original_tx.input[j].sequence {
+                    return Err(invalid("changed sequence of our input"));
+                }
+                if input.final_script_sig.is_some()
+                    || input.final_script_witness.is_some()
+                    || !input.partial_sigs.is_empty()
+                {
+                    return Err(invalid("signed our input"));
+                }
+                our_inputs.insert(j);
+                signed_tx.input[i].script_sig =
+                    original.inputs[j].final_script_sig.clone().unwrap_or_default();
+                signed_tx.input[i].witness =
+                    original.inputs[j].final_script_witness.clone().unwrap_or_default();
+                original_prevouts[j].clone()
+            }
+            None => {
+                if txin.sequence != original_tx.input[0].sequence {
+                    return Err(invalid("receiver input with a different sequence"));
+                }
+                if input.final_script_sig.is_none() && input.final_script_witness.is_none() {
+                    return Err(invalid("receiver input not finalized"));
+                }
+                let prevout = input_prevout(input, &txin.previous_output)
+                    .map_err(|_| invalid("inconsistent previous outputs of a receiver input"))?
+                    .ok_or_else(|| invalid("missing previous output of a receiver input"))?;
+                if is_mine(&prevout.script_pubkey) {
+                    return Err(invalid("receiver input belonging to the wallet"));
+                }
+                if script_kind(&prevout.script_pubkey) != our_script_kind {
+                    return Err(invalid("receiver input of a different script type"));
+                }
+                txin.script_sig = input.final_script_sig.clone().unwrap_or_default();
+                txin.witness = input.final_script_witness.clone().unwrap_or_default();
+                signed_tx.input[i] = txin.clone();
+                receiver_inputs_weight +=
+                    serialize(txin).len() * 4 + serialize(&txin.witness).len();
+                prevout
+            }
+        };
+        input_value += prevout.value;
+    }
+    if our_inputs.len() != original_tx.input.len() {
+        return Err(invalid("missing some of our inputs"));
+    }
+
+    // every original output must be in the proposal, with only the one chosen for the additional
+    // fee allowed to decrease
+    let mut matched = vec![false; tx.output.len()];
+    let mut contribution = 0;
+    for (vout, output) in original_tx.output.iter().enumerate() {
+        let i = (0..tx.output.len())
+            .find(|&i| !matched[i] && tx.output[i].script_pubkey == output.script_pubkey)
+            .ok_or_else(|| invalid("missing output"))?;
+        matched[i] = true;
+        if Some(vout) == params.additional_fee_output_index {
+            contribution = output.value.saturating_sub(tx.output[i].value);
+        } else if tx.output[i].value < output.value {
+            return Err(invalid("decreased output"));
+        }
+    }
+    if contribution > params.max_additional_fee_contribution {
+        return Err(invalid("fee contribution exceeding the maximum"));
+    }
+
+    let output_value: u64 = tx.output.iter().map(|o| o.value).sum();
+    let fee = input_value.checked_sub(output_value).ok_or_else(|| invalid("negative fee"))?;
+    let original_fee = original_prevouts.iter().map(|o| o.value).sum::<u64>()
+        - original_tx.output.iter().map(|o| o.value).sum::<u64>();
+    if fee < original_fee + contribution {
+        return Err(invalid("fee contribution not used for the fee"));
+    }
+    // the contribution can only pay for the receiver inputs at the fee rate of the original
+    let mut original_signed_tx = original_tx.clone();
+    for (txin, input) in original_signed_tx.input.iter_mut().zip(original.inputs.iter()) {
+        txin.script_sig = input.final_script_sig.clone().unwrap_or_default();
+        txin.witness = input.final_script_witness.clone().unwrap_or_default();
+    }
+    let original_fee_rate = original_fee as f64 / original_signed_tx.vsize() as f64;
+    let receiver_inputs_vsize = weight_to_vsize(receiver_inputs_weight);
+    if contribution as f64 > original_fee_rate * receiver_inputs_vsize as f64 {
+        return Err(invalid("fee contribution exceeding the fee of the receiver inputs"));
+    }
+    let vsize = signed_tx.vsize();
+    if (fee as f64) < params.min_fee_rate * vsize as f64 {
+        return Err(invalid("fee rate below the minimum"));
+    }
+    Ok((tx, fee))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::bitcoin::hashes::Hash;
+    use gdk_common::bitcoin::{OutPoint, PackedLockTime, Sequence, TxIn, TxOut, Txid, WPubkeyHash};
+
+    fn p2wpkh(n: u8) -> Script {
+        Script::new_v0_p2wpkh(&WPubkeyHash::from_inner([n; 20]))
+    }
+
+    fn txin(n: u8) -> TxIn {
+        TxIn {
+            previous_output: OutPoint::new(Txid::from_inner([n; 32]), 0),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::from_vec(vec![vec![0u8; 72], vec![0u8; 33]]),
+            ..Default::default()
+        }
+    }
+
+    fn txout(value: u64, n: u8) -> TxOut {
+        TxOut {
+            value,
+            script_pubkey: p2wpkh(n),
+        }
+    }
+
+    /// An original paying 50_000 with a change of 49_000 and its proposal adding an input of
+    /// the receiver of 20_000, paying 300 of its fee from our change
+    fn original_and_proposal() -> (PartiallySignedTransaction, PartiallySignedTransaction) {
+        let prev_tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![],
+            output: vec![txout(100_000, 1)],
+        };
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(100),
+            input: vec![txin(1)],
+            output: vec![txout(50_000, 2), txout(49_000, 3)],
+        };
+        tx.input[0].previous_output = OutPoint::new(prev_tx.txid(), 0);
+        let original = original_psbt(&tx, &[prev_tx]).unwrap();
+
+        let mut proposal_tx = original.unsigned_tx.clone();
+        proposal_tx.input.insert(0, txin(4));
+        proposal_tx.input[0].witness = Witness::default();
+        proposal_tx.output[0].value += 20_000;
+        proposal_tx.output[1].value -= 300;
+        let mut proposal = PartiallySignedTransaction::from_unsigned_tx(proposal_tx).unwrap();
+        proposal.inputs[0].witness_utxo = Some(txout(20_000, 4));
+        proposal.inputs[0].final_script_witness = Some(txin(4).witness);
+        (original, proposal)
+    }
+
+    fn params() -> PayjoinParams {
+        PayjoinParams {
+            additional_fee_output_index: Some(1),
+            max_additional_fee_contribution: 500,
+            min_fee_rate: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_validate_endpoint() {
+        assert!(validate_endpoint("https://example.com/pj", false).is_ok());
+        assert!(validate_endpoint("HTTP://example.onion:8080/pj?id=1", false).is_ok());
+        assert!(validate_endpoint("http://127.0.0.1:3000", true).is_ok());
+        for endpoint in ["http://example.com/pj", "example.com", "https:///pj", "ftp://a.onion"] {
+            assert!(validate_endpoint(endpoint, false).is_err(), "{}", endpoint);
+        }
+    }
+
+    #[test]
+    fn test_check_proposal() {
+        let (original, proposal) = original_and_proposal();
+        let (tx, fee) = check_proposal(&original, &proposal, &params(), |_| false).unwrap();
+        assert_eq!(fee, 1_300);
+        assert!(!tx.input[0].witness.is_empty());
+        assert!(tx.input[1].witness.is_empty());
+
+        assert!(check_proposal(&original, &proposal, &params(), |s| *s == p2wpkh(4)).is_err());
+        let low_contribution = PayjoinParams {
+            max_additional_fee_contribution: 200,
+            ..params()
+        };
+        assert!(check_proposal(&original, &proposal, &low_contribution, |_| false).is_err());
+
+        // 500 is more than the fee of the receiver input at the original fee rate
+        let (original, mut proposal) = original_and_proposal();
+        proposal.unsigned_tx.output[1].value -= 200;
+        assert!(check_proposal(&original, &proposal, &params(), |_| false).is_err());
+
+        let (original, mut proposal) = original_and_proposal();
+        proposal.unsigned_tx.output[0].value -= 20_001;
+        assert!(check_proposal(&original, &proposal, &params(), |_| false).is_err());
+
+        let (original, mut proposal) = original_and_proposal();
+        proposal.inputs[0].final_script_witness = None;
+        assert!(check_proposal(&original, &proposal, &params(), |_| false).is_err());
+
+        let (original, mut proposal) = original_and_proposal();
+        proposal.inputs[1].final_script_witness = original.inputs[0].final_script_witness.clone();
+        assert!(check_proposal(&original, &proposal, &params(), |_| false).is_err());
+    }
+}
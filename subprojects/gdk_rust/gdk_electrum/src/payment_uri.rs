@@ -0,0 +1,243 @@
+//! Payment URIs as defined by BIP21, and their Liquid adaptation using the `liquidnetwork` scheme
+//! and the `assetid` parameter.
+//!
+//! Amounts are decimal numbers of whole coins with up to 8 decimals, also for Liquid assets. The
+//! `pj` parameter is the BIP78 PayJoin endpoint of the receiver. Other parameters, such as
+//! `lightning` or `pjos`, are ignored unless they are marked as required with the `req-` prefix, in
+//! which case the URI is rejected.
+
+use std::fmt::Write;
+
+use crate::error::Error;
+
+const SATOSHI_PER_COIN: u64 = 100_000_000;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PaymentUri {
+    pub address: String,
+
+    /// The amount in satoshi
+    pub satoshi: Option<u64>,
+
+    pub asset_id: Option<String>,
+
+    /// The name of the recipient
+    pub label: Option<String>,
+
+    /// The description of the payment
+    pub message: Option<String>,
+
+    /// The BIP78 PayJoin endpoint of the receiver
+    pub payjoin_endpoint: Option<String>,
+}
+
+impl PaymentUri {
+    /// Parse a URI with the given scheme, which is matched case insensitively
+    pub fn parse(uri: &str, scheme: &str) -> Result<Self, Error> {
+        let uri = uri.trim();
+        let (uri_scheme, rest) =
+            uri.split_once(':').ok_or_else(|| invalid("missing scheme".into()))?;
+        if !uri_scheme.eq_ignore_ascii_case(scheme) {
+            return Err(invalid(format!("expected the {} scheme", scheme)));
+        }
+        let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+        if address.is_empty() {
+            return Err(Error::InvalidAddress);
+        }
+
+        let mut payment_uri = PaymentUri {
+            address: address.to_string(),
+            ..Default::default()
+        };
+        for param in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            let value = percent_decode(value)?;
+            let field = match key {
+                "amount" => {
+                    let satoshi = parse_amount(&value)?;
+                    if payment_uri.satoshi.replace(satoshi).is_some() {
+                        return Err(invalid("duplicated amount".into()));
+                    }
+                    continue;
+                }
+                "assetid" => &mut payment_uri.asset_id,
+                "label" => &mut payment_uri.label,
+                "message" => &mut payment_uri.message,
+                "pj" => &mut payment_uri.payjoin_endpoint,
+                _ if key.starts_with("req-") => {
+                    return Err(Error::UnknownPaymentUriParameter(key.to_string()))
+                }
+                _ => continue,
+            };
+            if field.replace(value).is_some() {
+                return Err(invalid(format!("duplicated {}", key)));
+            }
+        }
+        payment_uri.asset_id = payment_uri.asset_id.map(|a| a.to_lowercase());
+        Ok(payment_uri)
+    }
+
+    /// Serialize the URI with the given scheme
+    pub fn to_uri(&self, scheme: &str) -> String {
+        let mut params = vec![];
+        if let Some(satoshi) = self.satoshi {
+            params.push(format!("amount={}", format_amount(satoshi)));
+        }
+        if let Some(ref asset_id) = self.asset_id {
+            params.push(format!("assetid={}", asset_id));
+        }
+        if let Some(ref label) = self.label {
+            params.push(format!("label={}", percent_encode(label)));
+        }
+        if let Some(ref message) = self.message {
+            params.push(format!("message={}", percent_encode(message)));
+        }
+        if let Some(ref payjoin_endpoint) = self.payjoin_endpoint {
+            params.push(format!("pj={}", percent_encode(payjoin_endpoint)));
+        }
+        let mut uri = format!("{}:{}", scheme, self.address);
+        if !params.is_empty() {
+            uri.push('?');
+            uri.push_str(&params.join("&"));
+        }
+        uri
+    }
+}
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidPaymentUri(reason)
+}
+
+fn parse_amount(amount: &str) -> Result<u64, Error> {
+    let (coins, decimals) = amount.split_once('.').unwrap_or((amount, ""));
+    let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if (coins.is_empty() && decimals.is_empty())
+        || !is_digits(coins)
+        || !is_digits(decimals)
+        || decimals.len() > 8
+    {
+        return Err(invalid(format!("invalid amount {}", amount)));
+    }
+    let coins: u64 = if coins.is_empty() {
+        0
+    } else {
+        coins.parse().map_err(|_| invalid(format!("invalid amount {}", amount)))?
+    };
+    let decimals: u64 = format!("{:0<8}", decimals).parse().expect("checked digits");
+    coins
+        .checked_mul(SATOSHI_PER_COIN)
+        .and_then(|s| s.checked_add(decimals))
+        .ok_or_else(|| invalid(format!("invalid amount {}", amount)))
+}
+
+fn format_amount(satoshi: u64) -> String {
+    let decimals = format!("{:08}", satoshi % SATOSHI_PER_COIN);
+    let decimals = decimals.trim_end_matches('0');
+    if decimals.is_empty() {
+        format!("{}", satoshi / SATOSHI_PER_COIN)
+    } else {
+        format!("{}.{}", satoshi / SATOSHI_PER_COIN, decimals)
+    }
+}
+
+fn percent_decode(s: &str) -> Result<String, Error> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let byte = s
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| invalid("invalid percent encoding".into()))?;
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| invalid("invalid percent encoding".into()))
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => write!(encoded, "%{:02X}", byte).expect("writing to a string"),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_payment_uri() {
+        let uri = "bitcoin:BC1QAR0SRRR7XFKVY5L643LYDNW9RE59GTZZWF5MDQ?amount=20.3&label=Luke-Jr&message=Donation%20for%20project%20xyz&lightning=lnbc1";
+        let parsed = PaymentUri::parse(uri, "bitcoin").unwrap();
+        assert_eq!(parsed.address, "BC1QAR0SRRR7XFKVY5L643LYDNW9RE59GTZZWF5MDQ");
+        assert_eq!(parsed.satoshi, Some(2_030_000_000));
+        assert_eq!(parsed.label.as_deref(), Some("Luke-Jr"));
+        assert_eq!(parsed.message.as_deref(), Some("Donation for project xyz"));
+        assert_eq!(parsed.payjoin_endpoint, None);
+
+        let uri =
+            "bitcoin:175tWpb8K1S7NmH4Zx6rewF9WQrcZv245W?pj=https://example.com/pj%3Fid%3D1&pjos=0";
+        let parsed = PaymentUri::parse(uri, "bitcoin").unwrap();
+        assert_eq!(parsed.payjoin_endpoint.as_deref(), Some("https://example.com/pj?id=1"));
+
+        let parsed = PaymentUri::parse("BITCOIN:175tWpb8K1S7NmH4Zx6rewF9WQrcZv245W", "bitcoin");
+        assert_eq!(parsed.unwrap().satoshi, None);
+
+        let asset_id = "6f0279e9ed041c3d710a9f57d0c02928416460c4b722ae3457a11eec381c526d";
+        let uri =
+            format!("liquidnetwork:lq1qq?amount=.00000001&assetid={}", asset_id.to_uppercase());
+        let parsed = PaymentUri::parse(&uri, "liquidnetwork").unwrap();
+        assert_eq!(parsed.satoshi, Some(1));
+        assert_eq!(parsed.asset_id.as_deref(), Some(asset_id));
+    }
+
+    #[test]
+    fn test_invalid_payment_uri() {
+        for uri in [
+            "175tWpb8K1S7NmH4Zx6rewF9WQrcZv245W",
+            "liquidnetwork:175tWpb8K1S7NmH4Zx6rewF9WQrcZv245W",
+            "bitcoin:?amount=1",
+            "bitcoin:175tWpb8K1S7NmH4Zx6rewF9WQrcZv245W?amount=1.000000001",
+            "bitcoin:175tWpb8K1S7NmH4Zx6rewF9WQrcZv245W?amount=1e3",
+            "bitcoin:175tWpb8K1S7NmH4Zx6rewF9WQrcZv245W?amount=.",
+            "bitcoin:175tWpb8K1S7NmH4Zx6rewF9WQrcZv245W?amount=1&amount=2",
+            "bitcoin:175tWpb8K1S7NmH4Zx6rewF9WQrcZv245W?label=%ZZ",
+        ] {
+            assert!(PaymentUri::parse(uri, "bitcoin").is_err(), "{}", uri);
+        }
+        let err =
+            PaymentUri::parse("bitcoin:175tWpb8K1S7NmH4Zx6rewF9WQrcZv245W?req-x=1", "bitcoin");
+        assert!(matches!(err, Err(Error::UnknownPaymentUriParameter(p)) if p == "req-x"));
+    }
+
+    #[test]
+    fn test_payment_uri_roundtrip() {
+        let payment_uri = PaymentUri {
+            address: "175tWpb8K1S7NmH4Zx6rewF9WQrcZv245W".into(),
+            satoshi: Some(1_000_100_000),
+            asset_id: None,
+            label: Some("Alice & Bob".into()),
+            message: None,
+            payjoin_endpoint: None,
+        };
+        let uri = payment_uri.to_uri("bitcoin");
+        assert_eq!(
+            uri,
+            "bitcoin:175tWpb8K1S7NmH4Zx6rewF9WQrcZv245W?amount=10.001&label=Alice%20%26%20Bob"
+        );
+        assert_eq!(PaymentUri::parse(&uri, "bitcoin").unwrap(), payment_uri);
+        assert_eq!(format_amount(SATOSHI_PER_COIN), "1");
+    }
+}
@@ -0,0 +1,304 @@
+//! Bitcoin PSBTs as defined by BIP174, Liquid PSETs are handled by [`crate::pset`].
+
+use gdk_common::bitcoin::blockdata::script::Builder;
+use gdk_common::bitcoin::util::psbt::{Input, PartiallySignedTransaction};
+use gdk_common::bitcoin::{OutPoint, PublicKey, Script, Sequence, Transaction, TxOut, Witness};
+use gdk_common::scripts::{p2shwsh_script_sig, parse_sortedmulti_script, ScriptType};
+
+use crate::error::Error;
+
+/// Finalize the inputs of the PSBT having enough signatures and extract the signed transaction,
+/// which requires all the inputs to be finalized
+pub fn finalize(psbt: &mut PartiallySignedTransaction) -> Result<Transaction, Error> {
+    let outpoints = psbt.unsigned_tx.input.iter().map(|txin| txin.previous_output);
+    for (input, outpoint) in psbt.inputs.iter_mut().zip(outpoints) {
+        finalize_input(input, &outpoint)?;
+    }
+    if !psbt.inputs.iter().all(is_finalized) {
+        return Err(Error::IncompletePsbt);
+    }
+    Ok(psbt.clone().extract_tx())
+}
+
+/// The fee of the PSBT, if all its inputs have the output they spend
+pub fn fee(psbt: &PartiallySignedTransaction) -> Option<u64> {
+    let input_value = psbt
+        .inputs
+        .iter()
+        .zip(psbt.unsigned_tx.input.iter())
+        .map(|(input, txin)| {
+            input_prevout(input, &txin.previous_output).ok().flatten().map(|o| o.value)
+        })
+        .sum::<Option<u64>>()?;
+    input_value.checked_sub(psbt.unsigned_tx.output.iter().map(|o| o.value).sum())
+}
+
+/// The output spent by a PSBT input, from `witness_utxo` or `non_witness_utxo`
+///
+/// If both are present they must agree, otherwise a `witness_utxo` with an inflated value could
+/// make the signer pay an unexpected fee.
+pub fn input_prevout(input: &Input, outpoint: &OutPoint) -> Result<Option<TxOut>, Error> {
+    let non_witness_prevout = match input.non_witness_utxo {
+        Some(ref prev_tx) => {
+            if prev_tx.txid() != outpoint.txid {
+                return Err(Error::InvalidPsbtInput(outpoint.to_string()));
+            }
+            let prevout = prev_tx.output.get(outpoint.vout as usize).cloned();
+            Some(prevout.ok_or_else(|| Error::InvalidPsbtInput(outpoint.to_string()))?)
+        }
+        None => None,
+    };
+    match (&input.witness_utxo, non_witness_prevout) {
+        (Some(prevout), Some(non_witness_prevout)) if *prevout != non_witness_prevout => {
+            Err(Error::InvalidPsbtInput(outpoint.to_string()))
+        }
+        (Some(prevout), _) => Ok(Some(prevout.clone())),
+        (None, prevout) => Ok(prevout),
+    }
+}
+
+/// Check that the transactions spend the same outputs and pay the same outputs, their script sigs,
+/// sequences and witnesses can differ
+pub(crate) fn compare_except_script_sig_sequence(
+    tx1: &Transaction,
+    tx2: &Transaction,
+) -> Result<(), Error> {
+    let mut tx1 = tx1.clone();
+    let mut tx2 = tx2.clone();
+    for inp in tx1.input.iter_mut().chain(tx2.input.iter_mut()) {
+        inp.sequence = Sequence::ZERO;
+        inp.script_sig = Script::new();
+    }
+
+    let (tx1_id, tx2_id) = (tx1.txid(), tx2.txid());
+    if tx1_id != tx2_id {
+        Err(Error::PsbtAndTxMismatch(tx1_id, tx2_id))
+    } else {
+        Ok(())
+    }
+}
+
+fn is_finalized(input: &Input) -> bool {
+    input.final_script_sig.is_some() || input.final_script_witness.is_some()
+}
+
+/// Finalize a singlesig input with its signature, or a multisig input with enough signatures
+fn finalize_input(input: &mut Input, outpoint: &OutPoint) -> Result<(), Error> {
+    if is_finalized(input) {
+        return Ok(());
+    }
+    let script_pubkey = match input_prevout(input, outpoint)? {
+        Some(prevout) => prevout.script_pubkey,
+        None => return Ok(()),
+    };
+    if let Some(ref witness_script) = input.witness_script {
+        // the spent output must pay to the witness script, nested in the redeem script if any
+        let script_type = match input.redeem_script {
+            Some(ref redeem_script)
+                if *redeem_script == witness_script.to_v0_p2wsh()
+                    && script_pubkey == redeem_script.to_p2sh() =>
+            {
+                ScriptType::P2shP2wsh
+            }
+            None if script_pubkey == witness_script.to_v0_p2wsh() => ScriptType::P2wsh,
+            _ => return Ok(()),
+        };
+        if let Some((threshold, _)) = parse_sortedmulti_script(witness_script) {
+            finalize_multisig_input(input, threshold, script_type);
+        }
+        return Ok(());
+    }
+    if let Some(signature) = input.tap_key_sig {
+        input.final_script_witness = Some(Witness::from_vec(vec![signature.to_vec()]));
+        clear_finalized_input(input);
+        return Ok(());
+    }
+
+    let (public_key, signature): (&PublicKey, Vec<u8>) = match input.partial_sigs.iter().next() {
+        Some((public_key, signature)) => (public_key, signature.to_vec()),
+        None => return Ok(()),
+    };
+    let public_key = public_key.to_bytes();
+    if script_pubkey.is_p2pkh() {
+        input.final_script_sig =
+            Some(Builder::new().push_slice(&signature).push_slice(&public_key).into_script());
+    } else if script_pubkey.is_v0_p2wpkh() {
+        input.final_script_witness = Some(Witness::from_vec(vec![signature, public_key]));
+    } else if let (true, Some(redeem_script)) = (script_pubkey.is_p2sh(), &input.redeem_script) {
+        if !redeem_script.is_v0_p2wpkh() {
+            return Ok(());
+        }
+        input.final_script_sig =
+            Some(Builder::new().push_slice(redeem_script.as_bytes()).into_script());
+        input.final_script_witness = Some(Witness::from_vec(vec![signature, public_key]));
+    } else {
+        return Ok(());
+    }
+    clear_finalized_input(input);
+    Ok(())
+}
+
+/// Finalize a multisig PSBT input if it has enough signatures, as per BIP174 the other fields
+/// are then cleared.
+pub(crate) fn finalize_multisig_input(input: &mut Input, threshold: u32, script_type: ScriptType) {
+    let witness_script = match &input.witness_script {
+        Some(witness_script) => witness_script.clone(),
+        None => return,
+    };
+    let script_keys = match parse_sortedmulti_script(&witness_script) {
+        Some((_, script_keys)) => script_keys,
+        None => return,
+    };
+    // signatures must be in the same order of the keys in the script, which is sorted
+    let mut keys: Vec<&PublicKey> =
+        input.partial_sigs.keys().filter(|pk| script_keys.contains(pk)).collect();
+    keys.sort_by_key(|pk| pk.to_bytes());
+    if (keys.len() as u32) < threshold {
+        return;
+    }
+    let mut witness = vec![vec![]];
+    witness.extend(keys.iter().take(threshold as usize).map(|pk| input.partial_sigs[pk].to_vec()));
+    witness.push(witness_script.to_bytes());
+
+    if script_type == ScriptType::P2shP2wsh {
+        input.final_script_sig = Some(p2shwsh_script_sig(&witness_script));
+    }
+    input.final_script_witness = Some(Witness::from_vec(witness));
+    clear_finalized_input(input);
+}
+
+/// Remove what is no longer needed once the input is finalized, except the spent outputs
+pub(crate) fn clear_finalized_input(input: &mut Input) {
+    input.partial_sigs.clear();
+    input.sighash_type = None;
+    input.redeem_script = None;
+    input.witness_script = None;
+    input.bip32_derivation.clear();
+    input.tap_key_sig = None;
+    input.tap_internal_key = None;
+    input.tap_key_origins.clear();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::bitcoin::hashes::Hash;
+    use gdk_common::bitcoin::secp256k1::{ecdsa::Signature, Secp256k1, SecretKey};
+    use gdk_common::bitcoin::{EcdsaSig, PackedLockTime, TxIn, Txid};
+    use gdk_common::scripts::sortedmulti_script;
+
+    fn psbt_spending(script_pubkey: Script) -> PartiallySignedTransaction {
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 9_000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey,
+        });
+        psbt
+    }
+
+    fn key_and_signature() -> (PublicKey, EcdsaSig) {
+        let secret_key = SecretKey::from_slice(&[1u8; 32]).unwrap();
+        let public_key = PublicKey::new(secret_key.public_key(&Secp256k1::new()));
+        let signature = EcdsaSig::sighash_all(Signature::from_compact(&[1u8; 64]).unwrap());
+        (public_key, signature)
+    }
+
+    #[test]
+    fn test_finalize_singlesig() {
+        let (public_key, signature) = key_and_signature();
+        let wpkh = Script::new_v0_p2wpkh(&public_key.wpubkey_hash().unwrap());
+
+        let mut psbt = psbt_spending(wpkh.clone());
+        assert!(matches!(finalize(&mut psbt), Err(Error::IncompletePsbt)));
+        psbt.inputs[0].partial_sigs.insert(public_key, signature);
+        let tx = finalize(&mut psbt).unwrap();
+        assert_eq!(tx.input[0].witness.len(), 2);
+        assert!(tx.input[0].script_sig.is_empty());
+        assert!(psbt.inputs[0].partial_sigs.is_empty());
+        assert_eq!(fee(&psbt), Some(1_000));
+
+        let mut psbt = psbt_spending(Script::new_p2sh(&wpkh.script_hash()));
+        psbt.inputs[0].partial_sigs.insert(public_key, signature);
+        psbt.inputs[0].redeem_script = Some(wpkh);
+        let tx = finalize(&mut psbt).unwrap();
+        assert_eq!(tx.input[0].witness.len(), 2);
+        assert_eq!(tx.input[0].script_sig.len(), 23);
+
+        let mut psbt = psbt_spending(Script::new_p2pkh(&public_key.pubkey_hash()));
+        psbt.inputs[0].partial_sigs.insert(public_key, signature);
+        let tx = finalize(&mut psbt).unwrap();
+        assert!(tx.input[0].witness.is_empty());
+        assert!(!tx.input[0].script_sig.is_empty());
+    }
+
+    #[test]
+    fn test_finalize_multisig() {
+        let (public_key, signature) = key_and_signature();
+        let witness_script = sortedmulti_script(1, &[public_key]);
+
+        let mut psbt = psbt_spending(witness_script.to_v0_p2wsh());
+        psbt.inputs[0].partial_sigs.insert(public_key, signature);
+        psbt.inputs[0].witness_script = Some(witness_script);
+        let tx = finalize(&mut psbt).unwrap();
+        assert_eq!(tx.input[0].witness.len(), 3);
+
+        // a script starting like a multisig one is not finalized as such
+        let witness_script = Builder::new()
+            .push_int(1)
+            .push_opcode(gdk_common::bitcoin::blockdata::opcodes::all::OP_DROP)
+            .push_key(&public_key)
+            .push_opcode(gdk_common::bitcoin::blockdata::opcodes::all::OP_CHECKSIG)
+            .into_script();
+        let mut psbt = psbt_spending(witness_script.to_v0_p2wsh());
+        psbt.inputs[0].partial_sigs.insert(public_key, signature);
+        psbt.inputs[0].witness_script = Some(witness_script);
+        assert!(matches!(finalize(&mut psbt), Err(Error::IncompletePsbt)));
+    }
+
+    #[test]
+    fn test_input_prevout() {
+        let prev_tx = psbt_spending(Script::new()).unsigned_tx;
+        let outpoint = OutPoint::new(prev_tx.txid(), 0);
+        let mut input = Input {
+            non_witness_utxo: Some(prev_tx.clone()),
+            ..Default::default()
+        };
+        assert_eq!(input_prevout(&input, &outpoint).unwrap(), Some(prev_tx.output[0].clone()));
+        input.witness_utxo = Some(prev_tx.output[0].clone());
+        assert_eq!(input_prevout(&input, &outpoint).unwrap(), Some(prev_tx.output[0].clone()));
+
+        // the value of the witness utxo must be the one of the spent transaction
+        input.witness_utxo.as_mut().unwrap().value += 1;
+        assert!(input_prevout(&input, &outpoint).is_err());
+        input.witness_utxo = None;
+        assert!(input_prevout(&input, &OutPoint::new(Txid::all_zeros(), 0)).is_err());
+        assert!(input_prevout(&input, &OutPoint::new(prev_tx.txid(), 1)).is_err());
+        assert_eq!(input_prevout(&Input::default(), &outpoint).unwrap(), None);
+    }
+
+    #[test]
+    fn test_compare_except_script_sig_sequence() {
+        let tx = psbt_spending(Script::new()).unsigned_tx;
+        assert!(compare_except_script_sig_sequence(&tx, &tx).is_ok());
+
+        let mut tx2 = tx.clone();
+        tx2.input[0].script_sig = Script::from(vec![0u8]);
+        tx2.input[0].sequence = Sequence(1000);
+        tx2.input[0].witness = Witness::from_vec(vec![vec![42u8]]);
+        assert!(compare_except_script_sig_sequence(&tx, &tx2).is_ok());
+        tx2.output[0].value += 1;
+        assert!(compare_except_script_sig_sequence(&tx, &tx2).is_err());
+    }
+}
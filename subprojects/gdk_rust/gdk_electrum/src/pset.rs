@@ -1,11 +1,12 @@
 use crate::error::Error;
 use gdk_common::bitcoin::hashes::hex::{FromHex, ToHex};
 use gdk_common::elements;
+use gdk_common::elements::confidential::{AssetBlindingFactor, ValueBlindingFactor};
 use gdk_common::elements::encode::{deserialize, serialize};
 use gdk_common::elements::pset;
 use gdk_common::elements::pset::PartiallySignedTransaction;
 use gdk_common::elements::script::Builder;
-use gdk_common::elements::Transaction;
+use gdk_common::elements::{BlockHash, Transaction, TxOutSecrets};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize)]
@@ -66,7 +67,7 @@ pub struct FromTxResult {
 /// Return a pset built from the given raw tx hex
 pub fn from_tx(param: &FromTxParam) -> Result<FromTxResult, Error> {
     let tx = tx_from_hex(&param.transaction)?;
-    let mut pset = PartiallySignedTransaction::from_tx(tx);
+    let mut pset = pset_from_tx(tx);
     for output in pset.outputs_mut().iter_mut() {
         // Elements Core requires the blinder index to be set for each blinded output
         if output.is_marked_for_blinding() && output.blinder_index.is_none() {
@@ -79,6 +80,18 @@ pub fn from_tx(param: &FromTxParam) -> Result<FromTxResult, Error> {
     })
 }
 
+/// The pset of `tx`, with the previous output indexes of the inputs carrying an issuance fixed
+///
+/// `PartiallySignedTransaction::from_tx` keeps the issuance flag of the consensus encoding in the
+/// index, which would change the outpoint spent by the extracted tx and the issued asset ids.
+pub(crate) fn pset_from_tx(tx: Transaction) -> PartiallySignedTransaction {
+    let mut pset = PartiallySignedTransaction::from_tx(tx);
+    for input in pset.inputs_mut().iter_mut() {
+        input.previous_output_index &= !(1 << 31);
+    }
+    pset
+}
+
 fn pset_from_hex(pset_hex: &str) -> Result<pset::PartiallySignedTransaction, Error> {
     let pset_bytes = Vec::<u8>::from_hex(pset_hex)?;
     Ok(deserialize(&pset_bytes)?)
@@ -106,7 +119,10 @@ fn extract_sighashes(pset: &pset::PartiallySignedTransaction) -> Vec<u32> {
     pset.inputs().iter().map(|i| i.sighash_type.map_or(1, |s| s.to_u32())).collect()
 }
 
-fn compare_except_script_sig_sequence(tx1: &Transaction, tx2: &Transaction) -> Result<(), Error> {
+pub(crate) fn compare_except_script_sig_sequence(
+    tx1: &Transaction,
+    tx2: &Transaction,
+) -> Result<(), Error> {
     let mut tx1 = tx1.clone();
     let mut tx2 = tx2.clone();
     for inp in tx1.input.iter_mut() {
@@ -126,6 +142,96 @@ fn compare_except_script_sig_sequence(tx1: &Transaction, tx2: &Transaction) -> R
     }
 }
 
+/// Prefix of the proprietary keys added by GDK to the PSETs it creates
+const PROPRIETARY_PREFIX: &[u8] = b"gdk";
+
+/// Global proprietary subtype of the secrets of the output spent by an input, needed to blind the
+/// transaction. The key is the input index, as proprietary input keys are not kept by our parser.
+const INPUT_SECRETS_SUBTYPE: u8 = 0x00;
+
+/// Global proprietary subtype of the genesis block hash, committed by taproot signatures
+const GENESIS_HASH_SUBTYPE: u8 = 0x01;
+
+fn proprietary_key(subtype: u8, key: Vec<u8>) -> pset::raw::ProprietaryKey {
+    pset::raw::ProprietaryKey {
+        prefix: PROPRIETARY_PREFIX.to_vec(),
+        subtype,
+        key,
+    }
+}
+
+fn input_secrets_key(input_index: usize) -> pset::raw::ProprietaryKey {
+    proprietary_key(INPUT_SECRETS_SUBTYPE, serialize(&(input_index as u32)))
+}
+
+/// Store the secrets of the output spent by the input, serialized as asset id, asset blinding
+/// factor, value (little endian) and value blinding factor
+pub(crate) fn set_input_secrets(
+    pset: &mut PartiallySignedTransaction,
+    input_index: usize,
+    secrets: &TxOutSecrets,
+) {
+    let mut value = serialize(&secrets.asset);
+    value.extend_from_slice(secrets.asset_bf.into_inner().as_ref());
+    value.extend_from_slice(&secrets.value.to_le_bytes());
+    value.extend_from_slice(secrets.value_bf.into_inner().as_ref());
+    pset.global.proprietary.insert(input_secrets_key(input_index), value);
+}
+
+/// The secrets of the output spent by the input, if stored with [`set_input_secrets`]
+pub(crate) fn input_secrets(
+    pset: &PartiallySignedTransaction,
+    input_index: usize,
+) -> Result<Option<TxOutSecrets>, Error> {
+    let value = match pset.global.proprietary.get(&input_secrets_key(input_index)) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    if value.len() != 104 {
+        return Err(Error::Generic("invalid input secrets in PSET".into()));
+    }
+    let invalid = |_| Error::Generic("invalid input secrets in PSET".into());
+    let mut amount = [0u8; 8];
+    amount.copy_from_slice(&value[64..72]);
+    Ok(Some(TxOutSecrets {
+        asset: deserialize(&value[..32])?,
+        asset_bf: AssetBlindingFactor::from_slice(&value[32..64]).map_err(invalid)?,
+        value: u64::from_le_bytes(amount),
+        value_bf: ValueBlindingFactor::from_slice(&value[72..]).map_err(invalid)?,
+    }))
+}
+
+/// Store in the PSET the genesis block hash of the chain, needed to sign taproot inputs offline
+pub(crate) fn set_genesis_hash(pset: &mut PartiallySignedTransaction, genesis_hash: BlockHash) {
+    pset.global
+        .proprietary
+        .insert(proprietary_key(GENESIS_HASH_SUBTYPE, vec![]), serialize(&genesis_hash));
+}
+
+/// The genesis block hash of the chain, if stored with [`set_genesis_hash`]
+pub(crate) fn genesis_hash(pset: &PartiallySignedTransaction) -> Result<Option<BlockHash>, Error> {
+    match pset.global.proprietary.get(&proprietary_key(GENESIS_HASH_SUBTYPE, vec![])) {
+        Some(value) => Ok(Some(deserialize(value)?)),
+        None => Ok(None),
+    }
+}
+
+/// The transaction of the PSET as it was before blinding, with explicit assets and values and
+/// the blinding keys as nonces, so that it can be compared with the one we created
+pub(crate) fn unblinded_tx(pset: &PartiallySignedTransaction) -> Result<Transaction, Error> {
+    let mut tx = pset.extract_tx()?;
+    for (txout, output) in tx.output.iter_mut().zip(pset.outputs()) {
+        let mut output = output.clone();
+        output.asset_comm = None;
+        output.amount_comm = None;
+        output.ecdh_pubkey = None;
+        output.value_rangeproof = None;
+        output.asset_surjection_proof = None;
+        *txout = output.to_txout();
+    }
+    Ok(tx)
+}
+
 #[cfg(test)]
 mod test {
     use crate::pset::*;
@@ -235,6 +341,40 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_pset_from_tx_issuance() {
+        let mut tx = tx_from_hex(ONE_INPUT_TX).unwrap();
+        tx.input[0].previous_output.vout = 1;
+        tx.input[0].asset_issuance.amount = elements::confidential::Value::Explicit(1_000);
+        let pset = pset_from_tx(tx.clone());
+        assert_eq!(pset.inputs()[0].previous_output_index, 1);
+        assert_eq!(pset.inputs()[0].issuance_ids(), tx.input[0].issuance_ids());
+        assert_eq!(pset.extract_tx().unwrap(), tx);
+    }
+
+    #[test]
+    fn test_proprietary_data() {
+        let mut pset = pset_from_hex(ONE_INPUT_PSET).unwrap();
+        assert_eq!(input_secrets(&pset, 0).unwrap(), None);
+        assert_eq!(genesis_hash(&pset).unwrap(), None);
+
+        let secrets = TxOutSecrets {
+            asset: elements::AssetId::from_slice(&[1u8; 32]).unwrap(),
+            asset_bf: AssetBlindingFactor::from_slice(&[2u8; 32]).unwrap(),
+            value: 10_000,
+            value_bf: ValueBlindingFactor::from_slice(&[3u8; 32]).unwrap(),
+        };
+        let genesis = BlockHash::from_hex(&"04".repeat(32)).unwrap();
+        set_input_secrets(&mut pset, 0, &secrets);
+        set_genesis_hash(&mut pset, genesis);
+
+        let pset = pset_from_hex(&serialize(&pset).to_hex()).unwrap();
+        assert_eq!(input_secrets(&pset, 0).unwrap(), Some(secrets));
+        assert_eq!(input_secrets(&pset, 1).unwrap(), None);
+        assert_eq!(genesis_hash(&pset).unwrap(), Some(genesis));
+        assert_eq!(unblinded_tx(&pset).unwrap(), tx_from_hex(ONE_INPUT_TX).unwrap());
+    }
+
     #[test]
     fn test_compare_except_script_sig_sequence() {
         let tx = tx_from_hex(ONE_INPUT_TX).unwrap();
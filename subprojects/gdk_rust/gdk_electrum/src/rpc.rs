@@ -0,0 +1,419 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+
+use electrum_client::{GetHistoryRes, GetMerkleRes};
+use gdk_common::be::{BEOutPoint, BETransaction, BETxidConvert};
+use gdk_common::bitcoin::hashes::hex::{FromHex, ToHex};
+use gdk_common::log::{info, trace};
+use gdk_common::model::FeeEstimate;
+use gdk_common::{bitcoin, network, ureq, NetworkId};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::client::BlockchainClient;
+use crate::descriptor;
+use crate::error::Error;
+use crate::headers::merkle_branch;
+
+/// Wallet used on the node if the network parameters do not specify one.
+pub const DEFAULT_RPC_WALLET: &str = "gdk";
+
+// https://github.com/bitcoin/bitcoin/blob/master/src/rpc/protocol.h
+const RPC_INVALID_PARAMETER: i64 = -8;
+const RPC_WALLET_NOT_FOUND: i64 = -18;
+const RPC_WALLET_ALREADY_LOADED: i64 = -35;
+
+/// Connection details of a bitcoind or elementsd node.
+#[derive(Clone, Debug)]
+pub struct RpcConfig {
+    pub url: String,
+
+    /// `user:password` credentials, for instance the content of the node cookie file
+    pub auth: Option<String>,
+
+    /// Watch-only descriptor wallet on the node tracking the scripts of the session
+    pub wallet: String,
+
+    /// Unix time before which the wallet has no transactions, the node rescans from it
+    pub birthday: u64,
+
+    pub network: NetworkId,
+}
+
+/// A client for the JSON-RPC interface of bitcoind and elementsd.
+///
+/// The history of the wallet scripts is obtained by importing them as `raw()` descriptors in a
+/// watch-only wallet on the node, thus the node needs descriptor wallets support, and `-txindex`
+/// to fetch transactions not belonging to the wallet.
+///
+/// Importing a script makes the node rescan the chain from the wallet birthday. This is needed only
+/// for scripts that might have past history, as the ones of a restored wallet, the others are
+/// imported with the current time. Each import rescans the chain once, however many scripts it
+/// contains, thus the syncer looks up the scripts of restored wallets ahead in one batch, see
+/// `BlockchainClient::rescans_new_scripts`.
+pub struct RpcClient {
+    config: RpcConfig,
+    agent: ureq::Agent,
+
+    /// Whether the scripts looked up next might have past history, see `set_rescan`
+    rescan: Cell<bool>,
+
+    /// Hex of the scripts watched by the node wallet
+    imported: RefCell<HashSet<String>>,
+
+    /// Wallet transactions and their heights, cached for the lifetime of the client
+    wallet_txs: RefCell<Option<Vec<(BETransaction, i32)>>>,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: Option<Value>,
+    error: Option<RpcErrorResponse>,
+}
+
+#[derive(Deserialize)]
+struct RpcErrorResponse {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct ListTransactionsItem {
+    txid: bitcoin::Txid,
+    confirmations: i64,
+    blockheight: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct ListDescriptorsResult {
+    descriptors: Vec<ListDescriptorsItem>,
+}
+
+#[derive(Deserialize)]
+struct ListDescriptorsItem {
+    desc: String,
+}
+
+#[derive(Deserialize)]
+struct BlockHeaderResult {
+    height: u32,
+}
+
+#[derive(Deserialize)]
+struct BlockResult {
+    tx: Vec<bitcoin::Txid>,
+}
+
+#[derive(Deserialize)]
+struct EstimateSmartFeeResult {
+    feerate: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct NetworkInfoResult {
+    relayfee: f64,
+}
+
+fn btc_kvb_to_sat_kvb(fee: f64) -> u64 {
+    (fee * 100_000_000.0) as u64
+}
+
+impl RpcClient {
+    /// Create a client for the node, loading the watch-only wallet (or creating it if missing).
+    pub fn new(config: &RpcConfig, proxy: Option<&str>) -> Result<Self, Error> {
+        let client = RpcClient {
+            config: config.clone(),
+            agent: network::build_request_agent(proxy)?,
+            rescan: Cell::new(false),
+            imported: RefCell::new(HashSet::new()),
+            wallet_txs: RefCell::new(None),
+        };
+        client.load_wallet()?;
+        let imported: ListDescriptorsResult = client.call_wallet("listdescriptors", json!([]))?;
+        client.imported.replace(
+            imported
+                .descriptors
+                .into_iter()
+                .filter_map(|d| Some(d.desc.strip_prefix("raw(")?.split(')').next()?.to_string()))
+                .collect(),
+        );
+        Ok(client)
+    }
+
+    fn load_wallet(&self) -> Result<(), Error> {
+        let wallet = &self.config.wallet;
+        match self.call::<Value>("loadwallet", json!([wallet])) {
+            Ok(_) => Ok(()),
+            Err(Error::Rpc {
+                code: RPC_WALLET_ALREADY_LOADED,
+                ..
+            }) => Ok(()),
+            Err(Error::Rpc {
+                code: RPC_WALLET_NOT_FOUND,
+                ..
+            }) => {
+                info!("creating watch-only wallet {} on the node", wallet);
+                // disable_private_keys, blank, passphrase, avoid_reuse, descriptors
+                self.call::<Value>("createwallet", json!([wallet, true, true, "", false, true]))?;
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T, Error> {
+        self.call_url(&self.config.url, method, params)
+    }
+
+    fn call_wallet<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T, Error> {
+        let url =
+            format!("{}/wallet/{}", self.config.url.trim_end_matches('/'), self.config.wallet);
+        self.call_url(&url, method, params)
+    }
+
+    fn call_url<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        method: &str,
+        params: Value,
+    ) -> Result<T, Error> {
+        trace!("rpc call {} {}", method, params);
+        let request = json!({"jsonrpc": "1.0", "id": "gdk", "method": method, "params": params});
+        let mut builder = self.agent.post(url);
+        if let Some(auth) = self.config.auth.as_ref() {
+            builder = builder.set("Authorization", &format!("Basic {}", base64::encode(auth)));
+        }
+        let response: RpcResponse = match builder.send_json(request) {
+            Ok(response) => response.into_json()?,
+            // The node replies with an error status code, but the body still contains the error
+            Err(ureq::Error::Status(401, _)) => {
+                return Err(Error::Generic("rpc: invalid credentials".into()))
+            }
+            Err(ureq::Error::Status(_, response)) => response.into_json()?,
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(error) = response.error {
+            return Err(Error::Rpc {
+                code: error.code,
+                message: error.message,
+            });
+        }
+        Ok(serde_json::from_value(response.result.unwrap_or(Value::Null))?)
+    }
+
+    fn block_hash(&self, height: u32) -> Result<Option<String>, Error> {
+        match self.call("getblockhash", json!([height])) {
+            Ok(hash) => Ok(Some(hash)),
+            Err(Error::Rpc {
+                code: RPC_INVALID_PARAMETER,
+                ..
+            }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn block_header_raw(&self, hash: &str) -> Result<Vec<u8>, Error> {
+        let header: String = self.call("getblockheader", json!([hash, false]))?;
+        Ok(Vec::<u8>::from_hex(&header)?)
+    }
+
+    /// Import the scripts the wallet is not yet watching, rescanning the chain for their history
+    /// if they might have any.
+    fn import_scripts(&self, scripts: &[bitcoin::Script]) -> Result<(), Error> {
+        let timestamp = if self.rescans_new_scripts() {
+            json!(self.config.birthday)
+        } else {
+            json!("now")
+        };
+
+        let mut new_scripts = HashSet::new();
+        let mut requests = vec![];
+        for script in scripts {
+            let hex = script.to_hex();
+            if self.imported.borrow().contains(&hex) || new_scripts.contains(&hex) {
+                continue;
+            }
+            let desc = format!("raw({})", hex);
+            requests.push(json!({
+                "desc": format!("{}#{}", desc, descriptor::checksum(&desc)?),
+                "timestamp": timestamp,
+            }));
+            new_scripts.insert(hex);
+        }
+
+        if !requests.is_empty() {
+            info!("importing {} scripts in the node wallet", requests.len());
+            let results: Vec<Value> = self.call_wallet("importdescriptors", json!([requests]))?;
+            if let Some(failed) = results.iter().find(|r| r["success"] != json!(true)) {
+                return Err(Error::Generic(format!("rpc: import failed {}", failed)));
+            }
+            self.imported.borrow_mut().extend(new_scripts);
+            // New scripts may bring new transactions
+            self.wallet_txs.replace(None);
+        }
+        Ok(())
+    }
+
+    fn wallet_txs(&self) -> Result<Vec<(BETransaction, i32)>, Error> {
+        if let Some(txs) = self.wallet_txs.borrow().as_ref() {
+            return Ok(txs.clone());
+        }
+
+        let items: Vec<ListTransactionsItem> =
+            self.call_wallet("listtransactions", json!(["*", 1_000_000_000, 0, true]))?;
+        let mut heights = HashMap::new();
+        for item in items {
+            // Negative confirmations means the transaction conflicts with the best chain
+            if item.confirmations < 0 {
+                continue;
+            }
+            let height = item.blockheight.filter(|_| item.confirmations > 0).unwrap_or(0);
+            heights.insert(item.txid, height as i32);
+        }
+
+        let txids: Vec<bitcoin::Txid> = heights.keys().cloned().collect();
+        let mut txs = vec![];
+        for (txid, raw) in txids.iter().zip(self.batch_transaction_get_raw(&txids)?) {
+            txs.push((BETransaction::deserialize(&raw, self.config.network)?, heights[txid]));
+        }
+        self.wallet_txs.replace(Some(txs.clone()));
+        Ok(txs)
+    }
+}
+
+impl BlockchainClient for RpcClient {
+    fn ping(&self) -> Result<(), Error> {
+        self.call::<u32>("getblockcount", json!([]))?;
+        Ok(())
+    }
+
+    fn tip(&self) -> Result<(u32, Vec<u8>), Error> {
+        let hash: String = self.call("getbestblockhash", json!([]))?;
+        let header: BlockHeaderResult = self.call("getblockheader", json!([hash, true]))?;
+        Ok((header.height, self.block_header_raw(&hash)?))
+    }
+
+    fn batch_script_get_history(
+        &self,
+        scripts: &[bitcoin::Script],
+    ) -> Result<Vec<Vec<GetHistoryRes>>, Error> {
+        self.import_scripts(scripts)?;
+        let wallet_txs = self.wallet_txs()?;
+
+        scripts
+            .iter()
+            .map(|script| {
+                let script_hex = script.to_hex();
+                let mut funding = HashSet::<BEOutPoint>::new();
+                for (tx, _) in wallet_txs.iter() {
+                    for vout in 0..tx.output_len() as u32 {
+                        if tx.output_script(vout).to_hex() == script_hex {
+                            funding.insert(tx.outpoint(vout));
+                        }
+                    }
+                }
+                Ok(wallet_txs
+                    .iter()
+                    .filter(|(tx, _)| {
+                        funding.iter().any(|o| o.txid() == tx.txid())
+                            || tx.previous_outputs().iter().any(|o| funding.contains(o))
+                    })
+                    .map(|(tx, height)| GetHistoryRes {
+                        height: *height,
+                        tx_hash: tx.txid().into_bitcoin(),
+                        fee: None,
+                    })
+                    .collect())
+            })
+            .collect()
+    }
+
+    fn batch_transaction_get_raw(&self, txids: &[bitcoin::Txid]) -> Result<Vec<Vec<u8>>, Error> {
+        txids
+            .iter()
+            .map(|txid| {
+                let hex: String = self.call("getrawtransaction", json!([txid]))?;
+                Ok(Vec::<u8>::from_hex(&hex)?)
+            })
+            .collect()
+    }
+
+    fn batch_block_header_raw(&self, heights: Vec<u32>) -> Result<Vec<Vec<u8>>, Error> {
+        heights
+            .into_iter()
+            .map(|height| {
+                let hash = self.block_hash(height)?.ok_or_else(|| {
+                    Error::Generic(format!("rpc: block at height {} not found", height))
+                })?;
+                self.block_header_raw(&hash)
+            })
+            .collect()
+    }
+
+    fn block_headers(
+        &self,
+        start_height: usize,
+        count: usize,
+    ) -> Result<Vec<bitcoin::BlockHeader>, Error> {
+        let mut headers = vec![];
+        for height in start_height..start_height + count {
+            match self.block_hash(height as u32)? {
+                Some(hash) => {
+                    let raw = self.block_header_raw(&hash)?;
+                    headers.push(bitcoin::consensus::deserialize(&raw)?);
+                }
+                None => break,
+            }
+        }
+        Ok(headers)
+    }
+
+    fn transaction_get_merkle(
+        &self,
+        txid: &bitcoin::Txid,
+        height: usize,
+    ) -> Result<GetMerkleRes, Error> {
+        let hash = self
+            .block_hash(height as u32)?
+            .ok_or_else(|| Error::Generic(format!("rpc: block at height {} not found", height)))?;
+        let block: BlockResult = self.call("getblock", json!([hash, 1]))?;
+        let pos = block
+            .tx
+            .iter()
+            .position(|t| t == txid)
+            .ok_or_else(|| Error::Generic(format!("rpc: {} not in block {}", txid, hash)))?;
+        Ok(GetMerkleRes {
+            block_height: height,
+            pos,
+            merkle: merkle_branch(&block.tx, pos),
+        })
+    }
+
+    fn transaction_broadcast_raw(&self, raw_tx: &[u8]) -> Result<bitcoin::Txid, Error> {
+        self.call("sendrawtransaction", json!([raw_tx.to_hex()]))
+    }
+
+    fn set_rescan(&self, rescan: bool) {
+        self.rescan.set(rescan);
+    }
+
+    fn rescans_new_scripts(&self) -> bool {
+        // scripts imported in an empty wallet, as when the node changes, have past history too
+        self.rescan.get() || self.imported.borrow().is_empty()
+    }
+
+    fn fee_estimates(&self) -> Result<Vec<FeeEstimate>, Error> {
+        let network_info: NetworkInfoResult = self.call("getnetworkinfo", json!([]))?;
+        let relay_fee = btc_kvb_to_sat_kvb(network_info.relayfee);
+        let mut estimates = vec![FeeEstimate(relay_fee)];
+        for target in 1..25 {
+            // The node may not have enough data to estimate the fee, e.g. on regtest
+            let estimate: EstimateSmartFeeResult =
+                self.call("estimatesmartfee", json!([target]))?;
+            let fee = estimate.feerate.map(btc_kvb_to_sat_kvb).unwrap_or(relay_fee);
+            estimates.push(FeeEstimate(relay_fee.max(fee)));
+        }
+        Ok(estimates)
+    }
+}
@@ -10,11 +10,14 @@ use gdk_common::{
     model::*,
     notification::NativeNotif,
     session::{JsonError, Session},
-    ureq, NetworkParameters,
+    ureq, NetworkId, NetworkParameters,
 };
 use serde_json::Value;
 
-use crate::{account::Account, error::Error, interface::ElectrumUrl, socksify, ElectrumSession};
+use crate::filters::FiltersConfig;
+use crate::interface::{ElectrumUrl, ServerUrl};
+use crate::rpc::{RpcConfig, DEFAULT_RPC_WALLET};
+use crate::{account::Account, error::Error, socksify, ElectrumSession};
 
 impl ExchangeRatesCacher for ElectrumSession {
     fn xr_cache(&self) -> ExchangeRatesCache {
@@ -24,7 +27,7 @@ impl ExchangeRatesCacher for ElectrumSession {
 
 impl Session for ElectrumSession {
     fn new(network_parameters: NetworkParameters) -> Result<Self, JsonError> {
-        let url = determine_electrum_url(&network_parameters)?;
+        let url = determine_server_url(&network_parameters)?;
 
         Ok(Self {
             proxy: socksify(network_parameters.proxy.as_deref()),
@@ -38,7 +41,8 @@ impl Session for ElectrumSession {
             timeout: None,
             store: None,
             master_xpub: None,
-            master_xprv: None,
+            signer: None,
+            watch_only: false,
             recent_spent_utxos: Arc::new(RwLock::new(HashSet::<BEOutPoint>::new())),
             xr_cache: ExchangeRatesCache::default(),
             first_sync: Arc::new(AtomicBool::new(true)),
@@ -66,6 +70,7 @@ impl Session for ElectrumSession {
             "disconnect" => self.disconnect().to_json(),
 
             "login" => self.login(serde_json::from_value(input)?).to_json(),
+            "login_wo" => self.login_wo(serde_json::from_value(input)?).to_json(),
             "credentials_from_pin_data" => {
                 self.credentials_from_pin_data(serde_json::from_value(input)?).to_json()
             }
@@ -83,9 +88,15 @@ impl Session for ElectrumSession {
             "discover_subaccount" => {
                 self.discover_subaccount(serde_json::from_value(input)?).to_json()
             }
+            "recover_subaccounts" => {
+                self.recover_subaccounts(serde_json::from_value(input)?).to_json()
+            }
             "get_subaccount_root_path" => {
                 self.get_subaccount_root_path(serde_json::from_value(input)?).to_json()
             }
+            "get_subaccount_descriptors" => {
+                self.get_subaccount_descriptors(serde_json::from_value(input)?).to_json()
+            }
             "create_subaccount" => {
                 let opt: CreateAccountOpt = serde_json::from_value(input)?;
                 self.create_subaccount(opt).to_json()
@@ -119,9 +130,14 @@ impl Session for ElectrumSession {
                 })?)
                 .to_json(),
             "get_balance" => self.get_balance(&serde_json::from_value(input)?).to_json(),
+            "get_balance_details" => {
+                self.get_balance_details(&serde_json::from_value(input)?).to_json()
+            }
             "set_transaction_memo" => set_transaction_memo(self, &input),
             "create_transaction" => create_transaction(self, input).map_err(Into::into),
             "psbt_get_details" => self.psbt_get_details(serde_json::from_value(input)?).to_json(),
+            "create_psbt" => self.create_psbt(&serde_json::from_value(input)?).to_json(),
+            "finalize_psbt" => self.finalize_psbt(serde_json::from_value(input)?).to_json(),
             "sign_transaction" => self.sign_transaction(&serde_json::from_value(input)?).to_json(),
             "send_transaction" => self.send_transaction(&serde_json::from_value(input)?).to_json(),
             "broadcast_transaction" => self
@@ -158,6 +174,13 @@ impl Session for ElectrumSession {
             "start_threads" => self.start_threads().to_json(),
             "get_wallet_hash_id" => self.get_wallet_hash_id().to_json(),
             "get_address_data" => self.get_address_data(serde_json::from_value(input)?).to_json(),
+            "set_unspent_outputs_status" => {
+                self.set_unspent_outputs_status(serde_json::from_value(input)?).to_json()
+            }
+            "set_address_label" => self.set_address_label(serde_json::from_value(input)?).to_json(),
+            "get_address_label" => self.get_address_label(serde_json::from_value(input)?).to_json(),
+            "parse_payment_uri" => self.parse_payment_uri(serde_json::from_value(input)?).to_json(),
+            "sign_message" => self.sign_message(serde_json::from_value(input)?).to_json(),
 
             "remove_account" => self.remove_account().to_json(),
 
@@ -171,6 +194,63 @@ impl Session for ElectrumSession {
     }
 }
 
+pub fn determine_server_url(network: &NetworkParameters) -> Result<ServerUrl, Error> {
+    match network.server_type.as_deref() {
+        Some("esplora") => {
+            let esplora_url = network
+                .esplora_url
+                .as_ref()
+                .filter(|url| !url.is_empty())
+                .ok_or_else(|| Error::Generic("esplora url is missing".into()))?;
+            Ok(ServerUrl::Esplora(esplora_url.into()))
+        }
+        Some("rpc") => {
+            let rpc_url = network
+                .rpc_url
+                .as_ref()
+                .filter(|url| !url.is_empty())
+                .ok_or_else(|| Error::Generic("rpc url is missing".into()))?;
+            Ok(ServerUrl::Rpc(RpcConfig {
+                url: rpc_url.into(),
+                auth: network.rpc_auth.clone(),
+                wallet: network.rpc_wallet.clone().unwrap_or_else(|| DEFAULT_RPC_WALLET.into()),
+                birthday: network
+                    .rpc_wallet_birthday
+                    .ok_or_else(|| Error::Generic("rpc wallet birthday is missing".into()))?,
+                network: network.id(),
+            }))
+        }
+        Some("compact_filters") => {
+            let network_id = match network.id() {
+                NetworkId::Bitcoin(network_id) => network_id,
+                NetworkId::Elements(_) => {
+                    return Err(Error::Generic(
+                        "compact filters are not available in liquid".into(),
+                    ))
+                }
+            };
+            let peer = network
+                .compact_filters_peer
+                .as_ref()
+                .filter(|peer| !peer.is_empty())
+                .ok_or_else(|| Error::Generic("compact filters peer is missing".into()))?;
+            let check_peers = network.compact_filters_check_peers.clone().unwrap_or_default();
+            if check_peers.is_empty() {
+                log::warn!("filter headers from {} are not checked against other peers", peer);
+            }
+            Ok(ServerUrl::CompactFilters(FiltersConfig {
+                peer: peer.into(),
+                check_peers,
+                network: network_id,
+                state_dir: network.state_dir.as_str().into(),
+                start_height: network.compact_filters_start_height.unwrap_or(0),
+                state: Default::default(),
+            }))
+        }
+        _ => Ok(ServerUrl::Electrum(determine_electrum_url(network)?)),
+    }
+}
+
 pub fn determine_electrum_url(network: &NetworkParameters) -> Result<ElectrumUrl, Error> {
     if let Some(true) = network.use_tor {
         if let Some(electrum_onion_url) = network.electrum_onion_url.as_ref() {
@@ -0,0 +1,375 @@
+//! Signers hold the private keys of the wallet, the session and its accounts only know the public
+//! keys and ask the signer for signatures.
+//!
+//! [`SoftwareSigner`] keeps the keys derived from the mnemonic in memory, [`RemoteSigner`] forwards
+//! every request to a signer running in another process, for instance a hardware wallet driver,
+//! through a [`SignerChannel`].
+
+use std::convert::TryInto;
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Mutex;
+
+use gdk_common::bitcoin::hashes::hex::{FromHex, ToHex};
+use gdk_common::bitcoin::secp256k1::{ecdsa, schnorr, KeyPair, Message, Scalar};
+use gdk_common::bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey};
+use gdk_common::model::Credentials;
+use gdk_common::wally::MasterBlindingKey;
+use gdk_common::{bitcoin, rand};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::keys_from_credentials;
+
+/// The keys of a wallet, all of them derived from its master key
+pub trait Signer: Send + Sync {
+    /// The extended public key at `path` from the master key
+    fn xpub(&self, path: &DerivationPath) -> Result<ExtendedPubKey, Error>;
+
+    /// Sign `message` with the key at `path`, returning a low R signature
+    fn sign_ecdsa(
+        &self,
+        path: &DerivationPath,
+        message: &Message,
+    ) -> Result<ecdsa::Signature, Error>;
+
+    /// Sign `message` with the key at `path` tweaked by adding `tweak` to its x-only public key.
+    ///
+    /// The caller computes the taproot tweak, whose tagged hash differs between Bitcoin and
+    /// Liquid.
+    fn sign_schnorr(
+        &self,
+        path: &DerivationPath,
+        message: &Message,
+        tweak: &Scalar,
+    ) -> Result<schnorr::Signature, Error>;
+
+    /// The SLIP77 master blinding key, from which the blinding keys of Liquid scripts are derived
+    fn master_blinding_key(&self) -> Result<MasterBlindingKey, Error>;
+}
+
+/// A signer keeping the master key in memory
+pub struct SoftwareSigner {
+    master_xprv: ExtendedPrivKey,
+    master_blinding: MasterBlindingKey,
+}
+
+impl SoftwareSigner {
+    pub fn new(master_xprv: ExtendedPrivKey, master_blinding: MasterBlindingKey) -> Self {
+        SoftwareSigner {
+            master_xprv,
+            master_blinding,
+        }
+    }
+
+    /// The signer of the wallet whose seed is given by the mnemonic and passphrase
+    pub fn from_credentials(
+        credentials: &Credentials,
+        network: bitcoin::Network,
+    ) -> Result<Self, Error> {
+        let (master_xprv, _, master_blinding) = keys_from_credentials(credentials, network)?;
+        Ok(SoftwareSigner::new(master_xprv, master_blinding))
+    }
+
+    fn keypair(&self, path: &DerivationPath) -> Result<KeyPair, Error> {
+        let xprv = self.master_xprv.derive_priv(&crate::EC, path)?;
+        Ok(KeyPair::from_secret_key(&crate::EC, &xprv.private_key))
+    }
+}
+
+impl Signer for SoftwareSigner {
+    fn xpub(&self, path: &DerivationPath) -> Result<ExtendedPubKey, Error> {
+        let xprv = self.master_xprv.derive_priv(&crate::EC, path)?;
+        Ok(ExtendedPubKey::from_priv(&crate::EC, &xprv))
+    }
+
+    fn sign_ecdsa(
+        &self,
+        path: &DerivationPath,
+        message: &Message,
+    ) -> Result<ecdsa::Signature, Error> {
+        let xprv = self.master_xprv.derive_priv(&crate::EC, path)?;
+        Ok(crate::EC.sign_ecdsa_low_r(message, &xprv.private_key))
+    }
+
+    fn sign_schnorr(
+        &self,
+        path: &DerivationPath,
+        message: &Message,
+        tweak: &Scalar,
+    ) -> Result<schnorr::Signature, Error> {
+        let keypair = self.keypair(path)?.add_xonly_tweak(&crate::EC, tweak)?;
+        let aux_rand: [u8; 32] = rand::random();
+        Ok(crate::EC.sign_schnorr_with_aux_rand(message, &keypair, &aux_rand))
+    }
+
+    fn master_blinding_key(&self) -> Result<MasterBlindingKey, Error> {
+        Ok(self.master_blinding.clone())
+    }
+}
+
+/// A request to a signer, serialized as JSON with its `method` when sent to another process
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum SignerRequest {
+    Xpub {
+        path: DerivationPath,
+    },
+    SignEcdsa {
+        path: DerivationPath,
+        /// Hex of the 32 bytes message
+        message: String,
+    },
+    SignSchnorr {
+        path: DerivationPath,
+        /// Hex of the 32 bytes message
+        message: String,
+        /// Hex of the 32 bytes big endian tweak
+        tweak: String,
+    },
+    MasterBlindingKey,
+}
+
+/// The response of a signer to a [`SignerRequest`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignerResponse {
+    Xpub(ExtendedPubKey),
+    EcdsaSignature(ecdsa::Signature),
+    SchnorrSignature(schnorr::Signature),
+    MasterBlindingKey(MasterBlindingKey),
+    /// The signer could not handle the request, for instance because the user refused to sign
+    Error(String),
+}
+
+/// Answer a request with the given signer, as done by the other end of a [`SignerChannel`]
+pub fn handle_request(signer: &dyn Signer, request: &SignerRequest) -> SignerResponse {
+    let response = match request {
+        SignerRequest::Xpub {
+            path,
+        } => signer.xpub(path).map(SignerResponse::Xpub),
+        SignerRequest::SignEcdsa {
+            path,
+            message,
+        } => parse_message(message)
+            .and_then(|message| signer.sign_ecdsa(path, &message))
+            .map(SignerResponse::EcdsaSignature),
+        SignerRequest::SignSchnorr {
+            path,
+            message,
+            tweak,
+        } => parse_message(message)
+            .and_then(|message| Ok((message, parse_tweak(tweak)?)))
+            .and_then(|(message, tweak)| signer.sign_schnorr(path, &message, &tweak))
+            .map(SignerResponse::SchnorrSignature),
+        SignerRequest::MasterBlindingKey => {
+            signer.master_blinding_key().map(SignerResponse::MasterBlindingKey)
+        }
+    };
+    response.unwrap_or_else(|e| SignerResponse::Error(e.to_string()))
+}
+
+fn parse_message(message: &str) -> Result<Message, Error> {
+    Ok(Message::from_slice(&Vec::<u8>::from_hex(message)?)?)
+}
+
+fn parse_tweak(tweak: &str) -> Result<Scalar, Error> {
+    let tweak: [u8; 32] = Vec::<u8>::from_hex(tweak)?[..].try_into()?;
+    Scalar::from_be_bytes(tweak).map_err(|_| Error::Signer("invalid tweak".into()))
+}
+
+/// A request/response transport to a signer, which answers every request before the next one
+pub trait SignerChannel: Send {
+    fn call(&mut self, request: &SignerRequest) -> Result<SignerResponse, Error>;
+}
+
+/// Channels to a thread forwarding the requests, for instance to a hardware wallet
+impl SignerChannel for (Sender<SignerRequest>, Receiver<SignerResponse>) {
+    fn call(&mut self, request: &SignerRequest) -> Result<SignerResponse, Error> {
+        self.0.send(request.clone()).map_err(|_| Error::Signer("disconnected".into()))?;
+        self.1.recv().map_err(|_| Error::Signer("disconnected".into()))
+    }
+}
+
+/// A stream where each request and response is a line of JSON, such as the standard input and
+/// output of a signer process
+pub struct JsonLinesChannel<R: BufRead, W: Write> {
+    reader: R,
+    writer: W,
+}
+
+impl<R: BufRead, W: Write> JsonLinesChannel<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        JsonLinesChannel {
+            reader,
+            writer,
+        }
+    }
+}
+
+impl<R: BufRead + Send, W: Write + Send> SignerChannel for JsonLinesChannel<R, W> {
+    fn call(&mut self, request: &SignerRequest) -> Result<SignerResponse, Error> {
+        serde_json::to_writer(&mut self.writer, request)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(Error::Signer("disconnected".into()));
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+/// A signer in another process, reached through a [`SignerChannel`]
+pub struct RemoteSigner {
+    channel: Mutex<Box<dyn SignerChannel>>,
+}
+
+impl RemoteSigner {
+    pub fn new<C: SignerChannel + 'static>(channel: C) -> Self {
+        RemoteSigner {
+            channel: Mutex::new(Box::new(channel)),
+        }
+    }
+
+    fn call(&self, request: SignerRequest) -> Result<SignerResponse, Error> {
+        match self.channel.lock()?.call(&request)? {
+            SignerResponse::Error(e) => Err(Error::Signer(e)),
+            response => Ok(response),
+        }
+    }
+}
+
+fn unexpected(response: SignerResponse) -> Error {
+    Error::Signer(format!("unexpected response {:?}", response))
+}
+
+impl Signer for RemoteSigner {
+    fn xpub(&self, path: &DerivationPath) -> Result<ExtendedPubKey, Error> {
+        match self.call(SignerRequest::Xpub {
+            path: path.clone(),
+        })? {
+            SignerResponse::Xpub(xpub) => Ok(xpub),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    fn sign_ecdsa(
+        &self,
+        path: &DerivationPath,
+        message: &Message,
+    ) -> Result<ecdsa::Signature, Error> {
+        match self.call(SignerRequest::SignEcdsa {
+            path: path.clone(),
+            message: message[..].to_hex(),
+        })? {
+            SignerResponse::EcdsaSignature(signature) => Ok(signature),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    fn sign_schnorr(
+        &self,
+        path: &DerivationPath,
+        message: &Message,
+        tweak: &Scalar,
+    ) -> Result<schnorr::Signature, Error> {
+        match self.call(SignerRequest::SignSchnorr {
+            path: path.clone(),
+            message: message[..].to_hex(),
+            tweak: tweak.to_be_bytes().to_hex(),
+        })? {
+            SignerResponse::SchnorrSignature(signature) => Ok(signature),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    fn master_blinding_key(&self) -> Result<MasterBlindingKey, Error> {
+        match self.call(SignerRequest::MasterBlindingKey)? {
+            SignerResponse::MasterBlindingKey(master_blinding) => Ok(master_blinding),
+            response => Err(unexpected(response)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use std::str::FromStr;
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    fn software_signer() -> SoftwareSigner {
+        let master_xprv =
+            ExtendedPrivKey::new_master(bitcoin::Network::Regtest, &[1u8; 32]).unwrap();
+        SoftwareSigner::new(master_xprv, MasterBlindingKey([2u8; 64]))
+    }
+
+    #[test]
+    fn test_remote_signer() {
+        let (request_sender, request_receiver) = channel::<SignerRequest>();
+        let (response_sender, response_receiver) = channel();
+        let handle = thread::spawn(move || {
+            let signer = software_signer();
+            for request in request_receiver {
+                response_sender.send(handle_request(&signer, &request)).unwrap();
+            }
+        });
+        let remote = RemoteSigner::new((request_sender, response_receiver));
+        let signer = software_signer();
+
+        let path = DerivationPath::from_str("m/84'/1'/0'/0/3").unwrap();
+        let xpub = remote.xpub(&path).unwrap();
+        assert_eq!(xpub, signer.xpub(&path).unwrap());
+
+        let message = Message::from_slice(&[3u8; 32]).unwrap();
+        let signature = remote.sign_ecdsa(&path, &message).unwrap();
+        crate::EC.verify_ecdsa(&message, &signature, &xpub.public_key).unwrap();
+
+        let tweak = Scalar::from_be_bytes([4u8; 32]).unwrap();
+        let signature = remote.sign_schnorr(&path, &message, &tweak).unwrap();
+        let (internal_key, _) = xpub.public_key.x_only_public_key();
+        let (output_key, _) = internal_key.add_tweak(&crate::EC, &tweak).unwrap();
+        crate::EC.verify_schnorr(&signature, &message, &output_key).unwrap();
+
+        assert_eq!(remote.master_blinding_key().unwrap(), MasterBlindingKey([2u8; 64]));
+
+        drop(remote);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_json_lines_channel() {
+        let request = SignerRequest::SignEcdsa {
+            path: DerivationPath::from_str("m/0/1").unwrap(),
+            message: [3u8; 32].to_hex(),
+        };
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(
+            json,
+            format!(
+                r#"{{"method":"sign_ecdsa","path":"m/0/1","message":"{}"}}"#,
+                [3u8; 32].to_hex()
+            )
+        );
+        assert_eq!(serde_json::from_str::<SignerRequest>(&json).unwrap(), request);
+
+        let response = handle_request(&software_signer(), &request);
+        let responses = format!("{}\n", serde_json::to_string(&response).unwrap());
+        let mut requests = vec![];
+        let mut channel = JsonLinesChannel::new(Cursor::new(responses), &mut requests);
+        assert!(matches!(channel.call(&request).unwrap(), SignerResponse::EcdsaSignature(_)));
+        assert!(matches!(channel.call(&request), Err(Error::Signer(_))));
+        assert_eq!(String::from_utf8(requests).unwrap().lines().next().unwrap(), json);
+
+        let response = handle_request(
+            &software_signer(),
+            &SignerRequest::SignEcdsa {
+                path: DerivationPath::master(),
+                message: "00".into(),
+            },
+        );
+        assert!(matches!(response, SignerResponse::Error(_)));
+    }
+}
@@ -1,18 +1,21 @@
 use crate::account::xpubs_equivalent;
+use crate::filters::FiltersState;
 use crate::spv::CrossValidationResult;
 use crate::Error;
 use gdk_common::aes::Aes256GcmSiv;
-use gdk_common::be::BETxidConvert;
 use gdk_common::be::{
     BEBlockHash, BEBlockHeader, BEScript, BETransaction, BETransactionEntry, BETransactions, BETxid,
 };
+use gdk_common::be::{BEOutPoint, BEScriptConvert, BETxidConvert};
 use gdk_common::bitcoin::hashes::{sha256, Hash};
 use gdk_common::bitcoin::util::bip32::{DerivationPath, ExtendedPubKey};
-use gdk_common::bitcoin::{Transaction, Txid};
+use gdk_common::bitcoin::{OutPoint, Script, Transaction, Txid};
 use gdk_common::elements;
 use gdk_common::elements::TxOutSecrets;
 use gdk_common::log::{info, log, Level};
-use gdk_common::model::{AccountSettings, FeeEstimate, SPVVerifyTxResult, Settings};
+use gdk_common::model::{
+    AccountSettings, FeeEstimate, MultisigParams, SPVVerifyTxResult, Settings,
+};
 use gdk_common::store::{Decryptable, Encryptable, ToCipher};
 use gdk_common::wally::MasterBlindingKey;
 use gdk_common::NetworkId;
@@ -26,8 +29,12 @@ use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
 use std::time::Instant;
 
+/// The default gap limit, which is also the number of scripts requested at once while syncing
 pub const BATCH_SIZE: u32 = 20;
 
+/// The maximum gap limit that can be set for a subaccount
+pub const MAX_GAP_LIMIT: u32 = 1000;
+
 pub type Store = Arc<RwLock<StoreMeta>>;
 
 /// RawCache is a persisted and encrypted cache of wallet data, contains stuff like wallet transactions
@@ -71,6 +78,13 @@ pub struct RawCache {
 
     /// The master blinding key, available only in liquid
     pub master_blinding: Option<MasterBlindingKey>,
+
+    /// The hash of the genesis block, needed to sign liquid taproot inputs
+    pub genesis_hash: Option<BEBlockHash>,
+
+    /// What has been found matching compact block filters, only with the compact filters backend
+    #[serde(default)]
+    pub filters_state: Option<FiltersState>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -90,7 +104,7 @@ pub struct RawAccountCache {
     /// unblinded values (only for liquid)
     pub unblinded: HashMap<elements::OutPoint, TxOutSecrets>,
 
-    /// max used indexes for external derivation /0/* and internal derivation /1/* (change)
+    /// max returned indexes for external derivation /0/* and internal derivation /1/* (change)
     pub indexes: Indexes,
 
     /// the xpub of the account
@@ -102,6 +116,16 @@ pub struct RawAccountCache {
     /// used to establish if an account has some transactions without waiting for the syncer to
     /// download transactions.
     pub bip44_discovered: bool,
+
+    /// max indexes with transactions as of the last sync, unlike `indexes` these are not
+    /// incremented when new addresses are returned
+    #[serde(default)]
+    pub last_used: Indexes,
+
+    /// entropy of the assets issued in the wallet transactions, needed to reissue them (only for
+    /// liquid)
+    #[serde(default)]
+    pub issuances: HashMap<elements::issuance::AssetId, [u8; 32]>,
 }
 
 /// RawStore contains data that are not extractable from xpub+blockchain
@@ -117,6 +141,18 @@ pub struct RawStore {
     // additional fields should always be appended at the end as an `Option` to retain db backwards compatibility
     /// account settings
     accounts_settings: Option<HashMap<u32, AccountSettings>>,
+
+    /// multisig parameters of the multisig accounts, the cosigner xpubs are not derivable from ours
+    accounts_multisig: Option<HashMap<u32, MultisigParams>>,
+
+    /// address labels (script_pubkey -> label)
+    address_labels: Option<HashMap<Script, String>>,
+
+    /// output labels (outpoint -> label)
+    output_labels: Option<HashMap<OutPoint, String>>,
+
+    /// outputs excluded from the automatic coin selection
+    frozen_outpoints: Option<HashSet<OutPoint>>,
 }
 
 pub struct StoreMeta {
@@ -430,6 +466,108 @@ impl StoreMeta {
         self.store.memos.get(&txid.into_bitcoin())
     }
 
+    pub fn insert_address_label(&mut self, script: &BEScript, label: &str) -> Result<(), Error> {
+        // Coerced into a bitcoin::Script so that both networks share the same key type
+        let script = script.clone().into_bitcoin();
+        let labels = self.store.address_labels.get_or_insert_with(Default::default);
+        if label.is_empty() {
+            labels.remove(&script);
+        } else {
+            labels.insert(script, label.to_string());
+        }
+        self.flush_store()?;
+        Ok(())
+    }
+
+    pub fn get_address_label(&self, script: &BEScript) -> Option<&String> {
+        let labels = self.store.address_labels.as_ref()?;
+        labels.get(&script.clone().into_bitcoin())
+    }
+
+    pub fn get_output_label(&self, outpoint: &BEOutPoint) -> Option<&String> {
+        // Coerced into a bitcoin::OutPoint as the memos txids
+        let outpoint = OutPoint::new(outpoint.txid().into_bitcoin(), outpoint.vout());
+        self.store.output_labels.as_ref()?.get(&outpoint)
+    }
+
+    /// Freeze (`true`) or unfreeze (`false`) the given outputs
+    pub fn set_frozen(&mut self, outpoints: &[(BEOutPoint, bool)]) -> Result<(), Error> {
+        let frozen = self.store.frozen_outpoints.get_or_insert_with(Default::default);
+        for (outpoint, is_frozen) in outpoints {
+            let outpoint = OutPoint::new(outpoint.txid().into_bitcoin(), outpoint.vout());
+            if *is_frozen {
+                frozen.insert(outpoint);
+            } else {
+                frozen.remove(&outpoint);
+            }
+        }
+        self.flush_store()?;
+        Ok(())
+    }
+
+    /// Forget the frozen outputs spent by confirmed transactions, they can not be spent again
+    pub fn prune_frozen(&mut self) -> Result<(), Error> {
+        if self.frozen_outpoints().next().is_none() {
+            return Ok(());
+        }
+        let spent: HashSet<OutPoint> = self
+            .cache
+            .accounts
+            .values()
+            .flat_map(|acc_store| {
+                acc_store
+                    .heights
+                    .iter()
+                    .filter(|(_, height)| height.is_some())
+                    .filter_map(move |(txid, _)| acc_store.all_txs.get(txid))
+                    .flat_map(|txe| txe.tx.previous_outputs())
+            })
+            .map(|o| OutPoint::new(o.txid().into_bitcoin(), o.vout()))
+            .collect();
+        let frozen = self.store.frozen_outpoints.get_or_insert_with(Default::default);
+        let len = frozen.len();
+        frozen.retain(|o| !spent.contains(o));
+        if frozen.len() != len {
+            self.flush_store()?;
+        }
+        Ok(())
+    }
+
+    pub fn is_frozen(&self, outpoint: &BEOutPoint) -> bool {
+        let outpoint = OutPoint::new(outpoint.txid().into_bitcoin(), outpoint.vout());
+        self.store.frozen_outpoints.as_ref().map(|f| f.contains(&outpoint)).unwrap_or(false)
+    }
+
+    pub fn memos(&self) -> &HashMap<Txid, String> {
+        &self.store.memos
+    }
+
+    pub fn address_labels(&self) -> impl Iterator<Item = (&Script, &String)> {
+        self.store.address_labels.iter().flatten()
+    }
+
+    pub fn output_labels(&self) -> impl Iterator<Item = (&OutPoint, &String)> {
+        self.store.output_labels.iter().flatten()
+    }
+
+    pub fn frozen_outpoints(&self) -> impl Iterator<Item = &OutPoint> {
+        self.store.frozen_outpoints.iter().flatten()
+    }
+
+    /// Add the given memos and labels, replacing the existing ones with the same key
+    pub fn merge_labels(
+        &mut self,
+        memos: HashMap<Txid, String>,
+        address_labels: HashMap<Script, String>,
+        output_labels: HashMap<OutPoint, String>,
+    ) -> Result<(), Error> {
+        self.store.memos.extend(memos);
+        self.store.address_labels.get_or_insert_with(Default::default).extend(address_labels);
+        self.store.output_labels.get_or_insert_with(Default::default).extend(output_labels);
+        self.flush_store()?;
+        Ok(())
+    }
+
     pub fn insert_settings(&mut self, settings: Option<Settings>) -> Result<(), Error> {
         self.store.settings = settings;
         self.flush_store()?;
@@ -464,6 +602,23 @@ impl StoreMeta {
         Ok(())
     }
 
+    pub fn get_account_multisig(&self, account_num: u32) -> Option<&MultisigParams> {
+        self.store.accounts_multisig.as_ref().and_then(|m| m.get(&account_num))
+    }
+
+    pub fn set_account_multisig(
+        &mut self,
+        account_num: u32,
+        multisig: MultisigParams,
+    ) -> Result<(), Error> {
+        self.store
+            .accounts_multisig
+            .get_or_insert_with(Default::default)
+            .insert(account_num, multisig);
+        self.flush_store()?;
+        Ok(())
+    }
+
     pub fn spv_verification_status(&self, account_num: u32, txid: &BETxid) -> SPVVerifyTxResult {
         let acc_store = match self.account_cache(account_num) {
             Ok(store) => store,
@@ -499,6 +654,15 @@ impl StoreMeta {
         Err(Error::TxNotFound(txid.clone()))
     }
 
+    /// The entropy of an asset issued in the transactions of any account
+    pub fn issuance_entropy(&self, asset_id: &elements::issuance::AssetId) -> Option<[u8; 32]> {
+        self.cache
+            .accounts
+            .values()
+            .find_map(|acc_store| acc_store.issuances.get(asset_id))
+            .copied()
+    }
+
     pub fn update_tip(&mut self, new_height: u32, new_header: BEBlockHeader) -> Result<(), Error> {
         self.cache.tip_ = Some((new_height, new_header));
         self.flush_cache()?;
@@ -517,6 +681,8 @@ impl RawAccountCache {
             indexes: Default::default(),
             xpub,
             bip44_discovered,
+            last_used: Default::default(),
+            issuances: Default::default(),
         }
     }
     pub fn get_bitcoin_tx(&self, txid: &Txid) -> Result<Transaction, Error> {
@@ -560,18 +726,60 @@ mod tests {
         )
         .unwrap();
         let txid_btc = txid.ref_bitcoin().unwrap();
+        let script = BEScript::Bitcoin(Script::from(vec![0x51]));
 
         {
             let mut store = StoreMeta::new(&dir, &xpub, id).unwrap();
             store.make_account(0, xpub, true).unwrap(); // The xpub here is incorrect, but that's irrelevant for the sake of the test
             store.account_cache_mut(0).unwrap().heights.insert(txid, Some(1));
             store.store.memos.insert(*txid_btc, "memo".to_string());
+            store.insert_address_label(&script, "label").unwrap();
+            store.set_frozen(&[(BEOutPoint::new(txid.clone(), 1), true)]).unwrap();
         }
 
         let store = StoreMeta::new(&dir, &xpub, id).unwrap();
 
         assert_eq!(store.account_cache(0).unwrap().heights.get(&txid), Some(&Some(1)));
         assert_eq!(store.store.memos.get(txid_btc), Some(&"memo".to_string()));
+        assert_eq!(store.get_address_label(&script), Some(&"label".to_string()));
+        assert!(store.is_frozen(&BEOutPoint::new(txid.clone(), 1)));
+        assert!(!store.is_frozen(&BEOutPoint::new(txid, 0)));
+    }
+
+    #[test]
+    fn test_prune_frozen() {
+        let id = NetworkId::Bitcoin(Network::Testnet);
+        let mut dir = TempDir::new().unwrap().into_path();
+        dir.push(Kind::Store.to_string());
+        let xpub = ExtendedPubKey::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let prev_txid = Txid::from_inner([1; 32]);
+        let spent = BEOutPoint::Bitcoin(OutPoint::new(prev_txid, 0));
+        let unspent = BEOutPoint::Bitcoin(OutPoint::new(prev_txid, 1));
+        let tx = BETransaction::Bitcoin(Transaction {
+            version: 2,
+            lock_time: gdk_common::bitcoin::PackedLockTime::ZERO,
+            input: vec![gdk_common::bitcoin::TxIn {
+                previous_output: OutPoint::new(prev_txid, 0),
+                ..Default::default()
+            }],
+            output: vec![],
+        });
+        let txid = tx.txid();
+
+        let mut store = StoreMeta::new(&dir, &xpub, id).unwrap();
+        store.make_account(0, xpub, true).unwrap();
+        store.set_frozen(&[(spent.clone(), true), (unspent.clone(), true)]).unwrap();
+        let acc_store = store.account_cache_mut(0).unwrap();
+        acc_store.all_txs.insert(txid, tx.into());
+        acc_store.heights.insert(txid, None);
+
+        // an unconfirmed spending tx could still be replaced
+        store.prune_frozen().unwrap();
+        assert!(store.is_frozen(&spent));
+        store.account_cache_mut(0).unwrap().heights.insert(txid, Some(1));
+        store.prune_frozen().unwrap();
+        assert!(!store.is_frozen(&spent));
+        assert!(store.is_frozen(&unspent));
     }
 
     #[test]
@@ -0,0 +1,238 @@
+//! Watch-only sessions, made of account public keys only.
+//!
+//! Accounts are given either as SLIP-132 extended public keys or as single key output
+//! descriptors, like the ones returned in `core_descriptors` by `get_subaccount`.
+
+use std::collections::BTreeMap;
+
+use gdk_common::bitcoin::hashes::hex::FromHex;
+use gdk_common::bitcoin::hashes::{sha256, Hash, HashEngine};
+use gdk_common::bitcoin::util::base58;
+use gdk_common::bitcoin::util::bip32::{ChildNumber, ExtendedPrivKey, ExtendedPubKey};
+use gdk_common::model::WatchOnlyCredentials;
+use gdk_common::scripts::ScriptType;
+use gdk_common::wally::MasterBlindingKey;
+use gdk_common::NetworkParameters;
+
+use crate::account::{xpubs_equivalent, NUM_RESERVED_ACCOUNT_TYPES};
+use crate::descriptor::AccountDescriptor;
+use crate::error::Error;
+
+fn invalid(msg: impl Into<String>) -> Error {
+    Error::InvalidWatchOnlyCredentials(msg.into())
+}
+
+/// Parse a SLIP-132 extended public key, returning its script type and the key re-encoded with
+/// the standard BIP32 version bytes.
+pub fn parse_slip132_extended_pubkey(
+    s: &str,
+    network: &NetworkParameters,
+) -> Result<(ScriptType, ExtendedPubKey), Error> {
+    let mut data = base58::from_check(s).map_err(|_| invalid(format!("invalid base58 {}", s)))?;
+    if data.len() != 78 {
+        return Err(invalid(format!("invalid extended public key length {}", s)));
+    }
+    let (mainnet, script_type) = match data[0..4] {
+        [0x04, 0x88, 0xb2, 0x1e] => (true, ScriptType::P2pkh), // xpub
+        [0x04, 0x9d, 0x7c, 0xb2] => (true, ScriptType::P2shP2wpkh), // ypub
+        [0x04, 0xb2, 0x47, 0x46] => (true, ScriptType::P2wpkh), // zpub
+        [0x04, 0x35, 0x87, 0xcf] => (false, ScriptType::P2pkh), // tpub
+        [0x04, 0x4a, 0x52, 0x62] => (false, ScriptType::P2shP2wpkh), // upub
+        [0x04, 0x5f, 0x1c, 0xf6] => (false, ScriptType::P2wpkh), // vpub
+        _ => return Err(invalid(format!("unsupported extended public key version {}", s))),
+    };
+    if mainnet != network.mainnet {
+        return Err(invalid(format!("extended public key for the wrong network {}", s)));
+    }
+    let version = if mainnet {
+        [0x04, 0x88, 0xb2, 0x1e]
+    } else {
+        [0x04, 0x35, 0x87, 0xcf]
+    };
+    data[0..4].copy_from_slice(&version);
+    Ok((script_type, ExtendedPubKey::decode(&data)?))
+}
+
+/// Parse a single key output descriptor, see `AccountDescriptor::parse` for the supported forms.
+///
+/// Returns the script type, the account extended public key and the BIP32 account index, taken
+/// from the key origin if present or from the key itself otherwise.
+pub fn parse_core_descriptor(
+    s: &str,
+    network: &NetworkParameters,
+) -> Result<(ScriptType, ExtendedPubKey, u32), Error> {
+    let desc = AccountDescriptor::parse(s, network)?;
+    if desc.threshold.is_some() {
+        return Err(invalid(format!("multisig descriptors cannot be watched {}", s)));
+    }
+    let key = &desc.keys[0];
+    Ok((desc.script_type, key.xpub, desc.bip32_account(key)?))
+}
+
+/// The accounts described by the credentials, keyed by account number.
+pub fn watch_only_accounts(
+    credentials: &WatchOnlyCredentials,
+    network: &NetworkParameters,
+) -> Result<BTreeMap<u32, ExtendedPubKey>, Error> {
+    let mut keys = vec![];
+    for s in credentials.slip132_extended_pubkeys.iter() {
+        let (script_type, xpub) = parse_slip132_extended_pubkey(s, network)?;
+        let index = match xpub.child_number {
+            ChildNumber::Hardened {
+                index,
+            } => index,
+            ChildNumber::Normal {
+                ..
+            } => return Err(invalid(format!("account key must be hardened derived {}", s))),
+        };
+        keys.push((script_type, xpub, index));
+    }
+    for s in credentials.core_descriptors.iter() {
+        keys.push(parse_core_descriptor(s, network)?);
+    }
+
+    let mut accounts = BTreeMap::new();
+    for (script_type, xpub, bip32_account_num) in keys {
+        let account_num = bip32_account_num
+            .checked_mul(NUM_RESERVED_ACCOUNT_TYPES)
+            .and_then(|n| n.checked_add(script_type.first_account_num()))
+            .ok_or_else(|| invalid(format!("account index too large {}", bip32_account_num)))?;
+        // The external and internal descriptors of an account share the same key
+        if let Some(existing) = accounts.insert(account_num, xpub) {
+            xpubs_equivalent(&existing, &xpub)?;
+        }
+    }
+    if accounts.is_empty() {
+        return Err(invalid("no accounts"));
+    }
+    Ok(accounts)
+}
+
+/// A master xpub standing for the watch-only wallet, it identifies the store of the session.
+///
+/// It is derived from the account keys, so that logging in with the same accounts opens the same
+/// store, while the cache remains encrypted with a key that is unknown without them.
+pub fn watch_only_master_xpub(
+    accounts: &BTreeMap<u32, ExtendedPubKey>,
+    network: &NetworkParameters,
+) -> Result<ExtendedPubKey, Error> {
+    let mut engine = sha256::Hash::engine();
+    for (account_num, xpub) in accounts {
+        engine.input(&account_num.to_be_bytes());
+        engine.input(&xpub.public_key.serialize());
+        engine.input(&xpub.chain_code[..]);
+    }
+    let seed = sha256::Hash::from_engine(engine);
+    let master_xprv = ExtendedPrivKey::new_master(network.bip32_network(), &seed[..])?;
+    Ok(ExtendedPubKey::from_priv(&crate::EC, &master_xprv))
+}
+
+/// Parse a SLIP-77 master blinding key, given either as the 32 bytes key or as the 64 bytes
+/// value of which it is the second half.
+pub fn parse_slip77_key(s: &str) -> Result<MasterBlindingKey, Error> {
+    let bytes = Vec::<u8>::from_hex(s)?;
+    let mut key = [0u8; 64];
+    match bytes.len() {
+        32 => key[32..].copy_from_slice(&bytes),
+        64 => key.copy_from_slice(&bytes),
+        _ => return Err(invalid("SLIP-77 key must be 32 or 64 bytes")),
+    }
+    Ok(MasterBlindingKey(key))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gdk_common::bitcoin;
+    use gdk_common::network::NetworkParameters;
+
+    fn mainnet() -> NetworkParameters {
+        let mut network = NetworkParameters::default();
+        network.mainnet = true;
+        network
+    }
+
+    // Test vectors from SLIP-132 and BIP-84, "abandon ... about" mnemonic
+    const ZPUB: &str = "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs";
+    const XPUB: &str = "xpub6BosfCnifzxcFwrSzQiqu2DBVTshkCXacvNsWGYJVVhhawA7d4R5WSWGFNbi8Aw6ZRc1brxMyWMzG3DSSSSoekkudhUd9yLb6qx39T9nMdj";
+
+    #[test]
+    fn test_parse_slip132() {
+        let network = mainnet();
+        let (script_type, xpub) = parse_slip132_extended_pubkey(ZPUB, &network).unwrap();
+        assert_eq!(script_type, ScriptType::P2wpkh);
+        assert_eq!(xpub.child_number, ChildNumber::from_hardened_idx(0).unwrap());
+        assert_eq!(xpub.network, bitcoin::Network::Bitcoin);
+
+        let testnet = NetworkParameters::default();
+        assert!(parse_slip132_extended_pubkey(ZPUB, &testnet).is_err());
+        assert!(parse_slip132_extended_pubkey("zpub", &network).is_err());
+    }
+
+    #[test]
+    fn test_parse_descriptor() {
+        let network = mainnet();
+        let desc = format!("pkh([73c5da0a/44'/0'/0']{}/0/*)", XPUB);
+        let desc = format!("{}#{}", desc, crate::descriptor::checksum(&desc).unwrap());
+        let (script_type, xpub, index) = parse_core_descriptor(&desc, &network).unwrap();
+        assert_eq!(script_type, ScriptType::P2pkh);
+        assert_eq!(xpub.to_string(), XPUB);
+        assert_eq!(index, 0);
+
+        // Same key, no key origin
+        let desc = format!("sh(wpkh({}/1/*))", XPUB);
+        let (script_type, _, index) = parse_core_descriptor(&desc, &network).unwrap();
+        assert_eq!(script_type, ScriptType::P2shP2wpkh);
+        assert_eq!(index, 0);
+
+        // Purpose does not match the script type
+        let desc = format!("wpkh([73c5da0a/44'/0'/0']{}/0/*)", XPUB);
+        assert!(parse_core_descriptor(&desc, &network).is_err());
+        // Unsupported derivation
+        let desc = format!("wpkh({}/2/*)", XPUB);
+        assert!(parse_core_descriptor(&desc, &network).is_err());
+        // Unsupported script
+        let desc = format!("wsh(pk({}/0/*))", XPUB);
+        assert!(parse_core_descriptor(&desc, &network).is_err());
+        // Invalid checksum
+        let desc = format!("pkh({}/0/*)#00000000", XPUB);
+        assert!(parse_core_descriptor(&desc, &network).is_err());
+    }
+
+    #[test]
+    fn test_watch_only_accounts() {
+        let network = mainnet();
+        let credentials = WatchOnlyCredentials {
+            slip132_extended_pubkeys: vec![ZPUB.to_string()],
+            core_descriptors: vec![
+                format!("tr([73c5da0a/86'/0'/1']{}/0/*)", XPUB),
+                format!("tr([73c5da0a/86'/0'/1']{}/1/*)", XPUB),
+            ],
+            slip77_key: None,
+        };
+        let accounts = watch_only_accounts(&credentials, &network).unwrap();
+        assert_eq!(accounts.keys().copied().collect::<Vec<_>>(), vec![1, 19]);
+
+        let master_xpub = watch_only_master_xpub(&accounts, &network).unwrap();
+        assert_eq!(master_xpub, watch_only_master_xpub(&accounts, &network).unwrap());
+
+        // Different keys for the same account
+        let credentials = WatchOnlyCredentials {
+            slip132_extended_pubkeys: vec![ZPUB.to_string()],
+            core_descriptors: vec![format!("wpkh({}/0/*)", XPUB)],
+            slip77_key: None,
+        };
+        assert!(watch_only_accounts(&credentials, &network).is_err());
+
+        assert!(watch_only_accounts(&WatchOnlyCredentials::default(), &network).is_err());
+    }
+
+    #[test]
+    fn test_parse_slip77_key() {
+        let key = parse_slip77_key(&"11".repeat(32)).unwrap();
+        assert_eq!(&key.0[..32], &[0u8; 32]);
+        assert_eq!(&key.0[32..], &[0x11; 32]);
+        assert!(parse_slip77_key(&"11".repeat(64)).is_ok());
+        assert!(parse_slip77_key(&"11".repeat(16)).is_err());
+    }
+}
@@ -20,6 +20,7 @@ use gdk_common::exchange_rates::{ExchangeRatesCache, ExchangeRatesCacher};
 use gdk_common::log::{self, debug, info, LevelFilter, Metadata, Record};
 use gdk_common::session::{JsonError, Session};
 use gdk_common::ureq;
+use gdk_electrum::message::{self, VerifyMessageParam};
 use gdk_electrum::pset::{self, ExtractParam, FromTxParam, MergeTxParam};
 use gdk_electrum::{headers, ElectrumSession, NativeNotif};
 use serde::Serialize;
@@ -33,7 +34,8 @@ pub struct GdkSession {
 }
 
 pub enum GdkBackend {
-    // Rpc(RpcSession),
+    /// Singlesig session, syncing from an Electrum server, an Esplora server, a node RPC or the
+    /// compact block filters of a P2P node
     Electrum(ElectrumSession),
     Greenlight(GreenlightSession),
 }
@@ -154,9 +156,8 @@ fn create_session(network: &Value) -> Result<GdkSession, Value> {
     let parsed_network = parsed_network.unwrap();
 
     let backend = match network["server_type"].as_str() {
-        // Some("rpc") => GDKRUST_session::Rpc( GDKRPC_session::create_session(parsed_network.unwrap()).unwrap() ),
         Some("greenlight") => GdkBackend::Greenlight(GreenlightSession::default()),
-        Some("electrum") => {
+        Some("electrum") | Some("esplora") | Some("rpc") | Some("compact_filters") => {
             let session = ElectrumSession::new(parsed_network)?;
             GdkBackend::Electrum(session)
         }
@@ -222,6 +223,7 @@ fn call_session(sess: &mut GdkSession, method: &str, input: &str) -> Result<Valu
     // Redact inputs containing private data
     let methods_to_redact_in = vec![
         "login",
+        "login_wo",
         "register_user",
         "encrypt_with_pin",
         "decrypt_with_pin",
@@ -356,6 +358,10 @@ fn handle_call(method: &str, input: &str) -> Result<String, Error> {
             let param: MergeTxParam = serde_json::from_str(input)?;
             to_string(&pset::merge_tx(&param)?)
         }
+        "verify_message" => {
+            let param: VerifyMessageParam = serde_json::from_str(input)?;
+            to_string(&message::verify_message(&param)?)
+        }
         "spv_verify_tx" => {
             let param: SPVVerifyTxParams = serde_json::from_str(input)?;
             to_string(&headers::spv_verify_tx(&param)?.as_i32())
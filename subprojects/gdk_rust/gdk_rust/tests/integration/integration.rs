@@ -1,12 +1,18 @@
 use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
 use std::net::TcpListener;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc};
 use std::thread;
 use std::time::{Duration, Instant};
 
 use electrsd::bitcoind::bitcoincore_rpc::RpcApi;
 use electrum_client::ElectrumApi;
+use gdk_common::bitcoin::consensus::{deserialize, serialize};
+use gdk_common::bitcoin::hashes::hex::FromHex;
 use gdk_common::bitcoin::util::bip32::DerivationPath;
-use gdk_common::bitcoin::Witness;
+use gdk_common::bitcoin::util::psbt::{self, PartiallySignedTransaction};
+use gdk_common::bitcoin::{Transaction, TxIn, Witness};
 use gdk_common::log::info;
 use serde_json::Value;
 use tempfile::TempDir;
@@ -18,10 +24,12 @@ use gdk_common::session::Session;
 use gdk_common::{NetworkId, NetworkParameters, State};
 use gdk_electrum::error::Error;
 use gdk_electrum::headers::bitcoin::HeadersChain;
-use gdk_electrum::interface::ElectrumUrl;
+use gdk_electrum::interface::{ElectrumUrl, ServerUrl};
+use gdk_electrum::message::{self, VerifyMessageParam};
+use gdk_electrum::signer::{handle_request, RemoteSigner, SignerRequest};
 use gdk_electrum::{headers, spv, ElectrumSession};
 use gdk_test::utils;
-use gdk_test::{ElectrumSessionExt, TestSession};
+use gdk_test::{ElectrumSessionExt, RpcNodeExt, TestSession, TestSigner};
 
 static MEMO1: &str = "hello memo";
 static MEMO2: &str = "hello memo2";
@@ -140,6 +148,129 @@ fn roundtrip_liquid() {
     test_session.stop();
 }
 
+#[test]
+fn esplora_bitcoin() {
+    esplora(false);
+}
+
+#[test]
+fn esplora_liquid() {
+    esplora(true);
+}
+
+fn esplora(is_liquid: bool) {
+    let mut test_session = TestSession::new(is_liquid, |network| {
+        network.server_type = Some("esplora".into());
+    });
+    assert!(matches!(test_session.session.url, ServerUrl::Esplora(_)));
+
+    let node_address = test_session.node_getnewaddress(Some("bech32"));
+    test_session.fund(100_000_000, None);
+    let txid = test_session.send_tx(&node_address, 10_000, None, None, None, None, None);
+    test_session.is_verified(&txid, SPVVerifyTxResult::Unconfirmed);
+    test_session.mine_block();
+    test_session.fees();
+    test_session.is_verified(&txid, SPVVerifyTxResult::Verified);
+    test_session.reconnect();
+
+    test_session.stop();
+}
+
+#[test]
+fn rpc_bitcoin() {
+    rpc(false);
+}
+
+#[test]
+fn rpc_liquid() {
+    rpc(true);
+}
+
+fn rpc(is_liquid: bool) {
+    let mut test_session = TestSession::new(is_liquid, |network| {
+        network.server_type = Some("rpc".into());
+        // the regtest chain is as old as the test
+        network.rpc_wallet_birthday = Some(0);
+    });
+    assert!(matches!(test_session.session.url, ServerUrl::Rpc(_)));
+
+    let node_address = test_session.node_getnewaddress(Some("bech32"));
+    test_session.fund(100_000_000, None);
+    let txid = test_session.send_tx(&node_address, 10_000, None, None, None, None, None);
+    test_session.is_verified(&txid, SPVVerifyTxResult::Unconfirmed);
+    test_session.mine_block();
+    test_session.fees();
+    test_session.is_verified(&txid, SPVVerifyTxResult::Verified);
+    test_session.reconnect();
+
+    // Restoring the wallet in another node wallet rescans the history of its scripts
+    let mut network = test_session.network_parameters().clone();
+    let temp_dir = TempDir::new().unwrap();
+    network.state_dir = format!("{}", temp_dir.path().display());
+    network.rpc_wallet = Some("restored".into());
+    let mut restored = ElectrumSession::new(network.clone()).unwrap();
+    restored.connect(&serde_json::to_value(network).unwrap()).unwrap();
+    restored.login(test_session.credentials.clone()).unwrap();
+    restored.wait_account_n_txs(0, 2);
+    let opt = GetBalanceOpt {
+        subaccount: 0,
+        num_confs: 0,
+        confidential_utxos_only: None,
+        all_coins: None,
+    };
+    assert_eq!(
+        restored.get_balance(&opt).unwrap(),
+        test_session.session.get_balance(&opt).unwrap()
+    );
+    restored.disconnect().unwrap();
+
+    test_session.stop();
+}
+
+#[test]
+fn compact_filters_bitcoin() {
+    let mut test_session = TestSession::new(false, |network| {
+        network.server_type = Some("compact_filters".into());
+        // there is a single node, check the filter headers against another connection to it
+        network.compact_filters_check_peers = network.compact_filters_peer.clone().map(|p| vec![p]);
+    });
+    assert!(matches!(test_session.session.url, ServerUrl::CompactFilters(_)));
+
+    // the peer mempool is not queried, incoming txs are seen once confirmed
+    let satoshi = 100_000_000;
+    let ap = test_session.get_receive_address(0);
+    let funding_tx = test_session.node_sendtoaddress(&ap.address, satoshi, None);
+    test_session.mine_block();
+    test_session.wait_tx(vec![0], &funding_tx, Some(satoshi), Some(TransactionType::Incoming));
+    assert_eq!(test_session.balance_gdk(None), satoshi);
+
+    let node_address = test_session.node_getnewaddress(Some("bech32"));
+    let txid = test_session.send_tx(&node_address, 10_000, None, None, None, None, None);
+    test_session.is_verified(&txid, SPVVerifyTxResult::Unconfirmed);
+    let block = test_session.mine_block();
+    test_session.is_verified(&txid, SPVVerifyTxResult::Verified);
+    let height = test_session.node_get_block_count();
+    assert_eq!(test_session.get_tx_from_list(0, &txid).block_height, height);
+
+    // a reorg replacing its block with an empty one moves the tx to the next block
+    let client = &test_session.node.client;
+    client.call::<Value>("invalidateblock", &[block.into()]).unwrap();
+    let empty: Vec<Value> = vec![];
+    client.call::<Value>("generateblock", &[node_address.into(), empty.into()]).unwrap();
+    test_session.node_generate(1);
+    for _ in 0..60 {
+        if test_session.get_tx_from_list(0, &txid).block_height == height + 1 {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+    assert_eq!(test_session.get_tx_from_list(0, &txid).block_height, height + 1);
+    test_session.wait_tx_spv_change(&txid, "verified");
+    test_session.reconnect();
+
+    test_session.stop();
+}
+
 fn check_account_balances(test_session: &TestSession, balances: &HashMap<u32, u64>) {
     for (n, balance) in balances {
         assert_eq!(test_session.balance_account(*n, None, None), *balance);
@@ -521,6 +652,7 @@ fn coin_selection(is_liquid: bool) {
         address: node_address.to_string(),
         satoshi: sat8,
         asset_id: test_session.asset_id(),
+        data: None,
     });
     create_opt.utxos = CreateTxUtxos::default();
     create_opt.utxo_strategy = UtxoStrategy::Manual;
@@ -546,6 +678,7 @@ fn coin_selection(is_liquid: bool) {
             address: node_address.to_string(),
             satoshi: sat2_a,
             asset_id: Some(asset_a.clone()),
+            data: None,
         });
         utxos.0.remove_entry(&btc_key);
         create_opt.utxos = utils::convertutxos(&utxos);
@@ -627,6 +760,53 @@ fn coin_selection(is_liquid: bool) {
     }
 }
 
+#[test]
+fn coin_selection_strategies() {
+    let mut test_session = TestSession::new(false, |_| ());
+
+    let (sat1, sat2, sat3) = (10_000, 20_000, 35_000);
+    for sat in [sat1, sat2, sat3] {
+        let address = test_session.get_receive_address(0).address;
+        let txid = test_session.node_sendtoaddress(&address, sat, None);
+        test_session.wait_tx(vec![0], &txid, Some(sat), Some(TransactionType::Incoming));
+    }
+    let utxos = test_session.utxo("btc", vec![sat1, sat2, sat3]);
+    let node_address = test_session.node_getnewaddress(None);
+    let mut create = |strategy: UtxoStrategy| {
+        let mut create_opt =
+            test_session.create_opt(&node_address, 29_650, None, Some(1000), 0, utxos.clone());
+        create_opt.utxo_strategy = strategy;
+        test_session.session.create_transaction(&mut create_opt).unwrap()
+    };
+
+    // Largest first spends the biggest utxo and creates a change
+    let tx = create(UtxoStrategy::Default);
+    assert_eq!(tx.used_utxos.len(), 1);
+    assert_eq!(tx.changes_used, Some(1));
+
+    // The two smaller utxos cover the amount and the fee with an excess below the cost of change
+    for strategy in [UtxoStrategy::BranchAndBound, UtxoStrategy::LowestWaste] {
+        let tx = create(strategy);
+        let mut used: Vec<u64> = tx.used_utxos.iter().map(|u| u.satoshi).collect();
+        used.sort();
+        assert_eq!(used, vec![sat1, sat2]);
+        assert_eq!(tx.changes_used, Some(0));
+    }
+
+    let tx = create(UtxoStrategy::SingleRandomDraw);
+    let used: u64 = tx.used_utxos.iter().map(|u| u.satoshi).sum();
+    assert!(used > 29_650 + tx.fee);
+
+    // Coin selection is not performed when sending all
+    let mut create_opt = test_session.create_opt(&node_address, 0, None, None, 0, utxos.clone());
+    create_opt.send_all = true;
+    create_opt.utxo_strategy = UtxoStrategy::BranchAndBound;
+    let tx = test_session.session.create_transaction(&mut create_opt).unwrap();
+    assert_eq!(tx.used_utxos.len(), 3);
+
+    test_session.stop();
+}
+
 #[test]
 fn subaccounts_bitcoin() {
     subaccounts(false);
@@ -906,6 +1086,36 @@ fn subaccounts(is_liquid: bool) {
     new_session.get_subaccounts().unwrap();
     assert!(new_session.get_subaccount(new_account).is_ok());
 
+    // Recover the same subaccounts in a single call
+    let mut recover_session = {
+        let mut network = test_session.network_parameters().clone();
+        let temp_dir = TempDir::new().unwrap();
+        network.state_dir = format!("{}", temp_dir.path().display());
+        ElectrumSession::new(network).unwrap()
+    };
+    recover_session
+        .connect(&serde_json::to_value(recover_session.network.clone()).unwrap())
+        .unwrap();
+    recover_session.login(credentials.clone()).unwrap();
+    let recovered = recover_session.recover_subaccounts(RecoverAccountsOpt::default()).unwrap();
+    let mut recovered: Vec<_> = recovered.iter().map(|a| a.account_num).collect();
+    recovered.sort();
+    let mut expected = new_session.get_subaccount_nums().unwrap();
+    expected.retain(|n| *n != 0); // always present
+    assert_eq!(recovered, expected);
+    assert_eq!(
+        recover_session.get_subaccount_nums().unwrap(),
+        new_session.get_subaccount_nums().unwrap()
+    );
+    // One notification for every used subaccount, including 0, and for the first unused of each type
+    let events = recover_session.filter_events("subaccount_recovery");
+    assert_eq!(events.len(), expected.len() + 1 + ScriptType::types().len());
+    let unused = events.iter().filter(|e| e["subaccount_recovery"]["used"] == false).count();
+    assert_eq!(unused, ScriptType::types().len());
+    // Already existing subaccounts are not recreated
+    assert!(recover_session.recover_subaccounts(RecoverAccountsOpt::default()).unwrap().is_empty());
+    recover_session.disconnect().unwrap();
+
     let btc_key = test_session.btc_key();
 
     for subaccount in subaccounts.iter() {
@@ -915,6 +1125,7 @@ fn subaccounts(is_liquid: bool) {
             subaccount: subaccount.account_num,
             num_confs: 0,
             confidential_utxos_only: None,
+            all_coins: None,
         };
         let balance = *new_session.get_balance(&opt).unwrap().get(&btc_key).unwrap_or(&0i64) as u64;
         assert_eq!(
@@ -948,124 +1159,547 @@ fn subaccounts(is_liquid: bool) {
 }
 
 #[test]
-fn coinbase_bitcoin() {
-    coinbase(false);
+fn taproot_bitcoin() {
+    taproot(false);
 }
 
 #[test]
-fn coinbase_liquid() {
-    coinbase(true);
+fn taproot_liquid() {
+    taproot(true);
 }
 
-fn coinbase(is_liquid: bool) {
-    // Receive a coinbase transaction in the wallet
-    let test_session = TestSession::new(is_liquid, |_| ());
+fn taproot(is_liquid: bool) {
+    let mut test_session = TestSession::new(is_liquid, |_| ());
 
-    // Do a transaction so we have some fees to collect, note that this is necessary for Liquid
-    // since new blocks do not generate new coins.
-    test_session.node_sendtoaddress(&test_session.node_getnewaddress(None), 10000, None);
+    let account = test_session
+        .session
+        .create_subaccount(CreateAccountOpt {
+            subaccount: 3,
+            name: "Taproot".into(),
+            ..Default::default() // p2tr
+        })
+        .unwrap();
+    assert_eq!(account.script_type, ScriptType::P2tr);
+    assert!(account.slip132_extended_pubkey.is_none());
+    assert!(account.core_descriptors.iter().all(|d| d.starts_with("tr(")));
+    test_session.check_address_from_descriptor(3);
 
-    // Generate a coinbase sending an output to the wallet.
-    test_session.node_generatetoaddress(1, &test_session.get_receive_address(0).address);
-    test_session.wait_blockheight(102);
-    test_session.wait_account_n_txs(0, 1);
-    assert!(test_session.balance_account(0, None, None) > 0);
-    let txlist = test_session.get_tx_list(0);
-    assert_eq!(txlist.len(), 1);
-    assert_eq!(txlist[0].fee, 0);
+    let address = test_session.get_receive_address(3);
+    if is_liquid {
+        assert!(address.address.starts_with("el1p"));
+    } else {
+        assert!(address.address.starts_with("bcrt1p"));
+    }
+    let s = |v| DerivationPath::from(v).to_string();
+    assert_eq!(s(account.user_path), "m/86'/1'/0'");
 
-    // This coin is immature though, coinbase outputs cannot be spent until 101 blocks.
+    // Receive on and spend from the taproot subaccount
+    let sat = 54321;
+    let txid = test_session.node_sendtoaddress(&address.address, sat, None);
+    test_session.wait_tx(vec![3], &txid, Some(sat), Some(TransactionType::Incoming));
+    assert_eq!(test_session.balance_account(3, None, None), sat);
+
+    let sat_back = 12345;
+    let (txid, fee) =
+        test_session.send_tx_from(3, &test_session.get_receive_address(0).address, sat_back, None);
+    test_session.wait_tx(vec![0, 3], &txid, Some(sat_back), Some(TransactionType::Incoming));
+    assert_eq!(test_session.balance_account(3, None, None), sat - sat_back - fee);
+    assert_eq!(test_session.balance_account(0, None, None), sat_back);
+
+    test_session.stop();
 }
 
 #[test]
-fn spend_unsynced_bitcoin() {
-    spend_unsynced(false);
+fn multisig() {
+    let mut test_session = TestSession::new(false, |_| ());
+
+    // A 2-of-3 p2wsh subaccount, where the second cosigner runs another session
+    let path: DerivationPath = "m/48'/1'/0'/2'".parse().unwrap();
+    let network = test_session.network_parameters().bip32_network();
+    let with_origin = |signer: &TestSigner| CosignerXpub {
+        origin: Some((signer.account_xpub(&DerivationPath::master()).fingerprint(), path.clone())),
+        xpub: signer.account_xpub(&path),
+    };
+    let cosigner_xpub = |mnemonic: &str| {
+        let credentials = Credentials {
+            mnemonic: mnemonic.into(),
+            bip39_passphrase: "".into(),
+        };
+        with_origin(&TestSigner::new(&credentials, network, false))
+    };
+    let mnemonic2 = "legal winner thank year wave sausage worth useful legal winner thank yellow";
+    let xpub1 = with_origin(&test_session.test_signer());
+    let xpub2 = cosigner_xpub(mnemonic2);
+    let xpub3 = cosigner_xpub(
+        "letter advice cage absurd amount doctor acoustic avoid letter advice cage above",
+    );
+
+    let invalid_opt = CreateAccountOpt {
+        subaccount: 4,
+        multisig: Some(MultisigParams {
+            threshold: 4,
+            cosigner_xpubs: vec![xpub2.clone(), xpub3.clone()],
+        }),
+        ..Default::default()
+    };
+    assert!(test_session.session.create_subaccount(invalid_opt).is_err());
+    let account = test_session
+        .session
+        .create_subaccount(CreateAccountOpt {
+            subaccount: 4,
+            name: "Multisig".into(),
+            multisig: Some(MultisigParams {
+                threshold: 2,
+                cosigner_xpubs: vec![xpub2.clone(), xpub3.clone()],
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(account.script_type, ScriptType::P2wsh);
+    assert_eq!(account.multisig.as_ref().unwrap().threshold, 2);
+    assert!(account.slip132_extended_pubkey.is_none());
+    assert!(account.core_descriptors.iter().all(|d| d.starts_with("wsh(sortedmulti(2,")));
+    // with the key origins of all the cosigners
+    for cosigner in [&xpub1, &xpub2, &xpub3] {
+        assert!(account.core_descriptors.iter().all(|d| d.contains(&cosigner.to_string())));
+    }
+    test_session.check_address_from_descriptor(4);
+    let s = |v| DerivationPath::from(v).to_string();
+    assert_eq!(s(account.user_path), "m/48'/1'/0'/2'");
+
+    let mut cosigner_session = {
+        let mut network = test_session.network_parameters().clone();
+        let temp_dir = TempDir::new().unwrap();
+        network.state_dir = format!("{}", temp_dir.path().display());
+        ElectrumSession::new(network).unwrap()
+    };
+    cosigner_session
+        .connect(&serde_json::to_value(cosigner_session.network.clone()).unwrap())
+        .unwrap();
+    cosigner_session
+        .login(Credentials {
+            mnemonic: mnemonic2.into(),
+            bip39_passphrase: "".into(),
+        })
+        .unwrap();
+    cosigner_session
+        .create_subaccount(CreateAccountOpt {
+            subaccount: 4,
+            multisig: Some(MultisigParams {
+                threshold: 2,
+                cosigner_xpubs: vec![xpub1.clone(), xpub3.clone()],
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+
+    // Both cosigners derive the same addresses
+    let address = test_session.get_receive_address(4);
+    assert!(address.address.starts_with("bcrt1q"));
+    assert_eq!(address.address.len(), 64); // p2wsh
+    let addr_opt = GetAddressOpt {
+        subaccount: 4,
+        address_type: None,
+        is_internal: None,
+        uri: None,
+    };
+    assert_eq!(cosigner_session.get_receive_address(&addr_opt).unwrap().address, address.address);
+
+    let sat = 100_000;
+    let txid = test_session.node_sendtoaddress(&address.address, sat, None);
+    test_session.wait_tx(vec![4], &txid, Some(sat), Some(TransactionType::Incoming));
+    cosigner_session.wait_account_n_txs(4, 1);
+
+    // The first signature leaves the transaction incomplete
+    let node_address = test_session.node_getnewaddress(None);
+    let utxos = test_session.utxos(4);
+    let mut create_opt = test_session.create_opt(&node_address, 10_000, None, None, 4, utxos);
+    let tx = test_session.session.create_transaction(&mut create_opt).unwrap();
+    let signed_once = test_session.session.sign_transaction(&tx).unwrap();
+    assert!(test_session.session.broadcast_transaction(&signed_once.hex).is_err());
+
+    // Every cosigner finds its keys with its master fingerprint and full derivation path, both in
+    // the signed and in the exported PSBT
+    let exported = test_session.session.create_psbt(&tx).unwrap().psbt;
+    for psbt in [signed_once.psbt.as_ref().unwrap(), &exported] {
+        let psbt: PartiallySignedTransaction = deserialize(&base64::decode(psbt).unwrap()).unwrap();
+        for cosigner in [&xpub1, &xpub2, &xpub3] {
+            assert_eq!(psbt.xpub.get(&cosigner.xpub), cosigner.origin.as_ref());
+        }
+        let origins: Vec<_> = psbt.inputs[0].bip32_derivation.values().collect();
+        assert_eq!(origins.len(), 3);
+        for cosigner in [&xpub1, &xpub2, &xpub3] {
+            let (fingerprint, _) = cosigner.origin.as_ref().unwrap();
+            let (_, full_path) = origins.iter().find(|(f, _)| f == fingerprint).unwrap();
+            assert_eq!(full_path.to_string(), "m/48'/1'/0'/2'/0/1");
+        }
+    }
+
+    // The second cosigner completes it
+    let signed_twice = cosigner_session.sign_transaction(&signed_once).unwrap();
+    assert!(signed_twice.psbt.is_some());
+    let txid = test_session.session.broadcast_transaction(&signed_twice.hex).unwrap();
+    test_session.wait_tx(vec![4], &txid, None, Some(TransactionType::Outgoing));
+
+    cosigner_session.disconnect().unwrap();
+    test_session.stop();
 }
 
 #[test]
-fn spend_unsynced_liquid() {
-    spend_unsynced(true);
-}
+fn descriptors() {
+    let mut test_session = TestSession::new(false, |_| ());
 
-fn spend_unsynced(is_liquid: bool) {
-    let mut test_session = TestSession::new(is_liquid, |_| ());
+    // Export with key origin and checksum, as validated by the node
+    let opt = GetAccountPathOpt {
+        subaccount: 0,
+    };
+    let descriptors = test_session.session.get_subaccount_descriptors(opt).unwrap();
+    assert!(descriptors.external.starts_with("sh(wpkh([73c5da0a/49'/1'/0']tpub"));
+    for descriptor in [&descriptors.external, &descriptors.internal] {
+        let (desc, checksum) = descriptor.split_once('#').unwrap();
+        let ret =
+            test_session.node.client.call::<Value>("getdescriptorinfo", &[desc.into()]).unwrap();
+        assert_eq!(ret.get("checksum").unwrap().as_str().unwrap(), checksum);
+    }
 
-    // Fund the wallet
-    let sat1 = 10_000;
-    let address1 = test_session.get_receive_address(0).address;
-    let txid1 = test_session.node_sendtoaddress(&address1, sat1, None);
-    test_session.wait_tx(vec![0], &txid1, Some(sat1), Some(TransactionType::Incoming));
+    // Import a descriptor of the wallet, the subaccount number follows from its key origin
+    let path: DerivationPath = "m/84'/1'/0'".parse().unwrap();
+    let xpub = test_session.test_signer().account_xpub(&path);
+    let account = test_session
+        .session
+        .create_subaccount(CreateAccountOpt {
+            descriptor: Some(format!("wpkh([73c5da0a/84'/1'/0']{}/0/*)", xpub)),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(account.account_num, 1);
+    let opt = GetAccountPathOpt {
+        subaccount: 1,
+    };
+    let descriptors = test_session.session.get_subaccount_descriptors(opt).unwrap();
+    assert!(descriptors.internal.starts_with(&format!("wpkh([73c5da0a/84'/1'/0']{}/1/*)#", xpub)));
 
-    // Send a transcation, which will spend the only utxo we have.
-    let sat2 = 1_000;
-    let address2 = test_session.node_getnewaddress(None);
-    let utxos = test_session.utxos(0);
-    let mut create_opt = CreateTransaction::default();
-    create_opt.addressees.push(AddressAmount {
-        address: address2.to_string(),
-        satoshi: sat2,
-        asset_id: test_session.asset_id(),
-    });
-    create_opt.utxos = utils::convertutxos(&utxos);
-    let tx = test_session.session.create_transaction(&mut create_opt).unwrap();
-    let signed_tx = test_session.session.sign_transaction(&tx).unwrap();
-    let fee = signed_tx.fee;
-    let txid2 = test_session.session.broadcast_transaction(&signed_tx.hex).unwrap();
+    // Keys of other wallets and invalid checksums are rejected
+    let opt = CreateAccountOpt {
+        descriptor: Some(format!("pkh([00000000/44'/1'/0']{}/0/*)", xpub)),
+        ..Default::default()
+    };
+    assert!(test_session.session.create_subaccount(opt).is_err());
+    let opt = CreateAccountOpt {
+        descriptor: Some(format!("{}0", descriptors.external)),
+        ..Default::default()
+    };
+    assert!(test_session.session.create_subaccount(opt).is_err());
 
-    // Attempt to create a new transaction before we received the transaction notification and
-    // the db is updated with the sent transaction.
-    let events = test_session.session.filter_events("transaction");
-    assert!(events.iter().all(|e| e["transaction"]["txhash"].as_str().unwrap() != txid2));
+    test_session.stop();
+}
 
-    let btc_key = test_session.btc_key();
-    let utxos = test_session.utxos(0);
-    let utxos_btc = utxos.0.get(&btc_key).unwrap();
-    assert_eq!(utxos_btc.len(), 1);
-    assert!(utxos_btc.iter().any(|u| u.txhash == txid1));
+#[test]
+fn gap_limit() {
+    let mut test_session = TestSession::new(false, |_| ());
 
-    // We are reusing the utxo spend by txid2 and create tx fails.
-    // If we allowed to broadcast this transaction we would get a
-    // "bad-txns-inputs-missingorspent"
-    let mut create_opt = CreateTransaction::default();
-    create_opt.addressees.push(AddressAmount {
-        address: address2.to_string(),
-        satoshi: sat2,
-        asset_id: test_session.asset_id(),
-    });
-    create_opt.utxos = utils::convertutxos(&utxos);
-    let res = test_session.session.create_transaction(&mut create_opt);
-    assert!(res.is_err()); // insufficient funds
+    let opt = UpdateAccountOpt {
+        subaccount: 0,
+        gap_limit: Some(5),
+        ..Default::default()
+    };
+    test_session.session.update_subaccount(opt).unwrap();
+    assert_eq!(test_session.session.get_subaccount(0).unwrap().settings.gap_limit, Some(5));
 
-    // No notification yet
-    let events = test_session.session.filter_events("transaction");
-    assert!(events.iter().all(|e| e["transaction"]["txhash"].as_str().unwrap() != txid2));
+    // Warn when the last addresses within the gap limit are returned
+    let pointers: Vec<_> = (0..5).map(|_| test_session.get_receive_address(0)).collect();
+    assert_eq!(pointers.iter().map(|ap| ap.pointer).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+    assert_eq!(pointers[2].gap_limit_remaining, None);
+    assert_eq!(pointers[3].gap_limit_remaining, Some(1));
+    assert_eq!(pointers[4].gap_limit_remaining, Some(0));
 
-    // Now wait for notification
-    test_session.wait_tx(vec![0], &txid2, Some(sat2 + fee), Some(TransactionType::Outgoing));
+    // Beyond the gap limit, addresses are not reused
+    let ap = test_session.get_receive_address(0);
+    assert_eq!(ap.pointer, 6);
+    assert_eq!(ap.gap_limit_remaining, Some(0));
+    assert!(pointers.iter().all(|p| p.address != ap.address));
 
-    // Now that the db is synced, the utxo spent by txid2 is not included
-    let utxos = test_session.utxos(0);
-    let utxos_btc = utxos.0.get(&btc_key).unwrap();
-    assert_eq!(utxos_btc.len(), 1);
-    assert!(utxos_btc.iter().all(|u| u.txhash != txid1));
+    // Once an address is used, the window moves forward, without returning unused addresses again
+    let txid = test_session.node_sendtoaddress(&pointers[4].address, 10_000, None);
+    test_session.wait_tx(vec![0], &txid, Some(10_000), Some(TransactionType::Incoming));
+    let ap = test_session.get_receive_address(0);
+    assert_eq!(ap.pointer, 7);
+    assert_eq!(ap.gap_limit_remaining, None);
+
+    for gap_limit in [0, 1001] {
+        let opt = UpdateAccountOpt {
+            subaccount: 0,
+            gap_limit: Some(gap_limit),
+            ..Default::default()
+        };
+        assert!(test_session.session.update_subaccount(opt).is_err());
+    }
+
+    test_session.stop();
 }
 
 #[test]
-fn addresses_bitcoin() {
-    addresses(false);
+fn watch_only_bitcoin() {
+    watch_only(false);
 }
 
 #[test]
-fn addresses_liquid() {
-    addresses(true);
+fn watch_only_liquid() {
+    watch_only(true);
 }
 
-fn addresses(is_liquid: bool) {
+fn watch_only(is_liquid: bool) {
     let mut test_session = TestSession::new(is_liquid, |_| ());
 
-    // We send some coins to each address to ensure a new address is generated.
-    let sat = 1000;
-    for i in 1..12 {
+    let account1 = test_session
+        .session
+        .create_subaccount(CreateAccountOpt {
+            subaccount: 1,
+            name: "Account 1".into(),
+            ..Default::default()
+        })
+        .unwrap();
+    let account0 = test_session.session.get_subaccount(0).unwrap();
+
+    let sat = 100_000;
+    let address = test_session.get_receive_address(1).address;
+    let txid = test_session.node_sendtoaddress(&address, sat, None);
+    test_session.wait_tx(vec![1], &txid, Some(sat), Some(TransactionType::Incoming));
+
+    // Watch account 0 through its descriptors and account 1 through its xpub, if available
+    let mut credentials = WatchOnlyCredentials {
+        core_descriptors: account0.core_descriptors.clone(),
+        ..Default::default()
+    };
+    match account1.slip132_extended_pubkey.clone() {
+        Some(xpub) => credentials.slip132_extended_pubkeys.push(xpub),
+        None => credentials.core_descriptors.extend(account1.core_descriptors.clone()),
+    }
+    if is_liquid {
+        let key = test_session.session.get_master_blinding_key().unwrap().master_blinding_key;
+        credentials.slip77_key = serde_json::to_value(key).unwrap().as_str().map(String::from);
+    }
+
+    let mut wo_session = {
+        let mut network = test_session.network_parameters().clone();
+        let temp_dir = TempDir::new().unwrap();
+        network.state_dir = format!("{}", temp_dir.path().display());
+        ElectrumSession::new(network).unwrap()
+    };
+    wo_session.connect(&serde_json::to_value(wo_session.network.clone()).unwrap()).unwrap();
+    wo_session.login_wo(credentials.clone()).unwrap();
+
+    let subaccounts = wo_session.get_subaccounts().unwrap();
+    assert_eq!(subaccounts.len(), 2);
+    assert_eq!(wo_session.get_subaccount(0).unwrap().core_descriptors, account0.core_descriptors);
+    assert_eq!(wo_session.get_subaccount(1).unwrap().core_descriptors, account1.core_descriptors);
+    wo_session.wait_account_n_txs(1, 1);
+    let balance_opt = GetBalanceOpt {
+        subaccount: 1,
+        num_confs: 0,
+        confidential_utxos_only: None,
+        all_coins: None,
+    };
+    assert_eq!(
+        wo_session.get_balance(&balance_opt).unwrap(),
+        test_session.session.get_balance(&balance_opt).unwrap()
+    );
+
+    // Addresses match the ones of the full session
+    let wo_address = wo_session
+        .get_receive_address(&GetAddressOpt {
+            subaccount: 0,
+            address_type: None,
+            is_internal: None,
+            uri: None,
+        })
+        .unwrap();
+    assert_eq!(wo_address.address, test_session.get_receive_address(0).address);
+
+    // The watch-only session creates the transaction, but cannot sign it
+    let utxos = wo_session
+        .get_unspent_outputs(&GetUnspentOpt {
+            subaccount: 1,
+            num_confs: None,
+            confidential_utxos_only: None,
+            all_coins: None,
+        })
+        .unwrap();
+    let asset = test_session.session.network.policy_asset.clone();
+    let node_address = test_session.node_getnewaddress(None);
+    let mut create_opt =
+        test_session.create_opt(&node_address, 10_000, asset.clone(), None, 1, utxos.clone());
+    let tx = wo_session.create_transaction(&mut create_opt).unwrap();
+    assert!(tx.psbt.is_some());
+    assert!(matches!(wo_session.sign_transaction(&tx), Err(Error::IncompletePsbt)));
+
+    // A session holding the keys signs the signing request without connecting to any server
+    let mut offline_session = {
+        let mut network = test_session.network_parameters().clone();
+        let temp_dir = TempDir::new().unwrap();
+        network.state_dir = format!("{}", temp_dir.path().display());
+        network.electrum_url = Some("127.0.0.1:1".into());
+        ElectrumSession::new(network).unwrap()
+    };
+    offline_session
+        .connect(&serde_json::to_value(offline_session.network.clone()).unwrap())
+        .unwrap();
+    offline_session.login(test_session.credentials.clone()).unwrap();
+    offline_session
+        .create_subaccount(CreateAccountOpt {
+            subaccount: 1,
+            name: "Account 1".into(),
+            ..Default::default()
+        })
+        .unwrap();
+    let signed = offline_session.sign_transaction(&tx).unwrap();
+    assert_eq!(signed.hex, tx.hex);
+    assert_ne!(signed.psbt, tx.psbt);
+
+    // The signed request must be of the transaction created
+    let mut other_opt = test_session.create_opt(&node_address, 20_000, asset, None, 1, utxos);
+    let mut other_tx = wo_session.create_transaction(&mut other_opt).unwrap();
+    other_tx.psbt = signed.psbt.clone();
+    assert!(matches!(
+        wo_session.sign_transaction(&other_tx),
+        Err(Error::PsbtAndTxMismatch(..)) | Err(Error::PsetAndTxMismatch(..))
+    ));
+
+    // The watch-only session finalizes and broadcasts it
+    let signed_tx = wo_session.sign_transaction(&signed).unwrap();
+    let sent_tx = wo_session.send_transaction(&signed_tx).unwrap();
+    test_session.wait_tx(vec![1], &sent_tx.txid, None, Some(TransactionType::Outgoing));
+
+    // Logging in again with the same credentials opens the same wallet
+    let wallet_hash_id = wo_session.get_wallet_hash_id().unwrap().wallet_hash_id;
+    assert_eq!(wo_session.login_wo(credentials).unwrap().wallet_hash_id, wallet_hash_id);
+
+    wo_session.disconnect().unwrap();
+    offline_session.disconnect().unwrap();
+    test_session.stop();
+}
+
+#[test]
+fn coinbase_bitcoin() {
+    coinbase(false);
+}
+
+#[test]
+fn coinbase_liquid() {
+    coinbase(true);
+}
+
+fn coinbase(is_liquid: bool) {
+    // Receive a coinbase transaction in the wallet
+    let test_session = TestSession::new(is_liquid, |_| ());
+
+    // Do a transaction so we have some fees to collect, note that this is necessary for Liquid
+    // since new blocks do not generate new coins.
+    test_session.node_sendtoaddress(&test_session.node_getnewaddress(None), 10000, None);
+
+    // Generate a coinbase sending an output to the wallet.
+    test_session.node_generatetoaddress(1, &test_session.get_receive_address(0).address);
+    test_session.wait_blockheight(102);
+    test_session.wait_account_n_txs(0, 1);
+    assert!(test_session.balance_account(0, None, None) > 0);
+    let txlist = test_session.get_tx_list(0);
+    assert_eq!(txlist.len(), 1);
+    assert_eq!(txlist[0].fee, 0);
+
+    // This coin is immature though, coinbase outputs cannot be spent until 101 blocks.
+}
+
+#[test]
+fn spend_unsynced_bitcoin() {
+    spend_unsynced(false);
+}
+
+#[test]
+fn spend_unsynced_liquid() {
+    spend_unsynced(true);
+}
+
+fn spend_unsynced(is_liquid: bool) {
+    let mut test_session = TestSession::new(is_liquid, |_| ());
+
+    // Fund the wallet
+    let sat1 = 10_000;
+    let address1 = test_session.get_receive_address(0).address;
+    let txid1 = test_session.node_sendtoaddress(&address1, sat1, None);
+    test_session.wait_tx(vec![0], &txid1, Some(sat1), Some(TransactionType::Incoming));
+
+    // Send a transcation, which will spend the only utxo we have.
+    let sat2 = 1_000;
+    let address2 = test_session.node_getnewaddress(None);
+    let utxos = test_session.utxos(0);
+    let mut create_opt = CreateTransaction::default();
+    create_opt.addressees.push(AddressAmount {
+        address: address2.to_string(),
+        satoshi: sat2,
+        asset_id: test_session.asset_id(),
+        data: None,
+    });
+    create_opt.utxos = utils::convertutxos(&utxos);
+    let tx = test_session.session.create_transaction(&mut create_opt).unwrap();
+    let signed_tx = test_session.session.sign_transaction(&tx).unwrap();
+    let fee = signed_tx.fee;
+    let txid2 = test_session.session.broadcast_transaction(&signed_tx.hex).unwrap();
+
+    // Attempt to create a new transaction before we received the transaction notification and
+    // the db is updated with the sent transaction.
+    let events = test_session.session.filter_events("transaction");
+    assert!(events.iter().all(|e| e["transaction"]["txhash"].as_str().unwrap() != txid2));
+
+    let btc_key = test_session.btc_key();
+    let utxos = test_session.utxos(0);
+    let utxos_btc = utxos.0.get(&btc_key).unwrap();
+    assert_eq!(utxos_btc.len(), 1);
+    assert!(utxos_btc.iter().any(|u| u.txhash == txid1));
+
+    // We are reusing the utxo spend by txid2 and create tx fails.
+    // If we allowed to broadcast this transaction we would get a
+    // "bad-txns-inputs-missingorspent"
+    let mut create_opt = CreateTransaction::default();
+    create_opt.addressees.push(AddressAmount {
+        address: address2.to_string(),
+        satoshi: sat2,
+        asset_id: test_session.asset_id(),
+        data: None,
+    });
+    create_opt.utxos = utils::convertutxos(&utxos);
+    let res = test_session.session.create_transaction(&mut create_opt);
+    assert!(res.is_err()); // insufficient funds
+
+    // No notification yet
+    let events = test_session.session.filter_events("transaction");
+    assert!(events.iter().all(|e| e["transaction"]["txhash"].as_str().unwrap() != txid2));
+
+    // Now wait for notification
+    test_session.wait_tx(vec![0], &txid2, Some(sat2 + fee), Some(TransactionType::Outgoing));
+
+    // Now that the db is synced, the utxo spent by txid2 is not included
+    let utxos = test_session.utxos(0);
+    let utxos_btc = utxos.0.get(&btc_key).unwrap();
+    assert_eq!(utxos_btc.len(), 1);
+    assert!(utxos_btc.iter().all(|u| u.txhash != txid1));
+}
+
+#[test]
+fn addresses_bitcoin() {
+    addresses(false);
+}
+
+#[test]
+fn addresses_liquid() {
+    addresses(true);
+}
+
+fn addresses(is_liquid: bool) {
+    let mut test_session = TestSession::new(is_liquid, |_| ());
+
+    // We send some coins to each address to ensure a new address is generated.
+    let sat = 1000;
+    for i in 1..12 {
         let ap = test_session.get_receive_address(0);
         assert_eq!(ap.pointer, i);
         let txid = test_session.node_sendtoaddress(&ap.address, sat, None);
@@ -1172,6 +1806,7 @@ fn sighash(is_liquid: bool) {
             address: dest_address,
             satoshi: 5000,
             asset_id: test_session.asset_id(),
+            data: None,
         });
         create_opt.utxos = utils::convertutxos(&test_session.utxos(create_opt.subaccount));
         let mut txc = test_session.session.create_transaction(&mut create_opt).unwrap();
@@ -1210,6 +1845,458 @@ fn sighash(is_liquid: bool) {
     }
 }
 
+#[test]
+fn data_output_bitcoin() {
+    data_output(false);
+}
+
+#[test]
+fn data_output_liquid() {
+    data_output(true);
+}
+
+fn data_output(is_liquid: bool) {
+    let mut test_session = TestSession::new(is_liquid, |_| ());
+
+    let sat = 100_000;
+    let txid =
+        test_session.node_sendtoaddress(&test_session.get_receive_address(0).address, sat, None);
+    test_session.wait_tx(vec![0], &txid, Some(sat), Some(TransactionType::Incoming));
+
+    let data = "ab".repeat(32);
+    let data_addressee = AddressAmount {
+        address: "".into(),
+        satoshi: 0,
+        asset_id: test_session.asset_id(),
+        data: Some(data.clone()),
+    };
+    let mut create_opt = CreateTransaction::default();
+    create_opt.addressees.push(AddressAmount {
+        address: test_session.node_getnewaddress(None),
+        satoshi: 5000,
+        asset_id: test_session.asset_id(),
+        data: None,
+    });
+    create_opt.addressees.push(data_addressee.clone());
+    create_opt.utxos = utils::convertutxos(&test_session.utxos(0));
+
+    // Only a single output with no value and up to 80 bytes is standard
+    for addressee in [
+        AddressAmount {
+            data: Some("ab".repeat(81)),
+            ..data_addressee.clone()
+        },
+        AddressAmount {
+            satoshi: 1000,
+            ..data_addressee.clone()
+        },
+        data_addressee.clone(),
+    ] {
+        let mut invalid_opt = create_opt.clone();
+        invalid_opt.addressees.push(addressee);
+        assert!(matches!(
+            test_session.session.create_transaction(&mut invalid_opt),
+            Err(Error::InvalidDataOutput)
+        ));
+    }
+
+    let tx = test_session.session.create_transaction(&mut create_opt).unwrap();
+    let outputs: Vec<&TransactionOutput> =
+        tx.transaction_outputs.iter().filter(|o| o.data.is_some()).collect();
+    assert_eq!(outputs.len(), 1);
+    assert_eq!(outputs[0].data.as_deref(), Some(data.as_str()));
+    assert_eq!(outputs[0].satoshi, 0);
+
+    let signed_tx = test_session.session.sign_transaction(&tx).unwrap();
+    let tx_decoded = test_session
+        .node
+        .client
+        .call::<Value>("decoderawtransaction", &[signed_tx.hex.clone().into()])
+        .unwrap();
+    let null_data: Vec<&Value> = tx_decoded["vout"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|o| o["scriptPubKey"]["type"] == "nulldata")
+        .collect();
+    assert_eq!(null_data.len(), 1);
+    assert_eq!(null_data[0]["scriptPubKey"]["hex"], format!("6a40{}", data));
+    let txid = test_session.session.broadcast_transaction(&signed_tx.hex).unwrap();
+    test_session.wait_tx(vec![0], &txid, None, Some(TransactionType::Outgoing));
+
+    test_session.stop();
+}
+
+#[test]
+fn issuance_liquid() {
+    let mut test_session = TestSession::new(true, |_| ());
+
+    let sat = 1_000_000;
+    let txid =
+        test_session.node_sendtoaddress(&test_session.get_receive_address(0).address, sat, None);
+    test_session.wait_tx(vec![0], &txid, Some(sat), Some(TransactionType::Incoming));
+
+    // Issue an asset and its reissuance token, both received by the wallet
+    let mut create_opt = CreateTransaction {
+        issuance: Some(IssuanceOpt {
+            satoshi: 10_000,
+            token_satoshi: 1,
+            contract_hash: Some("ab".repeat(32)),
+        }),
+        ..Default::default()
+    };
+    let (tx, tx_decoded) = send_decoded(&mut test_session, &mut create_opt);
+    let asset_id = tx.issued_asset_id.clone().unwrap();
+    let token_id = tx.issued_token_id.clone().unwrap();
+    let issuance = decoded_issuance(&tx_decoded);
+    assert_eq!(issuance["isreissuance"], false);
+    assert_eq!(issuance["asset"], asset_id.as_str());
+    assert_eq!(issuance["token"], token_id.as_str());
+    let issuance_input =
+        tx_decoded["vin"].as_array().unwrap().iter().find(|i| i.get("issuance").is_some()).unwrap();
+    let prevout_txhash = issuance_input["txid"].as_str().unwrap().to_string();
+    let prevout_pt_idx = issuance_input["vout"].as_u64().unwrap() as u32;
+    assert_eq!(test_session.balance_account(0, Some(asset_id.clone()), None), 10_000);
+    assert_eq!(test_session.balance_account(0, Some(token_id.clone()), None), 1);
+
+    // Reissue the asset spending the token, which is received back by the wallet
+    let mut create_opt = CreateTransaction {
+        reissuance: Some(ReissuanceOpt {
+            asset_id: asset_id.clone(),
+            satoshi: 5_000,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let (tx, tx_decoded) = send_decoded(&mut test_session, &mut create_opt);
+    assert_eq!(tx.issued_asset_id.as_ref(), Some(&asset_id));
+    assert_eq!(tx.issued_token_id, None);
+    let issuance = decoded_issuance(&tx_decoded);
+    assert_eq!(issuance["isreissuance"], true);
+    assert_eq!(issuance["asset"], asset_id.as_str());
+    assert_eq!(test_session.balance_account(0, Some(asset_id.clone()), None), 15_000);
+    assert_eq!(test_session.balance_account(0, Some(token_id.clone()), None), 1);
+
+    // The entropy can also be given by the contract hash and the outpoint spent by the issuance
+    let mut create_opt = CreateTransaction {
+        reissuance: Some(ReissuanceOpt {
+            asset_id: asset_id.clone(),
+            satoshi: 1_000,
+            contract_hash: Some("ab".repeat(32)),
+            prevout_txhash: Some(prevout_txhash),
+            prevout_pt_idx: Some(prevout_pt_idx),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    send_decoded(&mut test_session, &mut create_opt);
+    assert_eq!(test_session.balance_account(0, Some(asset_id.clone()), None), 16_000);
+    let mut create_opt = CreateTransaction {
+        reissuance: Some(ReissuanceOpt {
+            asset_id: asset_id.clone(),
+            satoshi: 1_000,
+            entropy: Some("00".repeat(32)),
+            ..Default::default()
+        }),
+        utxos: utils::convertutxos(&test_session.utxos(0)),
+        ..Default::default()
+    };
+    assert!(matches!(
+        test_session.session.create_transaction(&mut create_opt),
+        Err(Error::InvalidIssuance(_))
+    ));
+
+    // Burn part of the asset with an OP_RETURN output
+    let mut create_opt = CreateTransaction::default();
+    create_opt.addressees.push(AddressAmount {
+        address: "".into(),
+        satoshi: 4_000,
+        asset_id: Some(asset_id.clone()),
+        data: Some("".into()),
+    });
+    let (_, tx_decoded) = send_decoded(&mut test_session, &mut create_opt);
+    let burns: Vec<&Value> = tx_decoded["vout"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|o| o["scriptPubKey"]["type"] == "nulldata")
+        .collect();
+    assert_eq!(burns.len(), 1);
+    assert_eq!(burns[0]["asset"], asset_id.as_str());
+    assert_eq!(test_session.balance_account(0, Some(asset_id.clone()), None), 12_000);
+
+    // Assets without issued amount or whose token is not in the wallet can not be issued
+    let mut create_opt = CreateTransaction {
+        issuance: Some(IssuanceOpt::default()),
+        utxos: utils::convertutxos(&test_session.utxos(0)),
+        ..Default::default()
+    };
+    assert!(matches!(
+        test_session.session.create_transaction(&mut create_opt),
+        Err(Error::InvalidIssuance(_))
+    ));
+    let mut create_opt = CreateTransaction {
+        reissuance: Some(ReissuanceOpt {
+            asset_id: token_id.clone(),
+            satoshi: 5_000,
+            ..Default::default()
+        }),
+        utxos: utils::convertutxos(&test_session.utxos(0)),
+        ..Default::default()
+    };
+    assert!(matches!(
+        test_session.session.create_transaction(&mut create_opt),
+        Err(Error::ReissuanceTokenNotFound(_))
+    ));
+
+    test_session.stop();
+}
+
+/// Create, sign and broadcast a transaction of subaccount 0 spending any of its utxos, returning it
+/// with its decoding by the node
+fn send_decoded(
+    test_session: &mut TestSession,
+    create_opt: &mut CreateTransaction,
+) -> (TransactionMeta, Value) {
+    create_opt.utxos = utils::convertutxos(&test_session.utxos(0));
+    let tx = test_session.session.create_transaction(create_opt).unwrap();
+    let signed_tx = test_session.session.sign_transaction(&tx).unwrap();
+    test_session.tx_checks(&signed_tx.hex);
+    let txid = test_session.session.broadcast_transaction(&signed_tx.hex).unwrap();
+    test_session.wait_tx(vec![0], &txid, None, None);
+    let tx_decoded = test_session
+        .node
+        .client
+        .call::<Value>("decoderawtransaction", &[signed_tx.hex.clone().into()])
+        .unwrap();
+    (tx, tx_decoded)
+}
+
+/// The issuance of the only input of the decoded transaction that has one
+fn decoded_issuance(tx_decoded: &Value) -> &Value {
+    let inputs = tx_decoded["vin"].as_array().unwrap();
+    let issuances: Vec<&Value> = inputs.iter().filter_map(|i| i.get("issuance")).collect();
+    assert_eq!(issuances.len(), 1);
+    issuances[0]
+}
+
+#[test]
+fn payment_uri_bitcoin() {
+    payment_uri(false);
+}
+
+#[test]
+fn payment_uri_liquid() {
+    payment_uri(true);
+}
+
+fn payment_uri(is_liquid: bool) {
+    let mut test_session = TestSession::new(is_liquid, |_| ());
+    let prefix = test_session.network_parameters().bip21_prefix();
+
+    let sat = 100_000;
+    let txid =
+        test_session.node_sendtoaddress(&test_session.get_receive_address(0).address, sat, None);
+    test_session.wait_tx(vec![0], &txid, Some(sat), Some(TransactionType::Incoming));
+
+    let address = test_session
+        .session
+        .get_receive_address(&GetAddressOpt {
+            subaccount: 0,
+            address_type: None,
+            is_internal: None,
+            uri: Some(AddressUriOpt {
+                satoshi: Some(15_000),
+                label: Some("Invoice 1".into()),
+            }),
+        })
+        .unwrap();
+    let uri = address.uri.unwrap();
+    assert!(uri.starts_with(&format!("{}:{}?amount=0.00015", prefix, address.address)));
+
+    let mut create_opt = test_session
+        .session
+        .parse_payment_uri(ParsePaymentUriOpt {
+            uri,
+            subaccount: 0,
+        })
+        .unwrap();
+    assert_eq!(create_opt.addressees[0].address, address.address);
+    assert_eq!(create_opt.addressees[0].satoshi, 15_000);
+    assert_eq!(create_opt.addressees[0].asset_id, test_session.asset_id());
+    assert_eq!(create_opt.memo.as_deref(), Some("Invoice 1"));
+    create_opt.utxos = utils::convertutxos(&test_session.utxos(0));
+    test_session.session.create_transaction(&mut create_opt).unwrap();
+
+    let parse = |uri: String| {
+        test_session.session.parse_payment_uri(ParsePaymentUriOpt {
+            uri,
+            subaccount: 0,
+        })
+    };
+    let err = parse(format!("{}:{}?req-pop=1", prefix, address.address));
+    assert!(matches!(err, Err(Error::UnknownPaymentUriParameter(_))));
+    let err = parse(format!("{}:{}x", prefix, address.address));
+    assert!(matches!(err, Err(Error::InvalidAddress)));
+    let err = parse(format!("{}:{}?amount=1", prefix, address.address));
+    if is_liquid {
+        assert!(matches!(err, Err(Error::MissingPaymentUriAssetId)));
+    } else {
+        assert!(err.is_ok());
+        let err = parse(format!("{}:{}?assetid={}", prefix, address.address, "00".repeat(32)));
+        assert!(matches!(err, Err(Error::InvalidPaymentUri(_))));
+    }
+
+    test_session.stop();
+}
+
+#[test]
+fn payjoin() {
+    let mut test_session = TestSession::new(false, |_| ());
+    test_session.fund(1_000_000, None);
+
+    // the receiver contributes an input of the same script type of the sender
+    let receiver_address = test_session.node_getnewaddress(Some("p2sh-segwit"));
+    let receiver_txid = test_session.node_sendtoaddress(&receiver_address, 200_000, None);
+    test_session.mine_block();
+    let receiver_tx: Transaction = deserialize(
+        &Vec::<u8>::from_hex(&test_session.node_getrawtransaction(&receiver_txid)).unwrap(),
+    )
+    .unwrap();
+    let receiver_vout = receiver_tx.output.iter().position(|o| o.value == 200_000).unwrap();
+    let receiver_txout = receiver_tx.output[receiver_vout].clone();
+
+    let payee = test_session.node_getnewaddress(None);
+    let payee_script = bitcoin::Address::from_str(&payee).unwrap().script_pubkey();
+    let create_payjoin = |test_session: &mut TestSession, listener: &TcpListener| {
+        let uri = format!(
+            "bitcoin:{}?amount=0.001&pj=http://{}/pj",
+            payee,
+            listener.local_addr().unwrap()
+        );
+        let mut create_opt = test_session
+            .session
+            .parse_payment_uri(ParsePaymentUriOpt {
+                uri,
+                subaccount: 0,
+            })
+            .unwrap();
+        assert!(create_opt.payjoin_endpoint.is_some());
+        create_opt.utxos = utils::convertutxos(&test_session.utxos(0));
+        let tx = test_session.session.create_transaction(&mut create_opt).unwrap();
+        test_session.session.sign_transaction(&tx).unwrap()
+    };
+
+    // the receiver adds its input taking the maximum fee contribution from our change
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let original = create_payjoin(&mut test_session, &listener);
+    let node = &test_session.node;
+    let session = &mut test_session.session;
+    let payjoin = thread::scope(|s| {
+        s.spawn(|| {
+            mock_payjoin_receiver(&listener, |query, original| {
+                let param = |name: &str| {
+                    query.split('&').find_map(|p| p.strip_prefix(&format!("{}=", name))).unwrap()
+                };
+                assert_eq!(param("v"), "1");
+                assert_eq!(param("disableoutputsubstitution"), "true");
+                let fee_index: usize = param("additionalfeeoutputindex").parse().unwrap();
+                let contribution: u64 = param("maxadditionalfeecontribution").parse().unwrap();
+
+                let mut psbt: PartiallySignedTransaction =
+                    deserialize(&base64::decode(original).unwrap()).unwrap();
+                for input in psbt.inputs.iter_mut() {
+                    input.final_script_sig = None;
+                    input.final_script_witness = None;
+                }
+                let sequence = psbt.unsigned_tx.input[0].sequence;
+                psbt.unsigned_tx.input.push(TxIn {
+                    previous_output: bitcoin::OutPoint::new(
+                        receiver_tx.txid(),
+                        receiver_vout as u32,
+                    ),
+                    sequence,
+                    ..Default::default()
+                });
+                psbt.inputs.push(psbt::Input {
+                    witness_utxo: Some(receiver_txout.clone()),
+                    ..Default::default()
+                });
+                let outputs = &mut psbt.unsigned_tx.output;
+                outputs.iter_mut().find(|o| o.script_pubkey == payee_script).unwrap().value +=
+                    receiver_txout.value;
+                outputs[fee_index].value -= contribution;
+                let processed =
+                    node.client.walletprocesspsbt(&base64::encode(serialize(&psbt)), true).unwrap();
+                processed["psbt"].as_str().unwrap().to_string()
+            })
+        });
+        session.send_transaction(&original).unwrap()
+    });
+    assert_ne!(payjoin.txid, original.txid);
+    assert!(payjoin.fee > original.fee);
+    let payjoin_tx: Transaction = deserialize(&Vec::<u8>::from_hex(&payjoin.hex).unwrap()).unwrap();
+    assert_eq!(payjoin_tx.input.len(), original.used_utxos.len() + 1);
+    test_session.wait_tx(vec![0], &payjoin.txid, None, Some(TransactionType::Outgoing));
+
+    // invalid proposals are ignored and the original transaction is sent
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let original = create_payjoin(&mut test_session, &listener);
+    let session = &mut test_session.session;
+    let sent = thread::scope(|s| {
+        s.spawn(|| mock_payjoin_receiver(&listener, |_, original| original.to_string()));
+        session.send_transaction(&original).unwrap()
+    });
+    assert_eq!(sent.txid, original.txid);
+    test_session.wait_tx(vec![0], &sent.txid, None, Some(TransactionType::Outgoing));
+
+    let mut create_opt = CreateTransaction {
+        payjoin_endpoint: Some("ftp://example.com".into()),
+        ..test_session.create_opt(&payee, 10_000, None, None, 0, test_session.utxos(0))
+    };
+    assert!(matches!(
+        test_session.session.create_transaction(&mut create_opt),
+        Err(Error::InvalidPayjoinEndpoint(_))
+    ));
+
+    test_session.stop();
+}
+
+/// Serve a single PayJoin request, replying with the proposal built from its query string and the
+/// base64 original PSBT
+fn mock_payjoin_receiver(listener: &TcpListener, proposal: impl FnOnce(&str, &str) -> String) {
+    let (stream, _) = listener.accept().unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).unwrap();
+        if header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap();
+            }
+        }
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).unwrap();
+
+    let target = request_line.split_whitespace().nth(1).unwrap();
+    let query = target.split_once('?').map(|(_, query)| query).unwrap_or_default();
+    let response = proposal(query, std::str::from_utf8(&body).unwrap());
+    write!(
+        reader.get_mut(),
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.len(),
+        response
+    )
+    .unwrap();
+}
+
 #[test]
 fn skip_signing_bitcoin() {
     skip_signing(false);
@@ -1238,6 +2325,7 @@ fn skip_signing(is_liquid: bool) {
         address: dest_address,
         satoshi: 15000,
         asset_id: test_session.asset_id(),
+        data: None,
     });
     create_opt.utxos = utils::convertutxos(&test_session.utxos(create_opt.subaccount));
     let mut txc = test_session.session.create_transaction(&mut create_opt).unwrap();
@@ -1335,6 +2423,7 @@ fn labels() {
         address: test_session.get_receive_address(account2.account_num).address,
         satoshi: sat,
         asset_id: None,
+        data: None,
     });
     create_opt.utxos = utils::convertutxos(&test_session.utxos(create_opt.subaccount));
     create_opt.memo = Some("Foo, Bar Foo".into());
@@ -1348,39 +2437,271 @@ fn labels() {
         Some(TransactionType::Outgoing),
     );
 
-    // Memos should be set across all accounts
-    assert_eq!(test_session.get_tx_from_list(account1.account_num, &txid).memo, "Foo, Bar Foo");
-    assert_eq!(test_session.get_tx_from_list(account2.account_num, &txid).memo, "Foo, Bar Foo");
+    // Memos should be set across all accounts
+    assert_eq!(test_session.get_tx_from_list(account1.account_num, &txid).memo, "Foo, Bar Foo");
+    assert_eq!(test_session.get_tx_from_list(account2.account_num, &txid).memo, "Foo, Bar Foo");
+
+    test_session.session.set_transaction_memo(&txid, "Bar, Foo Qux").unwrap();
+    assert_eq!(test_session.get_tx_from_list(account1.account_num, &txid).memo, "Bar, Foo Qux");
+    assert_eq!(test_session.get_tx_from_list(account2.account_num, &txid).memo, "Bar, Foo Qux");
+
+    // Using the external signer and broadcast_transaction does not the memo
+    let test_signer = test_session.test_signer();
+    let mut create_opt = CreateTransaction::default();
+    create_opt.subaccount = account1.account_num;
+    let sat = 50000;
+    create_opt.addressees.push(AddressAmount {
+        address: test_session.get_receive_address(account2.account_num).address,
+        satoshi: sat,
+        asset_id: None,
+        data: None,
+    });
+    create_opt.utxos = utils::convertutxos(&test_session.utxos(create_opt.subaccount));
+    create_opt.memo = Some("Foo, Bar Foo".into());
+    let tx = test_session.session.create_transaction(&mut create_opt).unwrap();
+    let signed_tx = test_signer.sign_tx(&tx);
+    let txid = test_session.session.broadcast_transaction(&signed_tx.hex).unwrap();
+    test_session.wait_tx(
+        vec![account1.account_num, account2.account_num],
+        &txid,
+        Some(sat + signed_tx.fee),
+        Some(TransactionType::Outgoing),
+    );
+
+    // Memos is not set across all accounts
+    assert_eq!(test_session.get_tx_from_list(account1.account_num, &txid).memo, "");
+    assert_eq!(test_session.get_tx_from_list(account2.account_num, &txid).memo, "");
+
+    test_session.stop();
+}
+
+#[test]
+fn address_labels() {
+    let mut test_session = TestSession::new(false, |_| ());
+
+    let address = test_session.get_receive_address(0).address;
+    let opt = SetAddressLabelOpt {
+        address: address.clone(),
+        label: "Customer 42".into(),
+    };
+    test_session.session.set_address_label(opt).unwrap();
+    let opt = GetAddressLabelOpt {
+        address: address.clone(),
+    };
+    assert_eq!(test_session.session.get_address_label(opt).unwrap(), "Customer 42");
+
+    let sat = 10_000;
+    let txid = test_session.node_sendtoaddress(&address, sat, None);
+    test_session.wait_tx(vec![0], &txid, Some(sat), Some(TransactionType::Incoming));
+
+    let opt = GetPreviousAddressesOpt {
+        subaccount: 0,
+        last_pointer: None,
+        is_internal: false,
+        count: 10,
+    };
+    let previous_addresses = test_session.session.get_previous_addresses(&opt).unwrap();
+    let previous = previous_addresses.list.iter().find(|a| a.address == address).unwrap();
+    assert_eq!(previous.address_label, "Customer 42");
+
+    let utxos = test_session.utxos(0);
+    assert_eq!(utxos.0.get("btc").unwrap()[0].address_label, "Customer 42");
+
+    let tx = test_session.get_tx_from_list(0, &txid);
+    let output = tx.outputs.iter().find(|o| o.is_relevant).unwrap();
+    assert_eq!(output.address_label, "Customer 42");
+
+    // The empty string removes the label
+    let opt = SetAddressLabelOpt {
+        address: address.clone(),
+        label: "".into(),
+    };
+    test_session.session.set_address_label(opt).unwrap();
+    let tx = test_session.get_tx_from_list(0, &txid);
+    assert!(tx.outputs.iter().all(|o| o.address_label.is_empty()));
+    let opt = SetAddressLabelOpt {
+        address: "not an address".into(),
+        label: "Customer 42".into(),
+    };
+    assert!(test_session.session.set_address_label(opt).is_err());
+
+    test_session.stop();
+}
+
+#[test]
+fn frozen_utxos() {
+    let mut test_session = TestSession::new(false, |_| ());
+
+    let (sat1, sat2) = (10_000, 20_000);
+    for sat in [sat1, sat2] {
+        let address = test_session.get_receive_address(0).address;
+        let txid = test_session.node_sendtoaddress(&address, sat, None);
+        test_session.wait_tx(vec![0], &txid, Some(sat), Some(TransactionType::Incoming));
+    }
+    let utxos = test_session.utxo("btc", vec![sat1, sat2]);
+    let frozen = utxos.0.get("btc").unwrap().iter().find(|u| u.satoshi == sat2).unwrap().clone();
+    assert_eq!(frozen.user_status, USER_STATUS_DEFAULT);
+
+    let status = |user_status| SetUnspentOutputsStatusOpt {
+        list: vec![UnspentOutputStatus {
+            txhash: frozen.txhash.clone(),
+            pt_idx: frozen.pt_idx,
+            user_status,
+        }],
+    };
+    test_session.session.set_unspent_outputs_status(status(USER_STATUS_FROZEN)).unwrap();
+    assert!(test_session.session.set_unspent_outputs_status(status(2)).is_err());
+
+    // Frozen outputs are returned and counted only with "all_coins"
+    test_session.utxo("btc", vec![sat1]);
+    assert_eq!(test_session.balance_account(0, None, None), sat1);
+    let opt = GetUnspentOpt {
+        subaccount: 0,
+        num_confs: None,
+        confidential_utxos_only: None,
+        all_coins: Some(true),
+    };
+    let all_utxos = test_session.session.get_unspent_outputs(&opt).unwrap();
+    let utxo = all_utxos.0.get("btc").unwrap().iter().find(|u| u.satoshi == sat2).unwrap();
+    assert_eq!(utxo.user_status, USER_STATUS_FROZEN);
+    let opt = GetBalanceOpt {
+        subaccount: 0,
+        num_confs: 0,
+        confidential_utxos_only: None,
+        all_coins: Some(true),
+    };
+    assert_eq!(test_session.session.get_balance(&opt).unwrap()["btc"] as u64, sat1 + sat2);
+    let details = test_session.session.get_balance_details(&opt).unwrap();
+    assert_eq!(details.spendable["btc"] as u64, sat1);
+    assert_eq!(details.frozen["btc"] as u64, sat2);
+
+    // The automatic coin selections skip the frozen output, even if it is passed explicitly
+    let node_address = test_session.node_getnewaddress(None);
+    for strategy in [
+        UtxoStrategy::Default,
+        UtxoStrategy::BranchAndBound,
+        UtxoStrategy::SingleRandomDraw,
+        UtxoStrategy::LowestWaste,
+    ] {
+        let mut create_opt =
+            test_session.create_opt(&node_address, 15_000, None, None, 0, all_utxos.clone());
+        create_opt.utxo_strategy = strategy;
+        assert!(matches!(
+            test_session.session.create_transaction(&mut create_opt),
+            Err(Error::InsufficientFunds)
+        ));
+    }
+
+    // It can be spent by selecting it manually
+    let mut frozen_only = all_utxos.clone();
+    frozen_only.0.get_mut("btc").unwrap().retain(|u| u.satoshi == sat2);
+    let mut create_opt = test_session.create_opt(&node_address, 15_000, None, None, 0, frozen_only);
+    create_opt.utxo_strategy = UtxoStrategy::Manual;
+    test_session.session.create_transaction(&mut create_opt).unwrap();
+
+    test_session.session.set_unspent_outputs_status(status(USER_STATUS_DEFAULT)).unwrap();
+    test_session.utxo("btc", vec![sat1, sat2]);
+    assert_eq!(test_session.balance_account(0, None, None), sat1 + sat2);
+    let details = test_session.session.get_balance_details(&opt).unwrap();
+    assert_eq!(details.spendable["btc"] as u64, sat1 + sat2);
+    assert_eq!(details.frozen["btc"], 0);
 
-    test_session.session.set_transaction_memo(&txid, "Bar, Foo Qux").unwrap();
-    assert_eq!(test_session.get_tx_from_list(account1.account_num, &txid).memo, "Bar, Foo Qux");
-    assert_eq!(test_session.get_tx_from_list(account2.account_num, &txid).memo, "Bar, Foo Qux");
+    test_session.stop();
+}
 
-    // Using the external signer and broadcast_transaction does not the memo
-    let test_signer = test_session.test_signer();
-    let mut create_opt = CreateTransaction::default();
-    create_opt.subaccount = account1.account_num;
-    let sat = 50000;
-    create_opt.addressees.push(AddressAmount {
-        address: test_session.get_receive_address(account2.account_num).address,
-        satoshi: sat,
-        asset_id: None,
-    });
-    create_opt.utxos = utils::convertutxos(&test_session.utxos(create_opt.subaccount));
-    create_opt.memo = Some("Foo, Bar Foo".into());
-    let tx = test_session.session.create_transaction(&mut create_opt).unwrap();
-    let signed_tx = test_signer.sign_tx(&tx);
-    let txid = test_session.session.broadcast_transaction(&signed_tx.hex).unwrap();
-    test_session.wait_tx(
-        vec![account1.account_num, account2.account_num],
-        &txid,
-        Some(sat + signed_tx.fee),
-        Some(TransactionType::Outgoing),
+#[test]
+fn bip329_labels() {
+    let mut test_session = TestSession::new(false, |_| ());
+
+    let sat = 10_000;
+    let address = test_session.get_receive_address(0).address;
+    let txid = test_session.node_sendtoaddress(&address, sat, None);
+    test_session.wait_tx(vec![0], &txid, Some(sat), Some(TransactionType::Incoming));
+    let outpoint = {
+        let utxos = test_session.utxos(0);
+        let utxo = &utxos.0.get("btc").unwrap()[0];
+        format!("{}:{}", utxo.txhash, utxo.pt_idx)
+    };
+
+    test_session.session.set_transaction_memo(&txid, "Memo").unwrap();
+    let opt = SetAddressLabelOpt {
+        address: address.clone(),
+        label: "Address".into(),
+    };
+    test_session.session.set_address_label(opt).unwrap();
+    let opt = UpdateAccountOpt {
+        subaccount: 0,
+        name: Some("Savings".into()),
+        ..Default::default()
+    };
+    test_session.session.update_subaccount(opt).unwrap();
+
+    let exported = test_session.session.export_labels().unwrap().labels;
+    let records: Vec<Value> = exported.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0], serde_json::json!({"type": "tx", "ref": txid, "label": "Memo"}));
+    assert_eq!(records[1], serde_json::json!({"type": "addr", "ref": address, "label": "Address"}));
+    assert_eq!(records[2]["type"], "xpub");
+    assert_eq!(records[2]["label"], "Savings");
+
+    // Import in a new session with the same mnemonic, along with records from another wallet
+    let mut new_session = {
+        let mut network = test_session.network_parameters().clone();
+        let temp_dir = TempDir::new().unwrap();
+        network.state_dir = format!("{}", temp_dir.path().display());
+        ElectrumSession::new(network).unwrap()
+    };
+    new_session.connect(&serde_json::to_value(new_session.network.clone()).unwrap()).unwrap();
+    new_session.login(test_session.credentials.clone()).unwrap();
+    let labels = format!(
+        "{}{}\n{}\n{}\n",
+        exported,
+        serde_json::json!({"type": "output", "ref": outpoint, "label": "Output"}),
+        serde_json::json!({"type": "pubkey", "ref": "00", "label": "Public key"}),
+        serde_json::json!({"type": "tx", "ref": txid}),
+    );
+    let result = new_session
+        .import_labels(Bip329Labels {
+            labels,
+        })
+        .unwrap();
+    assert_eq!(
+        result,
+        ImportLabelsResult {
+            imported: 4,
+            ignored: 2,
+        }
     );
 
-    // Memos is not set across all accounts
-    assert_eq!(test_session.get_tx_from_list(account1.account_num, &txid).memo, "");
-    assert_eq!(test_session.get_tx_from_list(account2.account_num, &txid).memo, "");
+    new_session.wait_account_n_txs(0, 1);
+    let mut opt = GetTransactionsOpt::default();
+    opt.count = 1;
+    assert_eq!(new_session.get_transactions(&opt).unwrap().0[0].memo, "Memo");
+    let opt = GetAddressLabelOpt {
+        address: address.clone(),
+    };
+    assert_eq!(new_session.get_address_label(opt).unwrap(), "Address");
+    assert_eq!(new_session.get_subaccount(0).unwrap().settings.name, "Savings");
+    let opt = GetUnspentOpt {
+        subaccount: 0,
+        num_confs: None,
+        confidential_utxos_only: None,
+        all_coins: None,
+    };
+    let utxos = new_session.get_unspent_outputs(&opt).unwrap();
+    assert_eq!(utxos.0.get("btc").unwrap()[0].output_label, "Output");
+
+    // Importing again keeps the other labels
+    let labels = serde_json::json!({"type": "addr", "ref": address, "label": "New"}).to_string();
+    new_session
+        .import_labels(Bip329Labels {
+            labels,
+        })
+        .unwrap();
+    let exported = new_session.export_labels().unwrap().labels;
+    assert_eq!(exported.lines().count(), 4);
+    assert!(exported.contains("\"New\""));
+    new_session.disconnect().unwrap();
 
     test_session.stop();
 }
@@ -1410,6 +2731,7 @@ fn rbf() {
         address: dest_address,
         satoshi: 50000,
         asset_id: None,
+        data: None,
     });
     create_opt.utxos = utils::convertutxos(&test_session.utxos(create_opt.subaccount));
     create_opt.fee_rate = Some(25000);
@@ -1464,6 +2786,331 @@ fn rbf() {
     test_session.stop();
 }
 
+#[test]
+fn cpfp() {
+    let mut test_session = TestSession::new(false, |_| ());
+
+    let sat = 100_000;
+    let address = test_session.get_receive_address(0).address;
+    let txid = test_session.node_sendtoaddress(&address, sat, None);
+    test_session.wait_tx(vec![0], &txid, Some(sat), Some(TransactionType::Incoming));
+    let txitem = test_session.get_tx_from_list(0, &txid);
+    assert!(txitem.can_cpfp && !txitem.can_rbf);
+
+    // Spend the incoming output back to the wallet, paying for both transactions
+    let fee_rate = 20_000;
+    let mut create_opt = CreateTransaction::default();
+    create_opt.previous_transaction = Some(txitem.clone());
+    create_opt.cpfp = true;
+    create_opt.fee_rate = Some(fee_rate);
+    create_opt.utxos = utils::convertutxos(&test_session.utxos(0));
+    let tx = test_session.session.create_transaction(&mut create_opt).unwrap();
+    assert!(tx.create_transaction.as_ref().unwrap().send_all);
+    assert_eq!(tx.used_utxos.len(), 1);
+    let signed_tx = test_session.session.sign_transaction(&tx).unwrap();
+    let child = BETransaction::from_hex(&signed_tx.hex, test_session.network.id()).unwrap();
+    let package_vsize = txitem.transaction_vsize as f64 + child.get_weight() as f64 / 4.0;
+    let package_fee = (txitem.fee + signed_tx.fee) as f64;
+    assert!(package_fee / package_vsize >= fee_rate as f64 / 1000.0);
+    let child_txid = test_session.session.broadcast_transaction(&signed_tx.hex).unwrap();
+    test_session.wait_tx(
+        vec![0],
+        &child_txid,
+        Some(signed_tx.fee),
+        Some(TransactionType::Redeposit),
+    );
+    assert_eq!(test_session.balance_account(0, None, None), sat - signed_tx.fee);
+
+    // Bumping the child pays for its unconfirmed parent too
+    let child_txitem = test_session.get_tx_from_list(0, &child_txid);
+    let fee_rate = 40_000;
+    let mut create_opt = CreateTransaction::default();
+    create_opt.previous_transaction = Some(child_txitem.clone());
+    create_opt.cpfp = true;
+    create_opt.fee_rate = Some(fee_rate);
+    create_opt.utxos = utils::convertutxos(&test_session.utxos(0));
+    let tx = test_session.session.create_transaction(&mut create_opt).unwrap();
+    let signed_tx = test_session.session.sign_transaction(&tx).unwrap();
+    let grandchild = BETransaction::from_hex(&signed_tx.hex, test_session.network.id()).unwrap();
+    let package_vsize = (txitem.transaction_vsize + child_txitem.transaction_vsize) as f64
+        + grandchild.get_weight() as f64 / 4.0;
+    let package_fee = (txitem.fee + child_txitem.fee + signed_tx.fee) as f64;
+    assert!(package_fee / package_vsize >= fee_rate as f64 / 1000.0);
+    let grandchild_txid = test_session.session.broadcast_transaction(&signed_tx.hex).unwrap();
+    test_session.wait_tx(
+        vec![0],
+        &grandchild_txid,
+        Some(signed_tx.fee),
+        Some(TransactionType::Redeposit),
+    );
+
+    // The cpfp option needs a transaction to bump and can not cancel it
+    let mut create_opt = CreateTransaction::default();
+    create_opt.cpfp = true;
+    create_opt.utxos = utils::convertutxos(&test_session.utxos(0));
+    assert!(matches!(
+        test_session.session.create_transaction(&mut create_opt),
+        Err(Error::InvalidCpfpRequest)
+    ));
+    create_opt.previous_transaction = Some(child_txitem);
+    create_opt.cancel_transaction = true;
+    assert!(matches!(
+        test_session.session.create_transaction(&mut create_opt),
+        Err(Error::InvalidCpfpRequest)
+    ));
+
+    // Confirmed transactions can not be bumped
+    test_session.mine_block();
+    let txitem = test_session.get_tx_from_list(0, &txid);
+    assert!(!txitem.can_cpfp);
+    let mut create_opt = CreateTransaction::default();
+    create_opt.previous_transaction = Some(txitem);
+    create_opt.cpfp = true;
+    create_opt.utxos = utils::convertutxos(&test_session.utxos(0));
+    assert!(matches!(
+        test_session.session.create_transaction(&mut create_opt),
+        Err(Error::InvalidCpfpRequest)
+    ));
+
+    test_session.stop();
+}
+
+#[test]
+fn rbf_cancel() {
+    let mut test_session = TestSession::new(false, |_| ());
+
+    let sat = 1_000_000;
+    let address = test_session.get_receive_address(0).address;
+    let txid = test_session.node_sendtoaddress(&address, sat, None);
+    test_session.wait_tx(vec![0], &txid, Some(sat), Some(TransactionType::Incoming));
+
+    // Incoming transactions can not be cancelled
+    let mut create_opt = CreateTransaction::default();
+    create_opt.previous_transaction = Some(test_session.get_tx_from_list(0, &txid));
+    create_opt.cancel_transaction = true;
+    assert!(matches!(
+        test_session.session.create_transaction(&mut create_opt),
+        Err(Error::InvalidReplacementRequest)
+    ));
+    test_session.mine_block();
+
+    let node_address = test_session.node_getnewaddress(None);
+    let mut create_opt = CreateTransaction::default();
+    create_opt.addressees.push(AddressAmount {
+        address: node_address,
+        satoshi: 50_000,
+        asset_id: None,
+        data: None,
+    });
+    create_opt.utxos = utils::convertutxos(&test_session.utxos(0));
+    create_opt.fee_rate = Some(5000);
+    let tx = test_session.session.create_transaction(&mut create_opt).unwrap();
+    let signed_tx = test_session.session.sign_transaction(&tx).unwrap();
+    let txid1 = test_session.session.broadcast_transaction(&signed_tx.hex).unwrap();
+    test_session.wait_tx(
+        vec![0],
+        &txid1,
+        Some(50_000 + signed_tx.fee),
+        Some(TransactionType::Outgoing),
+    );
+    let txitem = test_session.get_tx_from_list(0, &txid1);
+    assert!(txitem.can_rbf);
+
+    // Spend the change of the transaction to cancel, the child is evicted with its parent
+    let mut create_opt = CreateTransaction::default();
+    create_opt.previous_transaction = Some(txitem.clone());
+    create_opt.cpfp = true;
+    create_opt.fee_rate = Some(2000);
+    create_opt.utxos = utils::convertutxos(&test_session.utxos(0));
+    let tx = test_session.session.create_transaction(&mut create_opt).unwrap();
+    let child_tx = test_session.session.sign_transaction(&tx).unwrap();
+    let child_txid = test_session.session.broadcast_transaction(&child_tx.hex).unwrap();
+    test_session.wait_tx(
+        vec![0],
+        &child_txid,
+        Some(child_tx.fee),
+        Some(TransactionType::Redeposit),
+    );
+
+    // A fee rate below the replacement requirements is raised
+    let mut create_opt = CreateTransaction::default();
+    create_opt.previous_transaction = Some(txitem.clone());
+    create_opt.cancel_transaction = true;
+    create_opt.fee_rate = Some(1000);
+    let tx = test_session.session.create_transaction(&mut create_opt).unwrap();
+    assert_eq!(tx.used_utxos.len(), txitem.inputs.len());
+    let signed_tx = test_session.session.sign_transaction(&tx).unwrap();
+    let cancel_tx = BETransaction::from_hex(&signed_tx.hex, test_session.network.id()).unwrap();
+    assert_eq!(cancel_tx.output_len(), 1);
+    let vsize = cancel_tx.get_weight() as u64 / 4;
+    assert!(signed_tx.fee >= txitem.fee + child_tx.fee + vsize);
+    let txid2 = test_session.session.broadcast_transaction(&signed_tx.hex).unwrap();
+    test_session.wait_tx(vec![0], &txid2, Some(signed_tx.fee), Some(TransactionType::Redeposit));
+
+    // The cancelled transaction should be gone (after the next sync with the server)
+    for i in 0..60 {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        if test_session.get_tx_list(0).iter().all(|e| e.txhash != txid1) {
+            assert_eq!(test_session.balance_account(0, None, None), sat - signed_tx.fee);
+            break;
+        }
+        assert!(i < 59, "timeout waiting for cancelled transaction to disappear");
+    }
+
+    test_session.stop();
+}
+
+#[test]
+fn rbf_batching() {
+    let mut test_session = TestSession::new(false, |_| ());
+
+    let sat = 1_000_000;
+    let txid =
+        test_session.node_sendtoaddress(&test_session.get_receive_address(0).address, 60_000, None);
+    test_session.wait_tx(vec![0], &txid, Some(60_000), Some(TransactionType::Incoming));
+    let txid =
+        test_session.node_sendtoaddress(&test_session.get_receive_address(0).address, sat, None);
+    test_session.wait_tx(vec![0], &txid, Some(sat), Some(TransactionType::Incoming));
+    test_session.mine_block();
+
+    // Pay the first recipient using the smaller utxo only
+    let address1 = test_session.node_getnewaddress(None);
+    let mut create_opt = CreateTransaction::default();
+    create_opt.addressees.push(AddressAmount {
+        address: address1.clone(),
+        satoshi: 50_000,
+        asset_id: None,
+        data: None,
+    });
+    let mut utxos = test_session.utxos(0);
+    utxos.0.get_mut("btc").unwrap().retain(|u| u.satoshi == 60_000);
+    create_opt.utxos = utils::convertutxos(&utxos);
+    create_opt.fee_rate = Some(2000);
+    let tx = test_session.session.create_transaction(&mut create_opt).unwrap();
+    let signed_tx = test_session.session.sign_transaction(&tx).unwrap();
+    let txid1 = test_session.session.broadcast_transaction(&signed_tx.hex).unwrap();
+    test_session.wait_tx(
+        vec![0],
+        &txid1,
+        Some(50_000 + signed_tx.fee),
+        Some(TransactionType::Outgoing),
+    );
+    let txitem = test_session.get_tx_from_list(0, &txid1);
+
+    // Add a second recipient and repeat the payout to the first one, which needs another input
+    let address2 = test_session.node_getnewaddress(None);
+    let mut create_opt = CreateTransaction::default();
+    create_opt.previous_transaction = Some(txitem);
+    for (address, satoshi) in [(&address2, 100_000), (&address1, 50_000)] {
+        create_opt.add_addressees.push(AddressAmount {
+            address: address.clone(),
+            satoshi,
+            asset_id: None,
+            data: None,
+        });
+    }
+    create_opt.utxos = utils::convertutxos(&test_session.utxos(0));
+    create_opt.fee_rate = Some(3000);
+    let tx = test_session.session.create_transaction(&mut create_opt).unwrap();
+    assert!(!tx.addressees_read_only);
+    assert_eq!(tx.used_utxos.len(), 2);
+    let request = tx.create_transaction.clone().unwrap();
+    let addresses: Vec<&str> = request.addressees.iter().map(|a| a.address.as_str()).collect();
+    assert_eq!(addresses, vec![address1.as_str(), address2.as_str(), address1.as_str()]);
+
+    // Creating the transaction again from the result does not duplicate the recipients
+    let mut create_opt = request;
+    let tx = test_session.session.create_transaction(&mut create_opt).unwrap();
+    assert_eq!(tx.create_transaction.as_ref().unwrap().addressees.len(), 3);
+
+    let signed_tx = test_session.session.sign_transaction(&tx).unwrap();
+    let batch_tx = BETransaction::from_hex(&signed_tx.hex, test_session.network.id()).unwrap();
+    assert_eq!(batch_tx.output_len(), 4);
+    let txid2 = test_session.session.broadcast_transaction(&signed_tx.hex).unwrap();
+    test_session.wait_tx(
+        vec![0],
+        &txid2,
+        Some(200_000 + signed_tx.fee),
+        Some(TransactionType::Outgoing),
+    );
+    assert_eq!(test_session.balance_account(0, None, None), sat + 60_000 - 200_000 - signed_tx.fee);
+
+    // Recipients can be added only to a replaced transaction
+    let mut create_opt =
+        test_session.create_opt(&address2, 10_000, None, None, 0, test_session.utxos(0));
+    create_opt.add_addressees = create_opt.addressees.clone();
+    assert!(matches!(
+        test_session.session.create_transaction(&mut create_opt),
+        Err(Error::InvalidReplacementRequest)
+    ));
+
+    test_session.stop();
+}
+
+#[test]
+fn timelocks() {
+    let mut test_session = TestSession::new(false, |_| ());
+
+    let sat = 1_000_000;
+    let txid =
+        test_session.node_sendtoaddress(&test_session.get_receive_address(0).address, sat, None);
+    test_session.wait_tx(vec![0], &txid, Some(sat), Some(TransactionType::Incoming));
+    test_session.mine_block();
+    let node_address = test_session.node_getnewaddress(None);
+    let create_opt =
+        test_session.create_opt(&node_address, 50_000, None, None, 0, test_session.utxos(0));
+
+    // By default the locktime discourages fee sniping
+    let tip = test_session.session.block_status().unwrap().0;
+    let tx = test_session.session.create_transaction(&mut create_opt.clone()).unwrap();
+    assert!(tx.lock_time <= tip && tx.lock_time + 100 > tip);
+
+    // A transaction with a future locktime can not be broadcast until the chain reaches it
+    let mut locktime_opt = create_opt.clone();
+    locktime_opt.transaction_locktime = Some(tip + 2);
+    let tx = test_session.session.create_transaction(&mut locktime_opt).unwrap();
+    assert_eq!(tx.lock_time, tip + 2);
+    let signed_tx = test_session.session.sign_transaction(&tx).unwrap();
+    assert!(test_session.session.broadcast_transaction(&signed_tx.hex).is_err());
+    test_session.mine_block();
+    let txid = test_session.session.broadcast_transaction(&signed_tx.hex).unwrap();
+    test_session.wait_tx(
+        vec![0],
+        &txid,
+        Some(50_000 + signed_tx.fee),
+        Some(TransactionType::Outgoing),
+    );
+    test_session.mine_block();
+
+    // A final sequence on every input would disable the requested locktime
+    let mut utxos = test_session.utxos(0);
+    utxos.0.get_mut("btc").unwrap()[0].sequence = Some(0xffff_ffff);
+    let mut final_opt = test_session.create_opt(&node_address, 50_000, None, None, 0, utxos);
+    final_opt.utxo_strategy = UtxoStrategy::Manual;
+    final_opt.transaction_locktime = Some(tip);
+    assert!(matches!(
+        test_session.session.create_transaction(&mut final_opt),
+        Err(Error::InvalidTimelock(_))
+    ));
+
+    // A relative timelock of 3 blocks on the change output
+    let mut utxos = test_session.utxos(0);
+    let btc_utxos = utxos.0.get_mut("btc").unwrap();
+    assert_eq!(btc_utxos.len(), 1);
+    btc_utxos[0].sequence = Some(3);
+    let mut sequence_opt = test_session.create_opt(&node_address, 50_000, None, None, 0, utxos);
+    sequence_opt.utxo_strategy = UtxoStrategy::Manual;
+    let tx = test_session.session.create_transaction(&mut sequence_opt).unwrap();
+    assert_eq!(tx.used_utxos[0].sequence, Some(3));
+    let signed_tx = test_session.session.sign_transaction(&tx).unwrap();
+    assert!(test_session.session.broadcast_transaction(&signed_tx.hex).is_err());
+    test_session.mine_block();
+    test_session.mine_block();
+    test_session.session.broadcast_transaction(&signed_tx.hex).unwrap();
+
+    test_session.stop();
+}
+
 #[test]
 fn test_electrum_disconnect() {
     let mut test_session = TestSession::new(false, |_| ());
@@ -1883,3 +3530,116 @@ fn assert_unwrap_invalid(result: spv::CrossValidationResult) -> spv::CrossValida
         _ => panic!("expected cross-validation to fail"),
     }
 }
+
+#[test]
+fn remote_signer() {
+    let test_session = TestSession::new(false, |_| {});
+    let signer = test_session.test_signer();
+
+    // The signer runs in another thread, as it would in another process
+    let (request_sender, request_receiver) = mpsc::channel::<SignerRequest>();
+    let (response_sender, response_receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for request in request_receiver {
+            if response_sender.send(handle_request(&signer, &request)).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut remote_session = {
+        let mut network = test_session.network_parameters().clone();
+        let temp_dir = TempDir::new().unwrap();
+        network.state_dir = format!("{}", temp_dir.path().display());
+        ElectrumSession::new(network).unwrap()
+    };
+    remote_session.connect(&serde_json::to_value(remote_session.network.clone()).unwrap()).unwrap();
+    let remote_signer = RemoteSigner::new((request_sender, response_receiver));
+    remote_session.login_with_signer(Arc::new(remote_signer)).unwrap();
+    assert_eq!(remote_session.master_xpub, test_session.session.master_xpub);
+
+    let address = remote_session
+        .get_receive_address(&GetAddressOpt {
+            subaccount: 0,
+            address_type: None,
+            is_internal: None,
+            uri: None,
+        })
+        .unwrap()
+        .address;
+    test_session.node_sendtoaddress(&address, 100_000, None);
+    remote_session.wait_account_n_txs(0, 1);
+
+    let mut create_opt = CreateTransaction::default();
+    create_opt.subaccount = 0;
+    create_opt.addressees.push(AddressAmount {
+        address: test_session.node_getnewaddress(None),
+        satoshi: 10_000,
+        asset_id: None,
+        data: None,
+    });
+    let utxos = remote_session
+        .get_unspent_outputs(&GetUnspentOpt {
+            subaccount: 0,
+            num_confs: None,
+            confidential_utxos_only: None,
+            all_coins: None,
+        })
+        .unwrap();
+    create_opt.utxos = utils::convertutxos(&utxos);
+    let tx = remote_session.create_transaction(&mut create_opt).unwrap();
+    let signed = remote_session.sign_transaction(&tx).unwrap();
+    let txid = remote_session.send_transaction(&signed).unwrap().txid;
+    // Both sessions are of the same wallet
+    test_session.wait_tx(vec![0], &txid, None, None);
+    remote_session.wait_account_n_txs(0, 2);
+
+    remote_session.disconnect().unwrap();
+}
+
+#[test]
+fn sign_message() {
+    let mut test_session = TestSession::new(false, |_| {});
+    let message = "Hello World";
+
+    for subaccount in 1..4 {
+        test_session
+            .session
+            .create_subaccount(CreateAccountOpt {
+                subaccount,
+                name: format!("Account {}", subaccount),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+    // p2sh-p2wpkh, p2wpkh, p2pkh and p2tr
+    for subaccount in 0..4 {
+        let address = test_session.get_receive_address(subaccount).address;
+        for bip322_full in [false, true] {
+            let opt = SignMessageOpt {
+                address: address.clone(),
+                message: message.into(),
+                bip322_full,
+            };
+            let signature = test_session.session.sign_message(opt).unwrap().signature;
+            let mut param = VerifyMessageParam {
+                address: address.clone(),
+                message: message.into(),
+                signature,
+            };
+            assert!(message::verify_message(&param).unwrap().valid);
+            param.message = "Hello World!".into();
+            assert!(!message::verify_message(&param).unwrap().valid);
+        }
+    }
+
+    // Only wallet addresses can sign
+    let opt = SignMessageOpt {
+        address: test_session.node_getnewaddress(None),
+        message: message.into(),
+        bip322_full: false,
+    };
+    assert!(test_session.session.sign_message(opt).is_err());
+
+    test_session.stop();
+}
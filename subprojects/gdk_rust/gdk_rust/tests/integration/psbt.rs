@@ -2,6 +2,7 @@ use std::collections::HashMap;
 use std::iter::FromIterator;
 use std::str::FromStr;
 
+use gdk_common::bitcoin;
 use gdk_common::bitcoin::hashes::hex::FromHex;
 use gdk_common::elements::{AssetId, Script};
 use serde_json::{json, Map, Value};
@@ -76,7 +77,7 @@ fn net_balance(details: &PsbtGetDetailsResult, subaccount: u32) -> HashMap<Asset
 
     for output in details.outputs.iter().filter(|input| input.subaccount == subaccount) {
         balance
-            .entry(output.asset_id)
+            .entry(output.asset_id.unwrap())
             .and_modify(|v| *v += output.satoshi as i64)
             .or_insert(output.satoshi as i64);
     }
@@ -110,6 +111,70 @@ fn psbt_with_node_liquid() {
     assert_eq!(net_balance(&res, 1), HashMap::new());
 }
 
+#[test]
+fn psbt_bitcoin() {
+    let mut session = TestSession::new(false, |_| {});
+
+    let address = session.get_receive_address(SUBACCOUNT).address;
+    let txid = session.node_sendtoaddress(&address, 100_000, None);
+    session.wait_tx(vec![SUBACCOUNT], &txid, None, None);
+    let utxos = session.utxos(SUBACCOUNT).0.into_iter().next().unwrap().1;
+
+    let sat = 5000;
+    let mut opts = session.create_opt(
+        &session.node_getnewaddress(None),
+        sat,
+        None,
+        None,
+        SUBACCOUNT,
+        session.utxos(SUBACCOUNT),
+    );
+    let tx = session.session.create_transaction(&mut opts).unwrap();
+    let psbt = session.session.create_psbt(&tx).unwrap().psbt;
+
+    let res = session
+        .session
+        .psbt_get_details(PsbtGetDetailsParams {
+            psbt: psbt.clone(),
+            utxos,
+        })
+        .unwrap();
+    let spent: u64 = res.inputs.iter().map(|u| u.satoshi).sum();
+    let received: u64 = res.outputs.iter().map(|o| o.satoshi).sum();
+    assert_eq!(spent - received, sat + tx.fee);
+    assert!(res.outputs.iter().all(|o| o.asset_id.is_none() && o.subaccount == SUBACCOUNT));
+
+    let mut psbt: bitcoin::util::psbt::PartiallySignedTransaction =
+        bitcoin::consensus::deserialize(&base64::decode(&psbt).unwrap()).unwrap();
+    let (fingerprint, account_path) = psbt.xpub.values().next().unwrap().clone();
+    assert_eq!(account_path.len(), 3);
+    for input in psbt.inputs.iter() {
+        assert!(input.witness_utxo.is_some());
+        assert!(input.non_witness_utxo.is_some());
+        assert!(input.redeem_script.is_some());
+        let (origin_fingerprint, path) = input.bip32_derivation.values().next().unwrap();
+        assert_eq!(*origin_fingerprint, fingerprint);
+        assert!(path.as_ref().starts_with(account_path.as_ref()));
+    }
+    assert_eq!(psbt.outputs.iter().filter(|o| !o.bip32_derivation.is_empty()).count(), 1);
+
+    let err = session.session.finalize_psbt(FinalizePsbtOpt {
+        psbt: base64::encode(bitcoin::consensus::serialize(&psbt)),
+    });
+    assert!(err.is_err());
+
+    session.test_signer().sign_psbt(&mut psbt);
+    let signed = session
+        .session
+        .finalize_psbt(FinalizePsbtOpt {
+            psbt: base64::encode(bitcoin::consensus::serialize(&psbt)),
+        })
+        .unwrap();
+    assert_eq!(signed.fee, tx.fee);
+    let txid = session.session.send_transaction(&signed).unwrap().txid;
+    session.wait_tx(vec![SUBACCOUNT], &txid, None, None);
+}
+
 fn setup_user_address(session: &TestSession, account: u32) -> AddressPointer {
     let user_address = session.get_receive_address(account);
 
@@ -59,6 +59,8 @@ impl ElectrumSessionExt for ElectrumSession {
                 xpub: Some(xpub),
                 discovered: false,
                 is_already_created: true,
+                multisig: None,
+                descriptor: None,
             };
             self.create_subaccount(opt).unwrap();
         }
@@ -85,6 +87,7 @@ impl ElectrumSessionExt for ElectrumSession {
                 let opt = DiscoverAccountOpt {
                     script_type: *script_type,
                     xpub,
+                    gap_limit: None,
                 };
                 if self.discover_subaccount(opt).unwrap() {
                     let opt = CreateAccountOpt {
@@ -54,7 +54,7 @@ impl TestSession {
 
         let _ = env_logger::try_init();
 
-        let mut args = vec!["-fallbackfee=0.0001", "-dustrelayfee=0.00000001"];
+        let mut args = vec!["-fallbackfee=0.0001", "-dustrelayfee=0.00000001", "-txindex=1"];
         let network = if is_liquid {
             args.extend_from_slice(&[
                 "-chain=liquidregtest",
@@ -63,7 +63,7 @@ impl TestSession {
             ]);
             "liquidregtest"
         } else {
-            args.extend_from_slice(&["-regtest"]);
+            args.extend_from_slice(&["-regtest", "-blockfilterindex=1", "-peerblockfilters=1"]);
             "regtest"
         };
         let mut conf = electrsd::bitcoind::Conf::default();
@@ -92,7 +92,7 @@ impl TestSession {
         let mut conf = electrsd::Conf::default();
         conf.args = args;
         conf.view_stderr = is_debug;
-        conf.http_enabled = false;
+        conf.http_enabled = true;
         conf.network = network;
 
         let electrs = electrsd::ElectrsD::with_conf(&*electrs, &node, &conf).unwrap();
@@ -117,6 +117,10 @@ impl TestSession {
 
         let mut network = NetworkParameters::default();
         network.electrum_url = Some(electrs.electrum_url.clone());
+        network.esplora_url = electrs.esplora_url.clone();
+        network.rpc_url = Some(format!("http://{}", node.params.rpc_socket));
+        network.rpc_auth = Some(std::fs::read_to_string(&node.params.cookie_file).unwrap());
+        network.compact_filters_peer = Some(format!("127.0.0.1:{}", p2p_port));
         network.sync_interval = Some(1);
         network.development = true;
         network.spv_enabled = Some(true);
@@ -309,6 +313,7 @@ impl TestSession {
             address: address.to_string(),
             satoshi: 0,
             asset_id: asset_id.clone().or(self.asset_id()),
+            data: None,
         });
         create_opt.send_all = true;
         let tx = self.session.create_transaction(&mut create_opt).unwrap();
@@ -352,6 +357,7 @@ impl TestSession {
             address: address.to_string(),
             satoshi,
             asset_id: asset.clone().or(self.asset_id()),
+            data: None,
         });
         create_opt.memo = memo;
         create_opt.utxos = utils::convertutxos(&unspent_outputs.unwrap_or_else(|| self.utxos(0)));
@@ -368,7 +374,7 @@ impl TestSession {
         let num_used_utxos = tx.used_utxos.len();
         match create_opt.utxo_strategy {
             UtxoStrategy::Manual => assert_eq!(num_used_utxos, num_utxos),
-            UtxoStrategy::Default => assert!(num_used_utxos > 0 && num_used_utxos <= num_utxos),
+            _ => assert!(num_used_utxos > 0 && num_used_utxos <= num_utxos),
         }
         let signed_tx = self.session.sign_transaction(&tx).unwrap();
         self.check_fee_rate(fee_rate, &signed_tx, MAX_FEE_PERCENT_DIFF);
@@ -432,6 +438,7 @@ impl TestSession {
             address: address.to_string(),
             satoshi,
             asset_id: asset.clone().or(self.asset_id()),
+            data: None,
         });
         create_opt.utxos = utils::convertutxos(&self.utxos(create_opt.subaccount));
         let tx = self.session.create_transaction(&mut create_opt).unwrap();
@@ -495,6 +502,7 @@ impl TestSession {
             subaccount,
             address_type: None,
             is_internal: None,
+            uri: None,
         };
         self.session.get_receive_address(&addr_opt).unwrap()
     }
@@ -523,6 +531,7 @@ impl TestSession {
                 address: address.to_string(),
                 satoshi: amount,
                 asset_id,
+                data: None,
             });
         }
         create_opt.utxos = utils::convertutxos(&self.utxos(create_opt.subaccount));
@@ -597,6 +606,7 @@ impl TestSession {
             address: node_address.clone(),
             satoshi: init_sat, // not enough to pay the fee with confidential utxos only
             asset_id: self.asset_id(),
+            data: None,
         });
         create_opt.utxos = utils::convertutxos(&self.utxos(create_opt.subaccount));
         create_opt.confidential_utxos_only = true;
@@ -654,6 +664,7 @@ impl TestSession {
             address: address.to_string(),
             satoshi,
             asset_id: self.asset_id(),
+            data: None,
         });
         create_opt.utxos = utils::convertutxos(&self.utxos(create_opt.subaccount));
         let tx = self.session.create_transaction(&mut create_opt).unwrap();
@@ -689,6 +700,7 @@ impl TestSession {
             address: address.to_string(),
             satoshi,
             asset_id,
+            data: None,
         });
         create_opt
     }
@@ -935,6 +947,7 @@ impl TestSession {
             subaccount: 0,
             num_confs: 0,
             confidential_utxos_only: None,
+            all_coins: None,
         };
         self.session.get_balance(&opt).unwrap()
     }
@@ -954,6 +967,7 @@ impl TestSession {
             subaccount: account_num,
             num_confs: 0,
             confidential_utxos_only,
+            all_coins: None,
         };
         let balance = self.session.get_balance(&opt).unwrap();
         match self.network_id {
@@ -1041,6 +1055,7 @@ impl TestSession {
                 subaccount,
                 address_type: None,
                 is_internal: Some(i == 1),
+                uri: None,
             };
             let ap = self.session.get_receive_address(&addr_opt).unwrap();
 
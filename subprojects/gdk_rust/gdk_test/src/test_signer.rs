@@ -2,15 +2,18 @@ use gdk_common::bitcoin::blockdata::script::Builder as ScriptBuilder;
 use gdk_common::bitcoin::hashes::hex::{FromHex, ToHex};
 use gdk_common::bitcoin::hashes::Hash;
 use gdk_common::bitcoin::network::constants::Network as Bip32Network;
-use gdk_common::bitcoin::secp256k1::{All, Message, Secp256k1};
+use gdk_common::bitcoin::secp256k1::{ecdsa, schnorr, All, KeyPair, Message, Scalar, Secp256k1};
 use gdk_common::bitcoin::util::address::Address;
 use gdk_common::bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey};
+use gdk_common::bitcoin::util::psbt::PartiallySignedTransaction;
 use gdk_common::bitcoin::util::sighash::SighashCache;
-use gdk_common::bitcoin::{self, EcdsaSighashType, Witness};
+use gdk_common::bitcoin::{self, EcdsaSig, EcdsaSighashType, Witness};
 
 use gdk_common::be::BETransaction;
 use gdk_common::model::*;
 use gdk_common::wally::{self, asset_blinding_key_from_seed, MasterBlindingKey};
+use gdk_electrum::error::Error;
+use gdk_electrum::signer::Signer;
 
 /// Struct that holds the secret, so that we can replicate the resolver behavior
 pub struct TestSigner {
@@ -128,4 +131,72 @@ impl TestSigner {
         details_out.used_utxos = details.used_utxos.clone();
         details_out
     }
+
+    /// Add our signatures to the singlesig inputs of a Bitcoin PSBT, as an external signer would
+    /// do using only the data in the PSBT
+    pub fn sign_psbt(&self, psbt: &mut PartiallySignedTransaction) {
+        let fingerprint = self.master_xpub().fingerprint();
+        let tx = psbt.unsigned_tx.clone();
+        let mut cache = SighashCache::new(&tx);
+        for (i, input) in psbt.inputs.iter_mut().enumerate() {
+            let ours = input.bip32_derivation.iter().find(|(_, (f, _))| *f == fingerprint);
+            let (public_key, path) = match ours {
+                Some((public_key, (_, path))) => (*public_key, path.clone()),
+                None => continue,
+            };
+            let private_key = self.master_xprv().derive_priv(&self.secp, &path).unwrap().to_priv();
+            let public_key = bitcoin::PublicKey::new(public_key);
+            assert_eq!(private_key.public_key(&self.secp), public_key);
+
+            let sighash = input.sighash_type.map(|s| s.ecdsa_hash_ty().unwrap());
+            let sighash = sighash.unwrap_or(EcdsaSighashType::All);
+            let script_code = Address::p2pkh(&public_key, self.network).script_pubkey();
+            let signature_hash = match input.witness_utxo {
+                Some(ref prevout) => {
+                    cache.segwit_signature_hash(i, &script_code, prevout.value, sighash).unwrap()
+                }
+                None => tx.signature_hash(i, &script_code, sighash.to_u32()),
+            };
+            let message = Message::from_slice(&signature_hash.into_inner()[..]).unwrap();
+            let signature = self.secp.sign_ecdsa(&message, &private_key.inner);
+            input.partial_sigs.insert(
+                public_key,
+                EcdsaSig {
+                    sig: signature,
+                    hash_ty: sighash,
+                },
+            );
+        }
+    }
+}
+
+impl Signer for TestSigner {
+    fn xpub(&self, path: &DerivationPath) -> Result<ExtendedPubKey, Error> {
+        Ok(self.account_xpub(path))
+    }
+
+    fn sign_ecdsa(
+        &self,
+        path: &DerivationPath,
+        message: &Message,
+    ) -> Result<ecdsa::Signature, Error> {
+        let xprv = self.master_xprv().derive_priv(&self.secp, path)?;
+        Ok(self.secp.sign_ecdsa_low_r(message, &xprv.private_key))
+    }
+
+    fn sign_schnorr(
+        &self,
+        path: &DerivationPath,
+        message: &Message,
+        tweak: &Scalar,
+    ) -> Result<schnorr::Signature, Error> {
+        let xprv = self.master_xprv().derive_priv(&self.secp, path)?;
+        let keypair = KeyPair::from_secret_key(&self.secp, &xprv.private_key);
+        let keypair = keypair.add_xonly_tweak(&self.secp, tweak)?;
+        Ok(self.secp.sign_schnorr_no_aux_rand(message, &keypair))
+    }
+
+    fn master_blinding_key(&self) -> Result<MasterBlindingKey, Error> {
+        Ok(self.master_blinding())
+    }
 }